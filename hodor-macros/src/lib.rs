@@ -0,0 +1,128 @@
+// hodor-macros provides #[hodor::memoize]; enable it with hodor's `macros` feature rather than
+// depending on this crate directly
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Error, FnArg, ItemFn, LitInt, LitStr, Pat, ReturnType};
+
+// memoize caches a function's results in a static hodor::Memoized keyed by its arguments:
+//
+//     #[hodor::memoize(ttl = "30s", capacity = 10_000)]
+//     fn lookup(user: String) -> Profile { ... }
+//
+// Both settings are optional; without a ttl results are kept until evicted, and without a
+// capacity the cache is unbounded. Arguments must be owned, 'static, Clone, Hash and Eq, and the
+// result Clone. On async fns, concurrent calls with the same arguments await a single call.
+// Generic functions and methods taking self aren't supported.
+#[proc_macro_attribute]
+pub fn memoize(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut ttl = None;
+    let mut capacity = None;
+    let settings = syn::meta::parser(|meta| {
+        if meta.path.is_ident("ttl") {
+            let lit: LitStr = meta.value()?.parse()?;
+            let millis = parse_duration(&lit.value()).ok_or_else(|| Error::new(lit.span(), "expected a duration like \"500ms\", \"30s\", \"5m\", \"1h\" or \"1d\""))?;
+            ttl = Some(millis);
+            Ok(())
+        } else if meta.path.is_ident("capacity") {
+            let lit: LitInt = meta.value()?.parse()?;
+            capacity = Some(lit.base10_parse::<usize>()?);
+            Ok(())
+        } else {
+            Err(meta.error("expected `ttl` or `capacity`"))
+        }
+    });
+    parse_macro_input!(attr with settings);
+    let func = parse_macro_input!(item as ItemFn);
+
+    match expand(func, ttl, capacity) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(func: ItemFn, ttl: Option<u64>, capacity: Option<usize>) -> syn::Result<proc_macro2::TokenStream> {
+    let ItemFn{ attrs, vis, sig, block } = func;
+    if !sig.generics.params.is_empty() {
+        return Err(Error::new_spanned(&sig.generics, "memoize doesn't support generic fns"))
+    }
+
+    let mut names = Vec::new();
+    let mut types = Vec::new();
+    for arg in &sig.inputs {
+        match arg {
+            FnArg::Receiver(receiver) => return Err(Error::new_spanned(receiver, "memoize doesn't support methods taking self")),
+            FnArg::Typed(typed) => match &*typed.pat {
+                Pat::Ident(pat) if pat.by_ref.is_none() && pat.subpat.is_none() => {
+                    names.push(pat.ident.clone());
+                    types.push((*typed.ty).clone());
+                },
+                pat => return Err(Error::new_spanned(pat, "memoize needs arguments bound to plain names")),
+            },
+        }
+    }
+    let output = match &sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => quote!(#ty),
+    };
+    let ttl = match ttl {
+        Some(millis) => quote!(::std::option::Option::Some(::std::time::Duration::from_millis(#millis))),
+        None => quote!(::std::option::Option::None),
+    };
+    let capacity = match capacity {
+        Some(capacity) => quote!(::std::option::Option::Some(#capacity)),
+        None => quote!(::std::option::Option::None),
+    };
+    let memo = syn::Ident::new("__HODOR_MEMO", Span::call_site());
+
+    let key = quote!((#(::std::clone::Clone::clone(&#names),)*));
+    // async fns are coalesced: concurrent calls with the same arguments share one computation
+    let call = match sig.asyncness {
+        Some(_) => quote!(.get_or_insert_async(#key, move || async move #block).await),
+        None => quote!(.get_or_insert_with(#key, move || #block)),
+    };
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis #sig {
+            static #memo: ::std::sync::OnceLock<::hodor::Memoized<(#(#types,)*), #output>> = ::std::sync::OnceLock::new();
+            #memo.get_or_init(|| ::hodor::Memoized::new(#ttl, #capacity))#call
+        }
+    })
+}
+
+// parse_duration parses durations like "500ms", "30s", "5m", "1h" or "1d" into milliseconds
+fn parse_duration(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '_')?;
+    let (n, unit) = s.split_at(split);
+    let n: u64 = n.replace('_', "").parse().ok()?;
+    let scale = match unit.trim() {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => return None,
+    };
+    n.checked_mul(scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_duration;
+
+    #[test]
+    fn durations() {
+        assert_eq!(Some(500), parse_duration("500ms"));
+        assert_eq!(Some(30_000), parse_duration("30s"));
+        assert_eq!(Some(600_000), parse_duration("10m"));
+        assert_eq!(Some(3_600_000), parse_duration("1h"));
+        assert_eq!(Some(86_400_000), parse_duration("1d"));
+        assert_eq!(None, parse_duration("30"));
+        assert_eq!(None, parse_duration("s"));
+        assert_eq!(None, parse_duration("3w"));
+    }
+}
@@ -0,0 +1,195 @@
+// adapters implement hodor's Cache trait over other cache crates, so code written against the
+// trait can be run (and benchmarked) on either backend without changes
+#[cfg(feature = "lru")]
+pub use self::lru_adapter::LruAdapter;
+#[cfg(feature = "moka")]
+pub use self::moka_adapter::MokaAdapter;
+
+#[cfg(feature = "lru")]
+mod lru_adapter {
+    use std::hash::Hash;
+    use std::num::NonZeroUsize;
+    use std::time::{Duration, Instant};
+
+    use crate::Cache;
+
+    // LruAdapter wraps an lru::LruCache, storing each value with its deadline. Reads go through
+    // get_with(&self), which can't update the LRU order, so recency follows writes only.
+    pub struct LruAdapter<K: Hash+Eq, V> {
+        cache: lru::LruCache<K, (V, Option<Instant>)>,
+    }
+
+    impl<K: Hash+Eq, V> LruAdapter<K, V> {
+        // panics if capacity is 0
+        pub fn new(capacity: usize) -> LruAdapter<K, V> {
+            let capacity = NonZeroUsize::new(capacity).expect("capacity must be positive");
+            LruAdapter{ cache: lru::LruCache::new(capacity) }
+        }
+
+        pub fn inner(&self) -> &lru::LruCache<K, (V, Option<Instant>)> {
+            &self.cache
+        }
+
+        fn put(&mut self, key: K, value: V, deadline: Option<Instant>) -> Option<V> {
+            let now = Instant::now();
+            self.cache.put(key, (value, deadline))
+                .filter(|(_, deadline)| deadline.is_none_or(|at| at > now))
+                .map(|(v, _)| v)
+        }
+    }
+
+    impl<K: Hash+Eq+Clone, V> Cache<K, V> for LruAdapter<K, V> {
+        fn insert(&mut self, key: K, value: V) -> Option<V> {
+            self.put(key, value, None)
+        }
+
+        fn insert_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+            self.put(key, value, Some(Instant::now() + ttl))
+        }
+
+        fn get_with(&self, key: &K, f: &mut dyn FnMut(&V)) -> bool {
+            match self.cache.peek(key) {
+                Some((v, deadline)) if deadline.is_none_or(|at| at > Instant::now()) => {
+                    f(v);
+                    true
+                },
+                _ => false,
+            }
+        }
+
+        fn take(&mut self, key: K) -> Option<V> {
+            let (v, deadline) = self.cache.pop(&key)?;
+            if deadline.is_some_and(|at| at <= Instant::now()) {
+                return None
+            }
+            Some(v)
+        }
+
+        // lru keeps no index of expiring entries, so vacuum checks up to count entries from
+        // the least recently used end; retry_threshold is ignored
+        fn vacuum(&mut self, count : usize, _retry_threshold : f32 ) {
+            let now = Instant::now();
+            let expired: Vec<K> = self.cache.iter().rev().take(count)
+                .filter(|(_, (_, deadline))| deadline.is_some_and(|at| at <= now))
+                .map(|(k, _)| k.clone())
+                .collect();
+            for key in expired {
+                self.cache.pop(&key);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "moka")]
+mod moka_adapter {
+    use std::hash::Hash;
+    use std::time::{Duration, Instant};
+
+    use crate::Cache;
+
+    // PerEntry expires each moka entry after the TTL stored alongside its value
+    struct PerEntry;
+
+    impl<K, V> moka::Expiry<K, (V, Option<Duration>)> for PerEntry {
+        fn expire_after_create(&self, _: &K, value: &(V, Option<Duration>), _: Instant) -> Option<Duration> {
+            value.1
+        }
+
+        fn expire_after_update(&self, _: &K, value: &(V, Option<Duration>), _: Instant, _: Option<Duration>) -> Option<Duration> {
+            value.1
+        }
+    }
+
+    // MokaAdapter wraps a moka::sync::Cache. moka doesn't return the value an insert replaces,
+    // so insert reads it first (cloning it); benchmarks of inserts include that read.
+    pub struct MokaAdapter<K, V> {
+        cache: moka::sync::Cache<K, (V, Option<Duration>)>,
+    }
+
+    impl<K: Hash+Eq+Send+Sync+'static, V: Clone+Send+Sync+'static> MokaAdapter<K, V> {
+        pub fn new(max_capacity: u64) -> MokaAdapter<K, V> {
+            let cache = moka::sync::Cache::builder()
+                .max_capacity(max_capacity)
+                .expire_after(PerEntry)
+                .build();
+            MokaAdapter{ cache }
+        }
+
+        pub fn inner(&self) -> &moka::sync::Cache<K, (V, Option<Duration>)> {
+            &self.cache
+        }
+
+        fn put(&mut self, key: K, value: V, ttl: Option<Duration>) -> Option<V> {
+            let previous = self.cache.get(&key).map(|(v, _)| v);
+            self.cache.insert(key, (value, ttl));
+            previous
+        }
+    }
+
+    impl<K: Hash+Eq+Send+Sync+'static, V: Clone+Send+Sync+'static> Cache<K, V> for MokaAdapter<K, V> {
+        fn insert(&mut self, key: K, value: V) -> Option<V> {
+            self.put(key, value, None)
+        }
+
+        fn insert_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+            self.put(key, value, Some(ttl))
+        }
+
+        fn get_with(&self, key: &K, f: &mut dyn FnMut(&V)) -> bool {
+            match self.cache.get(key) {
+                Some((v, _)) => {
+                    f(&v);
+                    true
+                },
+                None => false,
+            }
+        }
+
+        fn take(&mut self, key: K) -> Option<V> {
+            self.cache.remove(&key).map(|(v, _)| v)
+        }
+
+        // moka expires entries itself; vacuum runs its pending maintenance now
+        fn vacuum(&mut self, _count : usize, _retry_threshold : f32 ) {
+            self.cache.run_pending_tasks();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Cache;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    // exercise runs the same checks against any Cache implementation
+    fn exercise<C: Cache<&'static str, String>>(mut cache: C) {
+        assert_eq!(None, cache.insert("id", "secret".to_string()));
+        assert_eq!(Some("secret".to_string()), cache.insert("id", "secret2".to_string()));
+        cache.insert_ttl("token", "secret3".to_string(), Duration::from_millis(10));
+        assert!(cache.get("token", |v| assert_eq!("secret3", v)));
+
+        sleep(Duration::from_millis(20));
+        cache.vacuum(10, 0.25);
+        assert!(!cache.get("token", |_| {}));
+        assert_eq!(Some("secret2".to_string()), cache.take("id"));
+        assert!(!cache.get("id", |_| {}));
+    }
+
+    #[test]
+    fn hodor() {
+        exercise(crate::HashCache::new());
+    }
+
+    #[cfg(feature = "lru")]
+    #[test]
+    fn lru() {
+        exercise(super::LruAdapter::new(10));
+    }
+
+    #[cfg(feature = "moka")]
+    #[test]
+    fn moka() {
+        exercise(super::MokaAdapter::new(10));
+    }
+}
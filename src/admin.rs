@@ -0,0 +1,251 @@
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use serde_json::json;
+
+use crate::{DefaultHashBuilder, ThreadSafeHashCache};
+
+// how many keys GET /keys lists unless ?limit= says otherwise
+const DEFAULT_KEY_LIMIT: usize = 10;
+
+// AdminResponse is the answer to an admin request; bodies are always JSON
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl AdminResponse {
+    fn ok(body: serde_json::Value) -> AdminResponse {
+        AdminResponse{ status: 200, body: body.to_string() }
+    }
+
+    fn error(status: u16, msg: &str) -> AdminResponse {
+        AdminResponse{ status, body: json!({ "error": msg }).to_string() }
+    }
+}
+
+// Admin answers operational requests about a running cache:
+//   GET /stats          counters and hit ratio
+//   GET /config         how the cache was built
+//   GET /keys?limit=N   the most read keys (with hits tracked by the eviction policy)
+//   POST /vacuum        remove every expired entry now
+// handle can be mounted in any web framework; serve runs a minimal HTTP server instead.
+pub struct Admin<K: Hash+Eq+Clone, V, S = DefaultHashBuilder> {
+    cache: Arc<ThreadSafeHashCache<K, V, S>>,
+}
+
+impl<K: Hash+Eq+Clone+fmt::Debug, V, S: BuildHasher> Admin<K, V, S> {
+    pub fn new(cache: Arc<ThreadSafeHashCache<K, V, S>>) -> Admin<K, V, S> {
+        Admin{ cache }
+    }
+
+    // handle answers a request given its method and path (including any query string)
+    pub fn handle(&self, method: &str, path: &str) -> AdminResponse {
+        let (path, query) = match path.split_once('?') {
+            Some((path, query)) => (path, query),
+            None => (path, ""),
+        };
+        match (method, path) {
+            ("GET", "/stats") => self.stats(),
+            ("GET", "/config") => self.config(),
+            ("GET", "/keys") => {
+                let limit = query.split('&')
+                    .find_map(|kv| kv.strip_prefix("limit="))
+                    .map(|n| n.parse());
+                match limit {
+                    None => self.keys(DEFAULT_KEY_LIMIT),
+                    Some(Ok(n)) => self.keys(n),
+                    Some(Err(_)) => AdminResponse::error(400, "limit must be a number"),
+                }
+            },
+            ("POST", "/vacuum") => {
+                let removed = self.cache.drain_expired().count();
+                AdminResponse::ok(json!({ "removed": removed }))
+            },
+            (_, "/stats") | (_, "/config") | (_, "/keys") | (_, "/vacuum") => AdminResponse::error(405, "method not allowed"),
+            _ => AdminResponse::error(404, "not found"),
+        }
+    }
+
+    fn stats(&self) -> AdminResponse {
+        let stats = self.cache.stats();
+        AdminResponse::ok(json!({
+            "len": self.cache.len(),
+            "capacity": self.cache.capacity(),
+            "hits": stats.hits,
+            "misses": stats.misses,
+            "hit_ratio": stats.hit_ratio(),
+            "inserts": stats.inserts,
+            "evictions": stats.evictions,
+            "expirations": stats.expirations,
+        }))
+    }
+
+    fn config(&self) -> AdminResponse {
+        let cache = self.cache.read_index();
+        let config = &cache.config;
+        AdminResponse::ok(json!({
+            "initial_capacity": config.initial_capacity,
+            "max_capacity": config.max_capacity,
+            "soft_capacity": config.soft_capacity,
+            "shrink_threshold": config.shrink_threshold,
+            "eviction": format!("{:?}", config.eviction),
+            "negative_filter": config.negative_filter.is_some(),
+            "early_expiration": config.early_expiration,
+            "refresh_after_ms": config.refresh_after.map(|d| d.as_millis() as u64),
+            "stale_while_revalidate_ms": config.stale_while_revalidate.map(|d| d.as_millis() as u64),
+            "expire_after_access_ms": config.expire_after_access.map(|d| d.as_millis() as u64),
+            "hot_keys": config.hot_keys.map(|(n, _)| n),
+            "vacuum_schedule": config.vacuum_schedule.map(|s| format!("{:?}", s)),
+            "exact_expiry": config.exact_expiry,
+            "expire_after": config.expire_after.is_some(),
+            "redact_values": config.redact_values,
+        }))
+    }
+
+    fn keys(&self, limit: usize) -> AdminResponse {
+        let cache = self.cache.read_index();
+        let mut keys: Vec<_> = cache.entries.iter()
+            .map(|(_, (k, v))| (k, v.access.hits.get()))
+            .collect();
+        keys.sort_by_key(|&(_, hits)| std::cmp::Reverse(hits));
+        let keys: Vec<_> = keys.into_iter().take(limit)
+            .map(|(k, hits)| json!({ "key": format!("{:?}", k), "hits": hits }))
+            .collect();
+        AdminResponse::ok(json!({ "keys": keys }))
+    }
+}
+
+impl<K: Hash+Eq+Clone+fmt::Debug+Send+Sync+'static, V: Send+Sync+'static, S: BuildHasher+Send+Sync+'static> Admin<K, V, S> {
+    // serve answers requests on addr from a background thread until the AdminServer is dropped
+    pub fn serve<A: ToSocketAddrs>(self, addr: A) -> io::Result<AdminServer> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::SeqCst) {
+                    break
+                }
+                // a broken connection only affects its own request
+                if let Ok(stream) = stream {
+                    let _ = self.respond(stream);
+                }
+            }
+        });
+        Ok(AdminServer{ addr, stop, thread: Some(thread) })
+    }
+
+    fn respond(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(&stream);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // skip the headers; no endpoint takes a body
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let mut parts = request.split_whitespace();
+        let response = match (parts.next(), parts.next()) {
+            (Some(method), Some(path)) => self.handle(method, path),
+            _ => AdminResponse::error(400, "bad request"),
+        };
+        let reason = match response.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "",
+        };
+        let mut stream = &stream;
+        write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            response.status, reason, response.body.len(), response.body)?;
+        stream.flush()
+    }
+}
+
+// AdminServer is a running admin endpoint; dropping it shuts the server down
+pub struct AdminServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AdminServer {
+    // addr is the bound address, e.g. to find the port when serving on port 0
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for AdminServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // wake the accept loop up so it sees the flag
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Admin;
+    use crate::{CacheBuilder, ThreadSafeHashCache};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn handle() {
+        let cache : Arc<ThreadSafeHashCache<&str,&str>> = Arc::new(CacheBuilder::new().max_capacity(10).build_thread_safe());
+        cache.insert("id", "secret");
+        cache.insert("id2", "secret2");
+        cache.insert_ttl("gone", "secret3", Duration::from_millis(1));
+        for _ in 0..3 {
+            cache.get("id2", |_| {});
+        }
+        cache.get("id", |_| {});
+        std::thread::sleep(Duration::from_millis(5));
+        let admin = Admin::new(cache.clone());
+
+        let json = |path| serde_json::from_str::<serde_json::Value>(&admin.handle("GET", path).body).unwrap();
+        assert_eq!(4, json("/stats")["hits"]);
+        assert_eq!(10, json("/config")["max_capacity"]);
+        assert_eq!("Lru", json("/config")["eviction"]);
+        let keys = json("/keys?limit=2");
+        assert_eq!(2, keys["keys"].as_array().unwrap().len());
+        assert_eq!("\"id2\"", keys["keys"][0]["key"]);
+        assert_eq!(3, keys["keys"][0]["hits"]);
+
+        assert_eq!(r#"{"removed":1}"#, admin.handle("POST", "/vacuum").body);
+        assert_eq!(405, admin.handle("GET", "/vacuum").status);
+        assert_eq!(404, admin.handle("GET", "/nope").status);
+        assert_eq!(400, admin.handle("GET", "/keys?limit=ten").status);
+    }
+
+    #[test]
+    fn serve() {
+        let cache : Arc<ThreadSafeHashCache<String,String>> = Arc::new(ThreadSafeHashCache::new());
+        cache.insert("id".to_string(), "secret".to_string());
+        let server = Admin::new(cache).serve("127.0.0.1:0").unwrap();
+
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.write_all(b"GET /stats HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(r#""len":1,"misses":0}"#), "{}", response);
+        drop(server);
+    }
+}
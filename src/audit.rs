@@ -0,0 +1,115 @@
+use std::hash::{BuildHasher, Hash};
+use std::time::Duration;
+
+use crate::ThreadSafeHashCache;
+
+// AuditHook receives an AuditEvent for every get, insert and take, see CacheBuilder::audit
+pub type AuditHook<K> = dyn Fn(&AuditEvent<'_, K>) + Send + Sync;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditOp {
+    Get,
+    Insert,
+    Remove,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditOutcome {
+    // a get found a live value, or didn't (absent, expired or filtered out)
+    Hit,
+    Miss,
+    // an insert stored a new key, or replaced a live value
+    Inserted,
+    Replaced,
+    // a take removed a live value, or found none
+    Removed,
+    Absent,
+}
+
+// AuditEvent describes one audited operation: context is what the caller passed to
+// ThreadSafeHashCache::audited (e.g. a principal or request id), or None for plain calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditEvent<'a, K> {
+    pub op: AuditOp,
+    pub key: &'a K,
+    pub outcome: AuditOutcome,
+    pub context: Option<&'a str>,
+}
+
+// Audited is a handle to a cache whose operations are reported to the audit hook with a context
+pub struct Audited<'a, K: Hash+Eq+Clone, V, S> {
+    cache: &'a ThreadSafeHashCache<K, V, S>,
+    context: &'a str,
+}
+
+impl<K: Hash+Eq+Clone, V, S: BuildHasher> ThreadSafeHashCache<K, V, S> {
+    // audited returns a handle whose operations reach the audit hook with context attached, e.g.
+    // cache.audited("user:42").get_copied(&token)
+    pub fn audited<'a>(&'a self, context: &'a str) -> Audited<'a, K, V, S> {
+        Audited{ cache: self, context }
+    }
+}
+
+impl<K: Hash+Eq+Clone, V, S: BuildHasher> Audited<'_, K, V, S> {
+    pub fn get<F>(&self, key: &K, f: F) -> bool where F: FnOnce(&V) {
+        if self.cache.definitely_absent_as(key, Some(self.context)) {
+            return false
+        }
+        self.cache.read_key(key).hit_as(key, Some(self.context)).map(f).is_some()
+    }
+
+    pub fn get_copied(&self, key: &K) -> Option<V> where V: Copy {
+        if self.cache.definitely_absent_as(key, Some(self.context)) {
+            return None
+        }
+        self.cache.read_key(key).hit_as(key, Some(self.context)).copied()
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.cache.write().insert_as(key, value, None, Some(self.context)).previous
+    }
+
+    pub fn insert_ttl(&self, key: K, value: V, ttl: Duration) -> Option<V> {
+        self.cache.write().insert_as(key, value, Some(ttl), Some(self.context)).previous
+    }
+
+    pub fn take(&self, key: &K) -> Option<V> {
+        self.cache.write().take_as(key, Some(self.context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditOp, AuditOutcome};
+    use crate::{CacheBuilder, ThreadSafeHashCache};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn audit_trail() {
+        let trail = Arc::new(Mutex::new(Vec::new()));
+        let log = trail.clone();
+        let cache : ThreadSafeHashCache<&str,u32> = CacheBuilder::<&str,u32>::new()
+            .negative_filter(100, 0.01)
+            .audit(move |e| log.lock().unwrap().push((e.op, e.key.to_string(), e.outcome, e.context.map(str::to_string))))
+            .build_thread_safe();
+
+        cache.insert("token", 1);
+        let admin = cache.audited("admin");
+        assert_eq!(Some(1), admin.get_copied(&"token"));
+        // misses answered by the filter are audited too
+        assert!(!admin.get(&"forged", |_| {}));
+        assert_eq!(Some(1), admin.insert("token", 2));
+        assert_eq!(Some(2), cache.take("token"));
+        assert_eq!(None, admin.take(&"token"));
+
+        let admin = || Some("admin".to_string());
+        assert_eq!(vec![
+            (AuditOp::Insert, "token".to_string(), AuditOutcome::Inserted, None),
+            (AuditOp::Get, "token".to_string(), AuditOutcome::Hit, admin()),
+            (AuditOp::Get, "forged".to_string(), AuditOutcome::Miss, admin()),
+            (AuditOp::Insert, "token".to_string(), AuditOutcome::Replaced, admin()),
+            (AuditOp::Remove, "token".to_string(), AuditOutcome::Removed, None),
+            (AuditOp::Remove, "token".to_string(), AuditOutcome::Absent, admin()),
+        ], *trail.lock().unwrap());
+    }
+}
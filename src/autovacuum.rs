@@ -0,0 +1,51 @@
+// the bounds auto_vacuum keeps its sample size within
+const MIN_SAMPLE: usize = 20;
+const MAX_SAMPLE: usize = 1024;
+// the bounds of the retry threshold: a pass repeats while more than this share of its sample had
+// expired
+const MAX_THRESHOLD: f32 = 0.25;
+const MIN_THRESHOLD: f32 = 0.05;
+// MAX_PASSES bounds how long one auto_vacuum holds the cache, however much has expired
+const MAX_PASSES: usize = 16;
+
+// AutoVacuum is the state auto_vacuum carries between runs, in the spirit of Redis' active expiry
+// cycle: a moving average of the share of sampled entries found expired, and the sample size it
+// has settled on. The sample grows while much of what it finds has expired and shrinks back
+// while little has, so quiet caches are cheap to vacuum and busy ones are kept clean.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AutoVacuum {
+    pub(crate) sample: usize,
+    pub(crate) expired_ratio: f32,
+}
+
+impl Default for AutoVacuum {
+    fn default() -> Self {
+        AutoVacuum{ sample: MIN_SAMPLE, expired_ratio: 0.0 }
+    }
+}
+
+impl AutoVacuum {
+    pub(crate) const MAX_PASSES: usize = MAX_PASSES;
+
+    // threshold is the retry threshold for an expiring index of len entries: a sample covering
+    // the whole index can stop at a higher expired share than one seeing a sliver of it
+    pub(crate) fn threshold(&self, len: usize) -> f32 {
+        let coverage = self.sample as f32 / len.max(1) as f32;
+        (MAX_THRESHOLD * coverage).clamp(MIN_THRESHOLD, MAX_THRESHOLD)
+    }
+
+    // observe records the expired share of one pass
+    pub(crate) fn observe(&mut self, ratio: f32) {
+        self.expired_ratio = 0.75 * self.expired_ratio + 0.25 * ratio;
+    }
+
+    // adjust resizes the sample after a run, for an expiring index of len entries
+    pub(crate) fn adjust(&mut self, len: usize) {
+        if self.expired_ratio > MAX_THRESHOLD {
+            self.sample *= 2;
+        } else if self.expired_ratio < MIN_THRESHOLD {
+            self.sample /= 2;
+        }
+        self.sample = self.sample.min(len.max(MIN_SAMPLE)).clamp(MIN_SAMPLE, MAX_SAMPLE);
+    }
+}
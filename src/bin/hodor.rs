@@ -0,0 +1,400 @@
+// hodor inspects and edits snapshot files written by a cache's save_snapshot
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process;
+use std::time::{Duration, SystemTime};
+
+use hodor::{Format, Snapshot, SnapshotEntry};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+const USAGE: &str = "usage:
+    hodor inspect <snapshot> [filters]            summarize a snapshot and list matching entries
+    hodor diff <old> <new>                        list keys added, removed or changed
+    hodor filter <in> <out> [filters]             keep only matching entries
+    hodor ttl <in> <out> --ttl <duration|none> [filters]
+                                                  set the TTL of matching entries
+
+filters:
+    --prefix <prefix>             keys starting with prefix
+    --expiring-within <duration>  entries with at most duration left, e.g. 60s, 5m, 2h, 500ms
+    --persistent                  entries without a TTL
+
+options:
+    --format <json|bincode|msgpack|cbor>  snapshot format; by default picked from the extension
+                                          (.json, .bin, .msgpack, .cbor)
+    --key <type>, --value <type>          types of keys and values in bincode snapshots, which
+                                          don't describe themselves: string (default), bytes,
+                                          u64, i64, f64 or bool";
+
+// Kind is a key or value type that bincode snapshots can be read as
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    String,
+    Bytes,
+    U64,
+    I64,
+    F64,
+    Bool,
+}
+
+// with_kind runs body with the alias $t bound to the Rust type for a Kind
+macro_rules! with_kind {
+    ($kind:expr, $t:ident => $body:expr) => {
+        match $kind {
+            Kind::String => { type $t = String; $body },
+            Kind::Bytes => { type $t = Vec<u8>; $body },
+            Kind::U64 => { type $t = u64; $body },
+            Kind::I64 => { type $t = i64; $body },
+            Kind::F64 => { type $t = f64; $body },
+            Kind::Bool => { type $t = bool; $body },
+        }
+    };
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    paths: Vec<String>,
+    format: Option<Format>,
+    key: Option<Kind>,
+    value: Option<Kind>,
+    prefix: Option<String>,
+    expiring_within: Option<Duration>,
+    persistent: bool,
+    // Some(None) makes entries persistent
+    ttl: Option<Option<Duration>>,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+impl Options {
+    fn parse(args: &[String]) -> io::Result<Options> {
+        let mut opts = Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| invalid(format!("{} needs a value", arg)));
+            match arg.as_str() {
+                "--format" => opts.format = Some(parse_format(value()?)?),
+                "--key" => opts.key = Some(parse_kind(value()?)?),
+                "--value" => opts.value = Some(parse_kind(value()?)?),
+                "--prefix" => opts.prefix = Some(value()?.clone()),
+                "--expiring-within" => opts.expiring_within = Some(parse_duration(value()?)?),
+                "--persistent" => opts.persistent = true,
+                "--ttl" => opts.ttl = Some(match value()?.as_str() {
+                    "none" => None,
+                    d => Some(parse_duration(d)?),
+                }),
+                flag if flag.starts_with("--") => return Err(invalid(format!("unknown option {}", flag))),
+                path => opts.paths.push(path.to_string()),
+            }
+        }
+        Ok(opts)
+    }
+
+    fn paths<const N: usize>(&self) -> io::Result<[&str; N]> {
+        if self.paths.len() != N {
+            return Err(invalid(format!("expected {} file(s), got {}\n\n{}", N, self.paths.len(), USAGE)))
+        }
+        let mut paths = [""; N];
+        for (p, path) in paths.iter_mut().zip(&self.paths) {
+            *p = path;
+        }
+        Ok(paths)
+    }
+
+    fn format_of(&self, path: &str) -> io::Result<Format> {
+        if let Some(format) = self.format {
+            return Ok(format)
+        }
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("json") => Ok(Format::Json),
+            Some("bin") | Some("bincode") => Ok(Format::Bincode),
+            Some("msgpack") | Some("mp") => Ok(Format::MessagePack),
+            Some("cbor") => Ok(Format::Cbor),
+            _ => Err(invalid(format!("can't tell the format of {}; pass --format", path))),
+        }
+    }
+
+    // matches reports whether an entry with the given remaining TTL passes the filters
+    fn matches(&self, key: &Value, remaining: Option<Duration>) -> bool {
+        if let Some(prefix) = &self.prefix {
+            if !key_string(key).starts_with(prefix.as_str()) {
+                return false
+            }
+        }
+        if let Some(within) = self.expiring_within {
+            if remaining.is_none_or(|r| r > within) {
+                return false
+            }
+        }
+        !self.persistent || remaining.is_none()
+    }
+
+    fn read(&self, path: &str) -> io::Result<Snapshot<Value, Value>> {
+        let r = BufReader::new(File::open(path)?);
+        match self.format_of(path)? {
+            Format::Bincode => with_kind!(self.key.unwrap_or(Kind::String), K => {
+                with_kind!(self.value.unwrap_or(Kind::String), V => read_typed::<K, V, _>(r))
+            }),
+            format => Snapshot::decode(r, format),
+        }
+    }
+
+    fn write(&self, path: &str, snapshot: &Snapshot<Value, Value>) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        match self.format_of(path)? {
+            Format::Bincode => with_kind!(self.key.unwrap_or(Kind::String), K => {
+                with_kind!(self.value.unwrap_or(Kind::String), V => write_typed::<K, V, _>(&mut w, snapshot))
+            })?,
+            format => snapshot.encode(&mut w, format)?,
+        }
+        w.flush()
+    }
+}
+
+fn parse_format(s: &str) -> io::Result<Format> {
+    match s {
+        "json" => Ok(Format::Json),
+        "bincode" => Ok(Format::Bincode),
+        "msgpack" => Ok(Format::MessagePack),
+        "cbor" => Ok(Format::Cbor),
+        _ => Err(invalid(format!("unknown format {}", s))),
+    }
+}
+
+fn parse_kind(s: &str) -> io::Result<Kind> {
+    match s {
+        "string" => Ok(Kind::String),
+        "bytes" => Ok(Kind::Bytes),
+        "u64" => Ok(Kind::U64),
+        "i64" => Ok(Kind::I64),
+        "f64" => Ok(Kind::F64),
+        "bool" => Ok(Kind::Bool),
+        _ => Err(invalid(format!("unknown type {}", s))),
+    }
+}
+
+// parse_duration reads durations like 500ms, 60s, 5m, 2h or 1d; a bare number is in seconds
+fn parse_duration(s: &str) -> io::Result<Duration> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (n, unit) = s.split_at(split);
+    let n: u64 = n.parse().map_err(|_| invalid(format!("invalid duration {}", s)))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(n)),
+        "" | "s" => Ok(Duration::from_secs(n)),
+        "m" => Ok(Duration::from_secs(n * 60)),
+        "h" => Ok(Duration::from_secs(n * 60 * 60)),
+        "d" => Ok(Duration::from_secs(n * 60 * 60 * 24)),
+        _ => Err(invalid(format!("invalid duration {}", s))),
+    }
+}
+
+fn to_value<T: Serialize>(t: T) -> io::Result<Value> {
+    serde_json::to_value(t).map_err(io::Error::from)
+}
+
+fn from_value<T: DeserializeOwned>(v: &Value) -> io::Result<T> {
+    T::deserialize(v).map_err(io::Error::from)
+}
+
+fn read_typed<K: DeserializeOwned+Serialize, V: DeserializeOwned+Serialize, R: io::Read>(r: R) -> io::Result<Snapshot<Value, Value>> {
+    let snapshot : Snapshot<K, V> = Snapshot::decode(r, Format::Bincode)?;
+    let entries = snapshot.entries.into_iter()
+        .map(|e| Ok(SnapshotEntry{ key: to_value(e.key)?, value: to_value(e.value)?, ttl: e.ttl, deadline: e.deadline }))
+        .collect::<io::Result<_>>()?;
+    Ok(Snapshot{ entries })
+}
+
+fn write_typed<K: DeserializeOwned+Serialize, V: DeserializeOwned+Serialize, W: Write>(w: W, snapshot: &Snapshot<Value, Value>) -> io::Result<()> {
+    let entries = snapshot.entries.iter()
+        .map(|e| Ok(SnapshotEntry{ key: from_value::<K>(&e.key)?, value: from_value::<V>(&e.value)?, ttl: e.ttl, deadline: e.deadline }))
+        .collect::<io::Result<_>>()?;
+    Snapshot{ entries }.encode(w, Format::Bincode)
+}
+
+fn key_string(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.clone(),
+        key => key.to_string(),
+    }
+}
+
+// remaining is the time an entry has left at now: None inside for a persistent entry, or None
+// outside if it has already expired
+fn remaining(e: &SnapshotEntry<Value, Value>, now: SystemTime) -> Option<Option<Duration>> {
+    match e.deadline {
+        Some(deadline) => deadline.duration_since(now).ok().map(Some),
+        None => Some(e.ttl),
+    }
+}
+
+fn format_ttl(remaining: Option<Duration>) -> String {
+    match remaining {
+        Some(r) => format!("{:.1}s", r.as_secs_f64()),
+        None => "-".to_string(),
+    }
+}
+
+fn summary(value: &Value) -> String {
+    let s = value.to_string();
+    match s.char_indices().nth(60) {
+        Some((cut, _)) => format!("{}…", &s[..cut]),
+        None => s,
+    }
+}
+
+fn inspect(opts: &Options) -> io::Result<()> {
+    let [path] = opts.paths()?;
+    let snapshot = opts.read(path)?;
+    let now = SystemTime::now();
+
+    let (mut persistent, mut expiring, mut expired) = (0, 0, 0);
+    let mut matched = Vec::new();
+    for e in &snapshot.entries {
+        match remaining(e, now) {
+            None => expired += 1,
+            Some(r) => {
+                if r.is_some() { expiring += 1 } else { persistent += 1 }
+                if opts.matches(&e.key, r) {
+                    matched.push((e, r));
+                }
+            },
+        }
+    }
+
+    let out = io::stdout();
+    let mut out = out.lock();
+    writeln!(out, "{}: {} entries ({} persistent, {} expiring, {} already expired)",
+        path, snapshot.entries.len(), persistent, expiring, expired)?;
+    for (e, r) in &matched {
+        writeln!(out, "{}\t{}\t{}", key_string(&e.key), format_ttl(*r), summary(&e.value))?;
+    }
+    writeln!(out, "{} matching", matched.len())
+}
+
+fn diff(opts: &Options) -> io::Result<()> {
+    let [old, new] = opts.paths()?;
+    let by_key = |s: Snapshot<Value, Value>| -> BTreeMap<String, Value> {
+        s.entries.into_iter().map(|e| (key_string(&e.key), e.value)).collect()
+    };
+    let old = by_key(opts.read(old)?);
+    let new = by_key(opts.read(new)?);
+
+    let out = io::stdout();
+    let mut out = out.lock();
+    for (key, value) in &old {
+        match new.get(key) {
+            None => writeln!(out, "- {}", key)?,
+            Some(v) if v != value => writeln!(out, "~ {}\t{} -> {}", key, summary(value), summary(v))?,
+            Some(_) => {},
+        }
+    }
+    for key in new.keys().filter(|k| !old.contains_key(*k)) {
+        writeln!(out, "+ {}", key)?;
+    }
+    Ok(())
+}
+
+fn filter(opts: &Options) -> io::Result<()> {
+    let [input, output] = opts.paths()?;
+    let mut snapshot = opts.read(input)?;
+    let now = SystemTime::now();
+    snapshot.entries.retain(|e| remaining(e, now).is_some_and(|r| opts.matches(&e.key, r)));
+    opts.write(output, &snapshot)
+}
+
+fn set_ttl(opts: &Options) -> io::Result<()> {
+    let [input, output] = opts.paths()?;
+    let ttl = opts.ttl.ok_or_else(|| invalid("ttl needs --ttl".to_string()))?;
+    let mut snapshot = opts.read(input)?;
+    let now = SystemTime::now();
+    for e in &mut snapshot.entries {
+        if remaining(e, now).is_some_and(|r| opts.matches(&e.key, r)) {
+            e.ttl = ttl;
+            e.deadline = ttl.map(|ttl| now + ttl);
+        }
+    }
+    opts.write(output, &snapshot)
+}
+
+fn run(args: &[String]) -> io::Result<()> {
+    let (command, rest) = args.split_first().ok_or_else(|| invalid(USAGE.to_string()))?;
+    let opts = Options::parse(rest)?;
+    match command.as_str() {
+        "inspect" => inspect(&opts),
+        "diff" => diff(&opts),
+        "filter" => filter(&opts),
+        "ttl" => set_ttl(&opts),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        },
+        _ => Err(invalid(format!("unknown command {}\n\n{}", command, USAGE))),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("hodor: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_duration, Kind, Options};
+    use hodor::{Snapshot, SnapshotEntry};
+    use serde_json::{json, Value};
+    use std::time::{Duration, SystemTime};
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(Duration::from_millis(500), parse_duration("500ms").unwrap());
+        assert_eq!(Duration::from_secs(60), parse_duration("60").unwrap());
+        assert_eq!(Duration::from_secs(300), parse_duration("5m").unwrap());
+        assert_eq!(Duration::from_secs(7200), parse_duration("2h").unwrap());
+        assert!(parse_duration("5 minutes").is_err());
+    }
+
+    #[test]
+    fn filters() {
+        let opts = Options::parse(&args("snap.json --prefix user: --expiring-within 60s")).unwrap();
+        assert_eq!(vec!["snap.json"], opts.paths);
+        assert!(opts.matches(&json!("user:1"), Some(Duration::from_secs(30))));
+        assert!(!opts.matches(&json!("user:1"), Some(Duration::from_secs(90))));
+        assert!(!opts.matches(&json!("user:1"), None));
+        assert!(!opts.matches(&json!("session:1"), Some(Duration::from_secs(30))));
+
+        let opts = Options::parse(&args("--persistent")).unwrap();
+        assert!(opts.matches(&json!(1), None));
+        assert!(!opts.matches(&json!(1), Some(Duration::from_secs(30))));
+        assert!(Options::parse(&args("--bogus")).is_err());
+    }
+
+    #[test]
+    fn bincode_round_trip() {
+        let path = std::env::temp_dir().join(format!("hodor-cli-{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        let deadline = SystemTime::now() + Duration::from_secs(60);
+        let snapshot : Snapshot<Value, Value> = Snapshot{ entries: vec![
+            SnapshotEntry{ key: json!("id"), value: json!(7), ttl: Some(Duration::from_secs(60)), deadline: Some(deadline) },
+        ]};
+
+        let opts = Options::parse(&args("--value u64")).unwrap();
+        assert_eq!(Some(Kind::U64), opts.value);
+        opts.write(path, &snapshot).unwrap();
+        assert_eq!(snapshot, opts.read(path).unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+}
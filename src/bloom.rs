@@ -0,0 +1,102 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use crate::DefaultHashBuilder;
+
+// NegativeFilter is a counting Bloom filter over the keys in a cache. It can answer "definitely
+// not present" without touching the store (or its lock); a positive answer only means "maybe".
+// Counters are atomic so the filter can be read and updated through a shared reference, and
+// saturate at u8::MAX rather than wrapping (a saturated counter is never decremented again, which
+// only costs false positives).
+pub(crate) struct NegativeFilter {
+    counters: Vec<AtomicU8>,
+    hashes: u32,
+    hash_builder: DefaultHashBuilder,
+}
+
+impl NegativeFilter {
+    // new sizes the filter for the expected number of keys at the given false positive rate
+    pub(crate) fn new(expected_items: usize, fp_rate: f64) -> NegativeFilter {
+        assert!(fp_rate > 0.0);
+        assert!(fp_rate < 1.0);
+
+        let n = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let slots = (-(n * fp_rate.ln()) / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let hashes = ((slots as f64 / n) * ln2).round().max(1.0) as u32;
+
+        NegativeFilter{
+            counters: (0..slots).map(|_| AtomicU8::new(0)).collect(),
+            hashes,
+            hash_builder: DefaultHashBuilder::default(),
+        }
+    }
+
+    // slots yields the counter indices for a key using double hashing
+    fn slots<K: Hash + ?Sized>(&self, key: &K) -> impl Iterator<Item=usize> {
+        let h = BuildHasher::hash_one(&self.hash_builder, key);
+        let (h1, h2) = (h as u32 as u64, (h >> 32) | 1);
+        let len = self.counters.len() as u64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    pub(crate) fn may_contain<K: Hash + ?Sized>(&self, key: &K) -> bool {
+        self.slots(key).all(|i| self.counters[i].load(Ordering::Relaxed) > 0)
+    }
+
+    pub(crate) fn add<K: Hash + ?Sized>(&self, key: &K) {
+        for i in self.slots(key) {
+            let _ = self.counters[i].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| c.checked_add(1));
+        }
+    }
+
+    // remove must only be called for keys that were added, once per add
+    pub(crate) fn remove<K: Hash + ?Sized>(&self, key: &K) {
+        for i in self.slots(key) {
+            let _ = self.counters[i].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| match c {
+                0 | u8::MAX => None,
+                c => Some(c - 1),
+            });
+        }
+    }
+}
+
+// SharedFilter is the filter as held by a HashCache; a ThreadSafeHashCache keeps a second handle
+// to the same filter outside of its lock. Cloning a SharedFilter copies the counters, so a cloned
+// cache never shares its filter with the original.
+pub(crate) struct SharedFilter(pub(crate) Arc<NegativeFilter>);
+
+impl Clone for SharedFilter {
+    fn clone(&self) -> Self {
+        let f = &self.0;
+        SharedFilter(Arc::new(NegativeFilter{
+            counters: f.counters.iter().map(|c| AtomicU8::new(c.load(Ordering::Relaxed))).collect(),
+            hashes: f.hashes,
+            hash_builder: f.hash_builder.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NegativeFilter;
+
+    #[test]
+    fn counting_filter() {
+        let filter = NegativeFilter::new(1000, 0.01);
+        for i in 0..1000 {
+            filter.add(&i);
+        }
+        assert!((0..1000).all(|i| filter.may_contain(&i)));
+
+        // absent keys are rejected at roughly the configured false positive rate
+        let false_positives = (1000..11000).filter(|i| filter.may_contain(i)).count();
+        assert!(false_positives < 300, "too many false positives: {}", false_positives);
+
+        for i in 0..1000 {
+            filter.remove(&i);
+        }
+        assert!(filter.counters.iter().all(|c| c.load(std::sync::atomic::Ordering::Relaxed) == 0));
+    }
+}
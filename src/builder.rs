@@ -0,0 +1,366 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::hotkeys::CopyKey;
+use crate::{AuditEvent, AuditHook, Clock, CoarseClock, DefaultHashBuilder, SystemClock, WallClock, HashCache, LoadingCache, Policy, ShardedCache, ThreadSafeHashCache, VacuumSchedule};
+
+// ExpirePolicy derives an entry's TTL from its key and value at insert time; None means the entry
+// is persistent
+pub type ExpirePolicy<K, V> = dyn Fn(&K, &V) -> Option<Duration> + Send + Sync;
+
+// OverwritePolicy decides what happens to an entry's deadline when its key is inserted again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverwritePolicy {
+    // Restart gives the entry the new insert's expiration, restarting its TTL
+    #[default]
+    Restart,
+    // KeepDeadline keeps a live entry's deadline, e.g. for values valid until a token expires
+    // however often they're refreshed. An entry without a deadline takes the new insert's.
+    KeepDeadline,
+}
+
+// Config holds the options chosen on a CacheBuilder; every cache carries its own copy
+pub(crate) struct Config<K, V> {
+    pub(crate) redact_values: bool,
+    pub(crate) initial_capacity: usize,
+    pub(crate) shrink_threshold: Option<f32>,
+    pub(crate) max_capacity: Option<usize>,
+    pub(crate) soft_capacity: Option<usize>,
+    pub(crate) max_expiring: Option<usize>,
+    pub(crate) eviction: Policy,
+    pub(crate) negative_filter: Option<(usize, f64)>,
+    pub(crate) early_expiration: Option<f64>,
+    pub(crate) refresh_after: Option<Duration>,
+    pub(crate) stale_while_revalidate: Option<Duration>,
+    pub(crate) expire_after_access: Option<Duration>,
+    pub(crate) min_ttl: Option<Duration>,
+    pub(crate) max_ttl: Option<Duration>,
+    // hot_keys is the sketch's capacity, with the function it copies keys with
+    pub(crate) hot_keys: Option<(usize, CopyKey<K>)>,
+    pub(crate) vacuum_schedule: Option<VacuumSchedule>,
+    pub(crate) exact_expiry: bool,
+    pub(crate) rng_seed: Option<u64>,
+    pub(crate) overwrite: OverwritePolicy,
+    pub(crate) expire_after: Option<Arc<ExpirePolicy<K, V>>>,
+    pub(crate) audit: Option<Arc<AuditHook<K>>>,
+    pub(crate) clock: Arc<dyn Clock>,
+}
+
+// Default and Clone are implemented by hand since deriving them would require K and V to be
+// Default and Clone as well
+impl<K, V> Default for Config<K, V> {
+    fn default() -> Self {
+        Config{
+            redact_values: false,
+            initial_capacity: 0,
+            shrink_threshold: None,
+            max_capacity: None,
+            soft_capacity: None,
+            max_expiring: None,
+            eviction: Policy::default(),
+            negative_filter: None,
+            early_expiration: None,
+            refresh_after: None,
+            stale_while_revalidate: None,
+            expire_after_access: None,
+            min_ttl: None,
+            max_ttl: None,
+            hot_keys: None,
+            vacuum_schedule: None,
+            exact_expiry: false,
+            rng_seed: None,
+            overwrite: OverwritePolicy::default(),
+            expire_after: None,
+            audit: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl<K, V> Clone for Config<K, V> {
+    fn clone(&self) -> Self {
+        Config{
+            redact_values: self.redact_values,
+            initial_capacity: self.initial_capacity,
+            shrink_threshold: self.shrink_threshold,
+            max_capacity: self.max_capacity,
+            soft_capacity: self.soft_capacity,
+            max_expiring: self.max_expiring,
+            eviction: self.eviction,
+            negative_filter: self.negative_filter,
+            early_expiration: self.early_expiration,
+            refresh_after: self.refresh_after,
+            stale_while_revalidate: self.stale_while_revalidate,
+            expire_after_access: self.expire_after_access,
+            min_ttl: self.min_ttl,
+            max_ttl: self.max_ttl,
+            hot_keys: self.hot_keys,
+            vacuum_schedule: self.vacuum_schedule,
+            exact_expiry: self.exact_expiry,
+            rng_seed: self.rng_seed,
+            overwrite: self.overwrite,
+            expire_after: self.expire_after.clone(),
+            audit: self.audit.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+impl<K, V> Config<K, V> {
+    // clamp_ttl applies min_ttl and max_ttl to a TTL
+    pub(crate) fn clamp_ttl(&self, ttl: Duration) -> Duration {
+        let ttl = self.min_ttl.map_or(ttl, |min| ttl.max(min));
+        self.max_ttl.map_or(ttl, |max| ttl.min(max))
+    }
+
+    // capacity is what the eviction policies size themselves by: max_capacity, or soft_capacity
+    // for a cache that's only trimmed
+    pub(crate) fn capacity(&self) -> Option<usize> {
+        self.max_capacity.or(self.soft_capacity)
+    }
+}
+
+// CacheBuilder configures optional cache behavior before constructing either cache type
+pub struct CacheBuilder<K, V> {
+    config: Config<K, V>,
+}
+
+impl<K: Hash+Eq, V> CacheBuilder<K, V> {
+    pub fn new() -> CacheBuilder<K, V> {
+        CacheBuilder{ config: Config::default() }
+    }
+
+    // redact_values hides values in Debug output, for caches holding secrets or tokens
+    pub fn redact_values(mut self, redact: bool) -> Self {
+        self.config.redact_values = redact;
+        self
+    }
+
+    // initial_capacity pre-allocates the store and expiring index for n entries
+    pub fn initial_capacity(mut self, n: usize) -> Self {
+        self.config.initial_capacity = n;
+        self
+    }
+
+    // shrink_below releases unused capacity after vacuum, retain and drain_expired whenever the
+    // ratio of entries to capacity falls below the threshold
+    // panics if threshold is not between 0 and 1.
+    pub fn shrink_below(mut self, threshold: f32) -> Self {
+        assert!(threshold > 0.0);
+        assert!(threshold < 1.0);
+        self.config.shrink_threshold = Some(threshold);
+        self
+    }
+
+    // max_capacity bounds the number of entries; inserting a new key into a full cache evicts an
+    // unpinned entry chosen by the eviction policy
+    pub fn max_capacity(mut self, n: usize) -> Self {
+        self.config.max_capacity = Some(n);
+        self
+    }
+
+    // soft_capacity sets a lower watermark than max_capacity: past it, inserts still don't evict,
+    // but each vacuum evicts (up to its sample count) back down to it, so writes only pay for
+    // eviction once the cache reaches the hard max_capacity
+    pub fn soft_capacity(mut self, n: usize) -> Self {
+        self.config.soft_capacity = Some(n);
+        self
+    }
+
+    // max_expiring bounds the expiring index, and so the number of entries with a TTL or idle
+    // timeout. Inserting one into a full index first removes every expired entry, and if none
+    // had, evicts the entry closest to expiring; either costs a scan of the index.
+    // panics if n is 0.
+    pub fn max_expiring(mut self, n: usize) -> Self {
+        assert!(n > 0, "n must be positive");
+        self.config.max_expiring = Some(n);
+        self
+    }
+
+    // eviction selects the policy used once max_capacity is reached (default: Policy::Lru)
+    pub fn eviction(mut self, policy: Policy) -> Self {
+        self.config.eviction = policy;
+        self
+    }
+
+    // negative_filter puts a counting Bloom filter sized for expected_items in front of the store,
+    // so lookups for absent keys return without probing the map or taking the cache's lock
+    // panics if fp_rate is not between 0 and 1.
+    pub fn negative_filter(mut self, expected_items: usize, fp_rate: f64) -> Self {
+        assert!(fp_rate > 0.0);
+        assert!(fp_rate < 1.0);
+        self.config.negative_filter = Some((expected_items, fp_rate));
+        self
+    }
+
+    // early_expiration enables probabilistic early expiration for entries inserted with
+    // insert_ttl_with_cost; beta scales how early (1.0 is the usual choice, > 1 favors earlier)
+    pub fn early_expiration(mut self, beta: f64) -> Self {
+        assert!(beta > 0.0);
+        self.config.early_expiration = Some(beta);
+        self
+    }
+
+    // refresh_after makes a LoadingCache reload an entry in the background when it is read within
+    // this window of its deadline
+    pub fn refresh_after(mut self, window: Duration) -> Self {
+        self.config.refresh_after = Some(window);
+        self
+    }
+
+    // stale_while_revalidate makes a LoadingCache serve an entry for up to window past its
+    // deadline, reloading it in the background, so a key that just expired costs no caller a
+    // load. Expired entries are only kept until vacuumed.
+    pub fn stale_while_revalidate(mut self, window: Duration) -> Self {
+        self.config.stale_while_revalidate = Some(window);
+        self
+    }
+
+    // expire_after_access expires entries that go unread for idle, on top of any TTL; inserting
+    // a key again restarts its idle time. Panics if idle is 0.
+    pub fn expire_after_access(mut self, idle: Duration) -> Self {
+        assert!(!idle.is_zero(), "idle must be positive");
+        self.config.expire_after_access = Some(idle);
+        self
+    }
+
+    // min_ttl and max_ttl clamp every TTL given to the cache (by insert_ttl, touch, expire_after
+    // and the like), guarding against upstream TTLs that are effectively forever or so short the
+    // entry churns. Persistent entries are left alone. If the two conflict, max_ttl wins.
+    pub fn min_ttl(mut self, ttl: Duration) -> Self {
+        self.config.min_ttl = Some(ttl);
+        self
+    }
+
+    pub fn max_ttl(mut self, ttl: Duration) -> Self {
+        self.config.max_ttl = Some(ttl);
+        self
+    }
+
+    // track_hot_keys keeps a SpaceSaving sketch of the keys read, for hot_keys to report which
+    // ones dominate traffic. It takes a lock on every read; capacity should be a few times the
+    // number of keys to report. Panics if capacity is 0.
+    pub fn track_hot_keys(mut self, capacity: usize) -> Self where K: Clone {
+        assert!(capacity > 0, "capacity must be positive");
+        self.config.hot_keys = Some((capacity, K::clone));
+        self
+    }
+
+    // exact_expiry keeps TTL deadlines in a min-heap, so vacuum removes exactly the entries that
+    // have expired (ignoring its count and threshold) instead of sampling, at the cost of a heap
+    // push on every insert with a TTL
+    pub fn exact_expiry(mut self, exact: bool) -> Self {
+        self.config.exact_expiry = exact;
+        self
+    }
+
+    // rng_seed seeds the generator behind vacuum sampling, random eviction and early expiration
+    // (instead of the thread's generator), so a test or simulation behaves the same every run.
+    // Each shard of a sharded cache is seeded from it in turn.
+    pub fn rng_seed(mut self, seed: u64) -> Self {
+        self.config.rng_seed = Some(seed);
+        self
+    }
+
+    // overwrite sets whether inserting an existing key restarts its TTL (the default) or keeps
+    // its deadline
+    pub fn overwrite(mut self, policy: OverwritePolicy) -> Self {
+        self.config.overwrite = policy;
+        self
+    }
+
+    // vacuum_schedule has a cache built with build_shared vacuum itself on a background thread,
+    // instead of callers running vacuum in a loop. Other build methods ignore it.
+    // panics if the schedule's interval is 0, or a Throttled max_cpu is not in (0, 1]
+    pub fn vacuum_schedule(mut self, schedule: VacuumSchedule) -> Self {
+        schedule.validate();
+        self.config.vacuum_schedule = Some(schedule);
+        self
+    }
+
+    // expire_after derives the TTL of entries stored with insert from their key and value, e.g.
+    // from a token's embedded expiry; insert_ttl still uses the TTL it is given
+    pub fn expire_after<F>(mut self, policy: F) -> Self where F: Fn(&K, &V) -> Option<Duration> + Send + Sync + 'static {
+        self.config.expire_after = Some(Arc::new(policy));
+        self
+    }
+
+    // audit calls hook on every get, insert and take (see AuditEvent), for an audit trail of the
+    // decisions a cache mediates. It runs under the cache's lock, so it should be quick, e.g. a
+    // channel send.
+    pub fn audit<F>(mut self, hook: F) -> Self where F: Fn(&AuditEvent<'_, K>) + Send + Sync + 'static {
+        self.config.audit = Some(Arc::new(hook));
+        self
+    }
+
+    // clock sets the time source used for expiration, e.g. a shared CoarseClock
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.config.clock = Arc::new(clock);
+        self
+    }
+
+    // coarse_clock reads time from a CoarseClock refreshed every resolution instead of calling
+    // Instant::now on every get, for read-heavy caches whose TTLs are much longer than the
+    // resolution
+    pub fn coarse_clock(self, resolution: Duration) -> Self {
+        self.clock(CoarseClock::new(resolution))
+    }
+
+    // wall_clock expires entries by the system's wall clock (see WallClock) instead of the
+    // monotonic clock, so deadlines keep their meaning in snapshots loaded after a restart or on
+    // another host
+    pub fn wall_clock(self) -> Self {
+        self.clock(WallClock::new())
+    }
+
+    pub fn build(self) -> HashCache<K, V> {
+        self.build_with_hasher(DefaultHashBuilder::default())
+    }
+
+    pub fn build_with_hasher<S: BuildHasher>(self, hash_builder: S) -> HashCache<K, V, S> {
+        HashCache::from_config(self.config, hash_builder)
+    }
+
+    pub fn build_thread_safe(self) -> ThreadSafeHashCache<K, V> {
+        self.build_thread_safe_with_hasher(DefaultHashBuilder::default())
+    }
+
+    pub fn build_thread_safe_with_hasher<S: BuildHasher>(self, hash_builder: S) -> ThreadSafeHashCache<K, V, S> {
+        ThreadSafeHashCache::from_config(self.config, hash_builder)
+    }
+
+    // build_shared builds a ThreadSafeHashCache behind an Arc, and starts its vacuum_schedule, if
+    // any, on a background thread that exits once the cache is dropped
+    pub fn build_shared(self) -> Arc<ThreadSafeHashCache<K, V>> where K: Send+Sync+'static, V: Send+Sync+'static {
+        let schedule = self.config.vacuum_schedule;
+        let cache = Arc::new(self.build_thread_safe());
+        if let Some(schedule) = schedule {
+            schedule.start(Arc::downgrade(&cache));
+        }
+        cache
+    }
+
+    // build_sharded builds a ShardedCache; capacities and the negative filter are split between
+    // the shards
+    // panics if shards is 0
+    pub fn build_sharded(self, shards: usize) -> ShardedCache<K, V> {
+        self.build_sharded_with_hasher(shards, DefaultHashBuilder::default())
+    }
+
+    pub fn build_sharded_with_hasher<S: BuildHasher+Clone>(self, shards: usize, hash_builder: S) -> ShardedCache<K, V, S> {
+        ShardedCache::from_config(self.config, shards, hash_builder)
+    }
+
+    // build_loading builds a LoadingCache that fills misses from loader, caching them for ttl
+    pub fn build_loading<F>(self, ttl: Duration, loader: F) -> LoadingCache<K, V>
+        where K: Clone+Send+Sync+'static, V: Clone+Send+Sync+'static, F: Fn(&K) -> Option<V> + Send + Sync + 'static {
+        LoadingCache::new(self.build_thread_safe(), ttl, loader)
+    }
+}
+
+impl<K: Hash+Eq, V> Default for CacheBuilder<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,122 @@
+use std::cell::UnsafeCell;
+use std::hash::{BuildHasher, Hash};
+use std::ops::Deref;
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+use crate::DefaultHashBuilder;
+use crate::eviction::Counter;
+
+// ValueCell holds a stored value. ThreadSafeHashCache::update changes a value in place while
+// holding the store's read lock, so other keys stay readable meanwhile; that takes an UnsafeCell.
+// It's sound because update also holds the key's stripe lock for writing, and whatever reads
+// values under the store's read lock holds the stripes of the keys it reads (see
+// ThreadSafeHashCache::read and read_key). Everywhere else the cache is either locked for writing
+// or not shared at all. There's no safe shared access: reading through &ValueCell is get, which
+// leaves that to the caller.
+pub(crate) struct ValueCell<V>(UnsafeCell<V>);
+
+// SAFETY: values are only changed through a shared reference under the stripe protocol above,
+// which excludes every other access to them, as an RwLock would
+unsafe impl<V: Send+Sync> Sync for ValueCell<V> {}
+
+impl<V> ValueCell<V> {
+    pub(crate) fn new(value: V) -> ValueCell<V> {
+        ValueCell(UnsafeCell::new(value))
+    }
+
+    pub(crate) fn into_inner(self) -> V {
+        self.0.into_inner()
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut V {
+        self.0.get_mut()
+    }
+
+    // get reads the value through a shared reference.
+    //
+    // # Safety
+    //
+    // No update may be running on the value for as long as the reference lives: the caller holds
+    // the value's stripe (or every stripe) along with the store's lock, holds the store's lock
+    // for writing, or has a cache that isn't inside a ThreadSafeHashCache at all.
+    pub(crate) unsafe fn get(&self) -> &V {
+        &*self.0.get()
+    }
+
+    // get_unchecked_mut changes the value through a shared reference.
+    //
+    // # Safety
+    //
+    // The caller must hold the value's stripe for writing, along with the store's lock.
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn get_unchecked_mut(&self) -> &mut V {
+        &mut *self.0.get()
+    }
+}
+
+// STRIPES is how many locks a ThreadSafeHashCache spreads its values over
+pub(crate) const STRIPES: usize = 16;
+
+// Stripes are the locks a ThreadSafeHashCache's values are read and updated under, each key's
+// value under the stripe its hash picks. They're taken after the store's lock, and in ascending
+// order when a reader takes them all.
+pub(crate) struct Stripes {
+    locks: [RwLock<()>; STRIPES],
+    router: DefaultHashBuilder,
+}
+
+impl Stripes {
+    pub(crate) fn new() -> Stripes {
+        Stripes{ locks: std::array::from_fn(|_| RwLock::new(())), router: DefaultHashBuilder::default() }
+    }
+
+    pub(crate) fn of<Q: Hash+?Sized>(&self, key: &Q) -> usize {
+        (BuildHasher::hash_one(&self.router, key) % STRIPES as u64) as usize
+    }
+
+    // read and write note in waits whether they had to wait for the stripe. A panic under a
+    // stripe is treated like one under the store's lock, so poisoning is ignored.
+    pub(crate) fn read(&self, i: usize, waits: &Counter) -> RwLockReadGuard<'_, ()> {
+        match self.locks[i].try_read() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                waits.incr();
+                self.locks[i].read().unwrap_or_else(PoisonError::into_inner)
+            },
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        }
+    }
+
+    pub(crate) fn write(&self, i: usize, waits: &Counter) -> RwLockWriteGuard<'_, ()> {
+        match self.locks[i].try_write() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                waits.incr();
+                self.locks[i].write().unwrap_or_else(PoisonError::into_inner)
+            },
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        }
+    }
+}
+
+// ReadGuard is a ThreadSafeHashCache's store locked for reading, along with the stripes of the
+// values it may be used to read: all of them from read, one key's from read_key, and none from
+// read_index, for readers that never look at a value
+pub(crate) struct ReadGuard<'a, T> {
+    inner: RwLockReadGuard<'a, T>,
+    _stripes: [Option<RwLockReadGuard<'a, ()>>; STRIPES],
+}
+
+impl<'a, T> ReadGuard<'a, T> {
+    pub(crate) fn new(inner: RwLockReadGuard<'a, T>, stripes: [Option<RwLockReadGuard<'a, ()>>; STRIPES]) -> ReadGuard<'a, T> {
+        ReadGuard{ inner, _stripes: stripes }
+    }
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
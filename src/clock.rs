@@ -0,0 +1,293 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// Clock is where a cache reads the current time for expiration checks; a cache uses SystemClock
+// unless another one is given to the builder
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+// SystemClock reads Instant::now on every call
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// WallClock follows the system's wall clock (SystemTime) rather than the monotonic clock, so
+// deadlines are pinned to Unix time: if the system time is stepped, or the machine is suspended,
+// entries expire by the wall clock. Pairs with snapshots, which record wall-clock deadlines.
+#[derive(Debug, Clone, Copy)]
+pub struct WallClock {
+    base: Instant,
+    base_system: SystemTime,
+}
+
+impl WallClock {
+    pub fn new() -> WallClock {
+        WallClock{ base: Instant::now(), base_system: SystemTime::now() }
+    }
+}
+
+impl Default for WallClock {
+    fn default() -> Self {
+        WallClock::new()
+    }
+}
+
+impl Clock for WallClock {
+    fn now(&self) -> Instant {
+        match SystemTime::now().duration_since(self.base_system) {
+            Ok(ahead) => self.base + ahead,
+            // the system time was set back past base
+            Err(e) => self.base.checked_sub(e.duration()).unwrap_or(self.base),
+        }
+    }
+}
+
+// CoarseClock serves a cached time that a background thread refreshes every resolution, so
+// reading it is a single atomic load. Deadlines are then only as precise as the resolution: an
+// entry may be served for up to one resolution past its TTL. The thread exits once every handle
+// to the clock is dropped.
+#[derive(Debug, Clone)]
+pub struct CoarseClock {
+    inner: Arc<Coarse>,
+}
+
+#[derive(Debug)]
+struct Coarse {
+    base: Instant,
+    // nanos is the time since base as of the last refresh
+    nanos: AtomicU64,
+}
+
+impl Coarse {
+    fn refresh(&self) {
+        self.nanos.store(self.base.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl CoarseClock {
+    pub fn new(resolution: Duration) -> CoarseClock {
+        let inner = Arc::new(Coarse{ base: Instant::now(), nanos: AtomicU64::new(0) });
+        let weak: Weak<Coarse> = Arc::downgrade(&inner);
+        thread::spawn(move || {
+            while let Some(coarse) = weak.upgrade() {
+                coarse.refresh();
+                drop(coarse);
+                thread::sleep(resolution);
+            }
+        });
+        CoarseClock{ inner }
+    }
+
+    // refresh updates the cached time immediately
+    pub fn refresh(&self) {
+        self.inner.refresh()
+    }
+}
+
+impl Clock for CoarseClock {
+    fn now(&self) -> Instant {
+        self.inner.base + Duration::from_nanos(self.inner.nanos.load(Ordering::Relaxed))
+    }
+}
+
+// JsClock reads time from JavaScript's Date.now(), for caches running on wasm32-unknown-unknown,
+// where std has no clock and Instant::now panics. Its instants count from an origin fixed when
+// it's created; Date.now() follows the wall clock, so if that's set back the clock stands still
+// until it catches up rather than going backwards.
+#[cfg(feature = "wasm")]
+#[derive(Debug)]
+pub struct JsClock {
+    origin: Instant,
+    start_ms: f64,
+    // nanos is the latest time handed out, since origin
+    nanos: AtomicU64,
+}
+
+#[cfg(feature = "wasm")]
+impl JsClock {
+    pub fn new() -> JsClock {
+        JsClock{ origin: JsClock::origin(), start_ms: js_sys::Date::now(), nanos: AtomicU64::new(0) }
+    }
+
+    // origin is the Instant the clock counts from. On wasm32-unknown-unknown std's Instant is a
+    // Duration since an arbitrary origin and Instant::now is unavailable, so it's the zero one.
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    fn origin() -> Instant {
+        // SAFETY: the target's Instant is a Duration, for which all zero bytes is a valid value
+        unsafe { std::mem::zeroed() }
+    }
+
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    fn origin() -> Instant {
+        Instant::now()
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl Default for JsClock {
+    fn default() -> Self {
+        JsClock::new()
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl Clock for JsClock {
+    fn now(&self) -> Instant {
+        let elapsed = ((js_sys::Date::now() - self.start_ms).max(0.0) * 1e6) as u64;
+        let nanos = self.nanos.fetch_max(elapsed, Ordering::Relaxed).max(elapsed);
+        self.origin + Duration::from_nanos(nanos)
+    }
+}
+
+// ManualClock is a clock for tests of expiration flows: it starts frozen and only moves when
+// advanced, so a test steps through TTLs without sleeping. thaw lets it follow real time again
+// from wherever it stands, and freeze stops it once more. Clones share one time.
+//
+// The time is only half of a deterministic test: vacuum samples the expiring index at random,
+// so which expired entries one call removes varies. Build the cache with exact_expiry, or use
+// drain_expired, vacuum_incremental or a vacuum count of at least expiring_len(), and the
+// result depends on the clock alone; or fix the sampling with CacheBuilder::rng_seed, or build
+// without the rand feature, where vacuum sweeps the index in order.
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<Mutex<Manual>>);
+
+#[derive(Debug)]
+struct Manual {
+    // at is the clock's time as of real time since; while frozen, since is None
+    at: Instant,
+    since: Option<Instant>,
+}
+
+impl Manual {
+    fn now(&self) -> Instant {
+        match self.since {
+            Some(since) => self.at + since.elapsed(),
+            None => self.at,
+        }
+    }
+}
+
+impl ManualClock {
+    // new returns a clock frozen at the current time
+    pub fn new() -> ManualClock {
+        ManualClock(Arc::new(Mutex::new(Manual{ at: Instant::now(), since: None })))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.0.lock().expect("lock poisoned").at += by;
+    }
+
+    // freeze stops the clock at its current time
+    pub fn freeze(&self) {
+        let mut manual = self.0.lock().expect("lock poisoned");
+        manual.at = manual.now();
+        manual.since = None;
+    }
+
+    // thaw lets the clock run at the speed of real time, starting from its current time
+    pub fn thaw(&self) {
+        let mut manual = self.0.lock().expect("lock poisoned");
+        manual.at = manual.now();
+        manual.since = Some(Instant::now());
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.0.lock().expect("lock poisoned").since.is_none()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.0.lock().expect("lock poisoned").now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, CoarseClock, ManualClock, WallClock};
+    use crate::{Cache, CacheBuilder, HashCache};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn coarse_clock() {
+        let clock = CoarseClock::new(Duration::from_millis(5));
+        let before = clock.now();
+        sleep(Duration::from_millis(20));
+        assert!(clock.now() > before);
+
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new().clock(clock.clone()).build();
+        cache.insert_ttl("id", "secret", Duration::from_millis(10));
+        assert!(cache.get("id", |_| {}));
+        sleep(Duration::from_millis(30));
+        clock.refresh();
+        assert!(!cache.get("id", |_| {}));
+    }
+
+    #[test]
+    fn wall_clock() {
+        let clock = WallClock::new();
+        let before = clock.now();
+        sleep(Duration::from_millis(5));
+        assert!(clock.now() >= before + Duration::from_millis(5));
+
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new().wall_clock().build();
+        cache.insert_ttl("id", "secret", Duration::from_millis(10));
+        assert!(cache.get("id", |_| {}));
+        sleep(Duration::from_millis(20));
+        assert!(!cache.get("id", |_| {}));
+    }
+
+    #[test]
+    fn manual_clock() {
+        let clock = ManualClock::new();
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new().clock(clock.clone()).exact_expiry(true).build();
+        cache.insert_ttl("id", "secret", Duration::new(60, 0));
+
+        // frozen, time only moves when advanced
+        let frozen = clock.now();
+        sleep(Duration::from_millis(5));
+        assert_eq!(frozen, clock.now());
+        clock.advance(Duration::new(59, 0));
+        cache.vacuum(1, 0.5);
+        assert!(cache.get("id", |_| {}));
+
+        // thawed, it runs from where it stood
+        clock.thaw();
+        assert!(!clock.is_frozen());
+        sleep(Duration::from_millis(5));
+        assert!(clock.now() >= frozen + Duration::new(59, 0) + Duration::from_millis(5));
+        clock.advance(Duration::new(1, 0));
+        clock.freeze();
+        cache.vacuum(1, 0.5);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn entry_meta_clock() {
+        let clock = ManualClock::new();
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new().clock(clock.clone()).build();
+        cache.insert_ttl("short", "a", Duration::new(10, 0));
+        cache.insert_ttl("long", "b", Duration::new(60, 0));
+        clock.advance(Duration::new(30, 0));
+
+        assert!(format!("{:?}", cache).contains("expires_in: 30s"));
+        cache.retain(|_, _, meta| !meta.is_expired());
+        assert!(!cache.get("short", |_| {}));
+        assert!(cache.get("long", |_| {}));
+    }
+}
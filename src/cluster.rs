@@ -0,0 +1,400 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// points each node gets on the ring; more points spread keys more evenly
+const POINTS_PER_NODE: usize = 160;
+
+// hash64 is FNV-1a followed by a splitmix64 finalizer: stable across processes, platforms and
+// Rust versions, so every client of a cluster builds the same ring
+fn hash64(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        h ^= u64::from(*b);
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xbf58476d1ce4e5b9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94d049bb133111eb);
+    h ^ (h >> 31)
+}
+
+// HashRing maps keys to node names with consistent hashing: adding or removing a node only
+// moves the keys on that node's share of the ring, rather than reshuffling everything
+#[derive(Debug, Clone, Default)]
+pub struct HashRing {
+    points: BTreeMap<u64, String>,
+}
+
+impl HashRing {
+    pub fn new() -> HashRing {
+        HashRing::default()
+    }
+
+    pub fn add(&mut self, node: &str) {
+        for i in 0..POINTS_PER_NODE {
+            self.points.insert(hash64(format!("{}#{}", node, i).as_bytes()), node.to_string());
+        }
+    }
+
+    pub fn remove(&mut self, node: &str) {
+        self.points.retain(|_, n| n != node);
+    }
+
+    // nodes_for returns up to n distinct nodes for a key, in preference order: the owner first,
+    // then the nodes to fail over to
+    pub fn nodes_for(&self, key: &[u8], n: usize) -> Vec<&str> {
+        let h = hash64(key);
+        let mut nodes: Vec<&str> = Vec::with_capacity(n);
+        for node in self.points.range(h..).chain(self.points.range(..h)).map(|(_, node)| node.as_str()) {
+            if nodes.len() == n {
+                break
+            }
+            if !nodes.contains(&node) {
+                nodes.push(node);
+            }
+        }
+        nodes
+    }
+
+    pub fn node_for(&self, key: &[u8]) -> Option<&str> {
+        self.nodes_for(key, 1).pop()
+    }
+}
+
+// Node is a remote cache the cluster client can talk to
+pub trait Node: Send + Sync {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>>;
+    // set stores a value, without a TTL if ttl is None
+    fn set(&self, key: &[u8], value: &[u8], ttl: Option<Duration>) -> io::Result<()>;
+    // delete returns whether the key was present
+    fn delete(&self, key: &[u8]) -> io::Result<bool>;
+}
+
+// MemcachedNode talks to memcached, or any server speaking its text protocol. It keeps one
+// connection, reconnecting after an error.
+pub struct MemcachedNode {
+    addr: SocketAddr,
+    timeout: Duration,
+    conn: Mutex<Option<BufReader<TcpStream>>>,
+}
+
+// memcached treats expiration times past 30 days as Unix timestamps
+const MAX_RELATIVE_EXPIRY: u64 = 60 * 60 * 24 * 30;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+impl MemcachedNode {
+    pub fn new(addr: SocketAddr, timeout: Duration) -> MemcachedNode {
+        MemcachedNode{ addr, timeout, conn: Mutex::new(None) }
+    }
+
+    // request sends a command and hands the connection to read_reply; any error drops the
+    // connection, since it may be left mid-reply
+    fn request<T, F>(&self, command: &[u8], read_reply: F) -> io::Result<T> where F: FnOnce(&mut BufReader<TcpStream>) -> io::Result<T> {
+        let mut conn = self.conn.lock().expect("lock poisoned");
+        if conn.is_none() {
+            let stream = TcpStream::connect_timeout(&self.addr, self.timeout)?;
+            stream.set_read_timeout(Some(self.timeout))?;
+            stream.set_write_timeout(Some(self.timeout))?;
+            stream.set_nodelay(true)?;
+            *conn = Some(BufReader::new(stream));
+        }
+        let stream = conn.as_mut().expect("connected above");
+        let reply = stream.get_mut().write_all(command).and_then(|_| read_reply(stream));
+        if reply.is_err() {
+            *conn = None;
+        }
+        reply
+    }
+}
+
+fn check_key(key: &[u8]) -> io::Result<()> {
+    if key.is_empty() || key.len() > 250 || key.iter().any(|b| b.is_ascii_whitespace() || b.is_ascii_control()) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "memcached keys are 1-250 bytes without spaces or control characters"))
+    }
+    Ok(())
+}
+
+fn read_line(r: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    if r.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"))
+    }
+    Ok(line.trim_end().to_string())
+}
+
+impl Node for MemcachedNode {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        check_key(key)?;
+        let command = [b"get ", key, b"\r\n"].concat();
+        self.request(&command, |r| {
+            let header = read_line(r)?;
+            if header == "END" {
+                return Ok(None)
+            }
+            // VALUE <key> <flags> <bytes>
+            let len: usize = header.strip_prefix("VALUE ")
+                .and_then(|h| h.rsplit(' ').next())
+                .and_then(|n| n.parse().ok())
+                .ok_or_else(|| invalid(&header))?;
+            let mut value = vec![0; len + 2];
+            r.read_exact(&mut value)?;
+            value.truncate(len);
+            match read_line(r)?.as_str() {
+                "END" => Ok(Some(value)),
+                line => Err(invalid(line)),
+            }
+        })
+    }
+
+    fn set(&self, key: &[u8], value: &[u8], ttl: Option<Duration>) -> io::Result<()> {
+        check_key(key)?;
+        let exptime = match ttl {
+            None => 0,
+            // round up, so a short TTL doesn't become 0 (no expiry)
+            Some(ttl) => {
+                let secs = ttl.as_secs() + u64::from(ttl.subsec_nanos() > 0);
+                if secs <= MAX_RELATIVE_EXPIRY {
+                    secs
+                } else {
+                    (SystemTime::now() + ttl).duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+                }
+            },
+        };
+        let command = [&b"set "[..], key, format!(" 0 {} {}\r\n", exptime, value.len()).as_bytes(), value, b"\r\n"].concat();
+        self.request(&command, |r| match read_line(r)?.as_str() {
+            "STORED" => Ok(()),
+            line => Err(invalid(line)),
+        })
+    }
+
+    fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        check_key(key)?;
+        let command = [b"delete ", key, b"\r\n"].concat();
+        self.request(&command, |r| match read_line(r)?.as_str() {
+            "DELETED" => Ok(true),
+            "NOT_FOUND" => Ok(false),
+            line => Err(invalid(line)),
+        })
+    }
+}
+
+// ClusterClient partitions keys across nodes with a HashRing. When a key's node fails, the
+// request is retried on the next nodes along the ring (up to attempts nodes in all), so a node
+// going down only loses its share of the cache rather than failing requests.
+pub struct ClusterClient<N: Node> {
+    state: RwLock<Cluster<N>>,
+    attempts: usize,
+}
+
+struct Cluster<N> {
+    ring: HashRing,
+    nodes: HashMap<String, N>,
+}
+
+impl<N: Node> ClusterClient<N> {
+    // new creates a client with no nodes; attempts is how many nodes a request tries
+    // panics if attempts is 0
+    pub fn new(attempts: usize) -> ClusterClient<N> {
+        assert!(attempts > 0);
+        ClusterClient{ state: RwLock::new(Cluster{ ring: HashRing::new(), nodes: HashMap::new() }), attempts }
+    }
+
+    // add_node adds (or replaces) a node; only keys that now hash to it move
+    pub fn add_node(&self, name: &str, node: N) {
+        let mut state = self.state.write().expect("lock poisoned");
+        if state.nodes.insert(name.to_string(), node).is_none() {
+            state.ring.add(name);
+        }
+    }
+
+    pub fn remove_node(&self, name: &str) -> Option<N> {
+        let mut state = self.state.write().expect("lock poisoned");
+        state.ring.remove(name);
+        state.nodes.remove(name)
+    }
+
+    pub fn node_names(&self) -> Vec<String> {
+        self.state.read().expect("lock poisoned").nodes.keys().cloned().collect()
+    }
+
+    // with_failover runs f against the key's nodes in ring order until one succeeds, returning
+    // the last error if none does
+    fn with_failover<T, F>(&self, key: &[u8], mut f: F) -> io::Result<T> where F: FnMut(&N) -> io::Result<T> {
+        let state = self.state.read().expect("lock poisoned");
+        let mut last = io::Error::new(io::ErrorKind::NotConnected, "no nodes in the cluster");
+        for name in state.ring.nodes_for(key, self.attempts) {
+            match f(&state.nodes[name]) {
+                Ok(t) => return Ok(t),
+                Err(e) if e.kind() == io::ErrorKind::InvalidInput => return Err(e),
+                Err(e) => {
+                    event!(WARN, node = name, error = %e, "cluster node failed");
+                    last = e;
+                },
+            }
+        }
+        Err(last)
+    }
+
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        self.with_failover(key, |node| node.get(key))
+    }
+
+    pub fn set(&self, key: &[u8], value: &[u8], ttl: Option<Duration>) -> io::Result<()> {
+        self.with_failover(key, |node| node.set(key, value, ttl))
+    }
+
+    pub fn delete(&self, key: &[u8]) -> io::Result<bool> {
+        self.with_failover(key, |node| node.delete(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClusterClient, HashRing, MemcachedNode, Node};
+    use std::collections::HashMap;
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn ring() {
+        let mut ring = HashRing::new();
+        for node in &["a", "b", "c", "d"] {
+            ring.add(node);
+        }
+        let keys: Vec<String> = (0..1000).map(|i| format!("key{}", i)).collect();
+        let owners: Vec<String> = keys.iter().map(|k| ring.node_for(k.as_bytes()).unwrap().to_string()).collect();
+        for node in &["a", "b", "c", "d"] {
+            let share = owners.iter().filter(|o| o == node).count();
+            assert!(share > 150 && share < 350, "{} owns {}", node, share);
+        }
+        assert_eq!(4, ring.nodes_for(b"key", 10).len());
+
+        // adding a node only moves keys to the new node
+        ring.add("e");
+        for (key, owner) in keys.iter().zip(&owners) {
+            let now = ring.node_for(key.as_bytes()).unwrap();
+            assert!(now == owner || now == "e");
+        }
+        ring.remove("e");
+        let restored: Vec<String> = keys.iter().map(|k| ring.node_for(k.as_bytes()).unwrap().to_string()).collect();
+        assert_eq!(owners, restored);
+    }
+
+    // MapNode is an in-memory node that can be taken down
+    #[derive(Default)]
+    struct MapNode {
+        down: AtomicBool,
+        entries: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+    }
+
+    impl MapNode {
+        fn check(&self) -> io::Result<()> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "down"))
+            }
+            Ok(())
+        }
+    }
+
+    impl Node for MapNode {
+        fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+            self.check()?;
+            Ok(self.entries.lock().unwrap().get(key).cloned())
+        }
+
+        fn set(&self, key: &[u8], value: &[u8], _: Option<Duration>) -> io::Result<()> {
+            self.check()?;
+            self.entries.lock().unwrap().insert(key.to_vec(), value.to_vec());
+            Ok(())
+        }
+
+        fn delete(&self, key: &[u8]) -> io::Result<bool> {
+            self.check()?;
+            Ok(self.entries.lock().unwrap().remove(key).is_some())
+        }
+    }
+
+    #[test]
+    fn failover() {
+        let client = ClusterClient::new(2);
+        assert!(client.get(b"id").is_err());
+        client.add_node("a", MapNode::default());
+        client.add_node("b", MapNode::default());
+
+        client.set(b"id", b"secret", None).unwrap();
+        assert_eq!(Some(b"secret".to_vec()), client.get(b"id").unwrap());
+
+        // with the owner down, requests go to the next node along the ring
+        let owner = client.state.read().unwrap().ring.node_for(b"id").unwrap().to_string();
+        client.state.read().unwrap().nodes[&owner].down.store(true, Ordering::SeqCst);
+        assert_eq!(None, client.get(b"id").unwrap());
+        client.set(b"id", b"secret2", None).unwrap();
+        assert_eq!(Some(b"secret2".to_vec()), client.get(b"id").unwrap());
+        assert!(client.delete(b"id").unwrap());
+
+        client.remove_node(&owner);
+        assert_eq!(1, client.node_names().len());
+    }
+
+    #[test]
+    fn memcached_protocol() {
+        // a minimal memcached server for a single connection
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut r = BufReader::new(stream.try_clone().unwrap());
+            let mut w = stream;
+            let mut entries: HashMap<String, Vec<u8>> = HashMap::new();
+            let mut line = String::new();
+            while r.read_line(&mut line).unwrap() > 0 {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                match parts[0] {
+                    "set" => {
+                        let mut data = vec![0; parts[4].parse::<usize>().unwrap() + 2];
+                        r.read_exact(&mut data).unwrap();
+                        data.truncate(data.len() - 2);
+                        assert_eq!("60", parts[3]);
+                        entries.insert(parts[1].to_string(), data);
+                        w.write_all(b"STORED\r\n").unwrap();
+                    },
+                    "get" => {
+                        if let Some(v) = entries.get(parts[1]) {
+                            write!(w, "VALUE {} 0 {}\r\n", parts[1], v.len()).unwrap();
+                            w.write_all(v).unwrap();
+                            w.write_all(b"\r\n").unwrap();
+                        }
+                        w.write_all(b"END\r\n").unwrap();
+                    },
+                    "delete" => {
+                        let reply: &[u8] = if entries.remove(parts[1]).is_some() { b"DELETED\r\n" } else { b"NOT_FOUND\r\n" };
+                        w.write_all(reply).unwrap();
+                    },
+                    _ => w.write_all(b"ERROR\r\n").unwrap(),
+                }
+                line.clear();
+            }
+        });
+
+        let node = MemcachedNode::new(addr, Duration::from_secs(5));
+        node.set(b"id", b"line one\r\nline two", Some(Duration::from_millis(59_500))).unwrap();
+        assert_eq!(Some(b"line one\r\nline two".to_vec()), node.get(b"id").unwrap());
+        assert_eq!(None, node.get(b"nope").unwrap());
+        assert!(node.delete(b"id").unwrap());
+        assert!(!node.delete(b"id").unwrap());
+        assert_eq!(io::ErrorKind::InvalidInput, node.get(b"has space").unwrap_err().kind());
+        drop(node);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::eviction::Counter;
+use crate::{DefaultHashBuilder, ThreadSafeHashCache};
+
+// Codec is the compression algorithm a CompressedCache uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    // Lz4 is fast on both ends at a modest ratio
+    #[cfg(feature = "lz4")]
+    Lz4,
+    // Zstd compresses better than Lz4 at some CPU cost; level ranges from 1 (fastest) to 22
+    #[cfg(feature = "zstd")]
+    Zstd { level: i32 },
+}
+
+impl Codec {
+    fn compress(self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => lz4_flex::compress_prepend_size(bytes),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd{ level } => zstd::bulk::compress(bytes, level).expect("compressing into a Vec can't fail"),
+        }
+    }
+
+    fn decompress(self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => lz4_flex::decompress_size_prepended(bytes).expect("compressed by insert"),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd{ .. } => zstd::decode_all(bytes).expect("compressed by insert"),
+        }
+    }
+}
+
+// Compressed is a CompressedCache value: compressed if that made it smaller, raw otherwise
+#[derive(Debug, Clone)]
+pub struct Compressed {
+    codec: Option<Codec>,
+    bytes: Vec<u8>,
+}
+
+impl Compressed {
+    fn unpack(self) -> Vec<u8> {
+        match self.codec {
+            Some(codec) => codec.decompress(&self.bytes),
+            None => self.bytes,
+        }
+    }
+
+    // stored_len is how many bytes the value takes in the cache
+    pub fn stored_len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+// CompressionStats counts the bytes inserted into a CompressedCache before and after compression
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    // values stored compressed, and stored raw because they were under the threshold or didn't
+    // shrink
+    pub compressed: u64,
+    pub raw: u64,
+    pub bytes_in: u64,
+    pub bytes_stored: u64,
+}
+
+impl CompressionStats {
+    // ratio is bytes_in / bytes_stored, or 1 before the first insert
+    pub fn ratio(&self) -> f64 {
+        if self.bytes_stored == 0 {
+            return 1.0
+        }
+        self.bytes_in as f64 / self.bytes_stored as f64
+    }
+}
+
+// CompressedCache caches byte values (e.g. serialized JSON), compressing those over a size
+// threshold at insert and decompressing them on get, trading CPU for memory. A configured cache
+// can be wrapped with CompressedCache::new.
+pub struct CompressedCache<K: Hash+Eq+Clone, S = DefaultHashBuilder> {
+    cache: ThreadSafeHashCache<K, Compressed, S>,
+    codec: Codec,
+    threshold: usize,
+    compressed: Counter,
+    raw: Counter,
+    bytes_in: AtomicU64,
+    bytes_stored: AtomicU64,
+}
+
+impl<K: Hash+Eq+Clone, S: BuildHasher> CompressedCache<K, S> {
+    // new compresses values longer than threshold bytes with codec
+    pub fn new(cache: ThreadSafeHashCache<K, Compressed, S>, codec: Codec, threshold: usize) -> CompressedCache<K, S> {
+        CompressedCache{
+            cache,
+            codec,
+            threshold,
+            compressed: Counter::default(),
+            raw: Counter::default(),
+            bytes_in: AtomicU64::new(0),
+            bytes_stored: AtomicU64::new(0),
+        }
+    }
+
+    // cache is the underlying cache, e.g. for vacuuming or subscribing
+    pub fn cache(&self) -> &ThreadSafeHashCache<K, Compressed, S> {
+        &self.cache
+    }
+
+    fn pack(&self, bytes: Vec<u8>) -> Compressed {
+        let len = bytes.len() as u64;
+        let packed = if bytes.len() > self.threshold {
+            Some(self.codec.compress(&bytes)).filter(|c| c.len() < bytes.len())
+        } else {
+            None
+        };
+        let value = match packed {
+            Some(packed) => {
+                self.compressed.incr();
+                Compressed{ codec: Some(self.codec), bytes: packed }
+            },
+            None => {
+                self.raw.incr();
+                Compressed{ codec: None, bytes }
+            },
+        };
+        self.bytes_in.fetch_add(len, Ordering::Relaxed);
+        self.bytes_stored.fetch_add(value.bytes.len() as u64, Ordering::Relaxed);
+        value
+    }
+
+    // insert compresses and stores a value, returning the previous one decompressed; values are
+    // compressed before the lock is taken
+    pub fn insert(&self, key: K, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.cache.insert(key, self.pack(value)).map(Compressed::unpack)
+    }
+
+    pub fn insert_ttl(&self, key: K, value: Vec<u8>, ttl: Duration) -> Option<Vec<u8>> {
+        self.cache.insert_ttl(key, self.pack(value), ttl).map(Compressed::unpack)
+    }
+
+    // get returns a live value, decompressed after the lock is released
+    pub fn get(&self, key: &K) -> Option<Vec<u8>> {
+        if self.cache.definitely_absent(key) {
+            return None
+        }
+        let value = self.cache.read_key(key).hit(key).cloned();
+        value.map(Compressed::unpack)
+    }
+
+    pub fn take(&self, key: K) -> Option<Vec<u8>> {
+        self.cache.take(key).map(Compressed::unpack)
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    // compression reports how well the values inserted so far compressed
+    pub fn compression(&self) -> CompressionStats {
+        CompressionStats{
+            compressed: self.compressed.get(),
+            raw: self.raw.get(),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_stored: self.bytes_stored.load(Ordering::Relaxed),
+        }
+    }
+
+    // vacuum samples the set of potentially expired keys and removes them if expired
+    // panics if retry-threshold is not between 0 and 1.
+    pub fn vacuum(&self, count : usize, retry_threshold : f32 ) {
+        self.cache.vacuum(count, retry_threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Codec, CompressedCache};
+    use crate::ThreadSafeHashCache;
+
+    fn round_trip(codec: Codec) {
+        let cache = CompressedCache::new(ThreadSafeHashCache::new(), codec, 64);
+        let json = br#"{"id": 1, "name": "hodor", "tags": ["door", "door", "door"]}"#.repeat(100);
+        cache.insert("blob", json.clone());
+        cache.insert("small", b"{}".to_vec());
+
+        assert_eq!(Some(json.clone()), cache.get(&"blob"));
+        assert_eq!(Some(b"{}".to_vec()), cache.get(&"small"));
+        assert!(cache.cache().write().lookup_mut(&"blob").unwrap().value.get_mut().stored_len() < json.len() / 10);
+
+        let stats = cache.compression();
+        assert_eq!((1, 1), (stats.compressed, stats.raw));
+        assert!(stats.ratio() > 10.0);
+        assert_eq!(Some(json), cache.take("blob"));
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4() {
+        round_trip(Codec::Lz4);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd() {
+        round_trip(Codec::Zstd{ level: 3 });
+    }
+}
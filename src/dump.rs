@@ -0,0 +1,102 @@
+use std::fmt::{self, Write as _};
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::{HashCache, ThreadSafeHashCache};
+
+// values longer than this are cut short in dumps
+const SUMMARY_CHARS: usize = 64;
+
+#[derive(Serialize)]
+struct Dump<'a, K> {
+    len: usize,
+    entries: Vec<DumpEntry<'a, K>>,
+}
+
+#[derive(Serialize)]
+struct DumpEntry<'a, K> {
+    key: &'a K,
+    // the value's Display output, shortened to SUMMARY_CHARS
+    value: String,
+    // remaining TTL in milliseconds, or null for a persistent entry
+    ttl_ms: Option<u64>,
+    // expired entries are still stored until vacuum or a read removes them
+    expired: bool,
+    pinned: bool,
+}
+
+// summarize renders a value for a dump, respecting redact_values
+fn summarize<V: fmt::Display>(value: &V, redact: bool) -> String {
+    if redact {
+        return "<redacted>".to_string()
+    }
+    let mut summary = String::new();
+    let _ = write!(summary, "{}", value);
+    if let Some((cut, _)) = summary.char_indices().nth(SUMMARY_CHARS) {
+        summary.truncate(cut);
+        summary.push('…');
+    }
+    summary
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher> HashCache<K, V, S> {
+    // dump_json writes every stored entry (expired or not) with a summary of its value, its
+    // remaining TTL and flags as JSON, for inspecting what a cache actually holds
+    pub fn dump_json<W: Write>(&self, w: W) -> io::Result<()> where K: Serialize, V: fmt::Display {
+        let now = self.now();
+        let redact = self.config.redact_values;
+        let entries = self.entries.iter().map(|(_, (key, v))| {
+            let ttl_ms = v.meta(now).expires_at().map(|at| at.saturating_duration_since(now).as_millis() as u64);
+            // SAFETY: see ValueCell::get; a ThreadSafeHashCache dumps under read, which holds
+            // every stripe
+            let value = summarize(unsafe { v.value.get() }, redact);
+            DumpEntry{ key: &**key, value, ttl_ms, expired: v.expired(now), pinned: v.pinned }
+        }).collect();
+        serde_json::to_writer_pretty(w, &Dump{ len: self.len(), entries })?;
+        Ok(())
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher> ThreadSafeHashCache<K, V, S> {
+    pub fn dump_json<W: Write>(&self, w: W) -> io::Result<()> where K: Serialize, V: fmt::Display {
+        self.read().dump_json(w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cache, CacheBuilder, HashCache};
+    use std::time::Duration;
+
+    #[test]
+    fn dump_json() {
+        let mut cache : HashCache<&str,String> = HashCache::new();
+        cache.insert("id", "x".repeat(100));
+        cache.insert_ttl("id2", "secret".to_string(), Duration::new(10, 0));
+        cache.insert_ttl("gone", "secret".to_string(), Duration::from_millis(1));
+        std::thread::sleep(Duration::from_millis(5));
+
+        let mut out = Vec::new();
+        cache.dump_json(&mut out).unwrap();
+        let dump: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let entries = dump["entries"].as_array().unwrap();
+        assert_eq!(3, entries.len());
+        let entry = |key| entries.iter().find(|e| e["key"] == key).unwrap();
+
+        assert_eq!(format!("{}…", "x".repeat(64)), entry("id")["value"]);
+        assert!(entry("id")["ttl_ms"].is_null());
+        assert!(entry("id2")["ttl_ms"].as_u64().unwrap() > 9000);
+        assert_eq!(false, entry("id2")["expired"]);
+        assert_eq!(0, entry("gone")["ttl_ms"]);
+        assert_eq!(true, entry("gone")["expired"]);
+
+        // redacted caches keep values out of dumps too
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new().redact_values(true).build();
+        cache.insert("id", "secret");
+        let mut out = Vec::new();
+        cache.dump_json(&mut out).unwrap();
+        assert!(!String::from_utf8(out).unwrap().contains("secret"));
+    }
+}
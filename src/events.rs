@@ -0,0 +1,188 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+use crate::{HashCache, ThreadSafeHashCache};
+
+// CacheEvent describes a change to a cache's contents
+// subscribers receive owned events (CacheEvent<K, V>); internally events are built from
+// references (CacheEvent<&K, &V>) so nothing is cloned unless someone is listening
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheEvent<K, V> {
+    // a new key was stored
+    Inserted { key: K, value: V },
+    // an existing key was overwritten with a new value
+    Replaced { key: K, value: V },
+    // an entry was removed because its TTL had passed (by vacuum, drain_expired or access)
+    Expired { key: K, value: V },
+    // an entry was removed to make room under max_capacity
+    Evicted { key: K, value: V },
+    // an entry was removed explicitly (take, retain)
+    Removed { key: K, value: V },
+}
+
+impl<K: Clone, V: Clone> CacheEvent<&K, &V> {
+    fn cloned(&self) -> CacheEvent<K, V> {
+        match *self {
+            CacheEvent::Inserted{ key, value } => CacheEvent::Inserted{ key: key.clone(), value: value.clone() },
+            CacheEvent::Replaced{ key, value } => CacheEvent::Replaced{ key: key.clone(), value: value.clone() },
+            CacheEvent::Expired{ key, value } => CacheEvent::Expired{ key: key.clone(), value: value.clone() },
+            CacheEvent::Evicted{ key, value } => CacheEvent::Evicted{ key: key.clone(), value: value.clone() },
+            CacheEvent::Removed{ key, value } => CacheEvent::Removed{ key: key.clone(), value: value.clone() },
+        }
+    }
+}
+
+// Listener handles an event, along with the TTL of an inserted or replaced entry, and returns
+// false once it no longer wants events
+type Listener<K, V> = Box<dyn FnMut(&CacheEvent<&K, &V>, Option<Duration>) -> bool + Send + Sync>;
+
+// Listeners are the subscribers of a single cache. Cloning a cache doesn't carry its subscribers
+// over, since they subscribed to the original.
+pub(crate) struct Listeners<K, V>(Vec<Listener<K, V>>);
+
+impl<K, V> Listeners<K, V> {
+    pub(crate) fn new() -> Listeners<K, V> {
+        Listeners(Vec::new())
+    }
+
+    pub(crate) fn add(&mut self, listener: Listener<K, V>) {
+        self.0.push(listener)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub(crate) fn emit(&mut self, event: CacheEvent<&K, &V>) {
+        self.emit_ttl(event, None)
+    }
+
+    pub(crate) fn emit_ttl(&mut self, event: CacheEvent<&K, &V>, ttl: Option<Duration>) {
+        if self.0.is_empty() {
+            return
+        }
+        self.0.retain_mut(|l| l(&event, ttl));
+    }
+}
+
+impl<K, V> Clone for Listeners<K, V> {
+    fn clone(&self) -> Self {
+        Listeners::new()
+    }
+}
+
+impl<K: Hash+Eq+Clone+Send+'static, V: Clone+Send+'static, S: BuildHasher> HashCache<K, V, S> {
+    // subscribe returns a receiver of every event from now on; dropping the receiver unsubscribes
+    pub fn subscribe(&mut self) -> Receiver<CacheEvent<K, V>> {
+        let (tx, rx) = channel();
+        self.listeners.add(Box::new(move |event, _| tx.send(event.cloned()).is_ok()));
+        rx
+    }
+
+    // watch returns a receiver of one key's changes from now on: its new value whenever it's
+    // inserted or replaced, and None whenever it's removed (expired, evicted or taken). Dropping
+    // the receiver unsubscribes.
+    pub fn watch(&mut self, key: K) -> Receiver<Option<V>> where K: Sync {
+        let (tx, rx) = channel();
+        self.listeners.add(Box::new(move |event, _| {
+            let change = match *event {
+                CacheEvent::Inserted{ key: k, value } | CacheEvent::Replaced{ key: k, value } => (k, Some(value.clone())),
+                CacheEvent::Expired{ key: k, .. } | CacheEvent::Evicted{ key: k, .. } | CacheEvent::Removed{ key: k, .. } => (k, None),
+            };
+            match change {
+                (k, value) if *k == key => tx.send(value).is_ok(),
+                _ => true,
+            }
+        }));
+        rx
+    }
+}
+
+impl<K: Hash+Eq+Clone+Send+'static, V: Clone+Send+'static, S: BuildHasher> ThreadSafeHashCache<K, V, S> {
+    pub fn subscribe(&self) -> Receiver<CacheEvent<K, V>> {
+        self.write().subscribe()
+    }
+
+    pub fn watch(&self, key: K) -> Receiver<Option<V>> where K: Sync {
+        self.write().watch(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cache, CacheBuilder, CacheEvent, HashCache, ThreadSafeHashCache};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn watch() {
+        let cache : ThreadSafeHashCache<&str,&str> = CacheBuilder::new().max_capacity(2).build_thread_safe();
+        let changes = cache.watch("feature_flags");
+
+        cache.insert("feature_flags", "v1");
+        cache.insert("other", "x");
+        cache.insert("feature_flags", "v2");
+        cache.take("feature_flags");
+        cache.insert("feature_flags", "v3");
+        cache.insert("other2", "y");
+        cache.insert("other3", "z");
+
+        assert_eq!(vec![Some("v1"), Some("v2"), None, Some("v3"), None], changes.try_iter().collect::<Vec<_>>());
+        drop(changes);
+        cache.insert("feature_flags", "v4");
+        assert_eq!(0, cache.read().listeners.len());
+    }
+
+    #[test]
+    fn lifecycle_events() {
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new().max_capacity(2).build();
+        let events = cache.subscribe();
+
+        cache.insert("id", "secret");
+        cache.insert("id", "secret2");
+        cache.insert_ttl("id2", "secret3", Duration::from_millis(10));
+        cache.take("id");
+        cache.insert("id3", "secret4");
+        cache.insert("id4", "secret5");
+        cache.retain(|_, _, _| false);
+
+        let mut events: Vec<_> = events.try_iter().collect();
+        // retain visits entries in map order
+        events[7..].sort_by_key(|e| format!("{:?}", e));
+        assert_eq!(vec![
+            CacheEvent::Inserted{ key: "id", value: "secret" },
+            CacheEvent::Replaced{ key: "id", value: "secret2" },
+            CacheEvent::Inserted{ key: "id2", value: "secret3" },
+            CacheEvent::Removed{ key: "id", value: "secret2" },
+            CacheEvent::Inserted{ key: "id3", value: "secret4" },
+            CacheEvent::Evicted{ key: "id2", value: "secret3" },
+            CacheEvent::Inserted{ key: "id4", value: "secret5" },
+            CacheEvent::Removed{ key: "id3", value: "secret4" },
+            CacheEvent::Removed{ key: "id4", value: "secret5" },
+        ], events);
+    }
+
+    #[test]
+    fn expired_events() {
+        let cache : ThreadSafeHashCache<&str,&str> = ThreadSafeHashCache::new();
+        let events = cache.subscribe();
+        cache.insert_ttl("id", "secret", Duration::from_millis(10));
+        cache.insert_ttl("id2", "secret2", Duration::from_millis(10));
+        sleep(Duration::from_millis(20));
+        cache.vacuum(1, 0.25);
+
+        let mut expired: Vec<_> = events.try_iter().skip(2).collect();
+        expired.sort_by_key(|e| format!("{:?}", e));
+        assert_eq!(vec![
+            CacheEvent::Expired{ key: "id", value: "secret" },
+            CacheEvent::Expired{ key: "id2", value: "secret2" },
+        ], expired);
+
+        // dropped receivers are unsubscribed on the next event
+        drop(events);
+        cache.insert("id3", "secret3");
+        assert_eq!(0, cache.read().listeners.0.len());
+    }
+}
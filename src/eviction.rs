@@ -0,0 +1,472 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::rng::CacheRng;
+use crate::slab::Slab;
+use crate::Value;
+
+// Policy decides which entry is evicted when an insert would grow the cache past its
+// max_capacity; pinned entries are never evicted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Policy {
+    // Lru evicts the entry that was read or written least recently, after any expired entry.
+    // Reads only record a tick, so each eviction scans every entry to find the oldest: an insert
+    // into a full cache costs O(n). Fine for small caches; prefer Sieve for large ones.
+    #[default]
+    Lru,
+    // Lfu evicts the entry that was read the fewest times, after any expired entry. Like Lru,
+    // each eviction scans every entry, so an insert into a full cache costs O(n).
+    Lfu,
+    // Sieve keeps entries in insertion order and sweeps a hand over them from oldest to newest,
+    // sparing (once) each entry read since the hand last passed and evicting the first one that
+    // wasn't, or that has expired. Reads only set a flag, nothing is reordered, and an eviction
+    // is O(1) amortized.
+    Sieve,
+    // Arc balances recency against frequency: entries start in a recency list and move to a
+    // frequency list once read again, and the split between the two adapts to hits on recently
+    // evicted keys, so a one-off scan can't flush the hot set. It is implemented as CAR (Clock
+    // with Adaptive Replacement), ARC's clock-based form, so reads only set a flag.
+    Arc,
+    // Slru (segmented LRU) keeps new entries in a probationary segment and evicts from there
+    // first; entries read while on probation move to a protected segment (up to 80% of the
+    // capacity), so one-off scans only ever displace each other. Reads only set a flag; entries
+    // change segment when an eviction reaches them.
+    Slru,
+    // Fifo evicts the oldest entry, however recently it was read
+    Fifo,
+    // Random evicts an entry chosen uniformly at random
+    Random,
+}
+
+impl Policy {
+    // tracks_access is false for the policies that ignore reads, letting get skip recording them
+    pub(crate) fn tracks_access(self) -> bool {
+        !matches!(self, Policy::Fifo | Policy::Random)
+    }
+}
+
+// Counter is a relaxed atomic counter that can be bumped through a shared reference, so the read
+// path (which only holds &self, or a read lock) can still record accesses
+#[derive(Debug, Default)]
+pub(crate) struct Counter(AtomicU64);
+
+impl Counter {
+    pub(crate) fn new(n: u64) -> Counter {
+        Counter(AtomicU64::new(n))
+    }
+
+    pub(crate) fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set(&self, n: u64) {
+        self.0.store(n, Ordering::Relaxed)
+    }
+
+    // incr adds one and returns the previous value
+    pub(crate) fn incr(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+impl Clone for Counter {
+    fn clone(&self) -> Self {
+        Counter::new(self.get())
+    }
+}
+
+// Flag is an atomic bool that, like Counter, can be set through a shared reference
+#[derive(Debug, Default)]
+pub(crate) struct Flag(AtomicBool);
+
+impl Flag {
+    pub(crate) fn set(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+
+    // take clears the flag and returns whether it was set
+    pub(crate) fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl Clone for Flag {
+    fn clone(&self) -> Self {
+        Flag(AtomicBool::new(self.0.load(Ordering::Relaxed)))
+    }
+}
+
+// Access records how recently (as a tick of the owning cache's logical clock) and how often an
+// entry has been read, and whether it was read since an eviction policy last looked at it
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Access {
+    pub(crate) last: Counter,
+    pub(crate) hits: Counter,
+    // read_at is when the entry was last read, in nanoseconds since it was stored
+    pub(crate) read_at: Counter,
+    pub(crate) referenced: Flag,
+}
+
+impl Access {
+    pub(crate) fn new(tick: u64) -> Access {
+        Access{ last: Counter::new(tick), ..Access::default() }
+    }
+
+    // read records any read; it's kept for entry metadata whatever the eviction policy
+    pub(crate) fn read(&self, since_created: Duration) {
+        self.hits.incr();
+        self.read_at.set(since_created.as_nanos() as u64);
+    }
+
+    // touch records a read for the eviction policies that order by access
+    pub(crate) fn touch(&self, tick: u64) {
+        self.last.set(tick);
+        self.referenced.set();
+    }
+}
+
+const NIL: usize = usize::MAX;
+
+#[derive(Clone, Copy)]
+struct Link {
+    prev: usize,
+    next: usize,
+    linked: bool,
+}
+
+// List is a doubly linked list threaded through slab indices, so policies can keep entries in
+// order and unlink any of them in O(1); front is the newest end
+#[derive(Clone)]
+pub(crate) struct List {
+    links: Vec<Link>,
+    head: usize,
+    tail: usize,
+    len: usize,
+}
+
+impl List {
+    pub(crate) fn new() -> List {
+        List{ links: Vec::new(), head: NIL, tail: NIL, len: 0 }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn contains(&self, i: usize) -> bool {
+        self.links.get(i).is_some_and(|l| l.linked)
+    }
+
+    pub(crate) fn back(&self) -> Option<usize> {
+        Some(self.tail).filter(|&t| t != NIL)
+    }
+
+    // prev is the next newer entry
+    pub(crate) fn prev(&self, i: usize) -> Option<usize> {
+        Some(self.links[i].prev).filter(|&p| p != NIL)
+    }
+
+    pub(crate) fn push_front(&mut self, i: usize) {
+        if i >= self.links.len() {
+            self.links.resize(i + 1, Link{ prev: NIL, next: NIL, linked: false });
+        }
+        self.links[i] = Link{ prev: NIL, next: self.head, linked: true };
+        match self.head {
+            NIL => self.tail = i,
+            head => self.links[head].prev = i,
+        }
+        self.head = i;
+        self.len += 1;
+    }
+
+    // remove unlinks i, returning false if it wasn't in the list
+    pub(crate) fn remove(&mut self, i: usize) -> bool {
+        if !self.contains(i) {
+            return false
+        }
+        let Link{ prev, next, .. } = self.links[i];
+        match prev {
+            NIL => self.head = next,
+            prev => self.links[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.links[next].prev = prev,
+        }
+        self.links[i].linked = false;
+        self.len -= 1;
+        true
+    }
+}
+
+// Ghosts is a bounded FIFO of the hashes of recently evicted keys, with O(1) membership tests;
+// removing a hash leaves a stale queue entry behind, skipped when it reaches the front
+#[derive(Clone, Default)]
+pub(crate) struct Ghosts {
+    order: VecDeque<(u64, u64)>,
+    // members maps each hash to the generation of its live queue entry
+    members: HashMap<u64, u64>,
+    generation: u64,
+}
+
+impl Ghosts {
+    fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    fn push(&mut self, hash: u64) {
+        self.generation += 1;
+        self.members.insert(hash, self.generation);
+        self.order.push_back((hash, self.generation));
+        if self.order.len() > 2 * self.members.len() + 16 {
+            let members = &self.members;
+            self.order.retain(|(h, g)| members.get(h) == Some(g));
+        }
+    }
+
+    fn remove(&mut self, hash: u64) -> bool {
+        self.members.remove(&hash).is_some()
+    }
+
+    fn pop_oldest(&mut self) {
+        while let Some((h, g)) = self.order.pop_front() {
+            if self.members.get(&h) == Some(&g) {
+                self.members.remove(&h);
+                return
+            }
+        }
+    }
+}
+
+// Tracker is the per-cache state of the eviction policy, kept in step with the cache's slab as
+// entries are inserted and removed. Reads never touch it (they only run under a read lock);
+// policies that care about reads look at the entries' access flags when choosing a victim.
+#[derive(Clone)]
+pub(crate) enum Tracker {
+    // Scan policies (Lru and Lfu) keep no state and scan the access counters instead, in O(n)
+    Scan,
+    Sieve {
+        queue: List,
+        // hand is the next entry to examine, moving from the back (oldest) to the front
+        hand: Option<usize>,
+    },
+    Arc(Box<Car>),
+    Slru {
+        probation: List,
+        protected: List,
+        protected_max: usize,
+    },
+    Fifo {
+        queue: List,
+    },
+    // Random needs no state, it picks slab slots at random
+    Random,
+}
+
+// Car keeps two clocks of resident entries, recent (seen once) and frequent (seen again), and
+// ghost lists of the keys recently evicted from each; target is the adaptive size of the recent
+// clock
+#[derive(Clone)]
+pub(crate) struct Car {
+    recent: List,
+    frequent: List,
+    recent_ghosts: Ghosts,
+    frequent_ghosts: Ghosts,
+    target: usize,
+    capacity: usize,
+    // the key hash of each entry, by slab index, for recording it as a ghost
+    hashes: Vec<u64>,
+}
+
+impl Car {
+    fn inserted(&mut self, index: usize, hash: u64) {
+        if index >= self.hashes.len() {
+            self.hashes.resize(index + 1, 0);
+        }
+        self.hashes[index] = hash;
+
+        // a hit on a ghost means its list was too small: grow that side's share
+        if self.recent_ghosts.remove(hash) {
+            let step = (self.frequent_ghosts.len() / (self.recent_ghosts.len() + 1)).max(1);
+            self.target = (self.target + step).min(self.capacity);
+            self.frequent.push_front(index);
+        } else if self.frequent_ghosts.remove(hash) {
+            let step = (self.recent_ghosts.len() / (self.frequent_ghosts.len() + 1)).max(1);
+            self.target = self.target.saturating_sub(step);
+            self.frequent.push_front(index);
+        } else {
+            // keep the ghost lists bounded: recent and its ghosts by capacity, everything by
+            // twice that
+            let resident = self.recent.len() + self.frequent.len();
+            if self.recent.len() + self.recent_ghosts.len() >= self.capacity {
+                self.recent_ghosts.pop_oldest();
+            } else if resident + self.recent_ghosts.len() + self.frequent_ghosts.len() >= 2 * self.capacity {
+                self.frequent_ghosts.pop_oldest();
+            }
+            self.recent.push_front(index);
+        }
+    }
+
+    fn removed(&mut self, index: usize) {
+        if !self.recent.remove(index) {
+            self.frequent.remove(index);
+        }
+    }
+
+    fn victim<K, V>(&mut self, entries: &Slab<(K, Value<V>)>, now: Instant) -> Option<usize> {
+        for _ in 0..=2 * (self.recent.len() + self.frequent.len()) {
+            // take from the recent clock while it's over its target share
+            let from_recent = self.recent.len() > 0
+                && (self.recent.len() >= self.target.max(1) || self.frequent.len() == 0);
+            let at = if from_recent { self.recent.back()? } else { self.frequent.back()? };
+            let v = &entries[at].1;
+            if !v.pinned && v.expired(now) {
+                return Some(at)
+            }
+            if !v.pinned && !v.access.referenced.take() {
+                let ghosts = if from_recent { &mut self.recent_ghosts } else { &mut self.frequent_ghosts };
+                ghosts.push(self.hashes[at]);
+                return Some(at)
+            }
+
+            // read (or pinned) entries go round again, at the front of the frequent clock
+            self.removed(at);
+            self.frequent.push_front(at);
+        }
+        None
+    }
+}
+
+impl Tracker {
+    pub(crate) fn new(policy: Policy, capacity: Option<usize>) -> Tracker {
+        match policy {
+            Policy::Lru | Policy::Lfu => Tracker::Scan,
+            Policy::Sieve => Tracker::Sieve{ queue: List::new(), hand: None },
+            Policy::Arc => Tracker::Arc(Box::new(Car{
+                recent: List::new(),
+                frequent: List::new(),
+                recent_ghosts: Ghosts::default(),
+                frequent_ghosts: Ghosts::default(),
+                target: 0,
+                capacity: capacity.unwrap_or(0),
+                hashes: Vec::new(),
+            })),
+            Policy::Slru => Tracker::Slru{
+                probation: List::new(),
+                protected: List::new(),
+                // four fifths of the capacity, without overflowing for a huge one
+                protected_max: capacity.map_or(0, |n| n - n.div_ceil(5)),
+            },
+            Policy::Fifo => Tracker::Fifo{ queue: List::new() },
+            Policy::Random => Tracker::Random,
+        }
+    }
+
+    // inserted records a new entry; hash is only called by policies that remember evicted keys
+    pub(crate) fn inserted<F: FnOnce() -> u64>(&mut self, index: usize, hash: F) {
+        match self {
+            Tracker::Scan | Tracker::Random => {},
+            Tracker::Sieve{ queue, .. } | Tracker::Fifo{ queue } => queue.push_front(index),
+            Tracker::Arc(car) => car.inserted(index, hash()),
+            Tracker::Slru{ probation, .. } => probation.push_front(index),
+        }
+    }
+
+    pub(crate) fn removed(&mut self, index: usize) {
+        match self {
+            Tracker::Scan | Tracker::Random => {},
+            Tracker::Fifo{ queue } => {
+                queue.remove(index);
+            },
+            Tracker::Sieve{ queue, hand } => {
+                if *hand == Some(index) {
+                    *hand = queue.prev(index);
+                }
+                queue.remove(index);
+            },
+            Tracker::Arc(car) => car.removed(index),
+            Tracker::Slru{ probation, protected, .. } => {
+                if !probation.remove(index) {
+                    protected.remove(index);
+                }
+            },
+        }
+    }
+
+    // victim picks the slab index of the entry to evict, or None if every entry is pinned; the
+    // caller must then remove it (and report the removal back through removed)
+    pub(crate) fn victim<K, V>(&mut self, entries: &Slab<(K, Value<V>)>, policy: Policy, now: Instant, rng: &CacheRng) -> Option<usize> {
+        match self {
+            Tracker::Scan => scan(entries, policy, now),
+            Tracker::Sieve{ queue, hand } => {
+                let mut at = hand.or_else(|| queue.back())?;
+                // every entry is spared at most once, so two laps are enough unless all are pinned
+                for _ in 0..=2 * queue.len() {
+                    let v = &entries[at].1;
+                    let newer = queue.prev(at);
+                    if !v.pinned && (v.expired(now) || !v.access.referenced.take()) {
+                        *hand = newer;
+                        return Some(at)
+                    }
+                    at = newer.or_else(|| queue.back())?;
+                }
+                None
+            },
+            Tracker::Arc(car) => car.victim(entries, now),
+            Tracker::Slru{ probation, protected, protected_max } => {
+                for _ in 0..=2 * (probation.len() + protected.len()) {
+                    // the protected segment is only evicted from once probation is empty
+                    let on_probation = probation.len() > 0;
+                    let at = if on_probation { probation.back()? } else { protected.back()? };
+                    let v = &entries[at].1;
+                    if !v.pinned && (v.expired(now) || !v.access.referenced.take()) {
+                        return Some(at)
+                    }
+
+                    // read (or pinned) entries move to the front of the protected segment; if
+                    // that overflows it, its least recently promoted entry drops back to probation
+                    if on_probation {
+                        probation.remove(at);
+                    } else {
+                        protected.remove(at);
+                    }
+                    protected.push_front(at);
+                    if protected.len() > *protected_max {
+                        if let Some(demoted) = protected.back() {
+                            protected.remove(demoted);
+                            probation.push_front(demoted);
+                        }
+                    }
+                }
+                None
+            },
+            Tracker::Fifo{ queue } => {
+                let mut at = queue.back()?;
+                while entries[at].1.pinned {
+                    at = queue.prev(at)?;
+                }
+                Some(at)
+            },
+            Tracker::Random => {
+                // a few random probes almost always land on an evictable entry; if they don't
+                // (a sparse slab, or mostly pinned entries) fall back to the first one
+                (0..32)
+                    .map(|_| rng.below(entries.slots().max(1) as u64) as usize)
+                    .find(|&i| entries.get(i).is_some_and(|(_, v)| !v.pinned))
+                    .or_else(|| entries.iter().find(|(_, (_, v))| !v.pinned).map(|(i, _)| i))
+            },
+        }
+    }
+}
+
+// scan picks the victim for the scanning policies by looking at every entry, so each eviction is
+// O(n)
+fn scan<K, V>(entries: &Slab<(K, Value<V>)>, policy: Policy, now: Instant) -> Option<usize> {
+    let candidates = entries.iter().filter(|(_, (_, v))| !v.pinned);
+    let (index, _) = match policy {
+        Policy::Lfu => candidates.min_by_key(|(_, (_, v))| (!v.expired(now), v.access.hits.get(), v.access.last.get())),
+        _ => candidates.min_by_key(|(_, (_, v))| (!v.expired(now), v.access.last.get())),
+    }?;
+    Some(index)
+}
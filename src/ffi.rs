@@ -0,0 +1,181 @@
+// ffi is a C interface to a thread-safe cache of byte strings, for C and C++ services embedding
+// hodor as a TTL cache. Build it as a library with
+//
+//     cargo rustc --release --features ffi --crate-type staticlib   (or cdylib)
+//
+// and generate a header with cbindgen. A cache is an opaque HodorCache pointer from
+// hodor_cache_new, freed with hodor_cache_free; every call on it is safe from any thread. Keys
+// and values are copied in and out, so the caller keeps ownership of what it passes; the bytes
+// hodor_cache_get hands back belong to the caller and are freed with hodor_bytes_free.
+use std::ptr;
+use std::slice;
+use std::time::Duration;
+
+use crate::{Cache, CacheBuilder, ThreadSafeHashCache};
+
+// HodorCache is the opaque cache handle
+pub struct HodorCache(ThreadSafeHashCache<Vec<u8>, Vec<u8>>);
+
+// HodorBytes is a value copied out of the cache; ptr is null when there's none
+#[repr(C)]
+pub struct HodorBytes {
+    pub ptr: *mut u8,
+    pub len: usize,
+}
+
+impl HodorBytes {
+    fn none() -> HodorBytes {
+        HodorBytes{ ptr: ptr::null_mut(), len: 0 }
+    }
+}
+
+// bytes borrows len bytes at ptr; a null ptr is an empty slice
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if ptr.is_null() {
+        return &[]
+    }
+    slice::from_raw_parts(ptr, len)
+}
+
+// hodor_cache_new creates a cache holding at most max_capacity entries, or any number if it's 0
+#[no_mangle]
+pub extern "C" fn hodor_cache_new(max_capacity: usize) -> *mut HodorCache {
+    let mut builder = CacheBuilder::new();
+    if max_capacity > 0 {
+        builder = builder.max_capacity(max_capacity);
+    }
+    Box::into_raw(Box::new(HodorCache(builder.build_thread_safe())))
+}
+
+/// hodor_cache_free frees a cache; no other call may be using it
+///
+/// # Safety
+/// cache must be null or come from hodor_cache_new, and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn hodor_cache_free(cache: *mut HodorCache) {
+    if !cache.is_null() {
+        drop(Box::from_raw(cache));
+    }
+}
+
+/// hodor_cache_insert stores a persistent entry and returns whether it replaced one
+///
+/// # Safety
+/// cache must be a live handle, and key and value must point to key_len and value_len readable
+/// bytes (or be null).
+#[no_mangle]
+pub unsafe extern "C" fn hodor_cache_insert(cache: *const HodorCache, key: *const u8, key_len: usize, value: *const u8, value_len: usize) -> bool {
+    match cache.as_ref() {
+        Some(cache) => cache.0.insert(bytes(key, key_len).to_vec(), bytes(value, value_len).to_vec()).is_some(),
+        None => false,
+    }
+}
+
+/// hodor_cache_insert_ttl stores an entry expiring after ttl_ms milliseconds and returns whether
+/// it replaced one
+///
+/// # Safety
+/// as for hodor_cache_insert.
+#[no_mangle]
+pub unsafe extern "C" fn hodor_cache_insert_ttl(cache: *const HodorCache, key: *const u8, key_len: usize, value: *const u8, value_len: usize, ttl_ms: u64) -> bool {
+    match cache.as_ref() {
+        Some(cache) => cache.0.insert_ttl(bytes(key, key_len).to_vec(), bytes(value, value_len).to_vec(), Duration::from_millis(ttl_ms)).is_some(),
+        None => false,
+    }
+}
+
+/// hodor_cache_get copies out a live value; a null HodorBytes means the key is absent or expired
+///
+/// # Safety
+/// cache must be a live handle, and key must point to key_len readable bytes (or be null).
+#[no_mangle]
+pub unsafe extern "C" fn hodor_cache_get(cache: *const HodorCache, key: *const u8, key_len: usize) -> HodorBytes {
+    let Some(cache) = cache.as_ref() else {
+        return HodorBytes::none()
+    };
+    let key = bytes(key, key_len).to_vec();
+    let mut found = None;
+    Cache::get_with(&cache.0, &key, &mut |v: &Vec<u8>| found = Some(v.clone()));
+    match found {
+        Some(value) => {
+            let value = Box::into_raw(value.into_boxed_slice());
+            HodorBytes{ ptr: value as *mut u8, len: value.len() }
+        },
+        None => HodorBytes::none(),
+    }
+}
+
+/// hodor_bytes_free frees a value returned by hodor_cache_get
+///
+/// # Safety
+/// bytes must come from hodor_cache_get, and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn hodor_bytes_free(bytes: HodorBytes) {
+    if !bytes.ptr.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(bytes.ptr, bytes.len)));
+    }
+}
+
+/// hodor_cache_remove removes an entry and returns whether there was a live one
+///
+/// # Safety
+/// as for hodor_cache_get.
+#[no_mangle]
+pub unsafe extern "C" fn hodor_cache_remove(cache: *const HodorCache, key: *const u8, key_len: usize) -> bool {
+    match cache.as_ref() {
+        Some(cache) => cache.0.take_once(&bytes(key, key_len).to_vec()).is_some(),
+        None => false,
+    }
+}
+
+/// hodor_cache_vacuum removes expired entries, tuning itself like auto_vacuum, and returns how
+/// many it removed
+///
+/// # Safety
+/// cache must be a live handle (or null).
+#[no_mangle]
+pub unsafe extern "C" fn hodor_cache_vacuum(cache: *const HodorCache) -> usize {
+    cache.as_ref().map_or(0, |cache| cache.0.auto_vacuum())
+}
+
+/// hodor_cache_len is the number of entries stored, expired or not
+///
+/// # Safety
+/// cache must be a live handle (or null).
+#[no_mangle]
+pub unsafe extern "C" fn hodor_cache_len(cache: *const HodorCache) -> usize {
+    cache.as_ref().map_or(0, |cache| cache.0.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    #[test]
+    fn ffi() {
+        unsafe {
+            let cache = hodor_cache_new(0);
+            let (key, value) = (b"key", b"value");
+            assert!(!hodor_cache_insert(cache, key.as_ptr(), key.len(), value.as_ptr(), value.len()));
+            assert!(!hodor_cache_insert_ttl(cache, b"tmp".as_ptr(), 3, ptr::null(), 0, 10));
+            assert_eq!(2, hodor_cache_len(cache));
+
+            let got = hodor_cache_get(cache, key.as_ptr(), key.len());
+            assert_eq!(b"value", slice::from_raw_parts(got.ptr, got.len));
+            hodor_bytes_free(got);
+            assert!(hodor_cache_get(cache, b"nope".as_ptr(), 4).ptr.is_null());
+
+            sleep(Duration::from_millis(20));
+            assert!(hodor_cache_get(cache, b"tmp".as_ptr(), 3).ptr.is_null());
+            assert_eq!(1, hodor_cache_vacuum(cache));
+            assert!(hodor_cache_remove(cache, key.as_ptr(), key.len()));
+            assert_eq!(0, hodor_cache_len(cache));
+            hodor_cache_free(cache);
+
+            // a null handle is a no-op rather than a crash
+            assert_eq!(0, hodor_cache_len(ptr::null()));
+            hodor_cache_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,218 @@
+// grpc serves a ShardedCache over gRPC, so hodor can run as a standalone cache process. The
+// schema is proto/hodor.proto; the messages and service below are written out by hand so
+// building doesn't need protoc.
+use std::convert::Infallible;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{http, Body, Service, StdError};
+use tonic::server::{Grpc, NamedService, UnaryService};
+use tonic::{Code, Request, Response, Status};
+
+use crate::ShardedCache;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetRequest {
+    #[prost(bytes = "bytes", tag = "1")]
+    pub key: Bytes,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetResponse {
+    #[prost(bool, tag = "1")]
+    pub found: bool,
+    #[prost(bytes = "bytes", tag = "2")]
+    pub value: Bytes,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SetRequest {
+    #[prost(bytes = "bytes", tag = "1")]
+    pub key: Bytes,
+    #[prost(bytes = "bytes", tag = "2")]
+    pub value: Bytes,
+    // None stores the entry without a TTL
+    #[prost(uint64, optional, tag = "3")]
+    pub ttl_ms: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SetResponse {
+    #[prost(bool, tag = "1")]
+    pub replaced: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteRequest {
+    #[prost(bytes = "bytes", tag = "1")]
+    pub key: Bytes,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteResponse {
+    #[prost(bool, tag = "1")]
+    pub deleted: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TouchRequest {
+    #[prost(bytes = "bytes", tag = "1")]
+    pub key: Bytes,
+    // None makes the entry persistent
+    #[prost(uint64, optional, tag = "2")]
+    pub ttl_ms: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TouchResponse {
+    #[prost(bool, tag = "1")]
+    pub found: bool,
+}
+
+// CacheService implements the hodor.Cache service over a shared cache; add it to a
+// tonic::transport::Server with add_service
+#[derive(Clone)]
+pub struct CacheService {
+    cache: Arc<ShardedCache<Bytes, Bytes>>,
+}
+
+impl CacheService {
+    pub fn new(cache: Arc<ShardedCache<Bytes, Bytes>>) -> CacheService {
+        CacheService{ cache }
+    }
+
+    pub fn cache(&self) -> &Arc<ShardedCache<Bytes, Bytes>> {
+        &self.cache
+    }
+
+    pub fn get(&self, req: GetRequest) -> GetResponse {
+        match self.cache.shard(&req.key).read_key(&req.key).hit(&req.key) {
+            Some(value) => GetResponse{ found: true, value: value.clone() },
+            None => GetResponse{ found: false, value: Bytes::new() },
+        }
+    }
+
+    pub fn set(&self, req: SetRequest) -> SetResponse {
+        let previous = match req.ttl_ms {
+            Some(ms) => self.cache.insert_ttl(req.key, req.value, Duration::from_millis(ms)),
+            None => self.cache.insert(req.key, req.value),
+        };
+        SetResponse{ replaced: previous.is_some() }
+    }
+
+    pub fn delete(&self, req: DeleteRequest) -> DeleteResponse {
+        DeleteResponse{ deleted: self.cache.take(req.key).is_some() }
+    }
+
+    pub fn touch(&self, req: TouchRequest) -> TouchResponse {
+        TouchResponse{ found: self.cache.touch(&req.key, req.ttl_ms.map(Duration::from_millis)) }
+    }
+}
+
+// Unary adapts one of CacheService's methods, none of which fail, to tonic's UnaryService
+struct Unary<F>(F);
+
+impl<Req, Resp, F> UnaryService<Req> for Unary<F> where F: FnMut(Req) -> Resp {
+    type Response = Resp;
+    type Future = Ready<Result<Response<Resp>, Status>>;
+
+    fn call(&mut self, req: Request<Req>) -> Self::Future {
+        ready(Ok(Response::new((self.0)(req.into_inner()))))
+    }
+}
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = Result<T, Infallible>> + Send>>;
+
+impl<B> Service<http::Request<B>> for CacheService
+    where B: Body + Send + 'static, B::Error: Into<StdError> + Send + 'static
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let svc = self.clone();
+        match req.uri().path() {
+            "/hodor.Cache/Get" => Box::pin(async move {
+                Ok(Grpc::new(ProstCodec::default()).unary(Unary(|r| svc.get(r)), req).await)
+            }),
+            "/hodor.Cache/Set" => Box::pin(async move {
+                Ok(Grpc::new(ProstCodec::default()).unary(Unary(|r| svc.set(r)), req).await)
+            }),
+            "/hodor.Cache/Delete" => Box::pin(async move {
+                Ok(Grpc::new(ProstCodec::default()).unary(Unary(|r| svc.delete(r)), req).await)
+            }),
+            "/hodor.Cache/Touch" => Box::pin(async move {
+                Ok(Grpc::new(ProstCodec::default()).unary(Unary(|r| svc.touch(r)), req).await)
+            }),
+            _ => Box::pin(ready(Ok(Status::new(Code::Unimplemented, "unknown method").into_http()))),
+        }
+    }
+}
+
+impl NamedService for CacheService {
+    const NAME: &'static str = "hodor.Cache";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheService, DeleteRequest, GetRequest, GetResponse, SetRequest, SetResponse, TouchRequest};
+    use crate::ShardedCache;
+    use bytes::Bytes;
+    use std::sync::Arc;
+    use tonic::codec::ProstCodec;
+    use tonic::codegen::http::uri::PathAndQuery;
+    use tonic::transport::{Endpoint, Server};
+
+    #[test]
+    fn methods() {
+        let svc = CacheService::new(Arc::new(ShardedCache::new(4)));
+        let key = Bytes::from_static(b"id");
+        let set = |ttl_ms| svc.set(SetRequest{ key: key.clone(), value: Bytes::from_static(b"secret"), ttl_ms });
+        assert!(!set(None).replaced);
+        assert!(set(Some(60_000)).replaced);
+
+        let got = svc.get(GetRequest{ key: key.clone() });
+        assert!(got.found);
+        assert_eq!(&b"secret"[..], &got.value[..]);
+
+        assert!(svc.touch(TouchRequest{ key: key.clone(), ttl_ms: None }).found);
+        assert!(svc.delete(DeleteRequest{ key: key.clone() }).deleted);
+        assert!(!svc.get(GetRequest{ key: key.clone() }).found);
+        assert!(!svc.touch(TouchRequest{ key, ttl_ms: Some(1) }).found);
+    }
+
+    #[test]
+    fn serve() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let svc = CacheService::new(Arc::new(ShardedCache::new(4)));
+            let incoming = tokio_stream::wrappers::TcpListenerStream::new(listener);
+            tokio::spawn(Server::builder().add_service(svc).serve_with_incoming(incoming));
+
+            let channel = Endpoint::from_shared(format!("http://{}", addr)).unwrap().connect().await.unwrap();
+            let mut client = tonic::client::Grpc::new(channel);
+            client.ready().await.unwrap();
+            let set = SetRequest{ key: Bytes::from_static(b"id"), value: Bytes::from_static(b"secret"), ttl_ms: Some(60_000) };
+            let resp : tonic::Response<SetResponse> = client.unary(tonic::Request::new(set), PathAndQuery::from_static("/hodor.Cache/Set"), ProstCodec::default()).await.unwrap();
+            assert!(!resp.into_inner().replaced);
+
+            client.ready().await.unwrap();
+            let get = GetRequest{ key: Bytes::from_static(b"id") };
+            let resp : tonic::Response<GetResponse> = client.unary(tonic::Request::new(get), PathAndQuery::from_static("/hodor.Cache/Get"), ProstCodec::default()).await.unwrap();
+            assert_eq!(&b"secret"[..], &resp.into_inner().value[..]);
+        });
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use crate::{DefaultHashBuilder, ThreadSafeHashCache};
+
+// KeyDigest is the 128-bit digest a HashedKeyCache stores in place of a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyDigest(u128);
+
+// HashedKeyCache stores a 128-bit digest of each key instead of the key itself, so long keys
+// such as URLs or serialized tuples cost 16 bytes an entry however long they are. Keys can't be
+// listed back out, and two keys with the same digest share an entry: with n keys the chance of
+// any collision is about n² / 2^129, negligible short of billions of keys. The digest is two
+// 64-bit hashes seeded randomly per cache, so collisions can't be precomputed for untrusted keys
+// (with the `ahash` feature, only as far as ahash resists them). A configured cache can be
+// wrapped with HashedKeyCache::from.
+pub struct HashedKeyCache<Q: ?Sized, V, S = DefaultHashBuilder> {
+    cache: ThreadSafeHashCache<KeyDigest, V, S>,
+    seeds: [DefaultHashBuilder; 2],
+    _key: PhantomData<fn(&Q)>,
+}
+
+impl<Q: Hash+?Sized, V> HashedKeyCache<Q, V> {
+    pub fn new() -> HashedKeyCache<Q, V> {
+        HashedKeyCache::from(ThreadSafeHashCache::new())
+    }
+}
+
+impl<Q: Hash+?Sized, V, S: BuildHasher> HashedKeyCache<Q, V, S> {
+    // cache is the underlying cache, e.g. for vacuuming or subscribing
+    pub fn cache(&self) -> &ThreadSafeHashCache<KeyDigest, V, S> {
+        &self.cache
+    }
+
+    // digest is the key under which the underlying cache stores key's entry
+    pub fn digest(&self, key: &Q) -> KeyDigest {
+        let [a, b] = &self.seeds;
+        KeyDigest(u128::from(a.hash_one(key)) << 64 | u128::from(b.hash_one(key)))
+    }
+
+    pub fn insert(&self, key: &Q, value: V) -> Option<V> {
+        self.cache.insert(self.digest(key), value)
+    }
+
+    pub fn insert_ttl(&self, key: &Q, value: V, ttl: Duration) -> Option<V> {
+        self.cache.insert_ttl(self.digest(key), value, ttl)
+    }
+
+    pub fn get<F>(&self, key: &Q, f: F) -> bool where F: Fn(&V) {
+        self.cache.get(self.digest(key), f)
+    }
+
+    pub fn get_copied(&self, key: &Q) -> Option<V> where V: Copy {
+        self.cache.get_copied(&self.digest(key))
+    }
+
+    pub fn take(&self, key: &Q) -> Option<V> {
+        self.cache.take(self.digest(key))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    // vacuum samples the set of potentially expired keys and removes them if expired
+    // panics if retry-threshold is not between 0 and 1.
+    pub fn vacuum(&self, count : usize, retry_threshold : f32 ) {
+        self.cache.vacuum(count, retry_threshold)
+    }
+}
+
+impl<Q: Hash+?Sized, V, S: BuildHasher> From<ThreadSafeHashCache<KeyDigest, V, S>> for HashedKeyCache<Q, V, S> {
+    fn from(cache: ThreadSafeHashCache<KeyDigest, V, S>) -> Self {
+        HashedKeyCache{ cache, seeds: Default::default(), _key: PhantomData }
+    }
+}
+
+impl<Q: Hash+?Sized, V, S: BuildHasher+Default> Default for HashedKeyCache<Q, V, S> {
+    fn default() -> Self {
+        HashedKeyCache::from(ThreadSafeHashCache::with_hasher(S::default()))
+    }
+}
+
+impl<Q: ?Sized, V: fmt::Debug, S: BuildHasher> fmt::Debug for HashedKeyCache<Q, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.cache.read().fmt_named("HashedKeyCache", f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashedKeyCache;
+    use crate::CacheBuilder;
+    use std::time::Duration;
+
+    #[test]
+    fn hashed_keys() {
+        let cache : HashedKeyCache<str, u32> = HashedKeyCache::from(CacheBuilder::new().max_capacity(100).build_thread_safe());
+        let url = format!("https://example.com/{}", "a".repeat(4096));
+        assert_eq!(None, cache.insert(&url, 1));
+        cache.insert_ttl("https://example.com/b", 2, Duration::new(60, 0));
+
+        assert_eq!(Some(1), cache.get_copied(&url));
+        assert_eq!(Some(2), cache.get_copied("https://example.com/b"));
+        assert_eq!(None, cache.get_copied("https://example.com/c"));
+        assert_eq!(cache.digest(&url), cache.digest(url.as_str()));
+        assert_eq!(Some(1), cache.take(&url));
+        assert_eq!(1, cache.len());
+
+        // every cache draws its own seeds
+        let other : HashedKeyCache<str, u32> = HashedKeyCache::new();
+        assert_ne!(cache.digest("https://example.com/b"), other.digest("https://example.com/b"));
+    }
+}
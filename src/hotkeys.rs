@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+// SpaceSaving estimates the most frequent keys of a stream in fixed space: it keeps `capacity`
+// counters, and a key without one takes over the smallest, inheriting its count as possible
+// error. Any key seen more than 1/capacity of the time is guaranteed a counter, and counts are
+// never underestimated.
+// CopyKey copies a key; it's K::clone, captured where K is known to be Clone
+pub(crate) type CopyKey<K> = fn(&K) -> K;
+
+pub(crate) struct SpaceSaving<K> {
+    // counters maps each monitored key to its estimated count and the error that estimate may
+    // carry over from the key it replaced
+    counters: HashMap<K, (u64, u64)>,
+    capacity: usize,
+    // copy makes the sketch's own copies of keys, so the sketch needs no Clone bound of its own
+    copy: CopyKey<K>,
+}
+
+impl<K: Hash+Eq> SpaceSaving<K> {
+    // panics if capacity is 0
+    pub(crate) fn with_copy(capacity: usize, copy: CopyKey<K>) -> SpaceSaving<K> {
+        assert!(capacity > 0, "capacity must be positive");
+        SpaceSaving{ counters: HashMap::with_capacity(capacity), capacity, copy }
+    }
+
+    pub(crate) fn record(&mut self, key: &K) {
+        if let Some((count, _)) = self.counters.get_mut(key) {
+            *count += 1;
+            return
+        }
+        if self.counters.len() < self.capacity {
+            self.counters.insert((self.copy)(key), (1, 0));
+            return
+        }
+        // a linear scan for the minimum; the sketch is meant to be small
+        let (min_key, &(min, _)) = self.counters.iter()
+            .min_by_key(|(_, &(count, _))| count)
+            .expect("capacity is positive");
+        let min_key = (self.copy)(min_key);
+        self.counters.remove(&min_key);
+        self.counters.insert((self.copy)(key), (min + 1, min));
+    }
+
+    // top returns up to n keys with their estimated counts, most frequent first
+    pub(crate) fn top(&self, n: usize) -> Vec<(K, u64)> {
+        let mut top: Vec<(K, u64)> = self.counters.iter().map(|(k, &(count, _))| ((self.copy)(k), count)).collect();
+        top.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        top.truncate(n);
+        top
+    }
+}
+
+// HotKeys is the sketch as held by a HashCache: reads record into it through a shared
+// reference, so it's behind a mutex. Cloning a cache copies the counts so far.
+pub(crate) struct HotKeys<K>(pub(crate) Mutex<SpaceSaving<K>>);
+
+impl<K: Clone> Clone for HotKeys<K> {
+    fn clone(&self) -> Self {
+        let sketch = self.0.lock().expect("lock poisoned");
+        HotKeys(Mutex::new(SpaceSaving{ counters: sketch.counters.clone(), capacity: sketch.capacity, copy: sketch.copy }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpaceSaving;
+
+    #[test]
+    fn heavy_hitters() {
+        let mut sketch = SpaceSaving::with_copy(8, String::clone);
+        // two heavy keys among a long tail of keys seen once
+        for i in 0..1000 {
+            sketch.record(&"hot".to_string());
+            if i % 2 == 0 {
+                sketch.record(&"warm".to_string());
+            }
+            sketch.record(&format!("cold{}", i));
+        }
+
+        let top = sketch.top(2);
+        assert_eq!(vec!["hot", "warm"], top.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>());
+        assert!(top[0].1 >= 1000);
+        assert!(top[1].1 >= 500);
+        assert_eq!(8, sketch.top(10).len());
+    }
+}
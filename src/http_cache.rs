@@ -0,0 +1,328 @@
+// http_cache caches HTTP responses the way a shared cache (a proxy or gateway) would: freshness
+// comes from the response's Cache-Control, Expires, Date and Age headers, and stale responses
+// with an ETag or Last-Modified are kept around to be revalidated with a conditional request.
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use http::header::{self, HeaderMap, HeaderValue};
+use http::{Method, Request, Response, StatusCode};
+
+use crate::ThreadSafeHashCache;
+
+// CachedResponse is a stored response and how long it stays fresh
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    // when the response was stored, by the cache's clock, and its age at that point
+    stored: Instant,
+    age: Duration,
+    fresh_until: Instant,
+}
+
+impl CachedResponse {
+    // is_fresh and age take the current time from the clock of the cache the response is in
+    pub fn is_fresh(&self, now: Instant) -> bool {
+        now < self.fresh_until
+    }
+
+    // age is the response's current age, as sent in the Age header when it is served
+    pub fn age(&self, now: Instant) -> Duration {
+        self.age + now.saturating_duration_since(self.stored)
+    }
+
+    fn has_validators(&self) -> bool {
+        self.headers.contains_key(header::ETAG) || self.headers.contains_key(header::LAST_MODIFIED)
+    }
+
+    // conditional returns the headers that ask the origin whether this response is still valid
+    fn conditional(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = self.headers.get(header::ETAG) {
+            headers.insert(header::IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(modified) = self.headers.get(header::LAST_MODIFIED) {
+            headers.insert(header::IF_MODIFIED_SINCE, modified.clone());
+        }
+        headers
+    }
+
+    fn to_response(&self, now: Instant) -> Response<Vec<u8>> {
+        let mut resp = Response::new(self.body.clone());
+        *resp.status_mut() = self.status;
+        *resp.headers_mut() = self.headers.clone();
+        resp.headers_mut().insert(header::AGE, HeaderValue::from(self.age(now).as_secs()));
+        resp
+    }
+}
+
+// Lookup is the result of looking a request up in an HttpCache
+#[derive(Debug)]
+pub enum Lookup {
+    // a fresh stored response, to serve as is
+    Fresh(Response<Vec<u8>>),
+    // a stale stored response; send the request with these conditional headers added and pass
+    // the origin's response (a 304 or a full one) to update
+    Revalidate(HeaderMap),
+    Miss,
+}
+
+// HttpCache stores responses to GET and HEAD requests in a ThreadSafeHashCache, keyed on method
+// and URL. Responses with a Vary header aren't stored, since they'd need the request headers in
+// the key.
+pub struct HttpCache {
+    cache: Arc<ThreadSafeHashCache<String, CachedResponse>>,
+    revalidate_for: Duration,
+}
+
+impl HttpCache {
+    pub fn new(cache: Arc<ThreadSafeHashCache<String, CachedResponse>>) -> HttpCache {
+        HttpCache{ cache, revalidate_for: Duration::from_secs(60 * 60) }
+    }
+
+    // revalidate_for sets how long a stale response with validators is kept for revalidation
+    // (default one hour); responses without validators are dropped as soon as they go stale
+    pub fn revalidate_for(mut self, window: Duration) -> HttpCache {
+        self.revalidate_for = window;
+        self
+    }
+
+    pub fn cache(&self) -> &Arc<ThreadSafeHashCache<String, CachedResponse>> {
+        &self.cache
+    }
+
+    // key is the cache key for a request
+    pub fn key<B>(req: &Request<B>) -> String {
+        format!("{} {}", req.method(), req.uri())
+    }
+
+    pub fn lookup<B>(&self, req: &Request<B>) -> Lookup {
+        if !cacheable_method(req.method()) || directives(req.headers()).no_cache {
+            return Lookup::Miss
+        }
+        let key = HttpCache::key(req);
+        let stored = self.cache.read_key(&key).hit(&key).cloned();
+        let now = self.cache.now();
+        match stored {
+            Some(stored) if stored.is_fresh(now) => Lookup::Fresh(stored.to_response(now)),
+            Some(stored) if stored.has_validators() => Lookup::Revalidate(stored.conditional()),
+            _ => Lookup::Miss,
+        }
+    }
+
+    // update handles the origin's response to req: a 304 refreshes the stored response, which is
+    // returned in its place, and a storable response is stored. A successful unsafe request
+    // (e.g. a POST) drops the stored response for its URL.
+    pub fn update<B>(&self, req: &Request<B>, resp: Response<Vec<u8>>) -> Response<Vec<u8>> {
+        if !cacheable_method(req.method()) {
+            if resp.status().is_success() || resp.status().is_redirection() {
+                let get = Request::get(req.uri().clone()).body(()).expect("valid request");
+                self.cache.take(HttpCache::key(&get));
+            }
+            return resp
+        }
+
+        let key = HttpCache::key(req);
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            let stored = self.cache.read_key(&key).hit(&key).cloned();
+            if let Some(mut stored) = stored {
+                for (name, value) in resp.headers() {
+                    stored.headers.insert(name, value.clone());
+                }
+                let served = stored.to_response(self.cache.now());
+                self.store(key, req, stored.status, stored.headers, stored.body);
+                return served
+            }
+            return resp
+        }
+
+        let (parts, body) = resp.into_parts();
+        self.store(key, req, parts.status, parts.headers.clone(), body.clone());
+        Response::from_parts(parts, body)
+    }
+
+    // store stores a response if it may be, returning whether it was
+    fn store<B>(&self, key: String, req: &Request<B>, status: StatusCode, headers: HeaderMap, body: Vec<u8>) -> bool {
+        let requested = directives(req.headers());
+        let cc = directives(&headers);
+        if !cacheable_status(status) || requested.no_store || cc.no_store || cc.private || headers.contains_key(header::VARY) {
+            return false
+        }
+        // a shared cache only stores authorized responses the origin explicitly allows it to
+        if req.headers().contains_key(header::AUTHORIZATION) && !(cc.public || cc.s_maxage.is_some()) {
+            return false
+        }
+
+        let now = SystemTime::now();
+        let date = http_date(&headers, header::DATE);
+        let lifetime = match (cc.s_maxage.or(cc.max_age), http_date(&headers, header::EXPIRES)) {
+            _ if cc.no_cache => Duration::ZERO,
+            (Some(max_age), _) => max_age,
+            (None, Some(expires)) => expires.duration_since(date.unwrap_or(now)).unwrap_or_default(),
+            (None, None) => Duration::ZERO,
+        };
+        let apparent = date.and_then(|date| now.duration_since(date).ok()).unwrap_or_default();
+        let age = apparent.max(header_secs(&headers, header::AGE).unwrap_or_default());
+        let fresh_for = lifetime.saturating_sub(age);
+
+        let stored = self.cache.now();
+        let response = CachedResponse{ status, headers, body, stored, age, fresh_until: stored + fresh_for };
+        let ttl = if response.has_validators() { fresh_for + self.revalidate_for } else { fresh_for };
+        if ttl.is_zero() {
+            self.cache.take(key);
+            return false
+        }
+        self.cache.insert_ttl(key, response, ttl);
+        true
+    }
+}
+
+fn cacheable_method(method: &Method) -> bool {
+    method == Method::GET || method == Method::HEAD
+}
+
+// cacheable_status is whether responses with this status may be stored (RFC 9110 15.1)
+fn cacheable_status(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 200 | 203 | 204 | 300 | 301 | 308 | 404 | 405 | 410 | 414 | 501)
+}
+
+// Directives are the Cache-Control directives that affect storing and serving
+#[derive(Debug, Default, PartialEq)]
+struct Directives {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    public: bool,
+    max_age: Option<Duration>,
+    s_maxage: Option<Duration>,
+}
+
+fn directives(headers: &HeaderMap) -> Directives {
+    let mut d = Directives::default();
+    for value in headers.get_all(header::CACHE_CONTROL) {
+        let value = match value.to_str() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        for directive in value.split(',') {
+            let (name, arg) = match directive.split_once('=') {
+                Some((name, arg)) => (name.trim(), Some(arg.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let secs = arg.and_then(|arg| arg.parse().ok()).map(Duration::from_secs);
+            match name.to_ascii_lowercase().as_str() {
+                "no-store" => d.no_store = true,
+                "no-cache" => d.no_cache = true,
+                "private" => d.private = true,
+                "public" => d.public = true,
+                "max-age" => d.max_age = secs,
+                "s-maxage" => d.s_maxage = secs,
+                _ => {},
+            }
+        }
+    }
+    d
+}
+
+fn http_date(headers: &HeaderMap, name: header::HeaderName) -> Option<SystemTime> {
+    httpdate::parse_http_date(headers.get(name)?.to_str().ok()?).ok()
+}
+
+fn header_secs(headers: &HeaderMap, name: header::HeaderName) -> Option<Duration> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok().map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{directives, HttpCache, Lookup};
+    use crate::clock::ManualClock;
+    use crate::{CacheBuilder, ThreadSafeHashCache};
+    use http::{header, Request, Response, StatusCode};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    fn response(status: u16, headers: &[(&str, &str)]) -> Response<Vec<u8>> {
+        let mut resp = Response::builder().status(status);
+        for (name, value) in headers {
+            resp = resp.header(*name, *value);
+        }
+        resp.body(b"hello".to_vec()).unwrap()
+    }
+
+    #[test]
+    fn cache_control() {
+        let d = directives(&response(200, &[("cache-control", "public, max-age=60"), ("cache-control", "s-maxage=\"30\"")]).headers().clone());
+        assert!(d.public);
+        assert_eq!(Some(Duration::from_secs(60)), d.max_age);
+        assert_eq!(Some(Duration::from_secs(30)), d.s_maxage);
+    }
+
+    #[test]
+    fn freshness() {
+        let cache = HttpCache::new(Arc::new(ThreadSafeHashCache::new()));
+        let req = Request::get("https://example.com/a").body(()).unwrap();
+        assert!(matches!(cache.lookup(&req), Lookup::Miss));
+
+        cache.update(&req, response(200, &[("cache-control", "max-age=60"), ("age", "10")]));
+        match cache.lookup(&req) {
+            Lookup::Fresh(resp) => {
+                assert_eq!(b"hello".to_vec(), *resp.body());
+                assert_eq!("10", resp.headers()[header::AGE]);
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Age counts up, and freshness runs out, by the cache's clock
+        let clock = ManualClock::new();
+        let timed = HttpCache::new(Arc::new(CacheBuilder::new().clock(clock.clone()).build_thread_safe()));
+        timed.update(&req, response(200, &[("cache-control", "max-age=60"), ("age", "10"), ("etag", "\"v1\"")]));
+        clock.advance(Duration::from_secs(30));
+        match timed.lookup(&req) {
+            Lookup::Fresh(resp) => assert_eq!("40", resp.headers()[header::AGE]),
+            other => panic!("unexpected {:?}", other),
+        }
+        clock.advance(Duration::from_secs(20));
+        assert!(matches!(timed.lookup(&req), Lookup::Revalidate(_)));
+
+        // a request asking for no-cache goes to the origin
+        let no_cache = Request::get("https://example.com/a").header("cache-control", "no-cache").body(()).unwrap();
+        assert!(matches!(cache.lookup(&no_cache), Lookup::Miss));
+
+        // already stale on arrival, private, or varying: not stored
+        let expires = httpdate::fmt_http_date(SystemTime::now() - Duration::from_secs(5));
+        for headers in [
+            vec![("expires", expires.as_str())],
+            vec![("cache-control", "private, max-age=60")],
+            vec![("cache-control", "max-age=60"), ("vary", "accept")],
+        ] {
+            let req = Request::get("https://example.com/b").body(()).unwrap();
+            cache.update(&req, response(200, &headers));
+            assert!(matches!(cache.lookup(&req), Lookup::Miss));
+        }
+
+        // a successful POST invalidates the URL
+        let post = Request::post("https://example.com/a").body(()).unwrap();
+        cache.update(&post, response(200, &[]));
+        assert!(matches!(cache.lookup(&req), Lookup::Miss));
+    }
+
+    #[test]
+    fn revalidation() {
+        let cache = HttpCache::new(Arc::new(ThreadSafeHashCache::new()));
+        let req = Request::get("https://example.com/a").body(()).unwrap();
+        cache.update(&req, response(200, &[("cache-control", "no-cache"), ("etag", "\"v1\"")]));
+
+        let conditional = match cache.lookup(&req) {
+            Lookup::Revalidate(headers) => headers,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!("\"v1\"", conditional[header::IF_NONE_MATCH]);
+
+        // a 304 is answered with the stored response, refreshed with the 304's headers
+        let resp = cache.update(&req, response(304, &[("cache-control", "max-age=60"), ("etag", "\"v1\"")]));
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(b"hello".to_vec(), *resp.body());
+        assert!(matches!(cache.lookup(&req), Lookup::Fresh(_)));
+    }
+}
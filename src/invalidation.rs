@@ -0,0 +1,303 @@
+use std::hash::BuildHasher;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+
+use crate::{DefaultHashBuilder, ThreadSafeHashCache};
+
+// Invalidation names the entries another process should drop from its local cache
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invalidation {
+    Key(String),
+    // every key starting with the prefix, e.g. a namespace or a tenant
+    Prefix(String),
+    // every key matching a glob pattern, see invalidate_matching
+    Matching(String),
+    All,
+}
+
+impl Invalidation {
+    // encode renders the invalidation as a single line of text, for buses that carry strings
+    pub fn encode(&self) -> String {
+        match self {
+            Invalidation::Key(key) => format!("key {}", key),
+            Invalidation::Prefix(prefix) => format!("prefix {}", prefix),
+            Invalidation::Matching(pattern) => format!("match {}", pattern),
+            Invalidation::All => "all".to_string(),
+        }
+    }
+
+    pub fn decode(s: &str) -> Option<Invalidation> {
+        match s.split_once(' ') {
+            Some(("key", key)) => Some(Invalidation::Key(key.to_string())),
+            Some(("prefix", prefix)) => Some(Invalidation::Prefix(prefix.to_string())),
+            Some(("match", pattern)) => Some(Invalidation::Matching(pattern.to_string())),
+            None if s == "all" => Some(Invalidation::All),
+            _ => None,
+        }
+    }
+
+    // apply removes the invalidated entries from cache, returning how many were removed
+    pub fn apply<V, S: BuildHasher>(&self, cache: &ThreadSafeHashCache<String, V, S>) -> usize {
+        match self {
+            Invalidation::Key(key) => cache.take(key.clone()).map_or(0, |_| 1),
+            Invalidation::Prefix(prefix) => cache.invalidate_prefix(prefix),
+            Invalidation::Matching(pattern) => cache.invalidate_matching(pattern),
+            Invalidation::All => {
+                let before = cache.len();
+                cache.retain(|_, _, _| false);
+                before
+            },
+        }
+    }
+}
+
+// InvalidationBus carries invalidations between processes that each keep a local cache. A bus
+// handle delivers to its subscribers only what other handles published, so a process doesn't
+// act on its own invalidations a second time.
+pub trait InvalidationBus: Send + Sync {
+    fn publish(&self, invalidation: &Invalidation) -> io::Result<()>;
+
+    // subscribe returns a receiver of every invalidation published elsewhere from now on;
+    // dropping the receiver unsubscribes
+    fn subscribe(&self) -> io::Result<Receiver<Invalidation>>;
+}
+
+// LocalBus is an in-process bus, for caches that share a process or for tests. Each clone is a
+// separate handle on the same bus.
+pub struct LocalBus {
+    id: u64,
+    hub: Arc<Hub>,
+}
+
+#[derive(Default)]
+struct Hub {
+    next_id: AtomicU64,
+    subscribers: Mutex<Vec<(u64, Sender<Invalidation>)>>,
+}
+
+impl LocalBus {
+    pub fn new() -> LocalBus {
+        LocalBus{ id: 0, hub: Arc::new(Hub::default()) }
+    }
+}
+
+impl Default for LocalBus {
+    fn default() -> Self {
+        LocalBus::new()
+    }
+}
+
+impl Clone for LocalBus {
+    fn clone(&self) -> Self {
+        LocalBus{ id: self.hub.next_id.fetch_add(1, Ordering::Relaxed) + 1, hub: self.hub.clone() }
+    }
+}
+
+impl InvalidationBus for LocalBus {
+    fn publish(&self, invalidation: &Invalidation) -> io::Result<()> {
+        let mut subscribers = self.hub.subscribers.lock().expect("lock poisoned");
+        subscribers.retain(|(id, tx)| *id == self.id || tx.send(invalidation.clone()).is_ok());
+        Ok(())
+    }
+
+    fn subscribe(&self) -> io::Result<Receiver<Invalidation>> {
+        let (tx, rx) = channel();
+        self.hub.subscribers.lock().expect("lock poisoned").push((self.id, tx));
+        Ok(rx)
+    }
+}
+
+// Coherent keeps a local cache coherent with the caches of other processes: its invalidate
+// methods remove entries locally and publish the invalidation on the bus, and invalidations
+// published by others are applied by a background thread as they arrive. The thread exits once
+// the cache is dropped (at the next invalidation) or the bus closes the subscription.
+pub struct Coherent<V, S = DefaultHashBuilder> {
+    cache: Arc<ThreadSafeHashCache<String, V, S>>,
+    bus: Arc<dyn InvalidationBus>,
+}
+
+impl<V: Send+Sync+'static, S: BuildHasher+Send+Sync+'static> Coherent<V, S> {
+    pub fn new(cache: Arc<ThreadSafeHashCache<String, V, S>>, bus: Arc<dyn InvalidationBus>) -> io::Result<Coherent<V, S>> {
+        let invalidations = bus.subscribe()?;
+        let local: Weak<ThreadSafeHashCache<String, V, S>> = Arc::downgrade(&cache);
+        thread::spawn(move || {
+            for invalidation in invalidations {
+                match local.upgrade() {
+                    Some(cache) => {
+                        let _removed = invalidation.apply(&cache);
+                        event!(DEBUG, removed = _removed, "applied remote invalidation");
+                    },
+                    None => return,
+                }
+            }
+        });
+        Ok(Coherent{ cache, bus })
+    }
+}
+
+impl<V, S: BuildHasher> Coherent<V, S> {
+    pub fn cache(&self) -> &Arc<ThreadSafeHashCache<String, V, S>> {
+        &self.cache
+    }
+
+    // invalidate applies an invalidation locally and publishes it to the other processes,
+    // returning how many local entries were removed
+    pub fn invalidate(&self, invalidation: Invalidation) -> io::Result<usize> {
+        let removed = invalidation.apply(&self.cache);
+        self.bus.publish(&invalidation)?;
+        Ok(removed)
+    }
+
+    pub fn invalidate_key(&self, key: &str) -> io::Result<usize> {
+        self.invalidate(Invalidation::Key(key.to_string()))
+    }
+
+    pub fn invalidate_prefix(&self, prefix: &str) -> io::Result<usize> {
+        self.invalidate(Invalidation::Prefix(prefix.to_string()))
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_bus::RedisBus;
+
+#[cfg(feature = "redis")]
+mod redis_bus {
+    use std::io;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::sync::Mutex;
+    use std::thread;
+
+    use super::{Invalidation, InvalidationBus};
+
+    fn other(e: redis::RedisError) -> io::Error {
+        io::Error::other(e)
+    }
+
+    // RedisBus publishes invalidations on a Redis pub/sub channel. Messages are tagged with the
+    // publishing handle's random id, so a handle skips its own.
+    pub struct RedisBus {
+        client: redis::Client,
+        channel: String,
+        id: u64,
+        publisher: Mutex<Option<redis::Connection>>,
+    }
+
+    impl RedisBus {
+        // new connects lazily, e.g. RedisBus::new("redis://127.0.0.1/", "hodor:invalidations")
+        pub fn new(url: &str, channel: &str) -> io::Result<RedisBus> {
+            let client = redis::Client::open(url).map_err(other)?;
+            Ok(RedisBus{ client, channel: channel.to_string(), id: rand::random(), publisher: Mutex::new(None) })
+        }
+    }
+
+    // payload tags an encoded invalidation with the id of the handle publishing it
+    pub(super) fn payload(id: u64, invalidation: &Invalidation) -> String {
+        format!("{:016x} {}", id, invalidation.encode())
+    }
+
+    // parse returns the invalidation in a payload, unless it was published by id itself
+    pub(super) fn parse(id: u64, payload: &str) -> Option<Invalidation> {
+        let (origin, invalidation) = payload.split_once(' ')?;
+        if u64::from_str_radix(origin, 16).ok()? == id {
+            return None
+        }
+        Invalidation::decode(invalidation)
+    }
+
+    impl InvalidationBus for RedisBus {
+        fn publish(&self, invalidation: &Invalidation) -> io::Result<()> {
+            let mut publisher = self.publisher.lock().expect("lock poisoned");
+            if publisher.is_none() {
+                *publisher = Some(self.client.get_connection().map_err(other)?);
+            }
+            let conn = publisher.as_mut().expect("connected above");
+            let published = redis::cmd("PUBLISH").arg(&self.channel).arg(payload(self.id, invalidation)).query::<i64>(conn);
+            if let Err(e) = published {
+                // reconnect on the next publish
+                *publisher = None;
+                return Err(other(e))
+            }
+            Ok(())
+        }
+
+        // the subscription runs on its own connection and thread, which exit when the
+        // connection fails or the first message after the receiver is dropped arrives
+        fn subscribe(&self) -> io::Result<Receiver<Invalidation>> {
+            let mut conn = self.client.get_connection().map_err(other)?;
+            conn.as_pubsub().subscribe(&self.channel).map_err(other)?;
+            let (tx, rx) = channel();
+            let id = self.id;
+            thread::spawn(move || {
+                let mut pubsub = conn.as_pubsub();
+                while let Ok(msg) = pubsub.get_message() {
+                    let payload: String = match msg.get_payload() {
+                        Ok(payload) => payload,
+                        Err(_) => continue,
+                    };
+                    if let Some(invalidation) = parse(id, &payload) {
+                        if tx.send(invalidation).is_err() {
+                            return
+                        }
+                    }
+                }
+            });
+            Ok(rx)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Coherent, Invalidation, LocalBus};
+    use crate::ThreadSafeHashCache;
+    use std::sync::Arc;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn encoding() {
+        for invalidation in [
+            Invalidation::Key("user 1".to_string()),
+            Invalidation::Prefix("tenant:".to_string()),
+            Invalidation::Matching("user:*".to_string()),
+            Invalidation::All,
+        ] {
+            assert_eq!(Some(invalidation.clone()), Invalidation::decode(&invalidation.encode()));
+        }
+        assert_eq!(None, Invalidation::decode("bogus"));
+    }
+
+    #[test]
+    fn coherent() {
+        let bus = LocalBus::new();
+        let caches: Vec<Coherent<&str>> = (0..3)
+            .map(|_| Coherent::new(Arc::new(ThreadSafeHashCache::new()), Arc::new(bus.clone())).unwrap())
+            .collect();
+        for c in &caches {
+            c.cache().insert("user:1".to_string(), "alice");
+            c.cache().insert("user:2".to_string(), "bob");
+            c.cache().insert("org:1".to_string(), "acme");
+        }
+
+        assert_eq!(1, caches[0].invalidate_key("user:1").unwrap());
+        assert_eq!(1, caches[1].invalidate_prefix("org:").unwrap());
+        sleep(Duration::from_millis(20));
+        for c in &caches {
+            assert_eq!(vec!["user:2".to_string()], c.cache().scan_prefix("").into_iter().map(|(k, _)| k).collect::<Vec<_>>());
+        }
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn redis_payloads() {
+        use super::redis_bus::{parse, payload};
+
+        let invalidation = Invalidation::Prefix("tenant:".to_string());
+        let p = payload(7, &invalidation);
+        assert_eq!(None, parse(7, &p));
+        assert_eq!(Some(invalidation), parse(8, &p));
+    }
+}
@@ -0,0 +1,251 @@
+// jwks caches JSON Web Key Sets for auth middleware that verifies tokens: key sets are fetched
+// once per issuer, kept for as long as the issuer's Cache-Control allows (within bounds), and
+// refreshed in the background before they expire, so requests never wait on the issuer.
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::ThreadSafeHashCache;
+
+// JwksResponse is what a fetcher got from an issuer's JWKS endpoint
+#[derive(Debug, Clone, Default)]
+pub struct JwksResponse {
+    pub body: Vec<u8>,
+    // the response's Cache-Control header, if any
+    pub cache_control: Option<String>,
+}
+
+// JwksFetcher fetches the JWKS at a URL, with whichever HTTP client the application uses
+pub type JwksFetcher = dyn Fn(&str) -> io::Result<JwksResponse> + Send + Sync;
+
+// KeySet is a parsed JWKS, with its keys indexed by kid
+#[derive(Debug)]
+pub struct KeySet {
+    keys: HashMap<String, Value>,
+    fetched: Instant,
+    ttl: Duration,
+}
+
+impl KeySet {
+    fn parse(body: &[u8], ttl: Duration) -> io::Result<KeySet> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let jwks: Value = serde_json::from_slice(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let keys = jwks.get("keys").and_then(Value::as_array).ok_or_else(|| invalid("JWKS has no keys array"))?;
+        let keys = keys.iter()
+            .filter_map(|key| Some((key.get("kid")?.as_str()?.to_string(), key.clone())))
+            .collect();
+        Ok(KeySet{ keys, fetched: Instant::now(), ttl })
+    }
+
+    // get returns the JWK with the given kid
+    pub fn get(&self, kid: &str) -> Option<&Value> {
+        self.keys.get(kid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    // due_for_refresh is whether the set is in the last fifth of its TTL
+    fn due_for_refresh(&self) -> bool {
+        self.fetched.elapsed() >= self.ttl - self.ttl / 5
+    }
+}
+
+// JwksCache caches key sets by JWKS URL
+pub struct JwksCache {
+    cache: Arc<ThreadSafeHashCache<String, Arc<KeySet>>>,
+    fetch: Arc<JwksFetcher>,
+    default_ttl: Duration,
+    min_ttl: Duration,
+    max_ttl: Duration,
+    // URLs with a background refresh in flight
+    refreshing: Arc<Mutex<HashSet<String>>>,
+}
+
+impl JwksCache {
+    pub fn new<F>(fetch: F) -> JwksCache where F: Fn(&str) -> io::Result<JwksResponse> + Send + Sync + 'static {
+        JwksCache{
+            cache: Arc::new(ThreadSafeHashCache::new()),
+            fetch: Arc::new(fetch),
+            default_ttl: Duration::from_secs(60 * 60),
+            min_ttl: Duration::from_secs(60),
+            max_ttl: Duration::from_secs(24 * 60 * 60),
+            refreshing: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    // default_ttl is how long key sets are kept when the response has no max-age (default one
+    // hour)
+    pub fn default_ttl(mut self, ttl: Duration) -> JwksCache {
+        self.default_ttl = ttl;
+        self
+    }
+
+    // ttl_bounds clamps the TTLs issuers ask for (default one minute to one day). The minimum
+    // also limits how often an unknown kid can trigger a refetch. Panics if min is 0 or more than
+    // max.
+    pub fn ttl_bounds(mut self, min: Duration, max: Duration) -> JwksCache {
+        assert!(!min.is_zero() && min <= max, "invalid ttl bounds");
+        self.min_ttl = min;
+        self.max_ttl = max;
+        self
+    }
+
+    pub fn cache(&self) -> &Arc<ThreadSafeHashCache<String, Arc<KeySet>>> {
+        &self.cache
+    }
+
+    // key_set returns the key set at url, fetching it on a miss
+    pub fn key_set(&self, url: &str) -> io::Result<Arc<KeySet>> {
+        let cached = self.cache.read_key(url).hit(&url.to_string()).cloned();
+        match cached {
+            Some(set) => {
+                if set.due_for_refresh() {
+                    self.refresh(url);
+                }
+                Ok(set)
+            },
+            None => self.fetch(url),
+        }
+    }
+
+    // get_key returns the key with the given kid from the key set at url. An unknown kid usually
+    // means the issuer rotated its keys, so it triggers a refetch, at most once per min_ttl.
+    pub fn get_key(&self, url: &str, kid: &str) -> io::Result<Option<Value>> {
+        let set = self.key_set(url)?;
+        if let Some(key) = set.get(kid) {
+            return Ok(Some(key.clone()))
+        }
+        if set.fetched.elapsed() < self.min_ttl {
+            return Ok(None)
+        }
+        Ok(self.fetch(url)?.get(kid).cloned())
+    }
+
+    fn fetch(&self, url: &str) -> io::Result<Arc<KeySet>> {
+        fetch(&*self.fetch, url, self.ttls())
+            .map(|set| store(&self.cache, url, set))
+    }
+
+    // refresh refetches a key set on a background thread, unless a refetch is already running;
+    // on failure the current set is kept until it expires
+    fn refresh(&self, url: &str) {
+        if !self.refreshing.lock().expect("lock poisoned").insert(url.to_string()) {
+            return
+        }
+
+        let (cache, fetcher, refreshing, ttls) = (self.cache.clone(), self.fetch.clone(), self.refreshing.clone(), self.ttls());
+        let url = url.to_string();
+        thread::spawn(move || {
+            match fetch(&*fetcher, &url, ttls) {
+                Ok(set) => { store(&cache, &url, set); },
+                Err(_e) => { event!(WARN, url = %url, error = %_e, "refreshing JWKS failed"); },
+            }
+            refreshing.lock().expect("lock poisoned").remove(&url);
+        });
+    }
+
+    fn ttls(&self) -> (Duration, Duration, Duration) {
+        (self.default_ttl, self.min_ttl, self.max_ttl)
+    }
+}
+
+fn fetch(fetcher: &JwksFetcher, url: &str, (default, min, max): (Duration, Duration, Duration)) -> io::Result<KeySet> {
+    let resp = fetcher(url)?;
+    let ttl = resp.cache_control.as_deref().map_or(default, |cc| ttl_from(cc, default, min)).clamp(min, max);
+    KeySet::parse(&resp.body, ttl)
+}
+
+fn store(cache: &ThreadSafeHashCache<String, Arc<KeySet>>, url: &str, set: KeySet) -> Arc<KeySet> {
+    let ttl = set.ttl;
+    let set = Arc::new(set);
+    cache.insert_ttl(url.to_string(), set.clone(), ttl);
+    set
+}
+
+// ttl_from reads a TTL from a Cache-Control header: max-age, or min for no-cache and no-store
+// (a key set must be kept for a while however it's served), or default if neither is present
+fn ttl_from(cache_control: &str, default: Duration, min: Duration) -> Duration {
+    let mut ttl = default;
+    for directive in cache_control.split(',').map(str::trim) {
+        let directive = directive.to_ascii_lowercase();
+        if directive == "no-cache" || directive == "no-store" {
+            return min
+        }
+        if let Some(secs) = directive.strip_prefix("max-age=").and_then(|s| s.trim_matches('"').parse().ok()) {
+            ttl = Duration::from_secs(secs);
+        }
+    }
+    ttl
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ttl_from, JwksCache, JwksResponse};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn ttls() {
+        let (default, min) = (Duration::new(3600, 0), Duration::new(60, 0));
+        assert_eq!(Duration::new(300, 0), ttl_from("public, max-age=300", default, min));
+        assert_eq!(min, ttl_from("no-cache", default, min));
+        assert_eq!(default, ttl_from("public", default, min));
+    }
+
+    #[test]
+    fn rotation() {
+        // the issuer adds a key on every fetch
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = fetches.clone();
+        let jwks = JwksCache::new(move |url| {
+            assert_eq!("https://issuer.example/jwks", url);
+            let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            let keys: Vec<String> = (0..n).map(|i| format!(r#"{{"kid": "k{}", "kty": "RSA"}}"#, i)).collect();
+            Ok(JwksResponse{ body: format!(r#"{{"keys": [{}]}}"#, keys.join(",")).into_bytes(), cache_control: Some("max-age=3600".to_string()) })
+        }).ttl_bounds(Duration::from_millis(50), Duration::new(3600, 0));
+        let url = "https://issuer.example/jwks";
+
+        assert_eq!("RSA", jwks.get_key(url, "k0").unwrap().unwrap()["kty"]);
+        assert!(jwks.get_key(url, "k0").unwrap().is_some());
+        assert_eq!(1, fetches.load(Ordering::SeqCst));
+
+        // an unknown kid doesn't refetch within min_ttl of the last fetch
+        assert!(jwks.get_key(url, "k1").unwrap().is_none());
+        assert_eq!(1, fetches.load(Ordering::SeqCst));
+        sleep(Duration::from_millis(60));
+        assert!(jwks.get_key(url, "k1").unwrap().is_some());
+        assert_eq!(2, fetches.load(Ordering::SeqCst));
+        assert_eq!(2, jwks.key_set(url).unwrap().len());
+    }
+
+    #[test]
+    fn background_refresh() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = fetches.clone();
+        let jwks = JwksCache::new(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(JwksResponse{ body: br#"{"keys": [{"kid": "k0"}]}"#.to_vec(), cache_control: None })
+        }).default_ttl(Duration::from_millis(100)).ttl_bounds(Duration::from_millis(10), Duration::new(60, 0));
+        let url = "https://issuer.example/jwks";
+
+        assert!(jwks.get_key(url, "k0").unwrap().is_some());
+        // in the last fifth of the TTL, the cached set is served while a refetch runs
+        sleep(Duration::from_millis(85));
+        assert!(jwks.get_key(url, "k0").unwrap().is_some());
+        sleep(Duration::from_millis(20));
+        assert_eq!(2, fetches.load(Ordering::SeqCst));
+        assert!(jwks.cache().get(url.to_string(), |_| {}));
+    }
+}
@@ -1,24 +1,357 @@
-use std::hash::Hash;
-use std::collections::HashMap;
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::iter::FromIterator;
 use std::time::{Duration, Instant};
-use std::sync::RwLock;
+
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+// lets #[hodor::memoize] expand to ::hodor paths inside this crate's own tests
+#[cfg(feature = "macros")]
+extern crate self as hodor;
+
+#[macro_use]
+mod trace;
+#[cfg(feature = "admin")]
+mod admin;
+mod adapters;
+mod audit;
+mod autovacuum;
+mod bloom;
+mod builder;
+mod cell;
+mod clock;
+mod cluster;
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+mod compress;
+#[cfg(feature = "snapshot")]
+mod dump;
+mod eviction;
+mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod hashed;
+mod hotkeys;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "http")]
+mod http_cache;
+mod invalidation;
+#[cfg(feature = "jwks")]
+mod jwks;
+mod loading;
+mod local;
+mod memoize;
+mod namespace;
+#[cfg(feature = "tokio")]
+mod notify;
+#[cfg(feature = "snapshot")]
+mod persist;
+#[cfg(feature = "python")]
+pub mod python;
+mod ratelimit;
+mod reaper;
+mod rng;
+mod registry;
+mod replication;
+mod report;
+mod scan;
+#[cfg(feature = "zeroize")]
+mod secret;
+mod sharded;
+#[cfg(feature = "snapshot")]
+mod snapshot;
+#[cfg(feature = "rand")]
+mod session;
+mod shared;
+mod slab;
+mod spill;
+mod stats;
+#[cfg(feature = "tokens")]
+mod token;
+mod transaction;
+mod typed;
+mod view;
+mod wait;
+#[cfg(feature = "tower")]
+pub mod tower;
+mod warmup;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "admin")]
+pub use crate::admin::{Admin, AdminResponse, AdminServer};
+#[cfg(feature = "lru")]
+pub use crate::adapters::LruAdapter;
+#[cfg(feature = "moka")]
+pub use crate::adapters::MokaAdapter;
+pub use crate::audit::{AuditEvent, AuditHook, AuditOp, AuditOutcome, Audited};
+pub use crate::builder::CacheBuilder;
+use crate::autovacuum::AutoVacuum;
+use crate::bloom::{NegativeFilter, SharedFilter};
+use crate::cell::{ReadGuard, Stripes, ValueCell};
+use crate::rng::CacheRng;
+use crate::hotkeys::{HotKeys, SpaceSaving};
+use crate::slab::Slab;
+pub use crate::builder::{ExpirePolicy, OverwritePolicy};
+pub use crate::clock::{Clock, CoarseClock, ManualClock, SystemClock, WallClock};
+#[cfg(feature = "wasm")]
+pub use crate::clock::JsClock;
+pub use crate::cluster::{ClusterClient, HashRing, MemcachedNode, Node};
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+pub use crate::compress::{Codec, Compressed, CompressedCache, CompressionStats};
+use crate::builder::Config;
+pub use crate::eviction::Policy;
+#[cfg(feature = "http")]
+pub use crate::http_cache::{CachedResponse, HttpCache, Lookup};
+pub use crate::hashed::{HashedKeyCache, KeyDigest};
+pub use crate::invalidation::{Coherent, Invalidation, InvalidationBus, LocalBus};
+#[cfg(feature = "redis")]
+pub use crate::invalidation::RedisBus;
+#[cfg(feature = "jwks")]
+pub use crate::jwks::{JwksCache, JwksFetcher, JwksResponse, KeySet};
+pub use crate::loading::{Loader, LoadingCache};
+pub use crate::local::LocalCache;
+pub use crate::memoize::Memoized;
+#[cfg(feature = "macros")]
+pub use hodor_macros::memoize;
+pub use crate::namespace::Namespace;
+pub use crate::ratelimit::{Bucket, Decision, RateLimiter, TokenBucket, Window, WindowCount};
+pub use crate::reaper::{Reaper, VacuumSchedule};
+pub use crate::registry::{CacheRegistry, Managed};
+pub use crate::replication::Replication;
+use crate::eviction::{Access, Counter, Tracker};
+pub use crate::events::CacheEvent;
+use crate::events::Listeners;
+#[cfg(feature = "snapshot")]
+pub use crate::persist::PersistentCache;
+#[cfg(feature = "snapshot")]
+pub use crate::snapshot::{Format, Snapshot, SnapshotEntry};
+#[cfg(feature = "zeroize")]
+pub use crate::secret::SecretCache;
+#[cfg(feature = "zeroize")]
+pub use zeroize::Zeroizing;
+#[cfg(feature = "rand")]
+pub use crate::session::{Session, SessionStore};
+pub use crate::shared::ArcCache;
+pub use crate::report::DebugReport;
+pub use crate::sharded::{ShardSchedule, ShardedCache};
+#[cfg(feature = "rayon")]
+pub use crate::sharded::VacuumReport;
+pub use crate::spill::{SpillCache, SpillFile, Spilled};
+pub use crate::stats::{Contention, ShardStats, Stats};
+use crate::stats::{LockRecorder, Recorder};
+#[cfg(feature = "tokens")]
+pub use crate::token::{TokenCache, TokenDigest};
+pub use crate::transaction::Transaction;
+pub use crate::typed::TypedCache;
+pub use crate::view::View;
+use crate::view::CachedView;
+pub use crate::warmup::Warmup;
+
+// DefaultHashBuilder is the hasher used when none is given: std's SipHash, or ahash when the
+// `ahash` feature is enabled
+#[cfg(not(feature = "ahash"))]
+pub type DefaultHashBuilder = std::collections::hash_map::RandomState;
+#[cfg(feature = "ahash")]
+pub type DefaultHashBuilder = ahash::RandomState;
 
 // Cache allows storing values that expire after a given time
 // and provides utils (vacuum) for garbage collecting expired keys in the background
-trait Cache<K, V> {
+// the trait is object safe, so backends can be swapped behind a Box<dyn Cache<K, V>>
+pub trait Cache<K, V> {
     fn insert(&mut self, key : K, value: V) -> Option<V>;
     fn insert_ttl(&mut self, key : K, value: V, ttl: Duration) -> Option<V>;
-    fn get<F>(&self, key: K, f: F) -> bool where F: Fn(&V);
+    // get_with calls f with the value if the key is present and not expired
+    fn get_with(&self, key: &K, f: &mut dyn FnMut(&V)) -> bool;
+    fn get<F>(&self, key: K, f: F) -> bool where F: Fn(&V), Self: Sized {
+        self.get_with(&key, &mut |v| f(v))
+    }
+    // take removes a live entry and hands ownership of its value to the caller
+    fn take(&mut self, key: K) -> Option<V>;
     fn vacuum(&mut self, count : usize, retry_threshold : f32 );
 }
 
 // Value wraps a stored value of type V with (optional) expiration data
 struct Value<V> {
-    value: V,
+    value: ValueCell<V>,
     expires: ExpireMeta,
+    // pinned entries are never chosen for capacity eviction (but still honor their TTL)
+    pinned: bool,
+    access: Access,
+    // slot is the entry's position in the expiring index, if it has a TTL
+    slot: Option<usize>,
+    // created is when the entry was stored
+    created: Instant,
+    // idle is how long the entry lives without being read, set by put from expire_after_access
+    idle: Option<Duration>,
+    // version is the tick of the last write to the entry, which fetch_update checks to detect
+    // concurrent changes; ticks are never reused, so a key removed and stored again gets a new one
+    version: Counter,
+}
+
+// Clone is implemented by hand so ValueCell needn't be Clone, which would make v.value.clone()
+// clone the cell rather than the value
+impl<V: Clone> Clone for Value<V> {
+    fn clone(&self) -> Self {
+        Value{
+            // SAFETY: a cache is only cloned by its owner, or under a ThreadSafeHashCache's read,
+            // which holds every stripe
+            value: ValueCell::new(V::clone(unsafe { self.value.get() })),
+            expires: self.expires.clone(),
+            pinned: self.pinned,
+            access: self.access.clone(),
+            slot: self.slot,
+            created: self.created,
+            idle: self.idle,
+            version: self.version.clone(),
+        }
+    }
+}
+
+impl<V> Value<V> {
+    fn new(value: V, expires: ExpireMeta, tick: u64, now: Instant) -> Value<V> {
+        Value{ value: ValueCell::new(value), expires, pinned: false, access: Access::new(tick), slot: None, created: now, idle: None,
+            version: Counter::new(tick) }
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        let idle = self.idle.is_some_and(|idle| now.saturating_duration_since(self.last_access()) > idle);
+        idle || match &self.expires {
+            ExpireMeta::Expires(e) => {
+                now.saturating_duration_since(e.inserted).gt(&e.ttl)
+            }
+            _ => { false }
+        }
+    }
+
+    // last_access is when the entry was last read, or stored if it never was
+    fn last_access(&self) -> Instant {
+        self.created + Duration::from_nanos(self.access.read_at.get())
+    }
+
+    // is_expiring is whether the entry can expire, and so belongs in the expiring index
+    fn is_expiring(&self) -> bool {
+        self.idle.is_some() || matches!(self.expires, ExpireMeta::Expires(_))
+    }
+
+    // expires_early implements probabilistic early expiration (XFetch): an entry is reported as
+    // expired when now - recompute * beta * ln(rand) passes its deadline, so as the deadline nears
+    // a single reader is increasingly likely to see a miss and refresh it before everyone misses
+    fn expires_early(&self, beta: f64, now: Instant, rng: &CacheRng) -> bool {
+        let e = match &self.expires {
+            ExpireMeta::Expires(e) if e.recompute > Duration::from_secs(0) => e,
+            _ => return false,
+        };
+
+        // 1 - gen() is in (0, 1], so the log is finite and never positive
+        let r: f64 = 1.0 - rng.unit();
+        let gap = e.recompute.as_secs_f64() * beta * -r.ln();
+        now.saturating_duration_since(e.inserted).as_secs_f64() + gap >= e.ttl.as_secs_f64()
+    }
+
+    // deadline is when the entry's TTL runs out, if it has one
+    // a TTL too long to represent as an Instant counts as never expiring
+    fn deadline(&self) -> Option<Instant> {
+        match &self.expires {
+            ExpireMeta::Expires(e) => e.inserted.checked_add(e.ttl),
+            ExpireMeta::Persistent => None,
+        }
+    }
+
+    // expires_at is when the entry expires unless it's read or touched first: its deadline or
+    // its idle deadline, whichever is sooner
+    fn expires_at(&self) -> Option<Instant> {
+        let idle = self.idle.and_then(|idle| self.last_access().checked_add(idle));
+        match (self.deadline(), idle) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    // ttl is the entry's full TTL, which for an entry being inserted is also the time it has left
+    fn ttl(&self) -> Option<Duration> {
+        match &self.expires {
+            ExpireMeta::Expires(e) => Some(e.ttl),
+            ExpireMeta::Persistent => None,
+        }
+    }
+
+    // meta reports the entry as of now, the cache clock's current time
+    fn meta(&self, now: Instant) -> EntryMeta {
+        let (inserted, ttl) = match &self.expires {
+            ExpireMeta::Expires(e) => (Some(e.inserted), Some(e.ttl)),
+            ExpireMeta::Persistent => (None, None),
+        };
+        let hits = self.access.hits.get();
+        let last_access = if hits > 0 { Some(self.last_access()) } else { None };
+        EntryMeta{ inserted, ttl, created: self.created, hits, last_access, idle: self.idle, as_of: now }
+    }
+}
+
+// EntryMeta is a read-only view of an entry's expiration and access data, handed to callers that
+// need to make decisions about individual entries (e.g. retain)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryMeta {
+    // inserted is when the entry's TTL started, at insert or its last touch; None if persistent
+    pub inserted: Option<Instant>,
+    pub ttl: Option<Duration>,
+    // created is when the entry was stored
+    pub created: Instant,
+    // hits counts the reads that returned the entry, and last_access is the latest (None if it
+    // was never read)
+    pub hits: u64,
+    pub last_access: Option<Instant>,
+    // idle is the cache's expire_after_access, if set
+    pub idle: Option<Duration>,
+    // as_of is the time by the cache's clock when the metadata was read, which is_expired judges by
+    pub as_of: Instant,
+}
+
+impl EntryMeta {
+    pub fn is_persistent(&self) -> bool {
+        self.ttl.is_none()
+    }
+
+    // expires_at is the entry's TTL deadline; see idle_expires_at for expire_after_access. Both
+    // are None for a deadline too far off for an Instant to hold.
+    pub fn expires_at(&self) -> Option<Instant> {
+        self.inserted?.checked_add(self.ttl?)
+    }
+
+    // idle_expires_at is when the entry expires unless it's read again, if the cache has
+    // expire_after_access
+    pub fn idle_expires_at(&self) -> Option<Instant> {
+        self.last_access.unwrap_or(self.created).checked_add(self.idle?)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        let since = |at: Instant| self.as_of.saturating_duration_since(at);
+        let expired = self.inserted.zip(self.ttl).is_some_and(|(inserted, ttl)| since(inserted) > ttl);
+        expired || self.idle.is_some_and(|idle| since(self.last_access.unwrap_or(self.created)) > idle)
+    }
+}
+
+// InsertOutcome reports what an insert replaced: previous is the value stored under the key
+// before, live or not, and previous_expired tells which, since an expired entry may still be
+// waiting to be vacuumed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InsertOutcome<V> {
+    pub previous: Option<V>,
+    pub previous_expired: bool,
+}
+
+impl<V> InsertOutcome<V> {
+    // replaced is whether the insert overwrote a live value
+    pub fn replaced(&self) -> bool {
+        self.previous.is_some() && !self.previous_expired
+    }
 }
 
 // A value is either persistent (never expires) or has expiration metadata attached
+#[derive(Clone)]
 enum ExpireMeta {
     Persistent,
     Expires(Expiration)
@@ -26,358 +359,2793 @@ enum ExpireMeta {
 
 // Expiration is determined based on the instant the value was inserted and the duration it should
 // live in the cache
+#[derive(Clone)]
 struct Expiration {
     inserted: Instant,
     ttl: Duration,
+    // recompute is how long the value took to produce, used for probabilistic early expiration
+    recompute: Duration,
 }
 
-// HashCache is a hashmap-backed cache implementation
-pub struct HashCache<K: Hash+Eq+Clone, V> {
-    store: HashMap<K,Value<V>>,
-    expiring: Vec<K>,
+impl ExpireMeta {
+    // after expires ttl from now
+    fn after(ttl: Duration, now: Instant) -> ExpireMeta {
+        ExpireMeta::Expires(Expiration{ inserted: now, ttl, recompute: Duration::from_secs(0) })
+    }
 }
 
-impl<K: Hash+Eq+Clone, V>  HashCache<K, V> {
-    pub fn new() -> HashCache<K,V> {
-        HashCache{ store: HashMap::new(), expiring: Vec::new()}
+// StoreKey is a key as the store holds it: the Arc its entry holds too, hashed and compared as
+// the key itself, so the store can be probed with a &K, or a &str for String keys
+struct StoreKey<K>(Arc<K>);
+
+impl<K> Clone for StoreKey<K> {
+    fn clone(&self) -> Self {
+        StoreKey(self.0.clone())
     }
+}
 
-    fn expired(&self, key: &K) -> bool {
-        match self.store.get(key) {
-            Some(v) => {
-                match &v.expires {
-                    ExpireMeta::Expires(e) => {
-                        e.inserted.elapsed().gt(&e.ttl)
-                    }
-                    _ => { false }
-                }
-            },
-            // report empty entries as expired
-            None => { true },
-        }
+impl<K: Hash> Hash for StoreKey<K> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
     }
+}
 
-    // called by vacuum, this just handles sampling and removing a single set (not retrying based
-    // on a threshold)
-    fn vacuum_sample(&mut self, count : usize) -> usize {
-        // amount is the max number of items we sample from the current set
-        let mut amount = count;
-        if count > self.expiring.len() {
-            amount = self.expiring.len()
-        }
+impl<K: PartialEq> PartialEq for StoreKey<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
 
-        // sample a random set of indices that have expiration set
-        let samples = rand::seq::index::sample(&mut rand::thread_rng(), self.expiring.len(), amount);
+impl<K: Eq> Eq for StoreKey<K> {}
 
-        let mut expired_indices = vec![];
+impl<K> Borrow<K> for StoreKey<K> {
+    fn borrow(&self) -> &K {
+        &self.0
+    }
+}
 
-        // if the key referenced by the index is expired, remove it from the cache (and self.expiring)
-        for index in samples.iter() {
-            if let Some(key) = self.expiring.get(index) {
-                if self.expired(&key) {
-                    self.store.remove(key);
-                    expired_indices.push(index);
-                }
-            }
+impl Borrow<str> for StoreKey<String> {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+// IntoKey is a key handed to an insert: an owned K, or the Arc of a key already stored, which
+// the insert reuses instead of allocating another
+trait IntoKey<K>: Borrow<K> + Into<Arc<K>> {}
+
+impl<K, Q: Borrow<K> + Into<Arc<K>>> IntoKey<K> for Q {}
+
+// DebugEntries formats a cache's entries with their expiry state, hiding values when redacted
+struct DebugEntries<'a, K, V> {
+    entries: &'a Slab<(Arc<K>, Value<V>)>,
+    redact: bool,
+    // now is the cache clock's time, which remaining TTLs are counted from
+    now: Instant,
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for DebugEntries<'_, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut entries = f.debug_map();
+        for (_, (key, v)) in self.entries.iter() {
+            entries.entry(key, &DebugValue{ value: v, redact: self.redact, now: self.now });
+        }
+        entries.finish()
+    }
+}
+
+struct DebugValue<'a, V> {
+    value: &'a Value<V>,
+    redact: bool,
+    now: Instant,
+}
+
+impl<V: fmt::Debug> fmt::Debug for DebugValue<'_, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut entry = f.debug_struct("Entry");
+        if self.redact {
+            entry.field("value", &format_args!("<redacted>"));
+        } else {
+            // SAFETY: a cache is only formatted under a ThreadSafeHashCache's read, which holds
+            // every stripe, or by its owner
+            entry.field("value", unsafe { self.value.value.get() });
         }
 
-        return expired_indices.iter().map(|i| self.expiring.remove(*i)).count();
+        // counted down from the TTL rather than to a deadline, which may be past what an Instant
+        // can hold
+        let meta = self.value.meta(self.now);
+        let left = |from: Instant, ttl: Duration| ttl.saturating_sub(self.now.saturating_duration_since(from));
+        let ttl = meta.inserted.zip(meta.ttl).map(|(inserted, ttl)| left(inserted, ttl));
+        let idle = meta.idle.map(|idle| left(meta.last_access.unwrap_or(meta.created), idle));
+        match ttl.into_iter().chain(idle).min() {
+            Some(expires_in) => entry.field("expires_in", &expires_in),
+            None => entry.field("expires_in", &format_args!("never")),
+        };
+        entry.finish()
     }
+}
 
+// HashCache is a hashmap-backed cache implementation
+// cloning a HashCache copies its entries with their original deadlines, not fresh TTLs
+#[derive(Clone)]
+pub struct HashCache<K: Hash+Eq, V, S = DefaultHashBuilder> {
+    // store maps each key to the slab index of its entry; entries share their key with the store
+    // so an index (from the expiring index or eviction) can be turned back into a map removal.
+    // Each key is stored once, behind an Arc, so K needn't be Clone.
+    store: HashMap<StoreKey<K>,usize,S>,
+    entries: Slab<(Arc<K>, Value<V>)>,
+    // pinned counts the pinned entries, so has_room can tell a cache full of them without a scan
+    pinned: usize,
+    // expiring holds the slab indices of the entries that have a TTL
+    expiring: Vec<usize>,
+    config: Config<K, V>,
+    // ticks is a logical clock ticked on every access, used to order entries for eviction
+    ticks: Counter,
+    tracker: Tracker,
+    // filter answers definite misses without probing the store, if configured
+    filter: Option<SharedFilter>,
+    // hot estimates the most read keys, if configured
+    hot: Option<HotKeys<K>>,
+    // auto is what auto_vacuum has learned from earlier runs
+    auto: AutoVacuum,
+    rng: CacheRng,
+    // sweep is where the next vacuum pass starts in the expiring index
+    #[cfg(not(feature = "rand"))]
+    sweep: usize,
+    // deadlines orders entries' TTL deadlines (with their slab index) in exact_expiry mode. It
+    // isn't updated when an entry is removed or its deadline changes; the outdated records are
+    // dropped as they come up.
+    deadlines: Option<BinaryHeap<Reverse<(Instant, usize)>>>,
+    listeners: Listeners<K, V>,
+    stats: Recorder,
 }
 
-impl<K: Hash+Eq+Clone, V>  Cache<K,V> for HashCache<K, V>  {
-    fn insert(&mut self, key: K, value: V) -> Option<V> {
-        let inserted = self.store.insert(key, Value{value: value, expires: ExpireMeta::Persistent})?;
-        Some(inserted.value)
+impl<K: Hash+Eq, V>  HashCache<K, V> {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> HashCache<K, V> {
+        HashCache::with_hasher(DefaultHashBuilder::default())
     }
 
-    fn insert_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
-        self.expiring.push(key.clone());
-        let inserted = self.store.insert(key, Value{value: value, expires:ExpireMeta::Expires(Expiration{inserted: Instant::now(), ttl})})?;
-        Some(inserted.value)
+    // with_capacity pre-allocates room for n entries, avoiding rehashing while warming a cache
+    pub fn with_capacity(n: usize) -> HashCache<K, V> {
+        HashCache::with_capacity_and_hasher(n, DefaultHashBuilder::default())
     }
+}
 
-    fn get<F>(&self, key: K, f: F) -> bool where F: Fn(&V) {
-        if self.expired(&key) {
-            return false
-        }
+impl<K: Hash+Eq, V, S: BuildHasher>  HashCache<K, V, S> {
+    // with_hasher hashes keys with the given builder, e.g. a faster hasher for trusted keys or a
+    // keyed one for keys that come from untrusted input
+    pub fn with_hasher(hash_builder: S) -> HashCache<K, V, S> {
+        HashCache::from_config(Config::default(), hash_builder)
+    }
 
-        // entry isn't expired, so fetch and unwrap it
-        if let Some(v) = self.store.get(&key) {
-            f(&v.value);
-            return true
+    pub fn with_capacity_and_hasher(n: usize, hash_builder: S) -> HashCache<K, V, S> {
+        HashCache::from_config(Config{ initial_capacity: n, ..Config::default() }, hash_builder)
+    }
+
+    pub(crate) fn from_config(config: Config<K, V>, hash_builder: S) -> HashCache<K, V, S> {
+        HashCache{
+            store: HashMap::with_capacity_and_hasher(config.initial_capacity, hash_builder),
+            entries: Slab::with_capacity(config.initial_capacity),
+            pinned: 0,
+            expiring: Vec::with_capacity(config.initial_capacity),
+            filter: config.negative_filter.map(|(n, p)| SharedFilter(Arc::new(NegativeFilter::new(n, p)))),
+            hot: config.hot_keys.map(|(n, copy)| HotKeys(Mutex::new(SpaceSaving::with_copy(n, copy)))),
+            auto: AutoVacuum::default(),
+            rng: CacheRng::new(config.rng_seed),
+            #[cfg(not(feature = "rand"))]
+            sweep: 0,
+            deadlines: if config.exact_expiry { Some(BinaryHeap::new()) } else { None },
+            tracker: Tracker::new(config.eviction, config.capacity()),
+            config,
+            ticks: Counter::default(),
+            listeners: Listeners::new(),
+            stats: Recorder::default(),
         }
-        false
     }
 
-    // vacuum samples the set of potentially expired keys and removes them if expired
-    // panics if retry-threshold is not between 0 and 1.
-    fn vacuum(&mut self, count : usize, retry_threshold : f32 ) {
+    // take_as is take on behalf of a caller-supplied audit context
+    fn take_as(&mut self, key: &K, context: Option<&str>) -> Option<V> {
+        let taken = self.take_entry(key);
+        self.audit(AuditOp::Remove, key, if taken.is_some() { AuditOutcome::Removed } else { AuditOutcome::Absent }, context);
+        taken
+    }
 
-        // if the ratio of expired keys to sample size > retry threshold,
-        // we perform an additional vacuum before exiting
-        assert!(retry_threshold > 0.0);
-        assert!(retry_threshold < 1.0);
+    fn take_entry(&mut self, key: &K) -> Option<V> {
+        let expired = self.expired(key);
+        let value = self.remove_entry(key)?.value.into_inner();
 
-        // initialize to amount so that we always iterate at least once
-        let mut expired_count = count as f32;
+        // an expired entry is dropped like vacuum would, but isn't handed out
+        if expired {
+            self.stats.expirations.incr();
+            self.listeners.emit(CacheEvent::Expired{ key, value: &value });
+            return None
+        }
+        self.listeners.emit(CacheEvent::Removed{ key, value: &value });
+        Some(value)
+    }
 
-        while expired_count/(count as f32) > retry_threshold {
-            expired_count = self.vacuum_sample(count) as f32;
+    // definitely_absent consults the negative filter, if there is one
+    fn definitely_absent(&self, key: &K) -> bool {
+        match &self.filter {
+            Some(filter) => !filter.0.may_contain(key),
+            None => false,
         }
     }
-}
 
-pub struct ThreadSafeHashCache<K: Hash+Eq+Clone, V> {
-    store: RwLock<HashMap<K,Value<V>>>,
-    expiring: RwLock<Vec<K>>,
-}
+    // forget must be called whenever a key is removed from the store, to keep the filter in sync
+    fn forget(&self, key: &K) {
+        if let Some(filter) = &self.filter {
+            filter.0.remove(key)
+        }
+    }
 
-impl<K: Hash+Eq+Clone, V>  ThreadSafeHashCache<K, V> {
-    pub fn new() -> ThreadSafeHashCache<K,V> {
-        ThreadSafeHashCache{ store: RwLock::new(HashMap::new()), expiring: RwLock::new(Vec::new())}
+    // now is the current time according to the cache's clock
+    fn now(&self) -> Instant {
+        self.config.clock.now()
     }
 
-    fn expired(&self, key: &K) -> bool {
-        let c = self.store.read().expect("lock poisoned");
+    fn lookup(&self, key: &K) -> Option<&Value<V>> {
+        self.store.get(key).map(|&i| &self.entries[i].1)
+    }
 
-        match c.get(key) {
-            Some(v) => {
-                match &v.expires {
-                    ExpireMeta::Expires(e) => {
-                        e.inserted.elapsed().gt(&e.ttl)
-                    }
-                    _ => { false }
-                }
-            },
+    // lookup_mut hands out an entry that may be changed in place, so it counts as a write to it
+    fn lookup_mut(&mut self, key: &K) -> Option<&mut Value<V>> {
+        let i = *self.store.get(key)?;
+        let v = &mut self.entries[i].1;
+        v.version.set(self.ticks.incr());
+        Some(v)
+    }
+
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+
+    // stats reports the cache's hit, miss, insert, eviction and expiration counts so far
+    pub fn stats(&self) -> Stats {
+        self.stats.stats()
+    }
+
+    // capacity is the number of entries the cache can hold before it has to reallocate
+    pub fn capacity(&self) -> usize {
+        self.store.capacity().min(self.entries.capacity())
+    }
+
+    // expiring_len is the length of the expiring index: the entries that have a TTL or idle
+    // timeout, expired or not
+    pub fn expiring_len(&self) -> usize {
+        self.expiring.len()
+    }
+
+    fn expired(&self, key: &K) -> bool {
+        match self.lookup(key) {
+            Some(v) => { v.expired(self.now()) },
             // report empty entries as expired
             None => { true },
         }
     }
 
-    // called by vacuum, this just handles sampling and removing a single set (not retrying based
-    // on a threshold)
-    fn vacuum_sample(&mut self, count : usize) -> usize {
-        // amount is the max number of items we sample from the current set
-        let mut amount = count;
-        let expire_len;
-        {
-            let e = self.expiring.read().expect("lock poisoned");
-            expire_len = e.len()
+    // hit is the read path shared by the get variants: it returns a live value and records the
+    // access, or None on a miss
+    fn hit(&self, key: &K) -> Option<&V> {
+        self.hit_as(key, None)
+    }
+
+    // hit_as is hit on behalf of a caller-supplied audit context
+    fn hit_as(&self, key: &K, context: Option<&str>) -> Option<&V> {
+        let v = self.probe(key);
+        self.audit(AuditOp::Get, key, if v.is_some() { AuditOutcome::Hit } else { AuditOutcome::Miss }, context);
+        v
+    }
+
+    // audit reports an operation to the audit hook, if there is one
+    fn audit(&self, op: AuditOp, key: &K, outcome: AuditOutcome, context: Option<&str>) {
+        if let Some(audit) = &self.config.audit {
+            audit(&AuditEvent{ op, key, outcome, context })
+        }
+    }
+
+    fn probe(&self, key: &K) -> Option<&V> {
+        if let Some(hot) = &self.hot {
+            hot.0.lock().expect("lock poisoned").record(key);
+        }
+        if self.definitely_absent(key) {
+            event!(TRACE, hit = false, "get");
+            self.stats.read(false);
+            return None
         }
 
-        if count > expire_len {
-            amount = expire_len
+        let now = self.now();
+        if let Some(v) = self.lookup(key).filter(|v| !v.expired(now)) {
+            if let Some(beta) = self.config.early_expiration {
+                if v.expires_early(beta, now, &self.rng) {
+                    event!(TRACE, hit = false, early = true, "get");
+                    self.stats.read(false);
+                    return None
+                }
+            }
+            v.access.read(now.saturating_duration_since(v.created));
+            if self.config.eviction.tracks_access() {
+                v.access.touch(self.ticks.incr());
+            }
+            event!(TRACE, hit = true, "get");
+            self.stats.read(true);
+            // SAFETY: see ValueCell::get; a ThreadSafeHashCache reads a key through here under
+            // its stripe
+            return Some(unsafe { v.value.get() })
         }
+        event!(TRACE, hit = false, "get");
+        self.stats.read(false);
+        None
+    }
+
+    // get_copied returns a copy of a live value, for small Copy values (counters, timestamps)
+    // where a closure is more ceremony than the value itself
+    pub fn get_copied(&self, key: &K) -> Option<V> where V: Copy {
+        self.hit(key).copied()
+    }
 
+    // metadata returns a live entry's expiration and access data, without counting as a read
+    pub fn metadata(&self, key: &K) -> Option<EntryMeta> {
+        let now = self.now();
+        self.lookup(key).filter(|v| !v.expired(now)).map(|v| v.meta(now))
+    }
 
-        // sample a random set of indices that have expiration set
-        let samples = rand::seq::index::sample(&mut rand::thread_rng(), expire_len, amount);
+    // hot_keys returns up to n of the most read keys, hits and misses alike, with their estimated
+    // read counts (which may overcount, never undercount), most read first. It's empty unless
+    // the cache was built with track_hot_keys. A ThreadSafeHashCache's negative filter answers
+    // some misses without reaching the sketch.
+    pub fn hot_keys(&self, n: usize) -> Vec<(K, u64)> {
+        self.hot.as_ref().map_or_else(Vec::new, |hot| hot.0.lock().expect("lock poisoned").top(n))
+    }
 
-        let mut expired_indices = vec![];
+    // cold_keys reports the live keys that haven't been read for at least unused_for (counting
+    // from when they were stored if they never were), coldest first
+    pub fn cold_keys(&self, unused_for: Duration) -> Vec<K> where K: Clone {
+        let now = self.now();
+        let mut cold: Vec<(Instant, &K)> = self.entries.iter()
+            .filter(|(_, (_, v))| !v.expired(now) && now.saturating_duration_since(v.last_access()) >= unused_for)
+            .map(|(_, (k, v))| (v.last_access(), &**k))
+            .collect();
+        cold.sort_by_key(|&(at, _)| at);
+        cold.into_iter().map(|(_, k)| K::clone(k)).collect()
+    }
 
-        {
-            let expiring = self.expiring.read().expect("lock poisoned");
-            // if the key referenced by the index is expired, remove it from the cache (and self.expiring)
-            for index in samples.iter() {
-                if let Some(key) = expiring.get(index) {
-                    if self.expired(&key) {
-                        let mut store = self.store.write().expect("lock poisoned");
-                        store.remove(key);
-                        expired_indices.push(index);
-                    }
+    // next_expiration returns the earliest deadline among the entries, so a caller-driven reaper
+    // can sleep until exactly then; it may have passed already, if the entry is yet to be
+    // vacuumed. In exact_expiry mode it's read off the deadlines heap (dropping outdated records
+    // on the way), otherwise it scans the expiring index.
+    pub fn next_expiration(&mut self) -> Option<Instant> {
+        let entries = &self.entries;
+        if let (Some(deadlines), None) = (&mut self.deadlines, self.config.expire_after_access) {
+            while let Some(&Reverse((at, index))) = deadlines.peek() {
+                if entries.get(index).is_some_and(|(_, v)| v.deadline() == Some(at)) {
+                    return Some(at)
                 }
+                deadlines.pop();
             }
+            return None
         }
+        self.expiring.iter().filter_map(|&i| entries[i].1.expires_at()).min()
+    }
 
-        let mut expiring = self.expiring.write().expect("lock poisoned");
-        return expired_indices.iter().map(|i| expiring.remove(*i)).count();
+    // get_stale returns a value whether or not it has expired, with how long ago it expired (zero
+    // if it's live). Expired entries can only be read this way until they're vacuumed or replaced.
+    pub fn get_stale(&self, key: &K) -> Option<(&V, Duration)> {
+        let now = self.now();
+        let v = self.lookup(key)?;
+        let stale = v.expires_at().map_or(Duration::ZERO, |at| now.saturating_duration_since(at));
+        // SAFETY: see ValueCell::get; a ThreadSafeHashCache reads a key through here under its
+        // stripe
+        Some((unsafe { v.value.get() }, stale))
     }
-}
 
-impl<K: Hash+Eq+Clone, V>  Cache<K,V> for ThreadSafeHashCache<K, V>  {
-    fn insert(&mut self, key: K, value: V) -> Option<V> {
-        let mut store = self.store.write().expect("lock poisoned");
-        let inserted = store.insert(key, Value{value: value, expires: ExpireMeta::Persistent})?;
-        Some(inserted.value)
+    // iter_expired yields the entries that have expired but are still stored, with how long ago
+    // each expired: the garbage vacuum hasn't got round to yet. It visits every entry, so it's
+    // meant for diagnostics rather than the hot path.
+    pub fn iter_expired(&self) -> impl Iterator<Item=(&K, &V, Duration)> {
+        let now = self.now();
+        self.entries.iter()
+            .filter(move |(_, (_, v))| v.expired(now))
+            .map(move |(_, (k, v))| {
+                let stale = v.expires_at().map_or(Duration::ZERO, |at| now.saturating_duration_since(at));
+                // SAFETY: see ValueCell::get; a ThreadSafeHashCache iterates under read, which
+                // holds every stripe
+                (&**k, unsafe { v.value.get() }, stale)
+            })
     }
 
-    fn insert_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
-        {
-            let mut expiring = self.expiring.write().expect("lock poisoned");
-            expiring.push(key.clone());
+    // drain_expired removes every expired entry and yields it, so callers can process values
+    // that vacuum would otherwise silently discard
+    pub fn drain_expired(&mut self) -> impl Iterator<Item=(K, V)> where K: Clone {
+        // keys are only shared with a clone of the cache, so this rarely has to copy one
+        self.remove_expired().into_iter().map(|(key, value)| (Arc::unwrap_or_clone(key), value))
+    }
+
+    // remove_expired is drain_expired, handing back the keys as stored
+    fn remove_expired(&mut self) -> Vec<(Arc<K>, V)> {
+        let now = self.now();
+        let expired: Vec<usize> = self.expiring.iter().copied().filter(|&i| self.entries[i].1.expired(now)).collect();
+        let mut drained = Vec::with_capacity(expired.len());
+        for index in expired {
+            let (key, mut v) = self.remove_at(index);
+            self.stats.expirations.incr();
+            self.listeners.emit(CacheEvent::Expired{ key: &key, value: v.value.get_mut() });
+            drained.push((key, v.value.into_inner()));
         }
-        let mut store = self.store.write().expect("lock poisoned");
-        let inserted = store.insert(key, Value { value: value, expires: ExpireMeta::Expires(Expiration { inserted: Instant::now(), ttl }) })?;
-        Some(inserted.value)
+        self.maybe_shrink();
+        drained
     }
 
-    fn get<F>(&self, key: K, f: F) -> bool where F: Fn(&V) {
-        if self.expired(&key) {
-            return false
+    // retain keeps only the entries for which the predicate returns true, expired or not
+    pub fn retain<F>(&mut self, mut f: F) where F: FnMut(&K, &V, &EntryMeta) -> bool {
+        let now = self.now();
+        let removed: Vec<usize> = self.entries.iter()
+            // SAFETY: &mut self excludes any update
+            .filter(|(_, (k, v))| !f(k, unsafe { v.value.get() }, &v.meta(now)))
+            .map(|(i, _)| i)
+            .collect();
+        for index in removed {
+            let (key, mut v) = self.entries.remove(index).expect("retained index is occupied");
+            self.pinned -= v.pinned as usize;
+            self.tracker.removed(index);
+            self.store.remove(&*key);
+            self.forget(&key);
+            self.listeners.emit(CacheEvent::Removed{ key: &key, value: v.value.get_mut() });
         }
 
-        // entry isn't expired, so fetch and unwrap it
-        if let Some(v) = self.store.read().expect("lock poisoned").get(&key) {
-            f(&v.value);
-            return true
+        // this already visited every entry, so rebuild the expiring index rather than fixing it up
+        let entries = &mut self.entries;
+        self.expiring.retain(|&i| entries.get(i).is_some());
+        for (slot, &i) in self.expiring.iter().enumerate() {
+            entries[i].1.slot = Some(slot);
         }
-        false
+        self.maybe_shrink();
     }
 
-    // vacuum samples the set of potentially expired keys and removes them if expired
-    // panics if retry-threshold is not between 0 and 1.
-    fn vacuum(&mut self, count : usize, retry_threshold : f32 ) {
-        // if the ratio of expired keys to sample size > retry threshold,
-        // we perform an additional vacuum before exiting
-        assert!(retry_threshold > 0.0);
-        assert!(retry_threshold < 1.0);
+    // shrink_to_fit releases memory held by the store and expiring index beyond what the current
+    // entries need, e.g. after a large batch of entries expired
+    pub fn shrink_to_fit(&mut self) {
+        self.store.shrink_to_fit();
+        self.entries.shrink_to(0);
+        self.expiring.shrink_to_fit();
+    }
 
-        // initialize to amount so that we always iterate at least once
-        let mut expired_count = count as f32;
+    // maybe_shrink applies the configured shrink policy: once occupancy falls below the threshold,
+    // capacity is released down to the entry count (but not below the initial capacity)
+    fn maybe_shrink(&mut self) {
+        let threshold = match self.config.shrink_threshold {
+            Some(t) => t,
+            None => return,
+        };
+
+        let capacity = self.store.capacity();
+        if capacity <= self.config.initial_capacity || capacity == 0 {
+            return
+        }
+        if (self.store.len() as f32) / (capacity as f32) < threshold {
+            let min = self.config.initial_capacity;
+            self.store.shrink_to(min.max(self.store.len()));
+            self.entries.shrink_to(min);
+            self.expiring.shrink_to(min.max(self.expiring.len()));
+        }
+    }
 
-        while expired_count/(count as f32) > retry_threshold {
-            expired_count = self.vacuum_sample(count) as f32;
+    // insert_ttl_with_cost is insert_ttl for a value that took `recompute` to produce; with the
+    // early_expiration builder option, get may then report the entry missing shortly before it
+    // expires so that one caller refreshes it instead of every caller missing at once
+    pub fn insert_ttl_with_cost(&mut self, key: K, value: V, ttl: Duration, recompute: Duration) -> Option<V> {
+        let now = self.now();
+        let mut expires = ExpireMeta::after(ttl, now);
+        if let ExpireMeta::Expires(e) = &mut expires {
+            e.recompute = recompute;
         }
+        let entry = Value::new(value, expires, self.ticks.incr(), now);
+        self.put(key, entry).previous
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::{HashCache, Cache, ThreadSafeHashCache};
-    use std::time::Duration;
-    use std::thread::{sleep, spawn};
-    use std::sync::{Arc, RwLock};
+    // insert_with_outcome is insert, reporting whether the value it replaced had expired
+    pub fn insert_with_outcome(&mut self, key: K, value: V) -> InsertOutcome<V> {
+        self.insert_as(key, value, None, None)
+    }
 
-    #[test]
-    fn store_retrieve() {
-        let mut cache : HashCache<&str,&str> = HashCache::new();
-        cache.insert("id", "secret");
-        assert_eq!(true,
-                   cache.get("id", |v| assert_eq!(*v, "secret")));
-        assert_eq!(false,
-                   cache.get("nope", |_| panic!("expected none")));
+    pub fn insert_ttl_with_outcome(&mut self, key: K, value: V, ttl: Duration) -> InsertOutcome<V> {
+        self.insert_as(key, value, Some(ttl), None)
     }
 
-    #[test]
-    fn expire_key() {
-        let mut cache : HashCache<&str,&str> = HashCache::new();
-        cache.insert_ttl("id", "secret", Duration::new(1, 0));
-        assert_eq!(cache.expiring.len(), 1);
+    // insert_as stores an entry with the given TTL, or the expire_after policy's if None, on
+    // behalf of a caller-supplied audit context
+    fn insert_as(&mut self, key: impl IntoKey<K>, value: V, ttl: Option<Duration>, context: Option<&str>) -> InsertOutcome<V> {
+        let now = self.now();
+        let expires = match (ttl, &self.config.expire_after) {
+            (Some(ttl), _) => ExpireMeta::after(ttl, now),
+            (None, Some(policy)) => policy(key.borrow(), &value).map_or(ExpireMeta::Persistent, |ttl| ExpireMeta::after(ttl, now)),
+            (None, None) => ExpireMeta::Persistent,
+        };
+        let entry = Value::new(value, expires, self.ticks.incr(), now);
+        self.put_as(key, entry, context)
+    }
 
-        // initial get should work
-        assert_eq!(true,
-                   cache.get("id", |v| assert_eq!(*v, "secret")));
+    // insert_pinned stores an entry that capacity eviction will never remove
+    pub fn insert_pinned(&mut self, key: K, value: V) -> Option<V> {
+        let mut entry = Value::new(value, ExpireMeta::Persistent, self.ticks.incr(), self.now());
+        entry.pinned = true;
+        self.put(key, entry).previous
+    }
 
-        sleep(Duration::new(1, 0));
+    // insert_pinned_ttl stores an entry that is never evicted for capacity, but still expires
+    pub fn insert_pinned_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        let now = self.now();
+        let mut entry = Value::new(value, ExpireMeta::after(ttl, now), self.ticks.incr(), now);
+        entry.pinned = true;
+        self.put(key, entry).previous
+    }
 
-        // fetch after ttl should be none
-        assert_eq!(false, cache.get("id", |_| panic!("expected none")));
+    // pin exempts an existing entry from capacity eviction; returns false if the key is absent
+    pub fn pin(&mut self, key: &K) -> bool {
+        self.set_pinned(key, true)
+    }
 
-        // even though the cache reports the key is gone, it's still tracked in the expiring list
-        // until a vacuum is performed
-        assert_eq!(cache.expiring.len(), 1);
-        assert_eq!(cache.store.len(), 1);
+    // unpin makes a pinned entry eligible for capacity eviction again
+    pub fn unpin(&mut self, key: &K) -> bool {
+        self.set_pinned(key, false)
     }
 
-    #[test]
-    fn vacuum() {
-        let mut cache : HashCache<&str,&str> = HashCache::new();
-        cache.insert_ttl("id", "secret", Duration::new(1, 0));
-        assert_eq!(cache.expiring.len(), 1);
+    // touch restarts a live entry's TTL from now, or makes it persistent if ttl is None; returns
+    // false if the key is absent or already expired
+    pub fn touch(&mut self, key: &K, ttl: Option<Duration>) -> bool {
+        let now = self.now();
+        let index = match self.store.get(key) {
+            Some(&index) if !self.entries[index].1.expired(now) => index,
+            _ => return false,
+        };
+        let expires = ttl.map_or(ExpireMeta::Persistent, |ttl| ExpireMeta::after(self.config.clamp_ttl(ttl), now));
+        let v = &mut self.entries[index].1;
+        v.expires = expires;
+        match (v.slot, v.is_expiring()) {
+            (Some(slot), false) => {
+                v.slot = None;
+                self.unindex(slot);
+            },
+            (None, true) => {
+                v.slot = Some(self.expiring.len());
+                self.expiring.push(index);
+            },
+            _ => {},
+        }
+        self.schedule(index);
+        true
+    }
 
-        // initial get should work
-        assert_eq!(true,
-                   cache.get("id", |v| assert_eq!(*v, "secret")));
-        cache.vacuum(10, 0.25);
+    // expire_all_after caps every live entry's remaining TTL at within, for incident response:
+    // whatever was cached before a bad deploy is gone within that time at the latest. Entries
+    // without a TTL are capped too if include_persistent is set. min_ttl doesn't apply. Returns
+    // how many entries got an earlier deadline.
+    pub fn expire_all_after(&mut self, within: Duration, include_persistent: bool) -> usize {
+        let now = self.now();
+        let cap = now + within;
+        let capped: Vec<usize> = self.entries.iter()
+            .filter(|(_, (_, v))| !v.expired(now))
+            .filter(|(_, (_, v))| v.deadline().map_or(include_persistent, |at| at > cap))
+            .map(|(i, _)| i)
+            .collect();
+        for &index in &capped {
+            let v = &mut self.entries[index].1;
+            v.expires = ExpireMeta::after(within, now);
+            if v.slot.is_none() {
+                v.slot = Some(self.expiring.len());
+                self.expiring.push(index);
+            }
+            self.schedule(index);
+        }
+        event!(INFO, capped = capped.len(), within_ms = within.as_millis() as u64, "expire_all_after");
+        capped.len()
+    }
 
-        sleep(Duration::new(1, 0));
+    fn set_pinned(&mut self, key: &K, pinned: bool) -> bool {
+        match self.lookup_mut(key) {
+            Some(v) => {
+                let was = std::mem::replace(&mut v.pinned, pinned);
+                self.pinned = self.pinned + pinned as usize - was as usize;
+                true
+            },
+            None => false,
+        }
+    }
 
-        cache.vacuum(10, 0.25);
+    // put stores an entry, first evicting another one if a new key would exceed max_capacity
+    // overwriting a pinned entry keeps it pinned
+    fn put(&mut self, key: impl IntoKey<K>, entry: Value<V>) -> InsertOutcome<V> {
+        self.put_as(key, entry, None)
+    }
 
-        // check that it's been removed from the hashmap entirely
-        // this skips the active removal, so it verifies vacuuming
-        if let Some(_) = cache.store.get("id") {
-            panic!("expected store to no longer have key")
+    // put_as is put on behalf of a caller-supplied audit context; the hook is shown the key as
+    // stored
+    fn put_as(&mut self, key: impl IntoKey<K>, entry: Value<V>, context: Option<&str>) -> InsertOutcome<V> {
+        let (index, outcome) = self.store_entry(key, entry);
+        if self.config.audit.is_some() {
+            let result = if outcome.replaced() { AuditOutcome::Replaced } else { AuditOutcome::Inserted };
+            self.audit(AuditOp::Insert, &self.entries[index].0, result, context);
         }
-
-        // check that key is no longer being tracked as an expiration candiate
-        assert_eq!(cache.expiring.len(), 0)
+        outcome
     }
 
-    #[test]
-    fn vacuum_sampling_retry() {
-        let mut cache : HashCache<&str,&str> = HashCache::new();
-        cache.insert_ttl("id", "secret", Duration::new(1, 0));
-        cache.insert_ttl("id2", "secret2", Duration::new(1, 0));
-        assert_eq!(cache.expiring.len(), 2);
+    // store_entry returns the entry's slab index along with what it replaced
+    fn store_entry(&mut self, key: impl IntoKey<K>, mut entry: Value<V>) -> (usize, InsertOutcome<V>) {
+        self.stats.inserts.incr();
+        entry.idle = self.config.expire_after_access;
+        if let ExpireMeta::Expires(e) = &mut entry.expires {
+            e.ttl = self.config.clamp_ttl(e.ttl);
+        }
+        if entry.is_expiring() && self.lookup(key.borrow()).is_none_or(|v| v.slot.is_none()) {
+            self.make_room_expiring();
+        }
 
-        // wait for keys to expire
-        sleep(Duration::new(1, 0));
+        if let Some(&index) = self.store.get(key.borrow()) {
+            let existing = &self.entries[index].1;
+            entry.pinned |= existing.pinned;
+            let keep = self.config.overwrite == OverwritePolicy::KeepDeadline;
+            if keep && existing.deadline().is_some() && !existing.expired(self.now()) {
+                entry.expires = existing.expires.clone();
+            }
+            let expiring = entry.is_expiring();
+            event!(TRACE, replaced = true, expiring, "insert");
+            let ttl = entry.ttl();
+            self.listeners.emit_ttl(CacheEvent::Replaced{ key: key.borrow(), value: entry.value.get_mut() }, ttl);
+
+            // a key is in the expiring index at most once: an overwrite reuses the existing slot
+            entry.slot = match (existing.slot, expiring) {
+                (Some(slot), true) => Some(slot),
+                (Some(slot), false) => {
+                    self.unindex(slot);
+                    None
+                },
+                (None, true) => {
+                    self.expiring.push(index);
+                    Some(self.expiring.len() - 1)
+                },
+                (None, false) => None,
+            };
+            let previous = std::mem::replace(&mut self.entries[index].1, entry);
+            self.pinned += (self.entries[index].1.pinned && !previous.pinned) as usize;
+            self.schedule(index);
+            let previous_expired = previous.expired(self.now());
+            return (index, InsertOutcome{ previous: Some(previous.value.into_inner()), previous_expired })
+        }
 
-        // count is 1, but there are two entries, so the retry threshold should be hit (0.5>0.25)
-        // and should clean up both entries
-        cache.vacuum(1, 0.25);
+        self.evict_for_insert();
+        if let Some(filter) = &self.filter {
+            filter.0.add(key.borrow());
+        }
+        let expiring = entry.is_expiring();
+        event!(TRACE, replaced = false, expiring, "insert");
+        let ttl = entry.ttl();
+        self.listeners.emit_ttl(CacheEvent::Inserted{ key: key.borrow(), value: entry.value.get_mut() }, ttl);
 
-        // check that it's been removed from the hashmap entirely
-        // this skips the active removal, so it verifies vacuuming
-        if let Some(_) = cache.store.get("id") {
+        if expiring {
+            entry.slot = Some(self.expiring.len());
+        }
+        self.pinned += entry.pinned as usize;
+        let key: Arc<K> = key.into();
+        let index = self.entries.insert((key.clone(), entry));
+        let store = &self.store;
+        self.tracker.inserted(index, || store.hasher().hash_one(&key));
+        self.store.insert(StoreKey(key), index);
+        if expiring {
+            self.expiring.push(index);
+        }
+        self.schedule(index);
+        (index, InsertOutcome{ previous: None, previous_expired: false })
+    }
+
+    // make_room_expiring keeps the expiring index under max_expiring before an entry is added to
+    // it: first by removing the expired entries, failing that by evicting the one expiring next
+    fn make_room_expiring(&mut self) {
+        let max = match self.config.max_expiring {
+            Some(max) if self.expiring.len() >= max => max,
+            _ => return,
+        };
+        let now = self.now();
+        let expired: Vec<usize> = self.expiring.iter().copied().filter(|&i| self.entries[i].1.expired(now)).collect();
+        for index in expired {
+            let (key, mut v) = self.remove_at(index);
+            self.stats.expirations.incr();
+            self.listeners.emit(CacheEvent::Expired{ key: &key, value: v.value.get_mut() });
+        }
+        while self.expiring.len() >= max {
+            let entries = &self.entries;
+            let next = self.expiring.iter().copied().min_by_key(|&i| entries[i].1.expires_at());
+            let (key, mut v) = self.remove_at(next.expect("index is full"));
+            self.stats.evictions.incr();
+            self.listeners.emit(CacheEvent::Evicted{ key: &key, value: v.value.get_mut() });
+        }
+        event!(DEBUG, expiring = self.expiring.len(), "expiring index compacted");
+    }
+
+    // schedule records the deadline of the entry at index in the deadlines heap, if there is one
+    fn schedule(&mut self, index: usize) {
+        if let Some(deadlines) = &mut self.deadlines {
+            if let Some(at) = self.entries[index].1.deadline() {
+                deadlines.push(Reverse((at, index)));
+            }
+        }
+    }
+
+    // vacuum_deadlines removes the entries whose deadlines have passed, in deadline order, at a
+    // cost proportional to the expirations (plus the outdated records it drops)
+    fn vacuum_deadlines(&mut self) -> usize {
+        let now = self.now();
+        let mut removed = 0;
+        while let Some(&Reverse((at, index))) = self.deadlines.as_ref().and_then(BinaryHeap::peek) {
+            if at >= now {
+                break
+            }
+            self.deadlines.as_mut().expect("peeked above").pop();
+            // the record is outdated if its entry was removed or given another deadline
+            if self.entries.get(index).is_some_and(|(_, v)| v.deadline() == Some(at)) {
+                let (key, mut v) = self.remove_at(index);
+                self.stats.expirations.incr();
+                self.listeners.emit(CacheEvent::Expired{ key: &key, value: v.value.get_mut() });
+                removed += 1;
+            }
+        }
+
+        // rebuild the heap once outdated records outnumber live ones
+        let (entries, expiring) = (&self.entries, &self.expiring);
+        if let Some(deadlines) = &mut self.deadlines {
+            if deadlines.len() > 2 * expiring.len() + 64 {
+                *deadlines = expiring.iter()
+                    .filter_map(|&i| Some(Reverse((entries[i].1.deadline()?, i))))
+                    .collect();
+            }
+        }
+        event!(DEBUG, removed, "deadline vacuum");
+        removed
+    }
+
+    fn evict_for_insert(&mut self) {
+        let max = match self.config.max_capacity {
+            Some(max) => max,
+            None => return,
+        };
+        while self.store.len() >= max {
+            // if everything left is pinned, the cache is allowed to grow past max_capacity
+            if !self.evict_one() {
+                return
+            }
+        }
+    }
+
+    // has_room reports whether key can be stored without growing past max_capacity: the key is
+    // already there, there's space, or some entry can be evicted for it. Only a cache full of
+    // pinned entries is cleared of its expired ones to make space, so the common case is O(1).
+    pub(crate) fn has_room(&mut self, key: &K) -> bool {
+        let max = match self.config.max_capacity {
+            Some(max) => max,
+            None => return true,
+        };
+        if self.store.len() < max || self.store.contains_key(key) || self.pinned < self.store.len() {
+            return true
+        }
+        self.remove_expired();
+        self.store.len() < max
+    }
+
+    // evict_one evicts the entry chosen by the eviction policy, returning false if every entry
+    // is pinned
+    fn evict_one(&mut self) -> bool {
+        match self.tracker.victim(&self.entries, self.config.eviction, self.now(), &self.rng) {
+            Some(index) => {
+                let (key, mut v) = self.remove_at(index);
+                self.stats.evictions.incr();
+                self.listeners.emit(CacheEvent::Evicted{ key: &key, value: v.value.get_mut() });
+                true
+            },
+            None => false,
+        }
+    }
+
+    // trim evicts up to budget entries while the cache is above its soft_capacity, and returns
+    // how many it evicted; vacuum calls it with its sample count
+    pub fn trim(&mut self, budget: usize) -> usize {
+        let soft = match self.config.soft_capacity {
+            Some(soft) => soft,
+            None => return 0,
+        };
+        let mut evicted = 0;
+        while evicted < budget && self.store.len() > soft && self.evict_one() {
+            evicted += 1;
+        }
+        evicted
+    }
+
+    // remove_entry removes an entry from the store and the expiring index
+    fn remove_entry(&mut self, key: &K) -> Option<Value<V>> {
+        let index = *self.store.get(key)?;
+        Some(self.remove_at(index).1)
+    }
+
+    // remove_at removes the entry in an occupied slab slot, along with its key and expiring slot
+    fn remove_at(&mut self, index: usize) -> (Arc<K>, Value<V>) {
+        let (key, removed) = self.entries.remove(index).expect("removed index is occupied");
+        self.pinned -= removed.pinned as usize;
+        self.tracker.removed(index);
+        self.store.remove(&*key);
+        self.forget(&key);
+        if let Some(slot) = removed.slot {
+            self.unindex(slot);
+        }
+        (key, removed)
+    }
+
+    // unindex drops a slot from the expiring index in O(1) by moving the last entry into it
+    // the entry that owned the slot must already be removed (or about to be replaced)
+    fn unindex(&mut self, slot: usize) {
+        self.expiring.swap_remove(slot);
+        if let Some(&moved) = self.expiring.get(slot) {
+            self.entries[moved].1.slot = Some(slot);
+        }
+    }
+
+    // auto_vacuum is vacuum without the hand tuning: it picks its sample size and retry threshold
+    // from how much earlier runs found expired and from the size of the expiring index, and
+    // returns how many entries it removed
+    pub fn auto_vacuum(&mut self) -> usize {
+        span!(DEBUG, "auto_vacuum", sample = self.auto.sample, expiring = self.expiring.len());
+        let mut removed = 0;
+        for _ in 0..AutoVacuum::MAX_PASSES {
+            let len = self.expiring.len();
+            if len == 0 {
+                break
+            }
+            let (sample, threshold) = (self.auto.sample.min(len), self.auto.threshold(len));
+            let expired = self.vacuum_sample(sample);
+            removed += expired;
+            let ratio = expired as f32 / sample as f32;
+            self.auto.observe(ratio);
+            if ratio <= threshold {
+                break
+            }
+        }
+        self.auto.adjust(self.expiring.len());
+        self.trim(self.auto.sample);
+        self.maybe_shrink();
+        removed
+    }
+
+    // vacuum_incremental examines up to budget entries from cursor on, removing the expired ones,
+    // and returns the cursor to pass next time; 0 means a sweep just finished. Unlike vacuum's
+    // random samples, a sweep examines every entry that stayed in the cache through it. It walks
+    // the entries in storage order, not the expiring index, since removals reorder the index but
+    // never move a stored entry.
+    pub fn vacuum_incremental(&mut self, cursor: usize, budget: usize) -> usize {
+        let now = self.now();
+        let end = cursor.saturating_add(budget).min(self.entries.slots());
+        let expired: Vec<usize> = (cursor.min(end)..end)
+            .filter(|&i| self.entries.get(i).is_some_and(|(_, v)| v.expired(now)))
+            .collect();
+        event!(DEBUG, cursor, examined = end.saturating_sub(cursor), removed = expired.len(), "incremental vacuum");
+        for index in expired {
+            let (key, mut v) = self.remove_at(index);
+            self.stats.expirations.incr();
+            self.listeners.emit(CacheEvent::Expired{ key: &key, value: v.value.get_mut() });
+        }
+
+        if end >= self.entries.slots() {
+            self.maybe_shrink();
+            return 0
+        }
+        end
+    }
+
+    // called by vacuum, this just handles sampling and removing a single set (not retrying based
+    // on a threshold)
+    fn vacuum_sample(&mut self, count : usize) -> usize {
+        // amount is the max number of items we sample from the current set
+        let mut amount = count;
+        if count > self.expiring.len() {
+            amount = self.expiring.len()
+        }
+
+        let samples = self.vacuum_positions(amount);
+
+        // collect the expired entries first: removing an entry moves another one into its slot,
+        // which would invalidate the remaining sampled positions (slab indices stay put)
+        let now = self.now();
+        let expired: Vec<usize> = samples.into_iter()
+            .filter_map(|slot| self.expiring.get(slot).copied())
+            .filter(|&index| self.entries[index].1.expired(now))
+            .collect();
+
+        // remove the expired entries from the cache (and self.expiring)
+        let removed = expired.len();
+        for index in expired {
+            let (key, mut v) = self.remove_at(index);
+            self.stats.expirations.incr();
+            self.listeners.emit(CacheEvent::Expired{ key: &key, value: v.value.get_mut() });
+        }
+
+        event!(DEBUG, sampled = amount, removed, "vacuum pass");
+        removed
+    }
+
+    // vacuum_positions picks amount distinct positions in the expiring index for a vacuum pass,
+    // at random
+    #[cfg(feature = "rand")]
+    fn vacuum_positions(&mut self, amount: usize) -> Vec<usize> {
+        let len = self.expiring.len();
+        self.rng.with(|rng| rand::seq::index::sample(rng, len, amount)).into_vec()
+    }
+
+    // without rand, passes sweep the expiring index round-robin from where the last one stopped
+    // instead. An entry that a removal moves behind the cursor waits for the next lap, so every
+    // entry is examined within two laps.
+    #[cfg(not(feature = "rand"))]
+    fn vacuum_positions(&mut self, amount: usize) -> Vec<usize> {
+        let len = self.expiring.len();
+        if len == 0 {
+            return Vec::new()
+        }
+        let start = self.sweep % len;
+        self.sweep = (start + amount) % len;
+        (start..start + amount).map(|i| i % len).collect()
+    }
+
+}
+
+impl<V, S: BuildHasher> HashCache<String, V, S> {
+    // insert_str and insert_str_ttl are insert and insert_ttl for a borrowed key: if the key is
+    // stored already (live or not), the stored String is reused, so refreshing an existing key
+    // allocates nothing; otherwise the key is copied once
+    pub fn insert_str(&mut self, key: &str, value: V) -> Option<V> {
+        let key = self.str_key(key);
+        self.insert_as(key, value, None, None).previous
+    }
+
+    pub fn insert_str_ttl(&mut self, key: &str, value: V, ttl: Duration) -> Option<V> {
+        let key = self.str_key(key);
+        self.insert_as(key, value, Some(ttl), None).previous
+    }
+
+    // str_key is the stored copy of key, or a new one if there's none
+    fn str_key(&self, key: &str) -> Arc<String> {
+        match self.store.get_key_value(key) {
+            Some((stored, _)) => stored.0.clone(),
+            None => Arc::new(key.to_owned()),
+        }
+    }
+}
+
+impl<K: Hash+Eq+fmt::Debug, V: fmt::Debug, S: BuildHasher> HashCache<K, V, S> {
+    fn fmt_named(&self, name: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct(name)
+            .field("len", &self.store.len())
+            .field("expiring", &self.expiring.len())
+            .field("entries", &DebugEntries{ entries: &self.entries, redact: self.config.redact_values, now: self.now() })
+            .finish()
+    }
+}
+
+impl<K: Hash+Eq+fmt::Debug, V: fmt::Debug, S: BuildHasher> fmt::Debug for HashCache<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_named("HashCache", f)
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher> Extend<(K, V)> for HashCache<K, V, S> {
+    fn extend<I: IntoIterator<Item=(K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher> Extend<(K, V, Duration)> for HashCache<K, V, S> {
+    fn extend<I: IntoIterator<Item=(K, V, Duration)>>(&mut self, iter: I) {
+        for (key, value, ttl) in iter {
+            self.insert_ttl(key, value, ttl);
+        }
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher+Default> FromIterator<(K, V)> for HashCache<K, V, S> {
+    fn from_iter<I: IntoIterator<Item=(K, V)>>(iter: I) -> Self {
+        let mut cache = HashCache::with_hasher(S::default());
+        cache.extend(iter);
+        cache
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher+Default> FromIterator<(K, V, Duration)> for HashCache<K, V, S> {
+    fn from_iter<I: IntoIterator<Item=(K, V, Duration)>>(iter: I) -> Self {
+        let mut cache = HashCache::with_hasher(S::default());
+        cache.extend(iter);
+        cache
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher>  Cache<K,V> for HashCache<K, V, S>  {
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.insert_with_outcome(key, value).previous
+    }
+
+    fn insert_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        self.insert_ttl_with_outcome(key, value, ttl).previous
+    }
+
+    fn get_with(&self, key: &K, f: &mut dyn FnMut(&V)) -> bool {
+        match self.hit(key) {
+            Some(v) => {
+                f(v);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn take(&mut self, key: K) -> Option<V> {
+        self.take_as(&key, None)
+    }
+
+    // vacuum samples the set of potentially expired keys and removes them if expired (sweeping
+    // them in turn instead in builds without the rand feature)
+    // in exact_expiry mode it removes exactly the entries whose TTL has run out instead, only
+    // sampling for expire_after_access
+    // panics if retry-threshold is not between 0 and 1.
+    fn vacuum(&mut self, count : usize, retry_threshold : f32 ) {
+        span!(DEBUG, "vacuum", count, retry_threshold = retry_threshold as f64, expiring = self.expiring.len());
+
+        // if the ratio of expired keys to sample size > retry threshold,
+        // we perform an additional vacuum before exiting
+        assert!(retry_threshold > 0.0);
+        assert!(retry_threshold < 1.0);
+
+        // initialize to amount so that we always iterate at least once
+        let mut expired_count = count as f32;
+        if self.deadlines.is_some() {
+            self.vacuum_deadlines();
+            if self.config.expire_after_access.is_none() {
+                expired_count = 0.0;
+            }
+        }
+
+        while expired_count/(count as f32) > retry_threshold {
+            expired_count = self.vacuum_sample(count) as f32;
+        }
+        self.trim(count);
+        self.maybe_shrink();
+    }
+}
+
+// ThreadSafeHashCache is a HashCache guarded by an RwLock; all operations take &self so one
+// instance can be shared between threads (e.g. behind an Arc) without extra locking. Values are
+// further locked by stripe, so an update in progress on one key only holds up reads of the keys
+// sharing its stripe.
+pub struct ThreadSafeHashCache<K: Hash+Eq, V, S = DefaultHashBuilder> {
+    inner: RwLock<HashCache<K, V, S>>,
+    stripes: Stripes,
+    // a handle to the inner cache's negative filter, so definite misses skip the lock entirely
+    filter: Option<Arc<NegativeFilter>>,
+    // a handle to the inner cache's audit hook, for the misses the filter answers
+    audit: Option<Arc<AuditHook<K>>>,
+    // a handle to the inner cache's clock, for callers that need the time without the lock
+    clock: Arc<dyn Clock>,
+    // misses answered by the filter, which the inner cache never sees
+    filtered: Counter,
+    locks: LockRecorder,
+    // generation counts write lock acquisitions and updates, telling snapshot whether its last
+    // copy is current
+    generation: Counter,
+    view: Mutex<Option<CachedView<K, V>>>,
+}
+
+impl<K: Hash+Eq, V>  ThreadSafeHashCache<K, V> {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> ThreadSafeHashCache<K, V> {
+        ThreadSafeHashCache::with_hasher(DefaultHashBuilder::default())
+    }
+
+    // with_capacity pre-allocates room for n entries, avoiding rehashing while warming a cache
+    pub fn with_capacity(n: usize) -> ThreadSafeHashCache<K, V> {
+        ThreadSafeHashCache::with_capacity_and_hasher(n, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher>  ThreadSafeHashCache<K, V, S> {
+    pub fn with_hasher(hash_builder: S) -> ThreadSafeHashCache<K, V, S> {
+        ThreadSafeHashCache::from_config(Config::default(), hash_builder)
+    }
+
+    pub fn with_capacity_and_hasher(n: usize, hash_builder: S) -> ThreadSafeHashCache<K, V, S> {
+        ThreadSafeHashCache::from_config(Config{ initial_capacity: n, ..Config::default() }, hash_builder)
+    }
+
+    pub(crate) fn from_config(config: Config<K, V>, hash_builder: S) -> ThreadSafeHashCache<K, V, S> {
+        ThreadSafeHashCache::wrap(HashCache::from_config(config, hash_builder))
+    }
+
+    fn wrap(cache: HashCache<K, V, S>) -> ThreadSafeHashCache<K, V, S> {
+        let filter = cache.filter.as_ref().map(|f| f.0.clone());
+        let audit = cache.config.audit.clone();
+        let clock = cache.config.clock.clone();
+        ThreadSafeHashCache{ inner: RwLock::new(cache), stripes: Stripes::new(), filter, audit, clock,
+            filtered: Counter::default(), locks: LockRecorder::default(), generation: Counter::default(), view: Mutex::new(None) }
+    }
+
+    // now is the current time according to the cache's clock, read without taking the lock
+    fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    // definitely_absent is only called on the read path, and counts the misses it answers
+    fn definitely_absent(&self, key: &K) -> bool {
+        self.definitely_absent_as(key, None)
+    }
+
+    fn definitely_absent_as(&self, key: &K, context: Option<&str>) -> bool {
+        let absent = match &self.filter {
+            Some(filter) => !filter.may_contain(key),
+            None => false,
+        };
+        if absent {
+            self.filtered.incr();
+            if let Some(audit) = &self.audit {
+                audit(&AuditEvent{ op: AuditOp::Get, key, outcome: AuditOutcome::Miss, context })
+            }
+        }
+        absent
+    }
+
+    // read and write count each acquisition, and whether it found the lock held and had to wait.
+    // They ignore poisoning: user code (get and update closures, listeners, audit hooks, expiry
+    // policies) only ever runs between the cache's own changes, never halfway through one, so a
+    // panic in it leaves the cache consistent. It propagates to the caller that ran it; other
+    // threads carry on. A value an update closure panicked halfway through changing stays as
+    // the closure left it.
+    //
+    // read holds every stripe too, so the guard may be used to read any value; read_key only
+    // holds key's, and read_index none, for readers that never look at a value.
+    fn read(&self) -> ReadGuard<'_, HashCache<K, V, S>> {
+        let inner = self.lock_store(&self.locks.reads, &self.locks.read_waits);
+        let stripes = std::array::from_fn(|i| Some(self.stripes.read(i, &self.locks.read_waits)));
+        ReadGuard::new(inner, stripes)
+    }
+
+    fn read_key<Q: Hash+?Sized>(&self, key: &Q) -> ReadGuard<'_, HashCache<K, V, S>> {
+        let inner = self.lock_store(&self.locks.reads, &self.locks.read_waits);
+        let stripe = self.stripes.of(key);
+        ReadGuard::new(inner, std::array::from_fn(|i| (i == stripe).then(|| self.stripes.read(i, &self.locks.read_waits))))
+    }
+
+    fn read_index(&self) -> ReadGuard<'_, HashCache<K, V, S>> {
+        ReadGuard::new(self.lock_store(&self.locks.reads, &self.locks.read_waits), Default::default())
+    }
+
+    // lock_store takes the store's lock for reading on behalf of a read or an update, which
+    // count their acquisitions and waits apart
+    fn lock_store(&self, count: &Counter, waits: &Counter) -> RwLockReadGuard<'_, HashCache<K, V, S>> {
+        count.incr();
+        timed_lock!("read", match self.inner.try_read() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                waits.incr();
+                self.inner.read().unwrap_or_else(PoisonError::into_inner)
+            },
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        })
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashCache<K, V, S>> {
+        self.locks.writes.incr();
+        let guard = timed_lock!("write", match self.inner.try_write() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                self.locks.write_waits.incr();
+                self.inner.write().unwrap_or_else(PoisonError::into_inner)
+            },
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        });
+        self.generation.incr();
+        guard
+    }
+
+    // contention reports how often the cache's lock was taken and how often callers waited for
+    // it; a ShardedCache spreads keys over several locks to bring the waits down
+    pub fn contention(&self) -> Contention {
+        self.locks.contention()
+    }
+
+    // update calls f on a live value, which it may change in place, and returns its result;
+    // None if the key is absent or expired. Only the key's stripe is locked meanwhile, so other
+    // keys can still be read; entries can't be added or removed until it returns.
+    pub fn update<F, R>(&self, key: &K, f: F) -> Option<R> where F: FnOnce(&mut V) -> R {
+        let inner = self.lock_store(&self.locks.writes, &self.locks.write_waits);
+        let _stripe = self.stripes.write(self.stripes.of(key), &self.locks.write_waits);
+        let now = inner.now();
+        let v = inner.lookup(key).filter(|v| !v.expired(now))?;
+        // counted before f runs, so a value f panics halfway through changing is still seen as
+        // changed
+        v.version.set(inner.ticks.incr());
+        self.generation.incr();
+        // SAFETY: the key's stripe is held for writing, and the store's lock for reading
+        Some(f(unsafe { v.value.get_unchecked_mut() }))
+    }
+
+    // fetch_update replaces a live value with f's result, like AtomicUsize::fetch_update: f is
+    // called without holding the lock, and again with the new value if the entry was written to
+    // meanwhile. The entry keeps its expiration. Returns the replaced value, or Err with the
+    // current one if f returned None (Err(None) if the key is absent or expired).
+    pub fn fetch_update<F>(&self, key: &K, mut f: F) -> Result<V, Option<V>> where F: FnMut(&V) -> Option<V>, V: Clone {
+        loop {
+            let (current, version) = {
+                let inner = self.read_key(key);
+                match inner.lookup(key) {
+                    // SAFETY: read_key holds the key's stripe
+                    Some(v) if !v.expired(inner.now()) => (V::clone(unsafe { v.value.get() }), v.version.get()),
+                    _ => return Err(None),
+                }
+            };
+            let new = match f(&current) {
+                Some(new) => new,
+                None => return Err(Some(current)),
+            };
+
+            let mut inner = self.write();
+            let now = inner.now();
+            match inner.lookup(key) {
+                Some(v) if v.version.get() == version && !v.expired(now) => {},
+                _ => continue,
+            }
+            let v = inner.lookup_mut(key).expect("checked live above");
+            return Ok(std::mem::replace(v.value.get_mut(), new))
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.write().insert(key, value)
+    }
+
+    pub fn insert_ttl(&self, key: K, value: V, ttl: Duration) -> Option<V> {
+        self.write().insert_ttl(key, value, ttl)
+    }
+
+    pub fn insert_with_outcome(&self, key: K, value: V) -> InsertOutcome<V> {
+        self.write().insert_with_outcome(key, value)
+    }
+
+    pub fn insert_ttl_with_outcome(&self, key: K, value: V, ttl: Duration) -> InsertOutcome<V> {
+        self.write().insert_ttl_with_outcome(key, value, ttl)
+    }
+
+    pub fn get<F>(&self, key: K, f: F) -> bool where F: Fn(&V) {
+        if self.definitely_absent(&key) {
+            return false
+        }
+        self.read_key(&key).get(key, f)
+    }
+
+    pub fn get_copied(&self, key: &K) -> Option<V> where V: Copy {
+        if self.definitely_absent(key) {
+            return None
+        }
+        self.read_key(key).get_copied(key)
+    }
+
+    pub fn metadata(&self, key: &K) -> Option<EntryMeta> {
+        self.read_index().metadata(key)
+    }
+
+    pub fn hot_keys(&self, n: usize) -> Vec<(K, u64)> {
+        self.read_index().hot_keys(n)
+    }
+
+    pub fn cold_keys(&self, unused_for: Duration) -> Vec<K> where K: Clone {
+        self.read_index().cold_keys(unused_for)
+    }
+
+    // get_stale calls f with a value, expired or not, and how long ago it expired
+    pub fn get_stale<F, R>(&self, key: &K, f: F) -> Option<R> where F: FnOnce(&V, Duration) -> R {
+        self.read_key(key).get_stale(key).map(|(v, stale)| f(v, stale))
+    }
+
+    pub fn take(&self, key: K) -> Option<V> {
+        self.write().take(key)
+    }
+
+    // take_once removes and returns a live value, for values that must be consumed at most once
+    // such as nonces and one-time codes: the lookup and removal happen under one write lock, so
+    // of any number of concurrent callers for a key exactly one gets the value. An expired value
+    // is removed but never returned.
+    pub fn take_once(&self, key: &K) -> Option<V> {
+        self.write().take_as(key, None)
+    }
+
+    // vacuum samples the set of potentially expired keys and removes them if expired
+    // panics if retry-threshold is not between 0 and 1.
+    pub fn vacuum(&self, count : usize, retry_threshold : f32 ) {
+        self.write().vacuum(count, retry_threshold)
+    }
+
+    pub fn auto_vacuum(&self) -> usize {
+        self.write().auto_vacuum()
+    }
+
+    pub fn next_expiration(&self) -> Option<Instant> {
+        self.write().next_expiration()
+    }
+
+    pub fn vacuum_incremental(&self, cursor: usize, budget: usize) -> usize {
+        self.write().vacuum_incremental(cursor, budget)
+    }
+
+    // iter_expired copies out the expired entries still stored, with how long ago each expired,
+    // so the lock isn't held while the caller works through them
+    pub fn iter_expired(&self) -> impl Iterator<Item=(K, V, Duration)> where K: Clone, V: Clone {
+        self.read().iter_expired().map(|(k, v, stale)| (k.clone(), v.clone(), stale)).collect::<Vec<_>>().into_iter()
+    }
+
+    // drain_expired removes every expired entry and yields it, so callers can process values
+    // that vacuum would otherwise silently discard
+    pub fn drain_expired(&self) -> impl Iterator<Item=(K, V)> where K: Clone {
+        // collected so the lock isn't held while the caller iterates
+        self.write().drain_expired().collect::<Vec<_>>().into_iter()
+    }
+
+    // retain keeps only the entries for which the predicate returns true, expired or not
+    pub fn retain<F>(&self, f: F) where F: FnMut(&K, &V, &EntryMeta) -> bool {
+        self.write().retain(f)
+    }
+
+    pub fn shrink_to_fit(&self) {
+        self.write().shrink_to_fit()
+    }
+
+    pub fn trim(&self, budget: usize) -> usize {
+        self.write().trim(budget)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.read_index().capacity()
+    }
+
+    pub fn expiring_len(&self) -> usize {
+        self.read_index().expiring_len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.read_index().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read_index().is_empty()
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.read_index().stats();
+        stats.misses += self.filtered.get();
+        stats
+    }
+
+    pub fn insert_ttl_with_cost(&self, key: K, value: V, ttl: Duration, recompute: Duration) -> Option<V> {
+        self.write().insert_ttl_with_cost(key, value, ttl, recompute)
+    }
+
+    pub fn insert_pinned(&self, key: K, value: V) -> Option<V> {
+        self.write().insert_pinned(key, value)
+    }
+
+    pub fn insert_pinned_ttl(&self, key: K, value: V, ttl: Duration) -> Option<V> {
+        self.write().insert_pinned_ttl(key, value, ttl)
+    }
+
+    pub fn pin(&self, key: &K) -> bool {
+        self.write().pin(key)
+    }
+
+    pub fn unpin(&self, key: &K) -> bool {
+        self.write().unpin(key)
+    }
+
+    pub fn touch(&self, key: &K, ttl: Option<Duration>) -> bool {
+        self.write().touch(key, ttl)
+    }
+
+    pub fn expire_all_after(&self, within: Duration, include_persistent: bool) -> usize {
+        self.write().expire_all_after(within, include_persistent)
+    }
+}
+
+impl<V, S: BuildHasher> ThreadSafeHashCache<String, V, S> {
+    pub fn insert_str(&self, key: &str, value: V) -> Option<V> {
+        self.write().insert_str(key, value)
+    }
+
+    pub fn insert_str_ttl(&self, key: &str, value: V, ttl: Duration) -> Option<V> {
+        self.write().insert_str_ttl(key, value, ttl)
+    }
+}
+
+impl<K: Hash+Eq+fmt::Debug, V: fmt::Debug, S: BuildHasher> fmt::Debug for ThreadSafeHashCache<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.read().fmt_named("ThreadSafeHashCache", f)
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher> Extend<(K, V)> for ThreadSafeHashCache<K, V, S> {
+    fn extend<I: IntoIterator<Item=(K, V)>>(&mut self, iter: I) {
+        self.write().extend(iter)
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher> Extend<(K, V, Duration)> for ThreadSafeHashCache<K, V, S> {
+    fn extend<I: IntoIterator<Item=(K, V, Duration)>>(&mut self, iter: I) {
+        self.write().extend(iter)
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher+Default> FromIterator<(K, V)> for ThreadSafeHashCache<K, V, S> {
+    fn from_iter<I: IntoIterator<Item=(K, V)>>(iter: I) -> Self {
+        ThreadSafeHashCache::wrap(HashCache::from_iter(iter))
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher+Default> FromIterator<(K, V, Duration)> for ThreadSafeHashCache<K, V, S> {
+    fn from_iter<I: IntoIterator<Item=(K, V, Duration)>>(iter: I) -> Self {
+        ThreadSafeHashCache::wrap(HashCache::from_iter(iter))
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher>  Cache<K,V> for ThreadSafeHashCache<K, V, S>  {
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        ThreadSafeHashCache::insert(self, key, value)
+    }
+
+    fn insert_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        ThreadSafeHashCache::insert_ttl(self, key, value, ttl)
+    }
+
+    fn get_with(&self, key: &K, f: &mut dyn FnMut(&V)) -> bool {
+        if self.definitely_absent(key) {
+            return false
+        }
+        self.read_key(key).get_with(key, f)
+    }
+
+    fn take(&mut self, key: K) -> Option<V> {
+        ThreadSafeHashCache::take(self, key)
+    }
+
+    fn vacuum(&mut self, count : usize, retry_threshold : f32 ) {
+        ThreadSafeHashCache::vacuum(self, count, retry_threshold)
+    }
+}
+
+// a shared handle can be passed anywhere a Cache is expected; the inner lock does the work
+impl<K: Hash+Eq, V, S: BuildHasher>  Cache<K,V> for Arc<ThreadSafeHashCache<K, V, S>>  {
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        ThreadSafeHashCache::insert(self, key, value)
+    }
+
+    fn insert_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        ThreadSafeHashCache::insert_ttl(self, key, value, ttl)
+    }
+
+    fn get_with(&self, key: &K, f: &mut dyn FnMut(&V)) -> bool {
+        if self.definitely_absent(key) {
+            return false
+        }
+        self.read_key(key).get_with(key, f)
+    }
+
+    fn take(&mut self, key: K) -> Option<V> {
+        ThreadSafeHashCache::take(self, key)
+    }
+
+    fn vacuum(&mut self, count : usize, retry_threshold : f32 ) {
+        ThreadSafeHashCache::vacuum(self, count, retry_threshold)
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher>  Cache<K,V> for Mutex<HashCache<K, V, S>>  {
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.get_mut().expect("lock poisoned").insert(key, value)
+    }
+
+    fn insert_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        self.get_mut().expect("lock poisoned").insert_ttl(key, value, ttl)
+    }
+
+    fn get_with(&self, key: &K, f: &mut dyn FnMut(&V)) -> bool {
+        self.lock().expect("lock poisoned").get_with(key, f)
+    }
+
+    fn take(&mut self, key: K) -> Option<V> {
+        self.get_mut().expect("lock poisoned").take(key)
+    }
+
+    fn vacuum(&mut self, count : usize, retry_threshold : f32 ) {
+        self.get_mut().expect("lock poisoned").vacuum(count, retry_threshold)
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher>  Cache<K,V> for RwLock<HashCache<K, V, S>>  {
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.get_mut().expect("lock poisoned").insert(key, value)
+    }
+
+    fn insert_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        self.get_mut().expect("lock poisoned").insert_ttl(key, value, ttl)
+    }
+
+    fn get_with(&self, key: &K, f: &mut dyn FnMut(&V)) -> bool {
+        self.read().expect("lock poisoned").get_with(key, f)
+    }
+
+    fn take(&mut self, key: K) -> Option<V> {
+        self.get_mut().expect("lock poisoned").take(key)
+    }
+
+    fn vacuum(&mut self, count : usize, retry_threshold : f32 ) {
+        self.get_mut().expect("lock poisoned").vacuum(count, retry_threshold)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::redundant_pattern_matching)]
+mod tests {
+    use crate::{HashCache, Cache, CacheBuilder, InsertOutcome, OverwritePolicy, Policy, ShardedCache, ThreadSafeHashCache, VacuumSchedule};
+    use std::time::{Duration, Instant};
+    use std::thread::{sleep, spawn};
+    use std::sync::{Arc, Mutex, RwLock};
+
+    #[test]
+    fn store_retrieve() {
+        let mut cache : HashCache<&str,&str> = HashCache::new();
+        cache.insert("id", "secret");
+        assert_eq!(true,
+                   cache.get("id", |v| assert_eq!(*v, "secret")));
+        assert_eq!(false,
+                   cache.get("nope", |_| panic!("expected none")));
+    }
+
+    #[test]
+    fn expire_key() {
+        let mut cache : HashCache<&str,&str> = HashCache::new();
+        cache.insert_ttl("id", "secret", Duration::new(1, 0));
+        assert_eq!(cache.expiring.len(), 1);
+
+        // initial get should work
+        assert_eq!(true,
+                   cache.get("id", |v| assert_eq!(*v, "secret")));
+
+        sleep(Duration::new(1, 0));
+
+        // fetch after ttl should be none
+        assert_eq!(false, cache.get("id", |_| panic!("expected none")));
+
+        // even though the cache reports the key is gone, it's still tracked in the expiring list
+        // until a vacuum is performed
+        assert_eq!(cache.expiring.len(), 1);
+        assert_eq!(cache.store.len(), 1);
+    }
+
+    #[test]
+    fn vacuum() {
+        let mut cache : HashCache<&str,&str> = HashCache::new();
+        cache.insert_ttl("id", "secret", Duration::new(1, 0));
+        assert_eq!(cache.expiring.len(), 1);
+
+        // initial get should work
+        assert_eq!(true,
+                   cache.get("id", |v| assert_eq!(*v, "secret")));
+        cache.vacuum(10, 0.25);
+
+        sleep(Duration::new(1, 0));
+
+        cache.vacuum(10, 0.25);
+
+        // check that it's been removed from the hashmap entirely
+        // this skips the active removal, so it verifies vacuuming
+        if let Some(_) = cache.store.get(&"id") {
             panic!("expected store to no longer have key")
         }
 
-        // check that key is no longer being tracked as an expiration candiate
-        assert_eq!(cache.expiring.len(), 0)
+        // check that key is no longer being tracked as an expiration candiate
+        assert_eq!(cache.expiring.len(), 0)
+    }
+
+    #[test]
+    fn vacuum_sampling_retry() {
+        let mut cache : HashCache<&str,&str> = HashCache::new();
+        cache.insert_ttl("id", "secret", Duration::new(1, 0));
+        cache.insert_ttl("id2", "secret2", Duration::new(1, 0));
+        assert_eq!(cache.expiring.len(), 2);
+
+        // wait for keys to expire
+        sleep(Duration::new(1, 0));
+
+        // count is 1, but there are two entries, so the retry threshold should be hit (0.5>0.25)
+        // and should clean up both entries
+        cache.vacuum(1, 0.25);
+
+        // check that it's been removed from the hashmap entirely
+        // this skips the active removal, so it verifies vacuuming
+        if let Some(_) = cache.store.get(&"id") {
+            panic!("expected store to no longer have key")
+        }
+
+        // check that key is no longer being tracked as an expiration candiate
+        assert_eq!(cache.expiring.len(), 0)
+    }
+
+    #[test]
+    fn vacuum_sampling_no_retry() {
+        let mut cache : HashCache<&str,&str> = HashCache::new();
+        cache.insert_ttl("id", "secret", Duration::new(1, 0));
+        cache.insert_ttl("id2", "secret2", Duration::new(1, 0));
+        cache.insert_ttl("id3", "secret", Duration::new(2, 0));
+        cache.insert_ttl("id4", "secret2", Duration::new(2, 0));
+        assert_eq!(cache.expiring.len(), 4);
+
+        // wait for 2 keys to expire
+        sleep(Duration::new(1, 1000));
+
+        // count is 4 and retry threshold is 0.60, so one iteration of vacuuming should leave
+        // two entries remaining
+        cache.vacuum(4, 0.60);
+
+        // check that two keys were vacuumed
+        assert_eq!(2, cache.expiring.len());
+    }
+
+    #[cfg(not(feature = "rand"))]
+    #[test]
+    fn vacuum_sweep() {
+        let clock = crate::ManualClock::new();
+        let mut cache : HashCache<u32,u32> = CacheBuilder::new().clock(clock.clone()).build();
+        for i in 0..10 {
+            cache.insert_ttl(i, i, Duration::new(10 + 90 * (i as u64 % 2), 0));
+        }
+        clock.advance(Duration::new(11, 0));
+
+        // each pass takes the next 3 positions of the index, where at most 2 of them are
+        // expired, so it never retries; 4 passes get round to all the even keys
+        let mut left = vec![];
+        for _ in 0..4 {
+            cache.vacuum(3, 0.9);
+            left.push(cache.expiring.len());
+        }
+        assert_eq!(vec![8, 7, 6, 5], left);
+        assert!((0..10).all(|i| cache.lookup(&i).is_some() == (i % 2 == 1)));
+    }
+
+    #[test]
+    fn take() {
+        let mut cache : HashCache<&str,&str> = HashCache::new();
+        cache.insert("id", "secret");
+        cache.insert_ttl("id2", "secret2", Duration::from_millis(50));
+
+        assert_eq!(Some("secret"), cache.take("id"));
+        assert_eq!(None, cache.take("id"));
+
+        // taking an expiring entry also stops tracking it for vacuum
+        assert_eq!(Some("secret2"), cache.take("id2"));
+        assert_eq!(0, cache.expiring.len());
+
+        // expired entries are cleaned up but not handed out
+        cache.insert_ttl("id3", "secret3", Duration::from_millis(50));
+        sleep(Duration::from_millis(100));
+        assert_eq!(None, cache.take("id3"));
+        assert_eq!(0, cache.store.len());
+        assert_eq!(0, cache.expiring.len());
+    }
+
+    #[test]
+    fn drain_expired() {
+        let mut cache : HashCache<&str,&str> = HashCache::new();
+        cache.insert("id", "secret");
+        cache.insert_ttl("id2", "secret2", Duration::from_millis(50));
+        cache.insert_ttl("id3", "secret3", Duration::new(10, 0));
+        sleep(Duration::from_millis(100));
+
+        let drained: Vec<_> = cache.drain_expired().collect();
+        assert_eq!(vec![("id2", "secret2")], drained);
+        assert_eq!(2, cache.store.len());
+        assert_eq!(1, cache.expiring.len());
+        assert_eq!(0, cache.drain_expired().count());
+    }
+
+    #[test]
+    fn iter_expired() {
+        let clock = crate::ManualClock::new();
+        let cache : ThreadSafeHashCache<&str,&str> = CacheBuilder::new().clock(clock.clone()).build_thread_safe();
+        cache.insert("id", "secret");
+        cache.insert_ttl("id2", "secret2", Duration::new(10, 0));
+        cache.insert_ttl("id3", "secret3", Duration::new(30, 0));
+        clock.advance(Duration::new(25, 0));
+
+        let expired: Vec<_> = cache.iter_expired().collect();
+        assert_eq!(vec![("id2", "secret2", Duration::new(15, 0))], expired);
+        // looking doesn't remove anything
+        assert_eq!(3, cache.len());
+        cache.drain_expired().for_each(drop);
+        assert_eq!(0, cache.iter_expired().count());
+    }
+
+    #[test]
+    fn threadsafe_drain_expired() {
+        let cache : ThreadSafeHashCache<&str,&str> = ThreadSafeHashCache::new();
+        cache.insert_ttl("id", "secret", Duration::from_millis(50));
+        sleep(Duration::from_millis(100));
+
+        let drained: Vec<_> = cache.drain_expired().collect();
+        assert_eq!(vec![("id", "secret")], drained);
+        assert_eq!(0, cache.read().expiring.len());
+    }
+
+    #[test]
+    fn retain() {
+        let mut cache : HashCache<&str,&str> = HashCache::new();
+        cache.insert("tenant1:a", "secret");
+        cache.insert_ttl("tenant1:b", "secret", Duration::new(10, 0));
+        cache.insert_ttl("tenant2:a", "secret", Duration::new(10, 0));
+
+        cache.retain(|k, _, _| !k.starts_with("tenant1:"));
+        assert_eq!(1, cache.store.len());
+        assert_eq!(vec![cache.store[&"tenant2:a"]], cache.expiring);
+
+        cache.retain(|_, _, meta| meta.is_persistent());
+        assert_eq!(0, cache.store.len());
+        assert_eq!(0, cache.expiring.len());
+    }
+
+    #[test]
+    fn threadsafe_retain() {
+        let cache : ThreadSafeHashCache<&str,&str> = ThreadSafeHashCache::new();
+        cache.insert("id", "secret");
+        cache.insert_ttl("id2", "secret2", Duration::new(10, 0));
+
+        cache.retain(|_, v, meta| *v == "secret2" && meta.expires_at().is_some());
+        assert!(!cache.get("id", |_| panic!("expected none")));
+        assert!(cache.get("id2", |v| assert_eq!(*v, "secret2")));
+        assert_eq!(1, cache.read().expiring.len());
+    }
+
+    #[test]
+    fn from_iter_extend() {
+        let mut cache : HashCache<&str,&str> = vec![("id", "secret")].into_iter().collect();
+        cache.extend(vec![("id2", "secret2", Duration::new(10, 0))]);
+        assert!(cache.get("id", |v| assert_eq!(*v, "secret")));
+        assert!(cache.get("id2", |v| assert_eq!(*v, "secret2")));
+        assert_eq!(1, cache.expiring.len());
+
+        let cache : ThreadSafeHashCache<&str,&str> = vec![("id", "secret", Duration::new(10, 0))].into_iter().collect();
+        assert!(cache.get("id", |v| assert_eq!(*v, "secret")));
+        assert_eq!(1, cache.read().expiring.len());
+    }
+
+    #[test]
+    fn clone_preserves_deadlines() {
+        let mut cache : HashCache<&str,&str> = HashCache::new();
+        cache.insert("id", "secret");
+        cache.insert_ttl("id2", "secret2", Duration::from_millis(100));
+        sleep(Duration::from_millis(60));
+
+        let mut cloned = cache.clone();
+        cloned.insert("id3", "secret3");
+        assert!(!cache.get("id3", |_| panic!("expected none")));
+        assert!(cloned.get("id", |v| assert_eq!(*v, "secret")));
+        assert!(cloned.get("id2", |v| assert_eq!(*v, "secret2")));
+
+        // the clone expires with the original, since the deadline was carried over
+        sleep(Duration::from_millis(60));
+        assert!(!cloned.get("id2", |_| panic!("expected none")));
+        assert_eq!(1, cloned.expiring.len());
+    }
+
+    #[test]
+    fn debug_redaction() {
+        let mut cache : HashCache<&str,&str> = HashCache::new();
+        cache.insert("id", "secret");
+        let out = format!("{:?}", cache);
+        assert!(out.contains("\"secret\""));
+        assert!(out.contains("never"));
+
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new().redact_values(true).build();
+        cache.insert_ttl("id", "secret", Duration::new(10, 0));
+        let out = format!("{:?}", cache);
+        assert!(out.contains("\"id\""));
+        assert!(out.contains("<redacted>"));
+        assert!(!out.contains("secret"));
+        cache.insert_ttl("id2", "secret2", Duration::MAX);
+        assert!(format!("{:?}", cache).contains("expires_in"));
+
+        let cache : ThreadSafeHashCache<&str,&str> = CacheBuilder::new().redact_values(true).build_thread_safe();
+        cache.insert("id", "secret");
+        let out = format!("{:?}", cache);
+        assert!(out.starts_with("ThreadSafeHashCache { len: 1, expiring: 0"));
+        assert!(!out.contains("secret"));
+    }
+
+    #[test]
+    fn trait_object() {
+        let backends : Vec<Box<dyn Cache<&str,&str>>> = vec![
+            Box::new(HashCache::new()),
+            Box::new(ThreadSafeHashCache::new()),
+        ];
+
+        for mut cache in backends {
+            cache.insert_ttl("id", "secret", Duration::new(10, 0));
+            let mut seen = None;
+            assert!(cache.get_with(&"id", &mut |v| seen = Some(*v)));
+            assert_eq!(Some("secret"), seen);
+            assert_eq!(Some("secret"), cache.take("id"));
+            cache.vacuum(10, 0.25);
+        }
+    }
+
+    #[test]
+    fn custom_hasher() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::BuildHasherDefault;
+
+        let mut cache : HashCache<&str,&str,BuildHasherDefault<DefaultHasher>> = HashCache::with_hasher(BuildHasherDefault::default());
+        cache.insert_ttl("id", "secret", Duration::new(10, 0));
+        assert!(cache.get("id", |v| assert_eq!(*v, "secret")));
+
+        let cache = ThreadSafeHashCache::with_hasher(BuildHasherDefault::<DefaultHasher>::default());
+        cache.insert("id", "secret");
+        assert!(cache.get("id", |v| assert_eq!(*v, "secret")));
     }
 
     #[test]
-    fn vacuum_sampling_no_retry() {
+    fn with_capacity() {
+        let cache : HashCache<&str,&str> = HashCache::with_capacity(100);
+        assert!(cache.capacity() >= 100);
+        assert!(cache.expiring.capacity() >= 100);
+
+        let cache : ThreadSafeHashCache<&str,&str> = CacheBuilder::new().initial_capacity(100).build_thread_safe();
+        assert!(cache.capacity() >= 100);
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let mut cache : HashCache<usize,usize> = HashCache::new();
+        cache.insert(0, 0);
+        cache.extend((1..1000).map(|i| (i, i, Duration::from_millis(10))));
+        sleep(Duration::from_millis(20));
+        assert_eq!(999, cache.drain_expired().count());
+        assert_eq!(1, cache.store.len());
+        assert!(cache.capacity() >= 1000);
+
+        cache.shrink_to_fit();
+        assert!(cache.capacity() < 1000);
+        assert!(cache.expiring.capacity() < 1000);
+    }
+
+    #[test]
+    fn shrink_policy() {
+        let mut cache : HashCache<usize,usize> = CacheBuilder::new().shrink_below(0.25).build();
+        cache.extend((0..1000).map(|i| (i, i, Duration::from_millis(10))));
+        sleep(Duration::from_millis(20));
+
+        // removing entries applies the policy without an explicit shrink
+        assert_eq!(1000, cache.drain_expired().count());
+        assert_eq!(0, cache.store.len());
+        assert!(cache.capacity() < 1000);
+    }
+
+    #[test]
+    fn max_capacity_lru() {
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new().max_capacity(2).build();
+        cache.insert("id", "secret");
+        cache.insert("id2", "secret2");
+
+        // reading id makes id2 the least recently used
+        assert!(cache.get("id", |_| {}));
+        cache.insert("id3", "secret3");
+        assert_eq!(2, cache.store.len());
+        assert!(!cache.get("id2", |_| panic!("expected none")));
+
+        // overwriting an existing key doesn't evict
+        cache.insert("id3", "secret4");
+        assert_eq!(2, cache.store.len());
+    }
+
+    #[test]
+    fn max_capacity_lfu() {
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new().max_capacity(2).eviction(Policy::Lfu).build();
+        cache.insert("id", "secret");
+        cache.insert("id2", "secret2");
+        assert!(cache.get("id", |_| {}));
+        assert!(cache.get("id", |_| {}));
+        assert!(cache.get("id2", |_| {}));
+
+        cache.insert("id3", "secret3");
+        assert!(!cache.get("id2", |_| panic!("expected none")));
+
+        // expired entries are evicted before live ones, however often they were read
+        cache.take("id3");
+        cache.insert_ttl("id4", "secret4", Duration::from_millis(10));
+        for _ in 0..5 {
+            assert!(cache.get("id4", |_| {}));
+        }
+        sleep(Duration::from_millis(20));
+        cache.insert("id5", "secret5");
+        assert!(cache.get("id", |_| {}));
+        assert!(!cache.store.contains_key(&"id4"));
+        assert_eq!(0, cache.expiring.len());
+    }
+
+    #[test]
+    fn max_capacity_sieve() {
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new().max_capacity(3).eviction(Policy::Sieve).build();
+        cache.insert("a", "1");
+        cache.insert("b", "2");
+        cache.insert("c", "3");
+        assert!(cache.get("a", |_| {}));
+
+        // the oldest entry was read, so it is spared and the hand moves on to the next one
+        cache.insert("d", "4");
+        assert!(!cache.store.contains_key(&"b"));
+        cache.insert("e", "5");
+        assert!(!cache.store.contains_key(&"c"));
+
+        // pinned entries are passed over, and a's reprieve was used up on the first lap
+        cache.pin(&"d");
+        cache.insert("f", "6");
+        assert!(!cache.store.contains_key(&"e"));
+        cache.insert("g", "7");
+        assert!(!cache.store.contains_key(&"a"));
+        assert_eq!(3, cache.len());
+        assert!(cache.get("d", |_| {}));
+    }
+
+    #[test]
+    fn max_capacity_arc() {
+        let mut cache : HashCache<u32,u32> = CacheBuilder::new().max_capacity(4).eviction(Policy::Arc).build();
+        cache.insert(0, 0);
+        cache.insert(1, 1);
+        assert!(cache.get(0, |_| {}));
+        assert!(cache.get(1, |_| {}));
+
+        // a scan of keys read once doesn't flush the entries that were read again
+        for i in 100..120 {
+            cache.insert(i, i);
+        }
+        assert!(cache.get(0, |_| {}));
+        assert!(cache.get(1, |_| {}));
+        assert_eq!(4, cache.len());
+
+        // a recently evicted key coming back is remembered as a ghost and goes straight to the
+        // frequent side
+        cache.insert(117, 117);
+        for i in 200..203 {
+            cache.insert(i, i);
+        }
+        assert!(cache.store.contains_key(&117));
+    }
+
+    #[test]
+    fn soft_capacity_arc() {
+        // with no max_capacity, ARC adapts within the soft limit it's trimmed to
+        let mut cache : HashCache<u32,u32> = CacheBuilder::new().soft_capacity(4).eviction(Policy::Arc).build();
+        cache.extend((0..8).map(|i| (i, i)));
+        assert_eq!(4, cache.trim(4));
+
+        // keys evicted from the recent side coming back grow its share, so it yields less
+        // to later evictions
+        cache.extend((0..3).map(|i| (i, i)));
+        assert_eq!(3, cache.trim(3));
+        assert!(cache.store.contains_key(&6) && cache.store.contains_key(&7));
+        assert_eq!(4, cache.len());
+    }
+
+    #[test]
+    fn max_capacity_slru() {
+        let mut cache : HashCache<u32,u32> = CacheBuilder::new().max_capacity(5).eviction(Policy::Slru).build();
+        for i in 0..3 {
+            cache.insert(i, i);
+            assert!(cache.get(i, |_| {}));
+        }
+
+        // the read entries are promoted out of probation, so the scan only evicts itself
+        for i in 100..120 {
+            cache.insert(i, i);
+        }
+        assert!((0..3).all(|i| cache.store.contains_key(&i)));
+        assert_eq!(5, cache.len());
+
+        // protected entries that go unread are eventually demoted and evicted
+        for i in 200..210 {
+            cache.insert(i, i);
+            assert!(cache.get(i, |_| {}));
+        }
+        assert!((0..3).all(|i| !cache.store.contains_key(&i)));
+    }
+
+    #[test]
+    fn soft_capacity_slru() {
+        // with no max_capacity, the protected segment is sized by the soft limit
+        let mut cache : HashCache<u32,u32> = CacheBuilder::new().soft_capacity(5).eviction(Policy::Slru).build();
+        for i in 0..3 {
+            cache.insert(i, i);
+            assert!(cache.get(i, |_| {}));
+        }
+        for i in 100..120 {
+            cache.insert(i, i);
+            cache.trim(1);
+        }
+        assert!((0..3).all(|i| cache.store.contains_key(&i)));
+        assert_eq!(5, cache.len());
+
+        let cache : HashCache<u32,u32> = CacheBuilder::new().max_capacity(usize::MAX).eviction(Policy::Slru).build();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn max_capacity_fifo_random() {
+        let mut cache : HashCache<u32,u32> = CacheBuilder::new().max_capacity(3).eviction(Policy::Fifo).build();
+        cache.insert_pinned(0, 0);
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        assert!(cache.get(1, |_| {}));
+
+        // reads don't matter, only insertion order (skipping pinned entries)
+        cache.insert(3, 3);
+        assert!(!cache.store.contains_key(&1));
+        cache.insert(4, 4);
+        assert!(!cache.store.contains_key(&2));
+        assert!(cache.store.contains_key(&0));
+
+        let mut cache : HashCache<u32,u32> = CacheBuilder::new().max_capacity(10).eviction(Policy::Random).build();
+        cache.insert_pinned(0, 0);
+        for i in 1..100 {
+            cache.insert(i, i);
+        }
+        assert_eq!(10, cache.len());
+        assert!(cache.store.contains_key(&0));
+    }
+
+    #[test]
+    fn soft_capacity() {
+        let mut cache : HashCache<u32,u32> = CacheBuilder::new().soft_capacity(10).max_capacity(20).build();
+        cache.extend((0..15).map(|i| (i, i)));
+        // past the soft limit inserts don't evict
+        assert_eq!(15, cache.len());
+
+        // vacuum trims back towards it, at most its sample count at a time
+        cache.vacuum(3, 0.25);
+        assert_eq!(12, cache.len());
+        cache.vacuum(10, 0.25);
+        assert_eq!(10, cache.len());
+        assert_eq!(5, cache.stats().evictions);
+
+        // the hard limit still evicts synchronously
+        cache.extend((100..120).map(|i| (i, i)));
+        assert_eq!(20, cache.len());
+    }
+
+    #[test]
+    fn pinned_entries() {
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new().max_capacity(2).build();
+        cache.insert_pinned("flag", "on");
+        cache.insert("id", "secret");
+        assert!(cache.pin(&"id"));
+        assert!(!cache.pin(&"nope"));
+
+        // both entries are pinned, so the cache grows past capacity instead of evicting
+        cache.insert("id2", "secret2");
+        assert_eq!(3, cache.store.len());
+        assert_eq!(2, cache.pinned);
+
+        // overwriting keeps the pin, unpinning makes the entry evictable again
+        cache.insert("id", "secret3");
+        assert_eq!(2, cache.pinned);
+        assert!(cache.unpin(&"id"));
+        assert_eq!(1, cache.pinned);
+        cache.insert("id3", "secret3");
+        assert!(cache.get("flag", |v| assert_eq!(*v, "on")));
+        assert!(!cache.get("id", |_| panic!("expected none")));
+        cache.retain(|k, _, _| *k != "flag");
+        assert_eq!(0, cache.pinned);
+
+        // pinned entries still honor their TTL
+        cache.insert_pinned_ttl("token", "secret", Duration::from_millis(10));
+        sleep(Duration::from_millis(20));
+        assert!(!cache.get("token", |_| panic!("expected none")));
+    }
+
+    #[test]
+    fn touch() {
         let mut cache : HashCache<&str,&str> = HashCache::new();
-        cache.insert_ttl("id", "secret", Duration::new(1, 0));
-        cache.insert_ttl("id2", "secret2", Duration::new(1, 0));
-        cache.insert_ttl("id3", "secret", Duration::new(2, 0));
-        cache.insert_ttl("id4", "secret2", Duration::new(2, 0));
-        assert_eq!(cache.expiring.len(), 4);
+        cache.insert_ttl("id", "secret", Duration::from_millis(10));
+        cache.insert("id2", "secret2");
+        assert!(!cache.touch(&"nope", None));
+
+        // extending a TTL keeps the entry alive past its original deadline
+        assert!(cache.touch(&"id", Some(Duration::new(10, 0))));
+        sleep(Duration::from_millis(20));
+        assert!(cache.get("id", |_| {}));
+
+        // moving entries in and out of the expiring index
+        assert!(cache.touch(&"id", None));
+        assert!(cache.touch(&"id2", Some(Duration::from_millis(1))));
+        assert_eq!(vec![cache.store[&"id2"]], cache.expiring);
+        sleep(Duration::from_millis(5));
+        assert!(!cache.touch(&"id2", None));
+    }
 
-        // wait for 2 keys to expire
-        sleep(Duration::new(1, 1000));
+    #[test]
+    fn metadata() {
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new().eviction(Policy::Fifo).build();
+        let before = Instant::now();
+        cache.insert("id", "secret");
+        cache.insert_ttl("token", "secret2", Duration::new(60, 0));
+        assert_eq!(None, cache.metadata(&"nope"));
+
+        let meta = cache.metadata(&"id").unwrap();
+        assert!(meta.is_persistent());
+        assert!(meta.created >= before);
+        assert_eq!((0, None), (meta.hits, meta.last_access));
+
+        // reads are counted whatever the eviction policy, and metadata itself isn't a read
+        assert!(cache.get("token", |_| {}));
+        assert!(cache.get("token", |_| {}));
+        let meta = cache.metadata(&"token").unwrap();
+        assert_eq!(2, meta.hits);
+        assert!(meta.last_access.unwrap() >= meta.created);
+        assert_eq!(Some(meta.created + Duration::new(60, 0)), meta.expires_at());
+        assert_eq!(2, cache.metadata(&"token").unwrap().hits);
+
+        // a deadline too far off to represent has no expires_at, and never passes
+        cache.insert_ttl("forever", "secret3", Duration::MAX);
+        let meta = cache.metadata(&"forever").unwrap();
+        assert_eq!((None, false), (meta.expires_at(), meta.is_expired()));
+        cache.retain(|_, _, meta| meta.expires_at().is_none_or(|at| at > meta.as_of));
+        assert_eq!(3, cache.len());
+    }
 
-        // count is 4 and retry threshold is 0.60, so one iteration of vacuuming should leave
-        // two entries remaining
-        cache.vacuum(4, 0.60);
+    #[test]
+    fn expire_after_access() {
+        let clock = crate::clock::ManualClock::new();
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new()
+            .expire_after_access(Duration::new(30, 0))
+            .clock(clock.clone())
+            .build();
+        cache.insert("read", "a");
+        cache.insert("unread", "b");
+        cache.insert_ttl("short", "c", Duration::new(40, 0));
+        assert_eq!(3, cache.expiring.len());
+
+        // reads keep an entry alive, but not past its TTL
+        clock.advance(Duration::new(20, 0));
+        assert_eq!(3, cache.cold_keys(Duration::new(15, 0)).len());
+        for _ in 0..3 {
+            cache.get("read", |_| {});
+            cache.get("short", |_| {});
+            clock.advance(Duration::new(20, 0));
+        }
+        assert!(cache.get("read", |_| {}));
+        assert!(!cache.get("unread", |_| {}));
+        assert!(!cache.get("short", |_| {}));
 
-        // check that two keys were vacuumed
+        let meta = cache.metadata(&"read").unwrap();
+        assert_eq!(meta.last_access.map(|at| at + Duration::new(30, 0)), meta.idle_expires_at());
+
+        // vacuum finds idle entries even without a TTL
+        clock.advance(Duration::new(31, 0));
+        cache.vacuum(10, 0.25);
+        assert_eq!(0, cache.len());
+    }
+
+    #[test]
+    fn insert_str() {
+        let mut cache : HashCache<String, u32> = HashCache::new();
+        assert_eq!(None, cache.insert_str("a", 1));
+        let stored = cache.entries[cache.store["a"]].0.clone();
+        assert_eq!(Some(1), cache.insert_str_ttl("a", 2, Duration::from_millis(5)));
+        // overwriting reused the stored key rather than copying "a" again
+        assert!(Arc::ptr_eq(&stored, &cache.entries[cache.store["a"]].0));
+        assert!(cache.get("a".to_string(), |&v| assert_eq!(2, v)));
+        sleep(Duration::from_millis(10));
+        assert!(!cache.get("a".to_string(), |_| {}));
+        assert_eq!(1, cache.len());
+
+        // sharded caches route a &str to the shard its String would go to
+        let sharded : ShardedCache<String, u32> = ShardedCache::new(4);
+        for i in 0..20 {
+            sharded.insert_str(&format!("k{}", i), i);
+        }
+        assert_eq!(Some(7), sharded.insert(format!("k{}", 7), 8));
+        assert_eq!(20, sharded.len());
+    }
+
+    #[test]
+    fn keys_without_clone() {
+        #[derive(Debug, PartialEq, Eq, Hash)]
+        struct Key(String);
+        let key = |s: &str| Key(s.to_string());
+
+        let cache : ThreadSafeHashCache<Key, u32> = CacheBuilder::new().max_capacity(2).build_thread_safe();
+        cache.insert(key("a"), 1);
+        cache.insert_ttl(key("b"), 2, Duration::from_millis(5));
+        assert!(cache.get(key("a"), |&v| assert_eq!(1, v)));
+        sleep(Duration::from_millis(10));
+        cache.vacuum(10, 0.5);
+        cache.insert(key("c"), 3);
+        assert_eq!(2, cache.len());
+        assert_eq!(Some(1), cache.take_once(&key("a")));
+
+        // the store and the entry share one allocation of the key
+        let inner = cache.read();
+        let (k, &i) = inner.store.iter().next().unwrap();
+        assert!(Arc::ptr_eq(&k.0, &inner.entries[i].0));
+        assert_eq!(2, Arc::strong_count(&k.0));
+        drop(inner);
+
+        let sharded : ShardedCache<Key, u32> = CacheBuilder::new().max_capacity(8).build_sharded(4);
+        sharded.insert(key("a"), 1);
+        assert_eq!(Some(1), sharded.update(&key("a"), |v| *v));
+        assert_eq!(Some(1), sharded.take_once(&key("a")));
+    }
+
+    #[test]
+    fn hot_keys() {
+        let cache : ThreadSafeHashCache<u32,u32> = CacheBuilder::new().track_hot_keys(16).build_thread_safe();
+        assert!(ThreadSafeHashCache::<u32,u32>::new().hot_keys(1).is_empty());
+        cache.insert(1, 1);
+        for i in 0..200 {
+            cache.get(1, |_| {});
+            // a missing key dominating reads shows up too
+            cache.get(2, |_| {});
+            cache.get(2, |_| {});
+            cache.get(100 + i, |_| {});
+        }
+        assert_eq!(vec![2, 1], cache.hot_keys(2).into_iter().map(|(k, _)| k).collect::<Vec<_>>());
+        assert!(cache.hot_keys(2)[0].1 >= 400);
+    }
+
+    #[test]
+    fn auto_vacuum() {
+        let clock = crate::clock::ManualClock::new();
+        let mut cache : HashCache<u32,u32> = CacheBuilder::new().clock(clock.clone()).build();
+        assert_eq!(0, cache.auto_vacuum());
+        for i in 0..10_000 {
+            cache.insert_ttl(i, i, Duration::new(if i % 10 == 0 { 60 } else { 1 }, 0));
+        }
+
+        // most of the index has expired, so runs remove a lot and the sample grows
+        clock.advance(Duration::new(2, 0));
+        let mut removed = 0;
+        for _ in 0..5 {
+            removed += cache.auto_vacuum();
+        }
+        assert!(removed > 1000);
+        assert!(cache.auto.sample > 20);
+
+        // once nothing expires, the sample shrinks back
+        cache.drain_expired().for_each(drop);
+        assert_eq!(1000, cache.len());
+        while cache.auto.sample > 20 {
+            assert_eq!(0, cache.auto_vacuum());
+        }
+        assert!(cache.auto.expired_ratio < 0.05);
+    }
+
+    #[test]
+    fn vacuum_schedule() {
+        let every = Duration::from_millis(10);
+        let cache : Arc<ThreadSafeHashCache<u32,u32>> = CacheBuilder::new().vacuum_schedule(VacuumSchedule::Every(every)).build_shared();
+        for i in 0..100 {
+            ThreadSafeHashCache::insert_ttl(&cache, i, i, Duration::from_millis(5));
+        }
+        sleep(Duration::from_millis(100));
+        assert_eq!(0, cache.len());
+
+        // under the watermark, expired entries are left alone
+        let watermark = VacuumSchedule::Watermark{ every, expiring: 50 };
+        let cache : Arc<ThreadSafeHashCache<u32,u32>> = CacheBuilder::new().vacuum_schedule(watermark).build_shared();
+        for i in 0..40 {
+            ThreadSafeHashCache::insert_ttl(&cache, i, i, Duration::from_millis(5));
+        }
+        sleep(Duration::from_millis(50));
+        assert_eq!(40, cache.len());
+        for i in 40..100 {
+            ThreadSafeHashCache::insert_ttl(&cache, i, i, Duration::from_millis(5));
+        }
+        sleep(Duration::from_millis(100));
+        assert!(cache.len() <= 50);
+    }
+
+    #[test]
+    fn vacuum_incremental() {
+        let clock = crate::clock::ManualClock::new();
+        let mut cache : HashCache<u32,u32> = CacheBuilder::new().clock(clock.clone()).build();
+        for i in 0..100 {
+            match i % 3 {
+                0 => cache.insert(i, i),
+                _ => cache.insert_ttl(i, i, Duration::new(i as u64 % 2 + 1, 0)),
+            };
+        }
+        clock.advance(Duration::new(3, 0));
+
+        // a sweep in steps of 7 examines every entry exactly once
+        let (mut cursor, mut steps) = (0, 0);
+        loop {
+            cursor = cache.vacuum_incremental(cursor, 7);
+            steps += 1;
+            if cursor == 0 {
+                break
+            }
+        }
+        assert_eq!(15, steps);
+        assert_eq!(34, cache.len());
+        assert_eq!(0, cache.expiring.len());
+        assert_eq!(66, cache.stats().expirations);
+    }
+
+    #[test]
+    fn insert_outcome() {
+        let clock = crate::clock::ManualClock::new();
+        let mut cache : HashCache<&str,u32> = CacheBuilder::new().clock(clock.clone()).build();
+        assert_eq!(InsertOutcome{ previous: None, previous_expired: false }, cache.insert_with_outcome("a", 1));
+        cache.insert_ttl("b", 1, Duration::new(10, 0));
+
+        let outcome = cache.insert_with_outcome("a", 2);
+        assert_eq!((Some(1), true), (outcome.previous, outcome.replaced()));
+
+        // a replaced value that expired but wasn't vacuumed yet is still handed back, flagged
+        clock.advance(Duration::new(11, 0));
+        let outcome = cache.insert_ttl_with_outcome("b", 2, Duration::new(10, 0));
+        assert_eq!(InsertOutcome{ previous: Some(1), previous_expired: true }, outcome);
+        assert!(!outcome.replaced());
+    }
+
+    #[test]
+    fn max_expiring() {
+        let clock = crate::clock::ManualClock::new();
+        let mut cache : HashCache<u32,u32> = CacheBuilder::new().max_expiring(10).clock(clock.clone()).build();
+        for i in 0..10 {
+            cache.insert_ttl(i, i, Duration::new(i as u64 + 1, 0));
+        }
+        cache.insert(100, 100);
+        // re-inserting keys that are already indexed doesn't grow the index
+        for _ in 0..100 {
+            cache.insert_ttl(9, 9, Duration::new(20, 0));
+        }
+        assert_eq!(10, cache.expiring_len());
+
+        // a full index is compacted first
+        clock.advance(Duration::from_millis(3_500));
+        cache.insert_ttl(10, 10, Duration::new(30, 0));
+        assert_eq!(8, cache.expiring_len());
+        assert_eq!(3, cache.stats().expirations);
+
+        // and when nothing has expired, the entry expiring next makes room
+        cache.insert_ttl(11, 11, Duration::new(30, 0));
+        cache.insert_ttl(12, 12, Duration::new(30, 0));
+        cache.insert_ttl(13, 13, Duration::new(30, 0));
+        assert_eq!(10, cache.expiring_len());
+        assert_eq!(1, cache.stats().evictions);
+        assert!(!cache.get(3, |_| {}));
+        assert!(cache.get(4, |_| {}));
+        assert!(cache.get(100, |_| {}));
+    }
+
+    #[test]
+    fn ttl_clamping() {
+        let mut cache : HashCache<&str,u32> = CacheBuilder::new()
+            .min_ttl(Duration::from_secs(1))
+            .max_ttl(Duration::from_secs(3600))
+            .expire_after(|_, v| Some(Duration::from_secs(*v as u64)))
+            .build();
+        cache.insert_ttl("churn", 1, Duration::from_micros(10));
+        cache.insert_ttl("forever", 1, Duration::from_secs(u32::MAX as u64));
+        cache.insert("policy", 0);
+        cache.insert_pinned("persistent", 1);
+        assert_eq!(Some(Duration::from_secs(1)), cache.metadata(&"churn").unwrap().ttl);
+        assert_eq!(Some(Duration::from_secs(3600)), cache.metadata(&"forever").unwrap().ttl);
+        assert_eq!(Some(Duration::from_secs(1)), cache.metadata(&"policy").unwrap().ttl);
+        assert_eq!(None, cache.metadata(&"persistent").unwrap().ttl);
+
+        cache.touch(&"churn", Some(Duration::from_secs(7200)));
+        assert_eq!(Some(Duration::from_secs(3600)), cache.metadata(&"churn").unwrap().ttl);
+    }
+
+    #[test]
+    fn expire_all_after() {
+        let clock = crate::clock::ManualClock::new();
+        let mut cache : HashCache<&str,u32> = CacheBuilder::new().clock(clock.clone()).build();
+        cache.insert_ttl("long", 1, Duration::new(3600, 0));
+        cache.insert_ttl("short", 1, Duration::new(10, 0));
+        cache.insert("persistent", 1);
+
+        assert_eq!(1, cache.expire_all_after(Duration::new(30, 0), false));
+        clock.advance(Duration::new(20, 0));
+        assert!(!cache.get("short", |_| {}));
+        assert!(cache.get("long", |_| {}));
+        clock.advance(Duration::new(11, 0));
+        assert!(!cache.get("long", |_| {}));
+        assert!(cache.get("persistent", |_| {}));
+
+        // persistent entries are only capped on request, and join the expiring index
+        assert_eq!(1, cache.expire_all_after(Duration::new(30, 0), true));
+        clock.advance(Duration::new(31, 0));
+        cache.vacuum(10, 0.25);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn overwrite_policy() {
+        let clock = crate::clock::ManualClock::new();
+        let mut cache : HashCache<&str,u32> = CacheBuilder::new()
+            .overwrite(OverwritePolicy::KeepDeadline)
+            .clock(clock.clone())
+            .build();
+        cache.insert_ttl("token", 1, Duration::new(60, 0));
+        cache.insert("persistent", 1);
+
+        // refreshing the value doesn't push its deadline out
+        clock.advance(Duration::new(50, 0));
+        assert_eq!(Some(1), cache.insert_ttl("token", 2, Duration::new(60, 0)));
+        assert_eq!(Some(2), cache.get_copied(&"token"));
+        // an entry without a deadline takes the new one
+        cache.insert_ttl("persistent", 2, Duration::new(20, 0));
+
+        clock.advance(Duration::new(11, 0));
+        assert!(!cache.get("token", |_| {}));
+        assert!(cache.get("persistent", |_| {}));
+
+        // an expired entry is replaced like an absent one
+        cache.insert_ttl("token", 3, Duration::new(60, 0));
+        clock.advance(Duration::new(30, 0));
+        assert_eq!(Some(3), cache.get_copied(&"token"));
+        assert!(!cache.get("persistent", |_| {}));
+    }
+
+    #[test]
+    fn exact_expiry() {
+        let clock = crate::clock::ManualClock::new();
+        let mut cache : HashCache<u32,u32> = CacheBuilder::new().exact_expiry(true).clock(clock.clone()).build();
+        for i in 0..100 {
+            cache.insert_ttl(i, i, Duration::new(i as u64, 0));
+        }
+        cache.insert(100, 100);
+        // outdated deadlines don't remove anything: 99 gets an earlier one, 98 a later one
+        cache.insert_ttl(99, 99, Duration::new(10, 0));
+        cache.touch(&98, Some(Duration::new(200, 0)));
+
+        clock.advance(Duration::from_millis(50_500));
+        cache.vacuum(1, 0.5);
+        assert_eq!(101 - 52, cache.len());
+        assert!((51..98).all(|i| cache.get(i, |_| {})));
+        assert!(cache.get(98, |_| {}));
+        assert_eq!(52, cache.stats().expirations);
+
+        // re-inserting and removing leaves outdated records behind, which are compacted away
+        for _ in 0..100 {
+            for i in 200..210 {
+                cache.insert_ttl(i, i, Duration::from_millis(100));
+            }
+        }
+        clock.advance(Duration::from_millis(200));
+        cache.vacuum(1, 0.5);
+        assert_eq!(101 - 52, cache.len());
+        assert!(cache.deadlines.as_ref().unwrap().len() <= 2 * cache.expiring.len() + 64);
+    }
+
+    #[test]
+    fn unbounded_ttl() {
+        // a deadline past what an Instant can hold is never reached, with or without the heap
+        for exact in [false, true] {
+            let mut cache : HashCache<&str,&str> = CacheBuilder::new().exact_expiry(exact).build();
+            cache.insert_ttl("a", "b", Duration::MAX);
+            assert!(cache.get("a", |v| assert_eq!(*v, "b")));
+            cache.vacuum(10, 0.25);
+            assert_eq!(1, cache.len());
+        }
+    }
+
+    #[test]
+    fn next_expiration() {
+        for exact in [false, true] {
+            let start = Instant::now();
+            let clock = crate::clock::ManualClock::new();
+            let mut cache : HashCache<u32,u32> = CacheBuilder::new().exact_expiry(exact).clock(clock.clone()).build();
+            cache.insert(0, 0);
+            assert_eq!(None, cache.next_expiration());
+
+            cache.insert_ttl(1, 1, Duration::new(30, 0));
+            cache.insert_ttl(2, 2, Duration::new(10, 0));
+            let at = cache.next_expiration().unwrap();
+            assert!(at >= start + Duration::new(10, 0) && at < start + Duration::new(11, 0));
+
+            // removing or refreshing the soonest entry moves the next expiration
+            cache.touch(&2, Some(Duration::new(60, 0)));
+            assert_eq!(cache.metadata(&1).unwrap().expires_at(), cache.next_expiration());
+            cache.take(1);
+            assert_eq!(cache.metadata(&2).unwrap().expires_at(), cache.next_expiration());
+        }
+    }
+
+    #[test]
+    fn cold_keys() {
+        let clock = crate::clock::ManualClock::new();
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new().clock(clock.clone()).build();
+        cache.insert("a", "1");
+        clock.advance(Duration::new(10, 0));
+        cache.insert("b", "2");
+        cache.insert("c", "3");
+        clock.advance(Duration::new(10, 0));
+        cache.get("c", |_| {});
+
+        assert_eq!(vec!["a", "b"], cache.cold_keys(Duration::new(5, 0)));
+        assert_eq!(vec!["a"], cache.cold_keys(Duration::new(15, 0)));
+        assert!(cache.cold_keys(Duration::new(30, 0)).is_empty());
+    }
+
+    #[test]
+    fn get_stale() {
+        let mut cache : HashCache<&str,&str> = HashCache::new();
+        cache.insert("id", "secret");
+        cache.insert_ttl("token", "secret2", Duration::from_millis(5));
+        assert_eq!(Some((&"secret", Duration::ZERO)), cache.get_stale(&"id"));
+        assert_eq!(None, cache.get_stale(&"nope"));
+
+        sleep(Duration::from_millis(15));
+        assert!(!cache.get("token", |_| {}));
+        let (value, stale) = cache.get_stale(&"token").unwrap();
+        assert_eq!("secret2", *value);
+        assert!(stale >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn take_once() {
+        let cache : ThreadSafeHashCache<u32,u32> = ThreadSafeHashCache::new();
+        for nonce in 0..100 {
+            cache.insert_ttl(nonce, nonce, Duration::new(60, 0));
+        }
+        let cache = Arc::new(cache);
+
+        // racing consumers redeem every nonce exactly once between them
+        let consumers : Vec<_> = (0..4).map(|_| {
+            let cache = cache.clone();
+            spawn(move || (0..100).filter(|n| cache.take_once(n).is_some()).count())
+        }).collect();
+        assert_eq!(100, consumers.into_iter().map(|c| c.join().unwrap()).sum::<usize>());
+
+        ThreadSafeHashCache::insert_ttl(&cache, 0, 0, Duration::from_millis(1));
+        sleep(Duration::from_millis(5));
+        assert_eq!(None, cache.take_once(&0));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn fetch_update() {
+        let cache : ThreadSafeHashCache<&str,u32> = ThreadSafeHashCache::new();
+        cache.insert_ttl("count", 0, Duration::new(60, 0));
+        let cache = Arc::new(cache);
+
+        // racing increments are retried rather than lost
+        let writers : Vec<_> = (0..4).map(|_| {
+            let cache = cache.clone();
+            spawn(move || for _ in 0..100 {
+                cache.fetch_update(&"count", |n| Some(n + 1)).unwrap();
+            })
+        }).collect();
+        writers.into_iter().for_each(|w| w.join().unwrap());
+        assert_eq!(Some(400), cache.get_copied(&"count"));
+        assert_eq!(Some(Duration::new(60, 0)), cache.metadata(&"count").unwrap().ttl);
+
+        assert_eq!(Err(Some(400)), cache.fetch_update(&"count", |_| None));
+        assert_eq!(Err(None), cache.fetch_update(&"missing", |n| Some(n + 1)));
+
+        // a write between reading the value and storing the new one makes f run again
+        let mut calls = 0;
+        assert_eq!(Ok(401), cache.fetch_update(&"count", |n| {
+            calls += 1;
+            if calls == 1 {
+                ThreadSafeHashCache::insert_ttl(&cache, "count", 401, Duration::new(60, 0));
+            }
+            Some(n * 2)
+        }));
+        assert_eq!(2, calls);
+        assert_eq!(Some(802), cache.get_copied(&"count"));
+    }
+
+    #[test]
+    fn panicking_closures() {
+        let cache : ThreadSafeHashCache<&str,u32> = ThreadSafeHashCache::new();
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        let cache = Arc::new(cache);
+
+        // a panic under the write lock poisons it; the cache carries on for everyone else
+        let updater = cache.clone();
+        assert!(spawn(move || updater.update(&"a", |v| {
+            *v = 10;
+            panic!("update failed")
+        })).join().is_err());
+        let reader = cache.clone();
+        assert!(spawn(move || reader.get("b", |_| panic!("get failed"))).join().is_err());
+
+        assert_eq!(Some(10), cache.get_copied(&"a"));
+        assert_eq!(Some(3), cache.update(&"b", |v| { *v += 1; *v }));
+        ThreadSafeHashCache::insert(&cache, "c", 3);
+        assert_eq!(Some(3), ThreadSafeHashCache::take(&cache, "c"));
+        assert_eq!(2, cache.len());
+    }
+
+    #[test]
+    fn negative_filter() {
+        let cache : ThreadSafeHashCache<usize,usize> = CacheBuilder::new().negative_filter(1000, 0.01).build_thread_safe();
+        for i in 0..100 {
+            cache.insert_ttl(i, i, Duration::new(10, 0));
+        }
+        assert!((0..100).all(|i| cache.get(i, |v| assert_eq!(*v, i))));
+        assert!((100..200).all(|i| !cache.get(i, |_| panic!("expected none"))));
+
+        // removed keys are dropped from the filter as well as the store
+        for i in 0..100 {
+            assert_eq!(Some(i), cache.take(i));
+        }
+        cache.retain(|_, _, _| false);
+        let filter = cache.filter.as_ref().unwrap();
+        assert!((0..100).filter(|i| filter.may_contain(i)).count() < 10);
+
+        // clones get their own copy of the filter
+        let mut original : HashCache<usize,usize> = CacheBuilder::new().negative_filter(1000, 0.01).build();
+        let mut cloned = original.clone();
+        cloned.insert(1, 1);
+        assert!(!original.get(1, |_| panic!("expected none")));
+        original.insert(2, 2);
+        assert!(original.get(2, |_| {}));
+    }
+
+    #[test]
+    fn early_expiration() {
+        let mut cache : HashCache<&str,&str> = CacheBuilder::new().early_expiration(1.0).build();
+        cache.insert_ttl("plain", "secret", Duration::from_millis(100));
+        cache.insert_ttl_with_cost("cheap", "secret", Duration::new(10, 0), Duration::from_nanos(1));
+        cache.insert_ttl_with_cost("costly", "secret", Duration::from_millis(100), Duration::new(10, 0));
+
+        // entries without a cost, or far from their deadline, are never expired early
+        assert!((0..100).all(|_| cache.get("plain", |_| {})));
+        assert!((0..100).all(|_| cache.get("cheap", |_| {})));
+
+        // a recompute cost far exceeding the TTL makes early misses all but certain
+        assert!((0..100).any(|_| !cache.get("costly", |_| {})));
+
+        // without the builder option costs are recorded but ignored
+        let mut cache : HashCache<&str,&str> = HashCache::new();
+        cache.insert_ttl_with_cost("costly", "secret", Duration::from_millis(100), Duration::new(10, 0));
+        assert!((0..100).all(|_| cache.get("costly", |_| {})));
+    }
+
+    #[test]
+    fn expire_after_policy() {
+        let mut cache : HashCache<&str,(&str,u64)> = CacheBuilder::new()
+            .expire_after(|_, v: &(&str, u64)| if v.1 > 0 { Some(Duration::from_millis(v.1)) } else { None })
+            .build();
+        cache.insert("token", ("secret", 10));
+        cache.insert("static", ("secret", 0));
+
+        // an explicit TTL takes precedence over the policy
+        cache.insert_ttl("explicit", ("secret", 10), Duration::new(10, 0));
         assert_eq!(2, cache.expiring.len());
+
+        sleep(Duration::from_millis(20));
+        assert!(!cache.get("token", |_| panic!("expected none")));
+        assert!(cache.get("static", |_| {}));
+        assert!(cache.get("explicit", |_| {}));
+    }
+
+    #[test]
+    fn expiring_index_slots() {
+        let mut cache : HashCache<usize,usize> = HashCache::new();
+        cache.extend((0..100).map(|i| (i, i, Duration::from_millis(if i % 2 == 0 { 10 } else { 10_000 }))));
+
+        // overwriting reuses the key's slot, and a persistent overwrite drops it from the index
+        cache.insert_ttl(1, 1, Duration::new(10, 0));
+        cache.insert(3, 3);
+        assert_eq!(99, cache.expiring.len());
+
+        sleep(Duration::from_millis(20));
+        cache.vacuum(100, 0.25);
+        assert_eq!(50, cache.store.len());
+        assert_eq!(49, cache.expiring.len());
+
+        // every indexed entry points back at its own slot
+        for (slot, &index) in cache.expiring.iter().enumerate() {
+            assert_eq!(Some(slot), cache.entries[index].1.slot);
+        }
+    }
+
+    #[test]
+    fn get_copied() {
+        let mut cache : HashCache<&str,u64> = HashCache::new();
+        cache.insert("hits", 42);
+        cache.insert_ttl("seen", 7, Duration::from_millis(10));
+        assert_eq!(Some(42), cache.get_copied(&"hits"));
+        assert_eq!(Some(7), cache.get_copied(&"seen"));
+        assert_eq!(None, cache.get_copied(&"nope"));
+
+        sleep(Duration::from_millis(20));
+        assert_eq!(None, cache.get_copied(&"seen"));
+
+        let cache : ThreadSafeHashCache<&str,u64> = ThreadSafeHashCache::new();
+        cache.insert("hits", 42);
+        assert_eq!(Some(42), cache.get_copied(&"hits"));
+    }
+
+    #[test]
+    fn stats() {
+        let cache : ThreadSafeHashCache<&str,&str> = CacheBuilder::new()
+            .max_capacity(1)
+            .negative_filter(100, 0.01)
+            .build_thread_safe();
+        cache.insert("id", "secret");
+        cache.insert("id2", "secret2");
+        assert!(cache.get("id2", |_| {}));
+        // answered by the negative filter without reaching the inner cache
+        assert!(!cache.get("id", |_| {}));
+
+        let stats = cache.stats();
+        assert_eq!((1, 1, 2, 1), (stats.hits, stats.misses, stats.inserts, stats.evictions));
+        assert_eq!(1, cache.len());
+    }
+
+    #[test]
+    fn threadsafe_take() {
+        let cache : ThreadSafeHashCache<&str,&str> = ThreadSafeHashCache::new();
+        cache.insert_ttl("id", "secret", Duration::new(1, 0));
+        assert_eq!(Some("secret"), cache.take("id"));
+        assert!(!cache.get("id", |_| panic!("expected none")));
+        assert_eq!(0, cache.read().expiring.len());
     }
 
     #[test]
     fn threadsafe_cache_e2e() {
-        let cache : Arc<RwLock<ThreadSafeHashCache<&str,&str>>> = Arc::new(RwLock::new(ThreadSafeHashCache::new()));
-        let vacuum_cache = cache.clone();
+        let mut cache : Arc<ThreadSafeHashCache<&str,&str>> = Arc::new(ThreadSafeHashCache::new());
+        let mut vacuum_cache = cache.clone();
 
         // start a vacuum thread
         spawn(move || {
             loop {
-                let mut c = vacuum_cache.write().expect("poisoned lock");
-                c.vacuum(10, 0.25);
+                vacuum_cache.vacuum(10, 0.25);
                 sleep(Duration::new(1,0));
             }
         });
 
-        let c = cache.clone();
-
         // insert a value
-        {
-            let mut outer = c.write().expect("poisoned lock");
-            outer.insert_ttl("id", "secret", Duration::new(1, 0));
-        }
+        cache.insert_ttl("id", "secret", Duration::new(1, 0));
 
         sleep(Duration::new(2,0));
 
         // check that key was vacuumed
-        {
-            let outer = c.read().expect("poisoned lock");
-            assert_eq!(0, outer.expiring.read().expect("poisoned lock").len());
-        }
+        assert_eq!(0, cache.read().expiring.len());
+    }
+
+    // accept_cache is a library function that takes any cache implementation
+    fn accept_cache<C: Cache<&'static str, &'static str>>(mut cache: C) {
+        cache.insert_ttl("id", "secret", Duration::new(10, 0));
+        assert!(cache.get("id", |v| assert_eq!(*v, "secret")));
+        assert_eq!(Some("secret"), cache.take("id"));
+    }
+
+    #[test]
+    fn wrapper_impls() {
+        let mut shared = Arc::new(ThreadSafeHashCache::new());
+        accept_cache(shared.clone());
+        shared.insert("id", "secret");
+        assert!(shared.get("id", |v| assert_eq!(*v, "secret")));
+
+        accept_cache(Mutex::new(HashCache::new()));
+        accept_cache(RwLock::new(HashCache::new()));
     }
 }
@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::{DefaultHashBuilder, ThreadSafeHashCache};
+
+// Loader produces the value for a key on a miss, or None if there is no value to cache
+pub type Loader<K, V> = dyn Fn(&K) -> Option<V> + Send + Sync;
+
+// LoadingCache is a ThreadSafeHashCache with an attached loader: misses are loaded (and cached
+// with the configured TTL) transparently. With refresh_after, a hit within that window of its
+// deadline triggers a background reload so hot keys are replaced before they ever miss; with
+// stale_while_revalidate, a hit shortly past its deadline is served stale while it reloads.
+pub struct LoadingCache<K: Hash+Eq+Clone, V, S = DefaultHashBuilder> {
+    cache: Arc<ThreadSafeHashCache<K, V, S>>,
+    loader: Arc<Loader<K, V>>,
+    ttl: Duration,
+    refresh_after: Option<Duration>,
+    stale_while_revalidate: Option<Duration>,
+    // keys with a background refresh in flight, so a hot key only spawns one reload at a time
+    refreshing: Arc<Mutex<HashSet<K>>>,
+}
+
+impl<K, V, S> LoadingCache<K, V, S>
+    where K: Hash+Eq+Clone+Send+Sync+'static, V: Clone+Send+Sync+'static, S: BuildHasher+Send+Sync+'static {
+    pub fn new<F>(cache: ThreadSafeHashCache<K, V, S>, ttl: Duration, loader: F) -> LoadingCache<K, V, S>
+        where F: Fn(&K) -> Option<V> + Send + Sync + 'static {
+        let (refresh_after, stale_while_revalidate) = {
+            let inner = cache.read_index();
+            (inner.config.refresh_after, inner.config.stale_while_revalidate)
+        };
+        LoadingCache{
+            cache: Arc::new(cache),
+            loader: Arc::new(loader),
+            ttl,
+            refresh_after,
+            stale_while_revalidate,
+            refreshing: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    // cache is the underlying cache, e.g. for inserting or vacuuming directly
+    pub fn cache(&self) -> &Arc<ThreadSafeHashCache<K, V, S>> {
+        &self.cache
+    }
+
+    // get returns the cached value, loading it on a miss
+    pub fn get(&self, key: K) -> Option<V> {
+        let hit = {
+            let inner = self.cache.read_key(&key);
+            let now = inner.now();
+            // SAFETY (for both gets): read_key holds the key's stripe
+            match inner.lookup(&key) {
+                Some(v) if !v.expired(now) => Some((unsafe { v.value.get() }.clone(), v.meta(now).expires_at(), now, false)),
+                // an expired entry within the stale window is served as is and reloaded
+                Some(v) if self.stale_while_revalidate.is_some_and(|window| {
+                    v.meta(now).expires_at().is_some_and(|at| now.saturating_duration_since(at) <= window)
+                }) => Some((unsafe { v.value.get() }.clone(), None, now, true)),
+                _ => None,
+            }
+        };
+
+        match hit {
+            Some((value, _, _, true)) => {
+                self.refresh(key);
+                Some(value)
+            },
+            Some((value, expires_at, now, false)) => {
+                if let (Some(window), Some(at)) = (self.refresh_after, expires_at) {
+                    if at.saturating_duration_since(now) <= window {
+                        self.refresh(key);
+                    }
+                }
+                Some(value)
+            },
+            None => {
+                let value = (self.loader)(&key)?;
+                self.cache.insert_ttl(key, value.clone(), self.ttl);
+                Some(value)
+            },
+        }
+    }
+
+    // refresh reloads a key on a background thread, unless a reload is already running
+    fn refresh(&self, key: K) {
+        if !self.refreshing.lock().expect("lock poisoned").insert(key.clone()) {
+            return
+        }
+
+        let cache = self.cache.clone();
+        let loader = self.loader.clone();
+        let refreshing = self.refreshing.clone();
+        let ttl = self.ttl;
+        thread::spawn(move || {
+            // a loader returning None leaves the current entry to expire normally
+            if let Some(value) = loader(&key) {
+                cache.insert_ttl(key.clone(), value, ttl);
+            }
+            refreshing.lock().expect("lock poisoned").remove(&key);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CacheBuilder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn load_on_miss() {
+        let loads = Arc::new(AtomicUsize::new(0));
+        let counter = loads.clone();
+        let cache = CacheBuilder::new().build_loading(Duration::new(10, 0), move |k: &u32| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            if *k == 0 { None } else { Some(k * 2) }
+        });
+
+        assert_eq!(Some(4), cache.get(2));
+        assert_eq!(Some(4), cache.get(2));
+        assert_eq!(1, loads.load(Ordering::SeqCst));
+
+        // missing values aren't cached
+        assert_eq!(None, cache.get(0));
+        assert_eq!(None, cache.get(0));
+        assert_eq!(3, loads.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn refresh_ahead() {
+        let loads = Arc::new(AtomicUsize::new(0));
+        let counter = loads.clone();
+        let cache = CacheBuilder::new()
+            .refresh_after(Duration::from_millis(150))
+            .build_loading(Duration::from_millis(200), move |_: &&str| {
+                Some(counter.fetch_add(1, Ordering::SeqCst))
+            });
+
+        assert_eq!(Some(0), cache.get("id"));
+
+        // inside the refresh window the current value is served while a reload runs
+        sleep(Duration::from_millis(100));
+        assert_eq!(Some(0), cache.get("id"));
+        sleep(Duration::from_millis(50));
+        assert_eq!(2, loads.load(Ordering::SeqCst));
+        assert_eq!(Some(1), cache.get("id"));
+
+        // past the original deadline the refreshed entry is still live
+        sleep(Duration::from_millis(100));
+        assert!(cache.cache().get("id", |_| {}));
+    }
+
+    #[test]
+    fn stale_while_revalidate() {
+        let loads = Arc::new(AtomicUsize::new(0));
+        let counter = loads.clone();
+        let cache = CacheBuilder::new()
+            .stale_while_revalidate(Duration::from_millis(100))
+            .build_loading(Duration::from_millis(20), move |_: &&str| {
+                Some(counter.fetch_add(1, Ordering::SeqCst))
+            });
+
+        assert_eq!(Some(0), cache.get("id"));
+
+        // just past its deadline the stale value is served while a reload runs
+        sleep(Duration::from_millis(30));
+        assert_eq!(Some(0), cache.get("id"));
+        sleep(Duration::from_millis(10));
+        assert_eq!(2, loads.load(Ordering::SeqCst));
+        assert_eq!(Some(1), cache.get("id"));
+
+        // past the stale window it's an ordinary miss
+        sleep(Duration::from_millis(150));
+        assert_eq!(Some(2), cache.get("id"));
+        assert_eq!(3, loads.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,177 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{DefaultHashBuilder, ThreadSafeHashCache};
+
+// INSTANCES numbers LocalCaches, to tell their entries apart in a thread's storage
+static INSTANCES: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // L0 holds each LocalCache's copies for this thread, by instance number
+    static L0: RefCell<HashMap<u64, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+// Copied is a value copied out of the shared cache, valid while the cache's write generation is
+// unchanged, until fresh_until
+struct Copied<V> {
+    value: V,
+    generation: u64,
+    fresh_until: Instant,
+}
+
+// LocalCache puts a small per-thread cache in front of a shared ThreadSafeHashCache, so repeated
+// reads of the hottest keys take no lock at all. A thread's copy of a value is used until any
+// write to the shared cache (which bumps its write generation), until ttl has passed, or until
+// the entry's own deadline, whichever comes first; so keep ttl short. Reads answered locally
+// don't reach the shared cache: they aren't counted in its stats and don't refresh its eviction
+// order or idle timers. Each thread keeps at most capacity copies per LocalCache, which stay
+// allocated until the thread exits.
+pub struct LocalCache<K: Hash+Eq+Clone, V, S = DefaultHashBuilder> {
+    shared: Arc<ThreadSafeHashCache<K, V, S>>,
+    id: u64,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl<K: Hash+Eq+Clone+'static, V: Clone+'static, S: BuildHasher> LocalCache<K, V, S> {
+    // panics if capacity is 0
+    pub fn new(shared: Arc<ThreadSafeHashCache<K, V, S>>, capacity: usize, ttl: Duration) -> LocalCache<K, V, S> {
+        assert!(capacity > 0);
+        LocalCache{ shared, id: INSTANCES.fetch_add(1, Ordering::Relaxed), capacity, ttl }
+    }
+
+    pub fn shared(&self) -> &Arc<ThreadSafeHashCache<K, V, S>> {
+        &self.shared
+    }
+
+    // with_copies runs f on this thread's copies
+    fn with_copies<R>(&self, f: impl FnOnce(&mut HashMap<K, Copied<V>>) -> R) -> R {
+        L0.with(|l0| {
+            let mut l0 = l0.borrow_mut();
+            let copies = l0.entry(self.id).or_insert_with(|| Box::new(HashMap::<K, Copied<V>>::new()));
+            f(copies.downcast_mut().expect("instance numbers are unique"))
+        })
+    }
+
+    // get returns a clone of a live value, from this thread's copy if it's still current
+    pub fn get(&self, key: &K) -> Option<V> {
+        let generation = self.shared.generation.get();
+        let now = self.shared.now();
+        let local = self.with_copies(|copies| match copies.get(key) {
+            Some(c) if c.generation == generation && now < c.fresh_until => Some(c.value.clone()),
+            _ => None,
+        });
+        if local.is_some() {
+            return local
+        }
+
+        let copy = {
+            let inner = self.shared.read_key(key);
+            // no writer holds the lock or the key's stripe, so the generation is no older than what's
+            // copied
+            let generation = self.shared.generation.get();
+            let at = inner.now();
+            let v = inner.lookup(key).filter(|v| !v.expired(at))?;
+            let left = v.expires_at().map_or(self.ttl, |deadline| deadline.saturating_duration_since(at));
+            // SAFETY: read_key holds the key's stripe
+            Copied{ value: unsafe { v.value.get() }.clone(), generation, fresh_until: now + left.min(self.ttl) }
+        };
+        let value = copy.value.clone();
+        self.with_copies(|copies| {
+            if copies.len() >= self.capacity && !copies.contains_key(key) {
+                copies.retain(|_, c| c.generation == copy.generation && now < c.fresh_until);
+                if copies.len() >= self.capacity {
+                    let oldest = copies.iter().min_by_key(|(_, c)| c.fresh_until).map(|(k, _)| k.clone());
+                    copies.remove(&oldest.expect("copies is full"));
+                }
+            }
+            copies.insert(key.clone(), copy);
+        });
+        Some(value)
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shared.insert(key, value)
+    }
+
+    pub fn insert_ttl(&self, key: K, value: V, ttl: Duration) -> Option<V> {
+        self.shared.insert_ttl(key, value, ttl)
+    }
+
+    pub fn take(&self, key: K) -> Option<V> {
+        self.shared.take(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::ManualClock;
+    use crate::{CacheBuilder, LocalCache, ThreadSafeHashCache};
+    use std::sync::Arc;
+    use std::thread::{sleep, spawn};
+    use std::time::Duration;
+
+    #[test]
+    fn local_cache() {
+        let shared : Arc<ThreadSafeHashCache<u32,u32>> = Arc::new(ThreadSafeHashCache::new());
+        let local = Arc::new(LocalCache::new(shared.clone(), 2, Duration::from_millis(50)));
+        local.insert(1, 10);
+        local.insert(2, 20);
+        local.insert(3, 30);
+
+        // the first read copies the value; later ones take no lock
+        assert_eq!(Some(10), local.get(&1));
+        let reads = shared.contention().reads;
+        for _ in 0..10 {
+            assert_eq!(Some(10), local.get(&1));
+        }
+        assert_eq!(reads, shared.contention().reads);
+        assert_eq!(None, local.get(&4));
+
+        // another thread has copies of its own
+        let other = local.clone();
+        assert_eq!(Some(10), spawn(move || other.get(&1)).join().unwrap());
+        assert_eq!(reads + 2, shared.contention().reads);
+
+        // a write anywhere makes every copy stale
+        local.insert(4, 40);
+        assert_eq!(Some(10), local.get(&1));
+        assert_eq!(reads + 3, shared.contention().reads);
+
+        // at capacity, copying a third key drops one of the others
+        local.get(&2);
+        local.get(&3);
+        local.with_copies(|copies| assert_eq!(2, copies.len()));
+
+        // copies last no longer than ttl, or the entry's own deadline
+        local.insert_ttl(5, 50, Duration::from_millis(10));
+        assert_eq!(Some(10), local.get(&1));
+        assert_eq!(Some(50), local.get(&5));
+        sleep(Duration::from_millis(20));
+        assert_eq!(None, local.get(&5));
+        let reads = shared.contention().reads;
+        assert_eq!(Some(10), local.get(&1));
+        assert_eq!(reads, shared.contention().reads);
+        sleep(Duration::from_millis(40));
+        assert_eq!(Some(10), local.get(&1));
+        assert_eq!(reads + 1, shared.contention().reads);
+    }
+
+    #[test]
+    fn local_cache_clock() {
+        let clock = ManualClock::new();
+        let shared : Arc<ThreadSafeHashCache<u32,u32>> = Arc::new(CacheBuilder::new().clock(clock.clone()).build_thread_safe());
+        let local = LocalCache::new(shared.clone(), 2, Duration::new(10, 0));
+        local.insert_ttl(1, 10, Duration::new(5, 0));
+        assert_eq!(Some(10), local.get(&1));
+
+        // copies go stale by the shared cache's clock, not the system's
+        clock.advance(Duration::new(6, 0));
+        assert_eq!(None, local.get(&1));
+    }
+}
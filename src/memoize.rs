@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use crate::{CacheBuilder, ThreadSafeHashCache};
+
+// Memoized caches a function's results by its arguments. It's what #[hodor::memoize] expands to
+// (with the `macros` feature), one static per function, but can be used directly too.
+pub struct Memoized<K: Hash+Eq+Clone, V> {
+    cache: ThreadSafeHashCache<K, V>,
+    ttl: Option<Duration>,
+    // the async computations currently running, by key, for callers to wait on
+    in_flight: Mutex<HashMap<K, Arc<Flight<V>>>>,
+}
+
+impl<K: Hash+Eq+Clone, V: Clone> Memoized<K, V> {
+    // new memoizes results for ttl (or until evicted, if None), keeping at most capacity of them
+    // (or any number, if None)
+    pub fn new(ttl: Option<Duration>, capacity: Option<usize>) -> Memoized<K, V> {
+        let mut builder = CacheBuilder::new();
+        if let Some(capacity) = capacity {
+            builder = builder.max_capacity(capacity);
+        }
+        Memoized{ cache: builder.build_thread_safe(), ttl, in_flight: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn cache(&self) -> &ThreadSafeHashCache<K, V> {
+        &self.cache
+    }
+
+    // get_or_insert_with returns the memoized result for key, calling f to compute it on a miss.
+    // f runs without the cache locked, so concurrent misses on one key may each call it.
+    pub fn get_or_insert_with<F>(&self, key: K, f: F) -> V where F: FnOnce() -> V {
+        if let Some(value) = self.cache.read_key(&key).hit(&key).cloned() {
+            return value
+        }
+        let value = f();
+        self.store(key, value.clone());
+        value
+    }
+
+    // get_or_insert_async is get_or_insert_with for async computations, which are coalesced:
+    // while f's future is running for a key, other callers with that key wait for its result
+    // instead of starting their own. If the running caller is dropped before finishing, one of
+    // the waiting callers takes over.
+    pub async fn get_or_insert_async<F, Fut>(&self, key: K, f: F) -> V where F: FnOnce() -> Fut, Fut: Future<Output = V> {
+        let mut f = Some(f);
+        loop {
+            let joined = {
+                let mut in_flight = self.in_flight.lock().expect("lock poisoned");
+                // checked under the in_flight lock, so a computation can't finish unseen between
+                // the check and joining it
+                if let Some(value) = self.cache.read_key(&key).hit(&key).cloned() {
+                    return value
+                }
+                match in_flight.get(&key) {
+                    Some(flight) => Some(flight.clone()),
+                    None => {
+                        in_flight.insert(key.clone(), Arc::new(Flight::new()));
+                        None
+                    },
+                }
+            };
+
+            match joined {
+                Some(flight) => match (Wait{ flight }).await {
+                    Some(value) => return value,
+                    None => continue,
+                },
+                None => {
+                    let mut running = Running{ memo: self, key: Some(key) };
+                    let f = f.take().expect("only called once");
+                    let value = f().await;
+                    running.finish(value.clone());
+                    return value
+                },
+            }
+        }
+    }
+
+    fn store(&self, key: K, value: V) {
+        match self.ttl {
+            Some(ttl) => self.cache.insert_ttl(key, value, ttl),
+            None => self.cache.insert(key, value),
+        };
+    }
+}
+
+// Flight is the shared state of a running async computation
+struct Flight<V> {
+    state: Mutex<FlightState<V>>,
+}
+
+enum FlightState<V> {
+    Running(Vec<Waker>),
+    Done(V),
+    // the computing caller was dropped before finishing
+    Abandoned,
+}
+
+impl<V: Clone> Flight<V> {
+    fn new() -> Flight<V> {
+        Flight{ state: Mutex::new(FlightState::Running(Vec::new())) }
+    }
+
+    fn complete(&self, state: FlightState<V>) {
+        let previous = std::mem::replace(&mut *self.state.lock().expect("lock poisoned"), state);
+        if let FlightState::Running(wakers) = previous {
+            wakers.into_iter().for_each(Waker::wake);
+        }
+    }
+}
+
+// Wait waits for a flight, resolving to its value or None if it was abandoned
+struct Wait<V> {
+    flight: Arc<Flight<V>>,
+}
+
+impl<V: Clone> Future for Wait<V> {
+    type Output = Option<V>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<V>> {
+        match &mut *self.flight.state.lock().expect("lock poisoned") {
+            FlightState::Running(wakers) => {
+                if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            },
+            FlightState::Done(value) => Poll::Ready(Some(value.clone())),
+            FlightState::Abandoned => Poll::Ready(None),
+        }
+    }
+}
+
+// Running is held by the caller computing a key; dropping it unfinished abandons the flight
+struct Running<'a, K: Hash+Eq+Clone, V: Clone> {
+    memo: &'a Memoized<K, V>,
+    key: Option<K>,
+}
+
+impl<K: Hash+Eq+Clone, V: Clone> Running<'_, K, V> {
+    fn finish(&mut self, value: V) {
+        let key = self.key.take().expect("finished once");
+        self.memo.store(key.clone(), value.clone());
+        self.end(&key, FlightState::Done(value));
+    }
+
+    fn end(&self, key: &K, state: FlightState<V>) {
+        let flight = self.memo.in_flight.lock().expect("lock poisoned").remove(key);
+        if let Some(flight) = flight {
+            flight.complete(state);
+        }
+    }
+}
+
+impl<K: Hash+Eq+Clone, V: Clone> Drop for Running<'_, K, V> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.end(&key, FlightState::Abandoned);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Memoized;
+    use std::cell::Cell;
+    use std::future::Future;
+    use std::task::{Context, Waker};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn memoizes() {
+        let memo : Memoized<(u32, u32), u32> = Memoized::new(Some(Duration::from_millis(10)), Some(2));
+        let calls = Cell::new(0);
+        let add = |a: u32, b: u32| memo.get_or_insert_with((a, b), || { calls.set(calls.get() + 1); a + b });
+
+        assert_eq!(3, add(1, 2));
+        assert_eq!(3, add(1, 2));
+        assert_eq!(1, calls.get());
+        assert_eq!(5, add(2, 3));
+        assert_eq!(2, calls.get());
+
+        sleep(Duration::from_millis(20));
+        assert_eq!(3, add(1, 2));
+        assert_eq!(3, calls.get());
+    }
+
+    #[test]
+    fn coalesces() {
+        let memo : Memoized<u32, u32> = Memoized::new(Some(Duration::new(60, 0)), None);
+        let calls = Cell::new(0);
+        let calls = &calls;
+        let double = |n: u32| memo.get_or_insert_async(n, move || async move {
+            calls.set(calls.get() + 1);
+            tokio::task::yield_now().await;
+            n * 2
+        });
+
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let results = rt.block_on(async { tokio::join!(double(1), double(1), double(2), double(1)) });
+        assert_eq!((2, 2, 4, 2), results);
+        assert_eq!(2, calls.get());
+        assert_eq!(2, rt.block_on(double(1)));
+        assert_eq!(2, calls.get());
+    }
+
+    #[test]
+    fn abandoned() {
+        let memo : Memoized<u32, u32> = Memoized::new(None, None);
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        rt.block_on(async {
+            // the first caller never finishes; the second takes over once it's dropped
+            let stuck = memo.get_or_insert_async(1, std::future::pending);
+            let waiting = memo.get_or_insert_async(1, || async { 7 });
+            let mut stuck = Box::pin(stuck);
+            let mut waiting = Box::pin(waiting);
+            let mut cx = Context::from_waker(Waker::noop());
+            assert!(stuck.as_mut().poll(&mut cx).is_pending());
+            assert!(waiting.as_mut().poll(&mut cx).is_pending());
+            drop(stuck);
+            assert_eq!(7, waiting.await);
+        });
+    }
+
+    #[cfg(feature = "macros")]
+    #[test]
+    fn attribute() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        #[crate::memoize(ttl = "30s", capacity = 10_000)]
+        fn greet(name: String, excited: bool) -> String {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            if excited {
+                return format!("hello, {}!", name)
+            }
+            format!("hello, {}", name)
+        }
+
+        assert_eq!("hello, bob!", greet("bob".to_string(), true));
+        assert_eq!("hello, bob!", greet("bob".to_string(), true));
+        assert_eq!("hello, bob", greet("bob".to_string(), false));
+        assert_eq!(2, CALLS.load(Ordering::SeqCst));
+    }
+
+    #[cfg(feature = "macros")]
+    #[test]
+    fn async_attribute() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        #[crate::memoize(ttl = "30s")]
+        async fn fetch(id: u32) -> String {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+            format!("user {}", id)
+        }
+
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let (a, b) = rt.block_on(async { tokio::join!(fetch(1), fetch(1)) });
+        assert_eq!(("user 1".to_string(), "user 1".to_string()), (a, b));
+        assert_eq!(1, CALLS.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,139 @@
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::stats::{Recorder, Stats};
+use crate::{DefaultHashBuilder, ThreadSafeHashCache};
+
+// SEPARATOR joins a namespace's name to its keys in the shared store; names can't contain it, so
+// two namespaces can never produce the same internal key
+const SEPARATOR: char = '\0';
+
+// Namespace is a handle to one partition of a shared string-keyed cache, e.g. "sessions" and
+// "pages" living in one store under one capacity limit. Each namespace has its own default TTL
+// and stats, and can be cleared without touching the others. Clones of a handle share its stats,
+// so create a namespace once and clone it rather than calling namespace again.
+pub struct Namespace<V, S = DefaultHashBuilder> {
+    cache: Arc<ThreadSafeHashCache<String, V, S>>,
+    prefix: String,
+    ttl: Option<Duration>,
+    stats: Arc<Recorder>,
+}
+
+impl<V, S: BuildHasher> ThreadSafeHashCache<String, V, S> {
+    // namespace returns a handle to the partition of this cache called name
+    // panics if name contains a NUL character
+    pub fn namespace(self: &Arc<Self>, name: &str) -> Namespace<V, S> {
+        assert!(!name.contains(SEPARATOR), "namespace names can't contain NUL");
+        Namespace{
+            cache: self.clone(),
+            prefix: format!("{}{}", name, SEPARATOR),
+            ttl: None,
+            stats: Arc::new(Recorder::default()),
+        }
+    }
+}
+
+impl<V, S: BuildHasher> Namespace<V, S> {
+    // with_ttl sets the TTL used by insert; without one, insert stores persistent entries
+    pub fn with_ttl(mut self, ttl: Duration) -> Namespace<V, S> {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.prefix[..self.prefix.len() - SEPARATOR.len_utf8()]
+    }
+
+    fn key(&self, key: &str) -> String {
+        let mut k = String::with_capacity(self.prefix.len() + key.len());
+        k.push_str(&self.prefix);
+        k.push_str(key);
+        k
+    }
+
+    // insert stores a value with the namespace's default TTL
+    pub fn insert(&self, key: &str, value: V) -> Option<V> {
+        match self.ttl {
+            Some(ttl) => self.insert_ttl(key, value, ttl),
+            None => {
+                self.stats.inserts.incr();
+                self.cache.insert(self.key(key), value)
+            },
+        }
+    }
+
+    pub fn insert_ttl(&self, key: &str, value: V, ttl: Duration) -> Option<V> {
+        self.stats.inserts.incr();
+        self.cache.insert_ttl(self.key(key), value, ttl)
+    }
+
+    pub fn get<F>(&self, key: &str, f: F) -> bool where F: Fn(&V) {
+        let hit = self.cache.get(self.key(key), f);
+        self.stats.read(hit);
+        hit
+    }
+
+    pub fn take(&self, key: &str) -> Option<V> {
+        self.cache.take(self.key(key))
+    }
+
+    // clear removes every entry in this namespace and returns how many
+    pub fn clear(&self) -> usize {
+        self.cache.invalidate_prefix(&self.prefix)
+    }
+
+    // stats counts the reads and inserts made through this namespace's handles; evictions and
+    // expirations happen in the shared store and are only reported by the cache as a whole
+    pub fn stats(&self) -> Stats {
+        self.stats.stats()
+    }
+}
+
+impl<V, S> Clone for Namespace<V, S> {
+    fn clone(&self) -> Self {
+        Namespace{
+            cache: self.cache.clone(),
+            prefix: self.prefix.clone(),
+            ttl: self.ttl,
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ThreadSafeHashCache;
+    use std::sync::Arc;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn namespaces() {
+        let cache : Arc<ThreadSafeHashCache<String,&str>> = Arc::new(ThreadSafeHashCache::new());
+        let sessions = cache.namespace("sessions").with_ttl(Duration::from_millis(10));
+        let pages = cache.namespace("pages");
+        assert_eq!("sessions", sessions.name());
+
+        sessions.insert("42", "session");
+        pages.insert("42", "page");
+        pages.insert("43", "page");
+        assert!(sessions.get("42", |v| assert_eq!(*v, "session")));
+        assert!(pages.get("42", |v| assert_eq!(*v, "page")));
+        assert!(!pages.get("44", |_| {}));
+
+        // the default TTL applies to the sessions namespace only
+        sleep(Duration::from_millis(20));
+        assert!(!sessions.get("42", |_| {}));
+        assert!(pages.get("42", |_| {}));
+
+        assert_eq!(2, pages.clear());
+        assert!(!pages.get("43", |_| {}));
+        assert_eq!(1, cache.drain_expired().count());
+
+        let stats = pages.clone().stats();
+        assert_eq!((2, 2, 2), (stats.hits, stats.misses, stats.inserts));
+        assert_eq!(0.5, stats.hit_ratio());
+        assert_eq!(1, sessions.stats().misses);
+    }
+}
@@ -0,0 +1,85 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+use crate::{CacheEvent, ThreadSafeHashCache};
+
+impl<K: Hash+Eq+Clone+Send+Sync+'static, V, S: BuildHasher> ThreadSafeHashCache<K, V, S> {
+    // notify_expired resolves once key expires or is removed (right away if it isn't live), for
+    // timeout-like patterns: do something unless the entry is refreshed in time. Overwriting or
+    // touching the entry moves the deadline it waits for. Needs a tokio runtime with time enabled.
+    pub async fn notify_expired(&self, key: K) {
+        let notify = Arc::new(Notify::new());
+        let listener: Weak<Notify> = Arc::downgrade(&notify);
+        let watched = key.clone();
+        // the listener unsubscribes at the first event after this future is dropped
+        self.write().listeners.add(Box::new(move |event: &CacheEvent<&K, &V>, _| {
+            let key = match *event {
+                CacheEvent::Inserted{ key, .. } | CacheEvent::Replaced{ key, .. } | CacheEvent::Expired{ key, .. }
+                    | CacheEvent::Evicted{ key, .. } | CacheEvent::Removed{ key, .. } => key,
+            };
+            match listener.upgrade() {
+                Some(notify) => {
+                    if *key == watched {
+                        notify.notify_one();
+                    }
+                    true
+                },
+                None => false,
+            }
+        }));
+
+        loop {
+            let deadline = {
+                let inner = self.read_key(&key);
+                let now = inner.now();
+                match inner.lookup(&key) {
+                    Some(v) if !v.expired(now) => v.expires_at(),
+                    _ => return,
+                }
+            };
+            // a change between the check above and here leaves a permit, so it isn't missed
+            match deadline {
+                // entries expire just after their deadline
+                Some(at) => tokio::select! {
+                    _ = tokio::time::sleep_until((at + Duration::from_millis(1)).into()) => {},
+                    _ = notify.notified() => {},
+                },
+                None => notify.notified().await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ThreadSafeHashCache;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn notify_expired() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        let cache : ThreadSafeHashCache<&str,&str> = ThreadSafeHashCache::new();
+        rt.block_on(async {
+            cache.notify_expired("missing").await;
+
+            let start = Instant::now();
+            cache.insert_ttl("lease", "holder", Duration::from_millis(30));
+            cache.insert("session", "data");
+            let renew = async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                cache.touch(&"lease", Some(Duration::from_millis(30)));
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                cache.take("session");
+            };
+
+            // the renewal pushes the lease's expiry back; removal ends the wait on a persistent
+            // entry
+            tokio::join!(cache.notify_expired("lease"), cache.notify_expired("session"), renew);
+            assert!(start.elapsed() >= Duration::from_millis(50));
+            assert!(!cache.get("lease", |_| {}));
+        });
+    }
+}
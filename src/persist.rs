@@ -0,0 +1,146 @@
+use std::fs;
+use std::hash::{BuildHasher, Hash};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[cfg(feature = "encryption")]
+use crate::Format;
+use crate::{DefaultHashBuilder, ThreadSafeHashCache, Warmup};
+
+// PersistentCache writes a snapshot of its cache to a file when dropped (or on flush), and can be
+// opened from that file again, so a gracefully stopped service restarts warm. Snapshots are
+// written to a temporary file and renamed into place, so a crash mid-write leaves the previous
+// snapshot intact.
+pub struct PersistentCache<K: Hash+Eq+Clone+Serialize, V: Serialize, S: BuildHasher = DefaultHashBuilder> {
+    cache: ThreadSafeHashCache<K, V, S>,
+    path: PathBuf,
+    // key seals the snapshot file, if it was opened with open_encrypted
+    #[cfg(feature = "encryption")]
+    key: Option<[u8; 32]>,
+}
+
+impl<K: Hash+Eq+Clone+Serialize, V: Serialize, S: BuildHasher> PersistentCache<K, V, S> {
+    // new persists cache to path from now on, without loading anything from it
+    pub fn new<P: AsRef<Path>>(cache: ThreadSafeHashCache<K, V, S>, path: P) -> PersistentCache<K, V, S> {
+        PersistentCache{
+            cache,
+            path: path.as_ref().to_path_buf(),
+            #[cfg(feature = "encryption")]
+            key: None,
+        }
+    }
+
+    // open warms cache from the snapshot at path, if there is one, and persists it there from now on
+    pub fn open<P: AsRef<Path>>(cache: ThreadSafeHashCache<K, V, S>, path: P, warmup: Warmup<'_>) -> io::Result<PersistentCache<K, V, S>>
+        where K: DeserializeOwned, V: DeserializeOwned
+    {
+        // the cache is only wrapped once loading worked: dropping a PersistentCache writes over the
+        // file it couldn't read
+        match cache.warm_from_snapshot(&path, warmup) {
+            Ok(_) => {},
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+        Ok(PersistentCache::new(cache, path))
+    }
+
+    // open_encrypted is open for a snapshot file sealed with key (see
+    // Snapshot::encode_encrypted), which flush then seals as well
+    #[cfg(feature = "encryption")]
+    pub fn open_encrypted<P: AsRef<Path>>(cache: ThreadSafeHashCache<K, V, S>, path: P, key: [u8; 32], warmup: Warmup<'_>) -> io::Result<PersistentCache<K, V, S>>
+        where K: DeserializeOwned, V: DeserializeOwned
+    {
+        match cache.warm_from_snapshot_encrypted(&path, Format::Json, &key, warmup) {
+            Ok(_) => {},
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+        let mut persistent = PersistentCache::new(cache, path);
+        persistent.key = Some(key);
+        Ok(persistent)
+    }
+
+    pub fn cache(&self) -> &ThreadSafeHashCache<K, V, S> {
+        &self.cache
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // flush writes a snapshot of the live entries to the configured path
+    pub fn flush(&self) -> io::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        #[cfg(feature = "encryption")]
+        if let Some(key) = &self.key {
+            self.cache.save_snapshot_encrypted(&tmp, Format::Json, key)?;
+            return fs::rename(&tmp, &self.path)
+        }
+        self.cache.save_snapshot(&tmp)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+impl<K: Hash+Eq+Clone+Serialize, V: Serialize, S: BuildHasher> Drop for PersistentCache<K, V, S> {
+    fn drop(&mut self) {
+        // there's no one to report to from drop; call flush to handle errors
+        if let Err(_e) = self.flush() {
+            event!(WARN, error = %_e, "failed to persist snapshot");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PersistentCache;
+    use crate::{ThreadSafeHashCache, Warmup};
+    use std::time::Duration;
+
+    #[test]
+    fn persists_on_drop() {
+        let path = std::env::temp_dir().join(format!("hodor-persist-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // nothing to load the first time
+        let cache = PersistentCache::open(ThreadSafeHashCache::new(), &path, Warmup::new()).unwrap();
+        assert_eq!(0, cache.cache().len());
+        cache.cache().insert("id".to_string(), "secret".to_string());
+        cache.cache().insert_ttl("id2".to_string(), "secret2".to_string(), Duration::new(60, 0));
+        drop(cache);
+
+        let cache : PersistentCache<String,String> = PersistentCache::open(ThreadSafeHashCache::new(), &path, Warmup::new()).unwrap();
+        assert_eq!(2, cache.cache().len());
+        assert!(cache.cache().get("id2".to_string(), |v| assert_eq!("secret2", v)));
+
+        // flush persists without dropping
+        cache.cache().take("id".to_string());
+        cache.flush().unwrap();
+        drop(cache);
+        let cache : ThreadSafeHashCache<String,String> = ThreadSafeHashCache::new();
+        assert_eq!(1, cache.warm_from_snapshot(&path, Warmup::new()).unwrap());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn persists_encrypted() {
+        let path = std::env::temp_dir().join(format!("hodor-persist-encrypted-{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let key = [7; 32];
+
+        let cache = PersistentCache::open_encrypted(ThreadSafeHashCache::new(), &path, key, Warmup::new()).unwrap();
+        cache.cache().insert("id".to_string(), "secret".to_string());
+        drop(cache);
+        assert!(PersistentCache::<String,String>::open(ThreadSafeHashCache::new(), &path, Warmup::new()).is_err());
+
+        let cache : PersistentCache<String,String> = PersistentCache::open_encrypted(ThreadSafeHashCache::new(), &path, key, Warmup::new()).unwrap();
+        assert!(cache.cache().get("id".to_string(), |v| assert_eq!("secret", v)));
+        drop(cache);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+// python is a Python module over a ThreadSafeHashCache, for data pipelines that want the same
+// cache semantics in Python:
+//
+//     import hodor
+//     cache = hodor.Cache(max_capacity=10_000, reap_every=1.0)
+//     cache.insert("user:42", {"name": "x"}, ttl=30)
+//     cache.get("user:42")
+//
+// Keys are str or bytes; values are any picklable object, stored pickled, so get returns a copy.
+// TTLs and intervals are in seconds. Build with maturin build --features python,pyo3/extension-module.
+// pymethods' generated wrappers convert PyErr into itself
+#![allow(clippy::useless_conversion)]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+
+use crate::{Cache, CacheBuilder, ThreadSafeHashCache, VacuumSchedule};
+
+// Key is a Python key: str and bytes keys never equal each other, as in a dict
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+impl<'py> FromPyObject<'py> for Key {
+    fn extract_bound(key: &Bound<'py, PyAny>) -> PyResult<Self> {
+        if let Ok(s) = key.downcast::<PyString>() {
+            return Ok(Key::Str(s.to_str()?.to_owned()))
+        }
+        if let Ok(b) = key.downcast::<PyBytes>() {
+            return Ok(Key::Bytes(b.as_bytes().to_vec()))
+        }
+        Err(PyTypeError::new_err("cache keys must be str or bytes"))
+    }
+}
+
+// seconds converts a Python duration in seconds
+fn seconds(s: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(s).map_err(|_| PyValueError::new_err("durations must be non-negative seconds"))
+}
+
+// Cache is the Python class. With reap_every, a background thread vacuums it at that interval
+// until it's garbage collected.
+#[pyclass(name = "Cache", module = "hodor", frozen)]
+struct PyCache(Arc<ThreadSafeHashCache<Key, Vec<u8>>>);
+
+#[pymethods]
+impl PyCache {
+    #[new]
+    #[pyo3(signature = (max_capacity=None, reap_every=None))]
+    fn new(max_capacity: Option<usize>, reap_every: Option<f64>) -> PyResult<Self> {
+        let mut builder = CacheBuilder::new();
+        if let Some(n) = max_capacity {
+            builder = builder.max_capacity(n);
+        }
+        if let Some(every) = reap_every.map(seconds).transpose()? {
+            if every.is_zero() {
+                return Err(PyValueError::new_err("reap_every must be positive"))
+            }
+            builder = builder.vacuum_schedule(VacuumSchedule::Every(every));
+        }
+        Ok(PyCache(builder.build_shared()))
+    }
+
+    // insert stores a value, persistent unless given a ttl, and returns whether it replaced one
+    #[pyo3(signature = (key, value, ttl=None))]
+    fn insert(&self, py: Python<'_>, key: Key, value: &Bound<'_, PyAny>, ttl: Option<f64>) -> PyResult<bool> {
+        let pickled: Vec<u8> = py.import_bound("pickle")?.call_method1("dumps", (value,))?.extract()?;
+        let ttl = ttl.map(seconds).transpose()?;
+        // the lock is taken without the GIL, so a reaper or another thread holding it can't
+        // deadlock against a thread waiting for the GIL
+        Ok(py.allow_threads(|| match ttl {
+            Some(ttl) => ThreadSafeHashCache::insert_ttl(&self.0, key, pickled, ttl),
+            None => ThreadSafeHashCache::insert(&self.0, key, pickled),
+        }).is_some())
+    }
+
+    // get returns a live value, or default if the key is absent or expired
+    #[pyo3(signature = (key, default=None))]
+    fn get(&self, py: Python<'_>, key: Key, default: Option<PyObject>) -> PyResult<PyObject> {
+        let mut found = None;
+        py.allow_threads(|| Cache::get_with(&*self.0, &key, &mut |v: &Vec<u8>| found = Some(v.clone())));
+        match found {
+            Some(pickled) => Ok(py.import_bound("pickle")?.call_method1("loads", (PyBytes::new_bound(py, &pickled),))?.unbind()),
+            None => Ok(default.unwrap_or_else(|| py.None())),
+        }
+    }
+
+    // take removes a key and returns its live value, or None
+    fn take(&self, py: Python<'_>, key: Key) -> PyResult<PyObject> {
+        match py.allow_threads(|| self.0.take_once(&key)) {
+            Some(pickled) => Ok(py.import_bound("pickle")?.call_method1("loads", (PyBytes::new_bound(py, &pickled),))?.unbind()),
+            None => Ok(py.None()),
+        }
+    }
+
+    // vacuum removes expired entries like auto_vacuum, returning how many
+    fn vacuum(&self, py: Python<'_>) -> usize {
+        py.allow_threads(|| self.0.auto_vacuum())
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.len()
+    }
+
+    fn __contains__(&self, py: Python<'_>, key: Key) -> bool {
+        py.allow_threads(|| Cache::get_with(&*self.0, &key, &mut |_| {}))
+    }
+}
+
+// hodor is the Python module
+#[pymodule]
+fn hodor(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyCache>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn python() {
+        pyo3::append_to_inittab!(hodor);
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let locals = PyDict::new_bound(py);
+            py.run_bound(r#"
+import time
+import hodor
+cache = hodor.Cache(max_capacity=100)
+assert not cache.insert("a", {"n": [1, 2]})
+assert cache.insert("a", {"n": [1, 2, 3]})
+cache.insert(b"a", "bytes key", ttl=0.01)
+assert cache.get("a") == {"n": [1, 2, 3]} and cache.get(b"a") == "bytes key"
+assert len(cache) == 2 and "a" in cache and "b" not in cache
+assert cache.get("b", 7) == 7
+time.sleep(0.02)
+assert b"a" not in cache
+assert cache.vacuum() == 1
+assert cache.take("a") == {"n": [1, 2, 3]} and len(cache) == 0
+try:
+    cache.insert(1, 1)
+    raise AssertionError("int key accepted")
+except TypeError:
+    pass
+reaped = hodor.Cache(reap_every=0.005)
+reaped.insert("k", "v", ttl=0.001)
+time.sleep(0.05)
+size = len(reaped)
+"#, None, Some(&locals)).unwrap();
+            let size: usize = locals.get_item("size").unwrap().unwrap().extract().unwrap();
+            assert_eq!(0, size);
+        });
+    }
+}
@@ -0,0 +1,302 @@
+use std::hash::{BuildHasher, Hash};
+use std::time::{Duration, Instant};
+
+use crate::{Cache, DefaultHashBuilder, ShardedCache, ThreadSafeHashCache};
+
+// Decision is a rate limiter's answer for one request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    // allowed, with this many more requests allowed in the current window
+    Allowed { remaining: u32 },
+    // over the limit; a request after retry_after would be allowed
+    Limited { retry_after: Duration },
+}
+
+impl Decision {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, Decision::Allowed{ .. })
+    }
+}
+
+// Window is how a rate limiter counts requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    // count requests in consecutive fixed windows; cheap, but allows up to twice the limit
+    // across a window boundary
+    Fixed,
+    // weight the previous window's count by how much of it still overlaps the trailing window,
+    // smoothing out bursts at the boundaries
+    Sliding,
+}
+
+// WindowCount is a key's request counts, stored as a cache entry that expires once it no longer
+// affects any decision
+#[derive(Debug, Clone, Copy)]
+pub struct WindowCount {
+    start: Instant,
+    count: u32,
+    previous: u32,
+}
+
+// RateLimiter limits requests per key, e.g. per user or per client address, keeping a counter
+// for each key in a cache so idle keys expire on their own
+pub struct RateLimiter<K: Hash+Eq+Clone, S = DefaultHashBuilder> {
+    cache: ThreadSafeHashCache<K, WindowCount, S>,
+    limit: u32,
+    window: Duration,
+    kind: Window,
+}
+
+impl<K: Hash+Eq+Clone> RateLimiter<K> {
+    // new allows limit requests per key per window; panics if limit or window is 0
+    pub fn new(limit: u32, window: Duration, kind: Window) -> RateLimiter<K> {
+        RateLimiter::with_cache(ThreadSafeHashCache::new(), limit, window, kind)
+    }
+}
+
+impl<K: Hash+Eq+Clone, S: BuildHasher> RateLimiter<K, S> {
+    // with_cache keeps the counters in cache, e.g. one built with a capacity bound or a clock;
+    // panics if limit or window is 0
+    pub fn with_cache(cache: ThreadSafeHashCache<K, WindowCount, S>, limit: u32, window: Duration, kind: Window) -> RateLimiter<K, S> {
+        assert!(limit > 0, "limit must be positive");
+        assert!(!window.is_zero(), "window must be positive");
+        RateLimiter{ cache, limit, window, kind }
+    }
+
+    pub fn cache(&self) -> &ThreadSafeHashCache<K, WindowCount, S> {
+        &self.cache
+    }
+
+    // check counts a request for key if it's allowed
+    pub fn check(&self, key: K) -> Decision {
+        let mut inner = self.cache.write();
+        let now = inner.now();
+        let live = inner.lookup(&key).is_some_and(|v| !v.expired(now));
+        if !live {
+            let counter = WindowCount{ start: now, count: 1, previous: 0 };
+            inner.insert_ttl(key, counter, self.retention());
+            return Decision::Allowed{ remaining: self.limit - 1 }
+        }
+
+        let counter = inner.lookup_mut(&key).expect("checked live above").value.get_mut();
+        let rolled = self.roll(counter, now);
+        let decision = match self.kind {
+            Window::Fixed => self.fixed(counter, now),
+            Window::Sliding => self.sliding(counter, now),
+        };
+        let start = counter.start;
+        if rolled {
+            // the counter moved to a new window, so it lives as long as that window matters
+            let expires = start + self.retention();
+            inner.touch(&key, Some(expires.saturating_duration_since(now)));
+        }
+        decision
+    }
+
+    // retention is how long after a window starts its counter still affects decisions
+    fn retention(&self) -> Duration {
+        match self.kind {
+            Window::Fixed => self.window,
+            Window::Sliding => self.window * 2,
+        }
+    }
+
+    // roll moves counter to the window containing now, returning whether it moved
+    fn roll(&self, counter: &mut WindowCount, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(counter.start);
+        if elapsed < self.window {
+            return false
+        }
+        let windows = (elapsed.as_nanos() / self.window.as_nanos()) as u32;
+        counter.previous = if windows == 1 { counter.count } else { 0 };
+        counter.count = 0;
+        counter.start += self.window * windows;
+        true
+    }
+
+    fn fixed(&self, counter: &mut WindowCount, now: Instant) -> Decision {
+        if counter.count >= self.limit {
+            return Decision::Limited{ retry_after: (counter.start + self.window).saturating_duration_since(now) }
+        }
+        counter.count += 1;
+        Decision::Allowed{ remaining: self.limit - counter.count }
+    }
+
+    fn sliding(&self, counter: &mut WindowCount, now: Instant) -> Decision {
+        let window = self.window.as_secs_f64();
+        let into = now.saturating_duration_since(counter.start).as_secs_f64() / window;
+        let weighted = counter.previous as f64 * (1.0 - into);
+        let estimate = weighted + counter.count as f64;
+        if estimate + 1.0 <= self.limit as f64 {
+            counter.count += 1;
+            let remaining = (self.limit as f64 - estimate - 1.0).floor() as u32;
+            return Decision::Allowed{ remaining }
+        }
+
+        // wait until the previous window's weight has decayed enough, or if this window is
+        // already full on its own, until this window's weight decays in the next one
+        let (full, fraction) = if counter.count < self.limit {
+            (0.0, 1.0 - (self.limit - counter.count - 1) as f64 / counter.previous as f64)
+        } else {
+            (1.0, 1.0 - (self.limit - 1) as f64 / counter.count as f64)
+        };
+        let at = (full + fraction) * window;
+        // rounded to the nanosecond, so float error doesn't show up in the duration
+        let retry_after = Duration::from_nanos(((at - into * window).max(0.0) * 1e9).round() as u64);
+        Decision::Limited{ retry_after }
+    }
+}
+
+// Bucket is a key's token bucket, stored as a cache entry that expires once the bucket would be
+// full again, since a full bucket behaves the same as a missing one
+#[derive(Debug, Clone, Copy)]
+pub struct Bucket {
+    tokens: u32,
+    // when tokens was last topped up; the time since counts towards the next token
+    updated: Instant,
+}
+
+// TokenBucket limits requests per key with token buckets: each key's bucket holds up to capacity
+// tokens and gains one every refill_every, and a request spends one or more. It allows bursts of
+// up to capacity while holding the long-run rate to one token per refill_every.
+pub struct TokenBucket<K: Hash+Eq+Clone, S = DefaultHashBuilder> {
+    cache: ShardedCache<K, Bucket, S>,
+    capacity: u32,
+    refill_every: Duration,
+}
+
+impl<K: Hash+Eq+Clone> TokenBucket<K> {
+    // panics if capacity or refill_every is 0
+    pub fn new(capacity: u32, refill_every: Duration) -> TokenBucket<K> {
+        TokenBucket::with_cache(ShardedCache::new(16), capacity, refill_every)
+    }
+}
+
+impl<K: Hash+Eq+Clone, S: BuildHasher> TokenBucket<K, S> {
+    // panics if capacity or refill_every is 0
+    pub fn with_cache(cache: ShardedCache<K, Bucket, S>, capacity: u32, refill_every: Duration) -> TokenBucket<K, S> {
+        assert!(capacity > 0, "capacity must be positive");
+        assert!(!refill_every.is_zero(), "refill_every must be positive");
+        TokenBucket{ cache, capacity, refill_every }
+    }
+
+    pub fn cache(&self) -> &ShardedCache<K, Bucket, S> {
+        &self.cache
+    }
+
+    // try_acquire takes tokens from key's bucket if it holds enough, atomically under the key's
+    // shard lock; remaining is the tokens left. Panics if tokens exceeds the capacity, since such
+    // a request could never be allowed.
+    pub fn try_acquire(&self, key: K, tokens: u32) -> Decision {
+        assert!(tokens <= self.capacity, "can't acquire more tokens than the capacity");
+        let mut inner = self.cache.shard(&key).write();
+        let now = inner.now();
+        let mut bucket = match inner.lookup_mut(&key) {
+            Some(v) if !v.expired(now) => *v.value.get_mut(),
+            _ => Bucket{ tokens: self.capacity, updated: now },
+        };
+        self.refill(&mut bucket, now);
+
+        if bucket.tokens < tokens {
+            let wait = self.refill_every * (tokens - bucket.tokens);
+            let retry_after = wait.saturating_sub(now.saturating_duration_since(bucket.updated));
+            return Decision::Limited{ retry_after }
+        }
+        bucket.tokens -= tokens;
+        let full_in = (self.refill_every * (self.capacity - bucket.tokens)).saturating_sub(now.saturating_duration_since(bucket.updated));
+        if full_in.is_zero() {
+            inner.take(key);
+        } else {
+            inner.insert_ttl(key, bucket, full_in);
+        }
+        Decision::Allowed{ remaining: bucket.tokens }
+    }
+
+    // refill adds the tokens earned since the bucket was last updated
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        let earned = (elapsed.as_nanos() / self.refill_every.as_nanos()).min(u32::MAX as u128) as u32;
+        if bucket.tokens.saturating_add(earned) >= self.capacity {
+            *bucket = Bucket{ tokens: self.capacity, updated: now };
+        } else {
+            bucket.tokens += earned;
+            bucket.updated += self.refill_every * earned;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decision, RateLimiter, TokenBucket, Window};
+    use crate::clock::ManualClock;
+    use crate::{CacheBuilder, ShardedCache};
+    use std::time::Duration;
+
+    fn limiter(kind: Window) -> (RateLimiter<&'static str>, ManualClock) {
+        let clock = ManualClock::new();
+        let cache = CacheBuilder::new().clock(clock.clone()).build_thread_safe();
+        (RateLimiter::with_cache(cache, 10, Duration::new(60, 0), kind), clock)
+    }
+
+    #[test]
+    fn fixed_window() {
+        let (limiter, clock) = limiter(Window::Fixed);
+        for remaining in (0..10).rev() {
+            assert_eq!(Decision::Allowed{ remaining }, limiter.check("alice"));
+        }
+        clock.advance(Duration::new(45, 0));
+        assert_eq!(Decision::Limited{ retry_after: Duration::new(15, 0) }, limiter.check("alice"));
+        assert!(limiter.check("bob").is_allowed());
+
+        // the counter expires with its window
+        clock.advance(Duration::new(15, 0));
+        assert_eq!(Decision::Allowed{ remaining: 9 }, limiter.check("alice"));
+    }
+
+    #[test]
+    fn sliding_window() {
+        let (limiter, clock) = limiter(Window::Sliding);
+        for _ in 0..10 {
+            assert!(limiter.check("alice").is_allowed());
+        }
+        // this window is full on its own: allowed again once 10% of it has slid out
+        assert_eq!(Decision::Limited{ retry_after: Duration::new(66, 0) }, limiter.check("alice"));
+
+        // 15s into the next window, the previous one still weighs 7.5 requests
+        clock.advance(Duration::new(75, 0));
+        assert_eq!(Decision::Allowed{ remaining: 1 }, limiter.check("alice"));
+        assert_eq!(Decision::Allowed{ remaining: 0 }, limiter.check("alice"));
+        assert_eq!(Decision::Limited{ retry_after: Duration::new(3, 0) }, limiter.check("alice"));
+
+        clock.advance(Duration::new(3, 0));
+        assert!(limiter.check("alice").is_allowed());
+
+        // after two idle windows the counter has expired
+        clock.advance(Duration::new(120, 0));
+        assert_eq!(1, limiter.cache().drain_expired().count());
+        assert_eq!(Decision::Allowed{ remaining: 9 }, limiter.check("alice"));
+    }
+
+    #[test]
+    fn token_bucket() {
+        let clock = ManualClock::new();
+        let cache : ShardedCache<&str, _> = CacheBuilder::new().clock(clock.clone()).build_sharded(4);
+        let bucket = TokenBucket::with_cache(cache, 5, Duration::new(1, 0));
+
+        assert_eq!(Decision::Allowed{ remaining: 2 }, bucket.try_acquire("alice", 3));
+        assert_eq!(Decision::Allowed{ remaining: 0 }, bucket.try_acquire("alice", 2));
+        assert_eq!(Decision::Limited{ retry_after: Duration::new(2, 0) }, bucket.try_acquire("alice", 2));
+        assert!(bucket.try_acquire("bob", 5).is_allowed());
+
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(Decision::Limited{ retry_after: Duration::from_millis(500) }, bucket.try_acquire("alice", 2));
+        assert_eq!(Decision::Allowed{ remaining: 0 }, bucket.try_acquire("alice", 1));
+
+        // idle buckets expire once they'd be full again
+        clock.advance(Duration::new(5, 0));
+        assert_eq!(2, bucket.cache().len());
+        bucket.cache().vacuum(10, 0.25);
+        assert_eq!(0, bucket.cache().len());
+        assert_eq!(Decision::Allowed{ remaining: 0 }, bucket.try_acquire("alice", 5));
+    }
+}
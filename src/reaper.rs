@@ -0,0 +1,98 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Weak;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::ThreadSafeHashCache;
+
+// Reaper is a background thread running a task (usually a vacuum) at a fixed interval. Dropping
+// the Reaper stops the thread and waits for a running pass to finish.
+pub struct Reaper {
+    // dropping the sender disconnects the channel, which wakes the thread up to exit
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Reaper {
+    pub(crate) fn spawn<F>(every: Duration, mut task: F) -> Reaper where F: FnMut() + Send + 'static {
+        let (stop, stopped) = channel::<()>();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(every) {
+                task();
+            }
+        });
+        Reaper{ stop: Some(stop), thread: Some(thread) }
+    }
+}
+
+impl Drop for Reaper {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// VacuumSchedule is how a cache built with CacheBuilder::vacuum_schedule vacuums itself; every
+// run is an auto_vacuum
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VacuumSchedule {
+    // Every vacuums at a fixed interval
+    Every(Duration),
+    // Throttled vacuums at an interval, stretched when a run is slow so that vacuuming takes at
+    // most max_cpu (in (0, 1]) of the reaper thread's time
+    Throttled{ every: Duration, max_cpu: f32 },
+    // Watermark checks the expiring index every interval, and only vacuums while it holds more
+    // than `expiring` entries
+    Watermark{ every: Duration, expiring: usize },
+}
+
+impl VacuumSchedule {
+    // panics if the interval is 0, or max_cpu is not in (0, 1]
+    pub(crate) fn validate(&self) {
+        if let VacuumSchedule::Throttled{ max_cpu, .. } = *self {
+            assert!(max_cpu > 0.0 && max_cpu <= 1.0, "max_cpu must be in (0, 1]");
+        }
+        assert!(!self.interval().is_zero(), "vacuum interval must be positive");
+    }
+
+    // start runs the schedule on a background thread, which exits once the cache is dropped
+    pub(crate) fn start<K, V, S>(self, cache: Weak<ThreadSafeHashCache<K, V, S>>)
+        where K: Hash+Eq+Send+Sync+'static, V: Send+Sync+'static, S: BuildHasher+Send+Sync+'static {
+        let mut wait = self.interval();
+        thread::spawn(move || loop {
+            thread::sleep(wait);
+            wait = match cache.upgrade() {
+                Some(cache) => self.run(&cache),
+                None => return,
+            };
+        });
+    }
+
+    // run is one scheduled step; it returns how long to wait before the next
+    fn run<K: Hash+Eq, V, S: BuildHasher>(&self, cache: &ThreadSafeHashCache<K, V, S>) -> Duration {
+        match *self {
+            VacuumSchedule::Every(every) => {
+                cache.auto_vacuum();
+                every
+            },
+            VacuumSchedule::Throttled{ every, max_cpu } => {
+                let start = Instant::now();
+                cache.auto_vacuum();
+                every.max(start.elapsed().mul_f32((1.0 - max_cpu) / max_cpu))
+            },
+            VacuumSchedule::Watermark{ every, expiring } => {
+                while cache.expiring_len() > expiring && cache.auto_vacuum() > 0 {}
+                every
+            },
+        }
+    }
+
+    fn interval(&self) -> Duration {
+        match *self {
+            VacuumSchedule::Every(every) | VacuumSchedule::Throttled{ every, .. } | VacuumSchedule::Watermark{ every, .. } => every,
+        }
+    }
+}
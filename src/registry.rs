@@ -0,0 +1,197 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::reaper::Reaper;
+use crate::{ArcCache, LoadingCache, ShardedCache, Stats, ThreadSafeHashCache};
+
+// Managed is the type-erased view of a cache that a CacheRegistry needs: vacuuming and stats
+pub trait Managed: Send + Sync {
+    fn vacuum(&self, count : usize, retry_threshold : f32 );
+    fn stats(&self) -> Stats;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, V, S> Managed for ThreadSafeHashCache<K, V, S>
+    where K: Hash+Eq+Send+Sync, V: Send+Sync, S: BuildHasher+Send+Sync {
+    fn vacuum(&self, count : usize, retry_threshold : f32 ) {
+        ThreadSafeHashCache::vacuum(self, count, retry_threshold)
+    }
+
+    fn stats(&self) -> Stats {
+        ThreadSafeHashCache::stats(self)
+    }
+
+    fn len(&self) -> usize {
+        ThreadSafeHashCache::len(self)
+    }
+}
+
+impl<K, V, S> Managed for ArcCache<K, V, S>
+    where K: Hash+Eq+Clone+Send+Sync, V: Send+Sync, S: BuildHasher+Send+Sync {
+    fn vacuum(&self, count : usize, retry_threshold : f32 ) {
+        self.cache().vacuum(count, retry_threshold)
+    }
+
+    fn stats(&self) -> Stats {
+        self.cache().stats()
+    }
+
+    fn len(&self) -> usize {
+        self.cache().len()
+    }
+}
+
+impl<K, V, S> Managed for LoadingCache<K, V, S>
+    where K: Hash+Eq+Clone+Send+Sync+'static, V: Clone+Send+Sync+'static, S: BuildHasher+Send+Sync+'static {
+    fn vacuum(&self, count : usize, retry_threshold : f32 ) {
+        self.cache().vacuum(count, retry_threshold)
+    }
+
+    fn stats(&self) -> Stats {
+        self.cache().stats()
+    }
+
+    fn len(&self) -> usize {
+        self.cache().len()
+    }
+}
+
+impl<K, V, S> Managed for ShardedCache<K, V, S>
+    where K: Hash+Eq+Send+Sync, V: Send+Sync, S: BuildHasher+Send+Sync {
+    fn vacuum(&self, count : usize, retry_threshold : f32 ) {
+        ShardedCache::vacuum(self, count, retry_threshold)
+    }
+
+    fn stats(&self) -> Stats {
+        ShardedCache::stats(self)
+    }
+
+    fn len(&self) -> usize {
+        ShardedCache::len(self)
+    }
+}
+
+// Registered keeps a cache both as Managed, for the registry's own use, and as Any, so callers
+// can get their concrete type back
+struct Registered {
+    managed: Arc<dyn Managed>,
+    any: Arc<dyn Any + Send + Sync>,
+}
+
+// CacheRegistry owns a set of named caches of any type, vacuums them together (on demand or from
+// one background thread) and reports their stats in one place. Cloning a registry gives another
+// handle to the same set of caches.
+#[derive(Clone, Default)]
+pub struct CacheRegistry {
+    caches: Arc<RwLock<BTreeMap<String, Registered>>>,
+}
+
+impl CacheRegistry {
+    pub fn new() -> CacheRegistry {
+        CacheRegistry::default()
+    }
+
+    // register takes ownership of a cache and returns a shared handle to it
+    // panics if a cache is already registered under name
+    pub fn register<C: Managed + 'static>(&self, name: &str, cache: C) -> Arc<C> {
+        let cache = Arc::new(cache);
+        let mut caches = self.caches.write().expect("lock poisoned");
+        assert!(!caches.contains_key(name), "a cache named {} is already registered", name);
+        caches.insert(name.to_string(), Registered{ managed: cache.clone(), any: cache.clone() });
+        cache
+    }
+
+    // get returns the cache registered under name, if there is one of type C
+    pub fn get<C: Managed + 'static>(&self, name: &str) -> Option<Arc<C>> {
+        let caches = self.caches.read().expect("lock poisoned");
+        caches.get(name)?.any.clone().downcast().ok()
+    }
+
+    // remove unregisters a cache; handles to it stay usable
+    pub fn remove(&self, name: &str) -> bool {
+        self.caches.write().expect("lock poisoned").remove(name).is_some()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.caches.read().expect("lock poisoned").keys().cloned().collect()
+    }
+
+    // handles copies out the registered caches, so the registry lock isn't held while they work
+    fn handles(&self) -> Vec<(String, Arc<dyn Managed>)> {
+        let caches = self.caches.read().expect("lock poisoned");
+        caches.iter().map(|(name, r)| (name.clone(), r.managed.clone())).collect()
+    }
+
+    // vacuum vacuums every registered cache in turn
+    // panics if retry-threshold is not between 0 and 1.
+    pub fn vacuum(&self, count : usize, retry_threshold : f32 ) {
+        for (_, cache) in self.handles() {
+            cache.vacuum(count, retry_threshold);
+        }
+    }
+
+    // start_vacuum vacuums every registered cache (including ones registered later) from a single
+    // background thread, every interval, until the returned Reaper is dropped
+    pub fn start_vacuum(&self, every: Duration, count : usize, retry_threshold : f32 ) -> Reaper {
+        assert!(retry_threshold > 0.0);
+        assert!(retry_threshold < 1.0);
+        let registry = self.clone();
+        Reaper::spawn(every, move || registry.vacuum(count, retry_threshold))
+    }
+
+    // stats sums the stats of every registered cache
+    pub fn stats(&self) -> Stats {
+        self.handles().into_iter().map(|(_, cache)| cache.stats()).fold(Stats::default(), |a, b| a + b)
+    }
+
+    // stats_by_name reports each registered cache's stats and entry count
+    pub fn stats_by_name(&self) -> Vec<(String, Stats, usize)> {
+        self.handles().into_iter().map(|(name, cache)| (name, cache.stats(), cache.len())).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CacheRegistry;
+    use crate::{ArcCache, CacheBuilder, ThreadSafeHashCache};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn registry() {
+        let registry = CacheRegistry::new();
+        let sessions = registry.register("sessions", ThreadSafeHashCache::<String,u64>::new());
+        registry.register("pages", ArcCache::<&str,Vec<u8>>::new());
+        registry.register("users", CacheBuilder::new().build_loading(Duration::new(10, 0), |k: &u32| Some(*k)));
+        assert_eq!(vec!["pages", "sessions", "users"], registry.names());
+
+        sessions.insert_ttl("a".to_string(), 1, Duration::from_millis(10));
+        let pages = registry.get::<ArcCache<&str,Vec<u8>>>("pages").unwrap();
+        pages.insert("/", vec![0; 16]);
+        assert!(pages.get(&"/").is_some());
+        assert!(pages.get(&"/404").is_none());
+
+        // the wrong type isn't handed out
+        assert!(registry.get::<ThreadSafeHashCache<String,u32>>("sessions").is_none());
+
+        // a single background thread vacuums every cache
+        let reaper = registry.start_vacuum(Duration::from_millis(5), 10, 0.25);
+        sleep(Duration::from_millis(40));
+        drop(reaper);
+        assert_eq!(0, sessions.len());
+
+        let stats = registry.stats();
+        assert_eq!((1, 1, 2, 1), (stats.hits, stats.misses, stats.inserts, stats.expirations));
+        let by_name = registry.stats_by_name();
+        assert_eq!(("pages".to_string(), 1), (by_name[0].0.clone(), by_name[0].2));
+
+        assert!(registry.remove("users"));
+        assert_eq!(2, registry.stats_by_name().len());
+    }
+}
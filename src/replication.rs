@@ -0,0 +1,137 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+#[cfg(feature = "snapshot")]
+use serde::{Deserialize, Serialize};
+
+use crate::{Cache, CacheEvent, HashCache, ThreadSafeHashCache};
+
+// Replication is a change to a primary cache, as applied by its replicas. It is serializable
+// (with the `snapshot` feature), so the stream can be shipped to replicas in other processes.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum Replication<K, V> {
+    // an entry was stored, with the TTL it had left (None if persistent)
+    Put { key: K, value: V, ttl: Option<Duration> },
+    // an entry was removed for any reason: taken, evicted or expired on the primary
+    Remove { key: K },
+}
+
+impl<K: Hash+Eq+Clone, V> Replication<K, V> {
+    pub fn apply<S: BuildHasher>(self, replica: &mut HashCache<K, V, S>) {
+        match self {
+            Replication::Put{ key, value, ttl: Some(ttl) } => { replica.insert_ttl(key, value, ttl); },
+            Replication::Put{ key, value, ttl: None } => { replica.insert(key, value); },
+            Replication::Remove{ key } => { replica.take(key); },
+        }
+    }
+}
+
+impl<K: Hash+Eq+Clone+Send+'static, V: Clone+Send+'static, S: BuildHasher> HashCache<K, V, S> {
+    // replicate returns a stream of the changes to this cache: first a Put for every live entry,
+    // then every change from now on. Applying the stream in order to an empty cache keeps it a
+    // replica of this one. Dropping the receiver stops the stream.
+    pub fn replicate(&mut self) -> Receiver<Replication<K, V>> {
+        let (tx, rx) = channel();
+        let now = self.now();
+        for (_, (key, v)) in self.entries.iter() {
+            if v.expired(now) {
+                continue
+            }
+            let ttl = v.meta(now).expires_at().map(|at| at.saturating_duration_since(now));
+            // SAFETY: &mut self excludes any update
+            let value = unsafe { v.value.get() }.clone();
+            let _ = tx.send(Replication::Put{ key: K::clone(key), value, ttl });
+        }
+        self.listeners.add(Box::new(move |event, ttl| {
+            let op = match *event {
+                CacheEvent::Inserted{ key, value } | CacheEvent::Replaced{ key, value } => {
+                    Replication::Put{ key: key.clone(), value: value.clone(), ttl }
+                },
+                CacheEvent::Expired{ key, .. } | CacheEvent::Evicted{ key, .. } | CacheEvent::Removed{ key, .. } => {
+                    Replication::Remove{ key: key.clone() }
+                },
+            };
+            tx.send(op).is_ok()
+        }));
+        rx
+    }
+}
+
+impl<K: Hash+Eq+Clone+Send+'static, V: Clone+Send+'static, S: BuildHasher> ThreadSafeHashCache<K, V, S> {
+    // replicate is HashCache::replicate; the initial entries and the subscription are taken under
+    // one lock, so no change falls between them
+    pub fn replicate(&self) -> Receiver<Replication<K, V>> {
+        self.write().replicate()
+    }
+}
+
+impl<K: Hash+Eq+Clone+Send+Sync+'static, V: Send+Sync+'static, S: BuildHasher+Send+Sync+'static> ThreadSafeHashCache<K, V, S> {
+    // follow applies a replication stream to this cache from a background thread, which exits
+    // when the stream ends or this cache is dropped (at the next change)
+    pub fn follow(self: &Arc<Self>, stream: Receiver<Replication<K, V>>) -> JoinHandle<()> {
+        let replica: Weak<Self> = Arc::downgrade(self);
+        thread::spawn(move || {
+            for op in stream {
+                match replica.upgrade() {
+                    Some(replica) => op.apply(&mut replica.write()),
+                    None => return,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Replication;
+    use crate::{CacheBuilder, ThreadSafeHashCache};
+    use std::sync::Arc;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn replicas() {
+        let primary : ThreadSafeHashCache<&str,&str> = CacheBuilder::new().max_capacity(3).build_thread_safe();
+        primary.insert("id", "secret");
+        primary.insert_ttl("token", "secret2", Duration::new(60, 0));
+
+        let replica : Arc<ThreadSafeHashCache<&str,&str>> = Arc::new(ThreadSafeHashCache::new());
+        let follower = replica.follow(primary.replicate());
+        primary.insert("id", "secret3");
+        primary.insert("id2", "secret4");
+        primary.insert("id3", "secret5");
+        primary.take("id2");
+        primary.insert_ttl("gone", "secret6", Duration::from_millis(5));
+
+        // once the stream ends, the replica holds what the primary holds: removals and evictions
+        // are replicated, and TTLs carry over
+        sleep(Duration::from_millis(20));
+        let mut expected = primary.scan_prefix("");
+        drop(primary);
+        follower.join().unwrap();
+        let mut replicated = replica.scan_prefix("");
+        expected.sort();
+        replicated.sort();
+        assert_eq!(2, expected.len());
+        assert_eq!(expected, replicated);
+        assert!(!replica.get("id2", |_| {}));
+    }
+
+    #[test]
+    fn ttl_carries_over() {
+        let primary : ThreadSafeHashCache<&str,&str> = ThreadSafeHashCache::new();
+        primary.insert_ttl("token", "secret", Duration::new(60, 0));
+        let stream = primary.replicate();
+        primary.insert_ttl("token2", "secret", Duration::new(30, 0));
+
+        match stream.recv().unwrap() {
+            Replication::Put{ ttl: Some(ttl), .. } => assert!(ttl <= Duration::new(60, 0) && ttl > Duration::new(59, 0)),
+            op => panic!("unexpected {:?}", op),
+        }
+        assert_eq!(Replication::Put{ key: "token2", value: "secret", ttl: Some(Duration::new(30, 0)) }, stream.recv().unwrap());
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hash};
+use std::time::{Duration, Instant};
+
+use crate::{HashCache, OverwritePolicy, Policy, Stats, ThreadSafeHashCache, VacuumSchedule};
+
+// DebugReport is a structural snapshot of a cache, to log (with {:?}) when it misbehaves in
+// production: how full it is, the state of its expiry bookkeeping, and the options it was built
+// with. Building one visits every entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugReport {
+    pub len: usize,
+    // entries past their deadline that haven't been removed yet
+    pub expired: usize,
+    pub pinned: usize,
+    // capacity is what's allocated; slots includes the free slots left behind by removals
+    pub capacity: usize,
+    pub slots: usize,
+    pub expiring: usize,
+    // duplicate_expiring counts entries listed in the expiring index more than once, and
+    // missing_expiring entries with a TTL that aren't listed; both should be 0
+    pub duplicate_expiring: usize,
+    pub missing_expiring: usize,
+    // outdated_deadlines counts the exact_expiry heap's records for removed or rescheduled entries
+    pub outdated_deadlines: usize,
+    // the earliest deadline of any entry, and how far past it is (zero if it's still ahead)
+    pub oldest_deadline: Option<Instant>,
+    pub overdue: Duration,
+    pub max_capacity: Option<usize>,
+    pub soft_capacity: Option<usize>,
+    pub max_expiring: Option<usize>,
+    pub eviction: Policy,
+    pub overwrite: OverwritePolicy,
+    pub exact_expiry: bool,
+    pub expire_after_access: Option<Duration>,
+    pub min_ttl: Option<Duration>,
+    pub max_ttl: Option<Duration>,
+    pub vacuum_schedule: Option<VacuumSchedule>,
+    pub stats: Stats,
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher> HashCache<K, V, S> {
+    pub fn debug_report(&self) -> DebugReport {
+        let now = self.now();
+        let config = &self.config;
+        let listed: HashSet<usize> = self.expiring.iter().copied().collect();
+        let entries = || self.entries.iter().map(|(i, (_, v))| (i, v));
+        let oldest_deadline = entries().filter_map(|(_, v)| v.expires_at()).min();
+        let live_deadlines = entries().filter(|(_, v)| v.deadline().is_some()).count();
+        DebugReport{
+            len: self.store.len(),
+            expired: entries().filter(|(_, v)| v.expired(now)).count(),
+            pinned: entries().filter(|(_, v)| v.pinned).count(),
+            capacity: self.capacity(),
+            slots: self.entries.slots(),
+            expiring: self.expiring.len(),
+            duplicate_expiring: self.expiring.len() - listed.len(),
+            missing_expiring: entries().filter(|&(i, v)| v.slot.is_some() && !listed.contains(&i)).count(),
+            outdated_deadlines: self.deadlines.as_ref().map_or(0, |d| d.len().saturating_sub(live_deadlines)),
+            oldest_deadline,
+            overdue: oldest_deadline.map_or(Duration::ZERO, |at| now.saturating_duration_since(at)),
+            max_capacity: config.max_capacity,
+            soft_capacity: config.soft_capacity,
+            max_expiring: config.max_expiring,
+            eviction: config.eviction,
+            overwrite: config.overwrite,
+            exact_expiry: config.exact_expiry,
+            expire_after_access: config.expire_after_access,
+            min_ttl: config.min_ttl,
+            max_ttl: config.max_ttl,
+            vacuum_schedule: config.vacuum_schedule,
+            stats: self.stats(),
+        }
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher> ThreadSafeHashCache<K, V, S> {
+    pub fn debug_report(&self) -> DebugReport {
+        self.read().debug_report()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::ManualClock;
+    use crate::{Cache, CacheBuilder, Clock, HashCache, Policy};
+    use std::time::Duration;
+
+    #[test]
+    fn debug_report() {
+        let clock = ManualClock::new();
+        let mut cache : HashCache<u32,u32> = CacheBuilder::new()
+            .clock(clock.clone())
+            .max_capacity(100)
+            .eviction(Policy::Lfu)
+            .exact_expiry(true)
+            .build();
+        let start = clock.now();
+        for i in 0..10 {
+            cache.insert_ttl(i, i, Duration::new(10 + i as u64, 0));
+        }
+        cache.insert_pinned(10, 10);
+        cache.insert_ttl(0, 0, Duration::new(60, 0));
+        cache.take(5);
+        clock.advance(Duration::from_millis(13_500));
+
+        let report = cache.debug_report();
+        assert_eq!((10, 3, 1), (report.len, report.expired, report.pinned));
+        assert_eq!((9, 11), (report.expiring, report.slots));
+        assert_eq!((0, 0), (report.duplicate_expiring, report.missing_expiring));
+        // the overwritten and the removed key's records are still in the heap
+        assert_eq!(2, report.outdated_deadlines);
+        assert_eq!(Some(start + Duration::new(11, 0)), report.oldest_deadline);
+        assert_eq!(Duration::from_millis(2_500), report.overdue);
+        assert_eq!((Some(100), Policy::Lfu, true), (report.max_capacity, report.eviction, report.exact_expiry));
+        assert_eq!(12, report.stats.inserts);
+    }
+}
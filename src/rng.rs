@@ -0,0 +1,130 @@
+use std::sync::Mutex;
+
+#[cfg(feature = "rand")]
+use rand::rngs::StdRng;
+#[cfg(feature = "rand")]
+use rand::{RngCore, SeedableRng};
+
+// CacheRng is where a HashCache draws its randomness from (vacuum sampling, random eviction and
+// early expiration): the thread's generator, or one seeded with CacheBuilder::rng_seed so runs
+// can be replayed. Reads draw through a shared reference, so a seeded generator is behind a
+// mutex. Cloning a cache copies the generator's state, so the clone continues the same sequence.
+pub(crate) struct CacheRng(Option<Mutex<Generator>>);
+
+#[cfg(feature = "rand")]
+type Generator = StdRng;
+#[cfg(not(feature = "rand"))]
+type Generator = XorShift;
+
+impl CacheRng {
+    pub(crate) fn new(seed: Option<u64>) -> CacheRng {
+        CacheRng(seed.map(|seed| Mutex::new(Generator::seed_from_u64(seed))))
+    }
+
+    #[cfg(feature = "rand")]
+    pub(crate) fn with<R>(&self, f: impl FnOnce(&mut dyn RngCore) -> R) -> R {
+        match &self.0 {
+            Some(rng) => f(&mut *rng.lock().expect("lock poisoned")),
+            None => f(&mut rand::thread_rng()),
+        }
+    }
+
+    #[cfg(not(feature = "rand"))]
+    fn next_u64(&self) -> u64 {
+        match &self.0 {
+            Some(rng) => rng.lock().expect("lock poisoned").next_u64(),
+            None => XorShift::from_entropy().next_u64(),
+        }
+    }
+
+    // below returns a number in [0, n); panics if n is 0
+    #[cfg(feature = "rand")]
+    pub(crate) fn below(&self, n: u64) -> u64 {
+        self.with(|rng| rand::Rng::gen_range(rng, 0, n))
+    }
+
+    #[cfg(not(feature = "rand"))]
+    pub(crate) fn below(&self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    // unit returns a number in [0, 1)
+    #[cfg(feature = "rand")]
+    pub(crate) fn unit(&self) -> f64 {
+        self.with(|rng| rand::Rng::gen::<f64>(rng))
+    }
+
+    #[cfg(not(feature = "rand"))]
+    pub(crate) fn unit(&self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Clone for CacheRng {
+    fn clone(&self) -> Self {
+        CacheRng(self.0.as_ref().map(|rng| Mutex::new(rng.lock().expect("lock poisoned").clone())))
+    }
+}
+
+// XorShift (xorshift64*) stands in for rand's generators in builds without the rand feature. It's
+// fast and plenty for picking eviction victims and jittering deadlines, but predictable: nothing
+// secret may come from it.
+#[cfg(not(feature = "rand"))]
+#[derive(Clone)]
+pub(crate) struct XorShift(u64);
+
+#[cfg(not(feature = "rand"))]
+impl XorShift {
+    fn seed_from_u64(seed: u64) -> XorShift {
+        // the state must not be 0; scrambling the seed also spreads out nearby seeds
+        XorShift(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    // from_entropy seeds a generator from the random keys std gives every RandomState
+    fn from_entropy() -> XorShift {
+        use std::hash::{BuildHasher, Hasher};
+        XorShift::seed_from_u64(std::collections::hash_map::RandomState::new().build_hasher().finish())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::ManualClock;
+    use crate::{Cache, CacheBuilder, HashCache, Policy};
+    use std::time::Duration;
+
+    // survivors runs the same inserts, evictions and vacuum on a seeded cache and reports which
+    // keys are left
+    fn survivors(seed: u64) -> Vec<u32> {
+        let clock = ManualClock::new();
+        let mut cache : HashCache<u32,u32> = CacheBuilder::new()
+            .clock(clock.clone())
+            .max_capacity(50)
+            .eviction(Policy::Random)
+            .rng_seed(seed)
+            .build();
+        for i in 0..100 {
+            cache.insert_ttl(i, i, Duration::new(10 + 90 * (i as u64 % 2), 0));
+        }
+        clock.advance(Duration::new(11, 0));
+        cache.vacuum(10, 0.9);
+        // lookup sees expired entries that vacuum hasn't removed yet
+        (0..100).filter(|i| cache.lookup(i).is_some()).collect()
+    }
+
+    #[test]
+    fn seeded_runs_repeat() {
+        let run = survivors(7);
+        assert_eq!(run, survivors(7));
+        // about half the samples are expired, under the threshold, so vacuum stops after one pass
+        assert!(run.len() > 40 && run.len() < 50);
+        assert_ne!(run, survivors(8));
+    }
+}
@@ -0,0 +1,192 @@
+use std::hash::{BuildHasher, Hash};
+
+use crate::{HashCache, ThreadSafeHashCache};
+
+// scanning works on string-like keys (String, &str, Box<str>, ...) and visits every entry, so it
+// is O(n) in the size of the cache rather than in the number of matches
+
+impl<K: Hash+Eq+AsRef<str>, V, S: BuildHasher> HashCache<K, V, S> {
+    // scan_prefix yields the live entries whose keys start with prefix, e.g. "user:42:"
+    pub fn scan_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item=(&'a K, &'a V)> + 'a {
+        let now = self.now();
+        self.entries.iter()
+            .filter(move |(_, (k, v))| (**k).as_ref().starts_with(prefix) && !v.expired(now))
+            // SAFETY: see ValueCell::get; a ThreadSafeHashCache scans under read, which holds
+            // every stripe
+            .map(|(_, (k, v))| (&**k, unsafe { v.value.get() }))
+    }
+
+    // invalidate_prefix removes every entry whose key starts with prefix and returns how many
+    pub fn invalidate_prefix(&mut self, prefix: &str) -> usize {
+        let before = self.store.len();
+        self.retain(|k, _, _| !k.as_ref().starts_with(prefix));
+        before - self.store.len()
+    }
+
+    // invalidate_matching removes every entry whose key matches a glob pattern (see glob_match),
+    // e.g. "tenant:x:*", and returns how many
+    pub fn invalidate_matching(&mut self, pattern: &str) -> usize {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let before = self.store.len();
+        self.retain(|k, _, _| !glob_match(&pattern, k.as_ref()));
+        before - self.store.len()
+    }
+}
+
+impl<K: Hash+Eq+Clone+AsRef<str>, V, S: BuildHasher> ThreadSafeHashCache<K, V, S> {
+    // scan_prefix copies out the live entries whose keys start with prefix, so the lock isn't
+    // held while the caller works through them
+    pub fn scan_prefix(&self, prefix: &str) -> Vec<(K, V)> where V: Clone {
+        self.read().scan_prefix(prefix).map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    pub fn invalidate_prefix(&self, prefix: &str) -> usize {
+        self.write().invalidate_prefix(prefix)
+    }
+
+    pub fn invalidate_matching(&self, pattern: &str) -> usize {
+        self.write().invalidate_matching(pattern)
+    }
+}
+
+// glob_match reports whether key matches a Redis-style glob: * matches any run of characters,
+// ? any single character, [abc] / [a-z] / [^a] a character class, and \ escapes the next one
+fn glob_match(p: &[char], key: &str) -> bool {
+    let k: Vec<char> = key.chars().collect();
+    let (mut pi, mut ki) = (0, 0);
+    // on a mismatch, backtrack to just after the last * and let it swallow one more character
+    let mut star: Option<(usize, usize)> = None;
+
+    while ki < k.len() {
+        let next = match p.get(pi) {
+            Some('*') => {
+                star = Some((pi + 1, ki));
+                pi += 1;
+                continue
+            },
+            Some('?') => Some(pi + 1),
+            Some('[') => match_class(p, pi + 1, k[ki]),
+            Some('\\') if pi + 1 < p.len() => Some(pi + 2).filter(|_| p[pi + 1] == k[ki]),
+            Some(&c) => Some(pi + 1).filter(|_| c == k[ki]),
+            None => None,
+        };
+
+        match (next, star) {
+            (Some(next), _) => {
+                pi = next;
+                ki += 1;
+            },
+            (None, Some((after, swallowed))) => {
+                pi = after;
+                ki = swallowed + 1;
+                star = Some((after, swallowed + 1));
+            },
+            (None, None) => return false,
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+// match_class matches c against the class that starts at p[start] (just after the '['),
+// returning the position after its closing ']' if c is in the class. An unterminated class
+// matches nothing.
+fn match_class(p: &[char], start: usize, c: char) -> Option<usize> {
+    let mut i = start;
+    let negate = p.get(i) == Some(&'^');
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    loop {
+        match p.get(i)? {
+            ']' => break,
+            '\\' => {
+                matched |= *p.get(i + 1)? == c;
+                i += 2;
+            },
+            &lo if p.get(i + 1) == Some(&'-') && p.get(i + 2).is_some_and(|&hi| hi != ']') => {
+                let hi = p[i + 2];
+                matched |= (lo.min(hi)..=lo.max(hi)).contains(&c);
+                i += 3;
+            },
+            &other => {
+                matched |= other == c;
+                i += 1;
+            },
+        }
+    }
+
+    if matched != negate {
+        Some(i + 1)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+    use crate::{Cache, HashCache, ThreadSafeHashCache};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn scan_prefix() {
+        let mut cache : HashCache<&str,u32> = HashCache::new();
+        cache.insert("user:42:name", 1);
+        cache.insert("user:42:email", 2);
+        cache.insert("user:420:name", 3);
+        cache.insert_ttl("user:42:token", 4, Duration::from_millis(10));
+        sleep(Duration::from_millis(20));
+
+        let mut found: Vec<_> = cache.scan_prefix("user:42:").map(|(_, v)| *v).collect();
+        found.sort();
+        assert_eq!(vec![1, 2], found);
+
+        // expired entries are invalidated too, they just weren't visible to the scan
+        assert_eq!(3, cache.invalidate_prefix("user:42:"));
+        assert!(cache.get("user:420:name", |_| {}));
+
+        let cache : ThreadSafeHashCache<String,u32> = ThreadSafeHashCache::new();
+        cache.insert("tenant:a".to_string(), 1);
+        cache.insert("other".to_string(), 2);
+        assert_eq!(vec![("tenant:a".to_string(), 1)], cache.scan_prefix("tenant:"));
+        assert_eq!(1, cache.invalidate_prefix("tenant:"));
+        assert!(cache.scan_prefix("tenant:").is_empty());
+    }
+
+    #[test]
+    fn glob() {
+        let matches = |pattern: &str, key: &str| glob_match(&pattern.chars().collect::<Vec<_>>(), key);
+        assert!(matches("tenant:x:*", "tenant:x:sessions"));
+        assert!(matches("tenant:x:*", "tenant:x:"));
+        assert!(!matches("tenant:x:*", "tenant:xy:a"));
+        assert!(matches("*:name", "user:42:name"));
+        assert!(matches("user:*:*", "user:42:name"));
+        assert!(matches("h?llo", "hello"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-c]llo", "hbllo"));
+        assert!(!matches("h[a-c]llo", "hdllo"));
+        assert!(matches("lit\\*", "lit*"));
+        assert!(!matches("lit\\*", "literal"));
+        assert!(!matches("h[ae", "ha"));
+    }
+
+    #[test]
+    fn invalidate_matching() {
+        let cache : ThreadSafeHashCache<String,u32> = ThreadSafeHashCache::new();
+        for key in &["tenant:x:a", "tenant:x:b", "tenant:y:a", "global"] {
+            cache.insert(key.to_string(), 0);
+        }
+
+        assert_eq!(2, cache.invalidate_matching("tenant:x:*"));
+        assert_eq!(0, cache.invalidate_matching("tenant:x:*"));
+        assert_eq!(1, cache.invalidate_matching("*:a"));
+        assert!(cache.get("global".to_string(), |_| {}));
+    }
+}
@@ -0,0 +1,55 @@
+use zeroize::Zeroizing;
+
+use crate::{DefaultHashBuilder, ThreadSafeHashCache};
+
+// SecretCache is a cache for secrets such as tokens and keys: each value is held in a Zeroizing
+// wrapper, which wipes it when dropped. Since every way out of the cache (expiry, eviction,
+// replacement, take, clear, or dropping the cache) ends in a drop, either inside the cache or
+// in the hands of whoever received the value, no value is freed unwiped. Values that own heap
+// memory (String, Vec) are wiped in place; their buffers never move when the cache grows, only
+// the small headers pointing at them do.
+pub type SecretCache<K, V, S = DefaultHashBuilder> = ThreadSafeHashCache<K, Zeroizing<V>, S>;
+
+#[cfg(test)]
+mod tests {
+    use super::SecretCache;
+    use crate::clock::ManualClock;
+    use crate::CacheBuilder;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use zeroize::{Zeroize, Zeroizing};
+
+    // Secret records its id in a shared log when it's wiped
+    struct Secret(u32, Arc<Mutex<Vec<u32>>>);
+
+    impl Zeroize for Secret {
+        fn zeroize(&mut self) {
+            self.1.lock().unwrap().push(self.0);
+            self.0.zeroize();
+        }
+    }
+
+    #[test]
+    fn wipes_values() {
+        let wiped = Arc::new(Mutex::new(Vec::new()));
+        let secret = |id| Zeroizing::new(Secret(id, wiped.clone()));
+        let clock = ManualClock::new();
+        let cache : SecretCache<&str, Secret> = CacheBuilder::new().max_capacity(3).clock(clock.clone()).build_thread_safe();
+
+        cache.insert_ttl("expired", secret(1), Duration::new(10, 0));
+        cache.insert("replaced", secret(2));
+        cache.insert("evicted", secret(3));
+        drop(cache.insert("replaced", secret(4)));
+        clock.advance(Duration::new(11, 0));
+        cache.vacuum(10, 0.25);
+        assert_eq!(vec![2, 1], *wiped.lock().unwrap());
+        cache.insert("kept", secret(5));
+        cache.insert("new", secret(6));
+        assert_eq!(vec![2, 1, 3], *wiped.lock().unwrap());
+
+        drop(cache);
+        let mut wiped = wiped.lock().unwrap().clone();
+        wiped.sort();
+        assert_eq!(vec![1, 2, 3, 4, 5, 6], wiped);
+    }
+}
@@ -0,0 +1,151 @@
+use std::hash::BuildHasher;
+use std::time::{Duration, Instant};
+
+use crate::{Cache, DefaultHashBuilder, HashCache, ThreadSafeHashCache};
+
+// Session is a session's data and when it was created, as stored in a SessionStore
+#[derive(Debug, Clone)]
+pub struct Session<T> {
+    data: T,
+    created: Instant,
+}
+
+impl<T> Session<T> {
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+}
+
+// SessionStore keeps web sessions by random id. A session expires after idle_timeout without
+// being used, and after max_lifetime regardless of use.
+pub struct SessionStore<T, S = DefaultHashBuilder> {
+    cache: ThreadSafeHashCache<String, Session<T>, S>,
+    idle_timeout: Duration,
+    max_lifetime: Duration,
+}
+
+impl<T> SessionStore<T> {
+    // panics if idle_timeout is 0 or longer than max_lifetime
+    pub fn new(idle_timeout: Duration, max_lifetime: Duration) -> SessionStore<T> {
+        SessionStore::with_cache(ThreadSafeHashCache::new(), idle_timeout, max_lifetime)
+    }
+}
+
+impl<T, S: BuildHasher> SessionStore<T, S> {
+    // with_cache keeps the sessions in cache, e.g. one built with a capacity bound or a clock;
+    // panics if idle_timeout is 0 or longer than max_lifetime
+    pub fn with_cache(cache: ThreadSafeHashCache<String, Session<T>, S>, idle_timeout: Duration, max_lifetime: Duration) -> SessionStore<T, S> {
+        assert!(!idle_timeout.is_zero(), "idle_timeout must be positive");
+        assert!(idle_timeout <= max_lifetime, "idle_timeout must not exceed max_lifetime");
+        SessionStore{ cache, idle_timeout, max_lifetime }
+    }
+
+    pub fn cache(&self) -> &ThreadSafeHashCache<String, Session<T>, S> {
+        &self.cache
+    }
+
+    // create starts a session holding data, returning its id
+    pub fn create(&self, data: T) -> String {
+        let id = session_id();
+        let mut inner = self.cache.write();
+        let created = inner.now();
+        inner.insert_ttl(id.clone(), Session{ data, created }, self.idle_timeout);
+        id
+    }
+
+    // with_session calls f on a live session's data, which it may change, and extends the
+    // session's idle timeout; None if there is no such session
+    pub fn with_session<F, R>(&self, id: &str, f: F) -> Option<R> where F: FnOnce(&mut T) -> R {
+        let mut inner = self.cache.write();
+        let id = id.to_string();
+        let ttl = self.extend(&mut inner, &id)?;
+        let result = f(&mut inner.lookup_mut(&id).expect("checked live above").value.get_mut().data);
+        inner.touch(&id, Some(ttl));
+        Some(result)
+    }
+
+    // get returns a copy of a live session's data, extending its idle timeout
+    pub fn get(&self, id: &str) -> Option<T> where T: Clone {
+        self.with_session(id, |data| data.clone())
+    }
+
+    // regenerate moves a live session to a new id, e.g. after login to prevent session fixation,
+    // returning the new id. The session keeps its creation time, so max_lifetime still counts
+    // from when it was first created.
+    pub fn regenerate(&self, id: &str) -> Option<String> {
+        let mut inner = self.cache.write();
+        let ttl = self.extend(&mut inner, &id.to_string())?;
+        let session = inner.take(id.to_string()).expect("checked live above");
+        let new_id = session_id();
+        inner.insert_ttl(new_id.clone(), session, ttl);
+        Some(new_id)
+    }
+
+    // destroy ends a session, returning its data if it was live
+    pub fn destroy(&self, id: &str) -> Option<T> {
+        self.cache.take(id.to_string()).map(|session| session.data)
+    }
+
+    // extend returns the TTL a live session is extended to on use: the idle timeout, cut short
+    // by the max lifetime. Sessions past their max lifetime are removed.
+    fn extend(&self, inner: &mut HashCache<String, Session<T>, S>, id: &String) -> Option<Duration> {
+        let now = inner.now();
+        let created = match inner.lookup_mut(id) {
+            Some(v) if !v.expired(now) => v.value.get_mut().created,
+            _ => return None,
+        };
+        let left = (created + self.max_lifetime).saturating_duration_since(now);
+        if left.is_zero() {
+            inner.take(id.clone());
+            return None
+        }
+        Some(left.min(self.idle_timeout))
+    }
+}
+
+// session_id returns a random 128-bit id, hex encoded
+fn session_id() -> String {
+    format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionStore;
+    use crate::clock::ManualClock;
+    use crate::CacheBuilder;
+    use std::time::Duration;
+
+    #[test]
+    fn sessions() {
+        let clock = ManualClock::new();
+        let cache = CacheBuilder::new().clock(clock.clone()).build_thread_safe();
+        let store : SessionStore<Vec<&str>> = SessionStore::with_cache(cache, Duration::new(30, 0), Duration::new(100, 0));
+
+        let id = store.create(vec![]);
+        assert_eq!(32, id.len());
+        assert_eq!(Some(1), store.with_session(&id, |cart| { cart.push("book"); cart.len() }));
+
+        // each use slides the idle timeout
+        for _ in 0..3 {
+            clock.advance(Duration::new(20, 0));
+            assert_eq!(Some(vec!["book"]), store.get(&id));
+        }
+
+        // regenerating keeps the data but not the old id
+        let new_id = store.regenerate(&id).unwrap();
+        assert_ne!(id, new_id);
+        assert_eq!(None, store.get(&id));
+        assert_eq!(Some(vec!["book"]), store.get(&new_id));
+
+        // max lifetime caps the session however recently it was used
+        clock.advance(Duration::new(25, 0));
+        assert!(store.get(&new_id).is_some());
+        clock.advance(Duration::new(15, 0));
+        assert_eq!(None, store.get(&new_id));
+
+        // an idle session expires
+        let idle = store.create(vec!["pen"]);
+        clock.advance(Duration::new(31, 0));
+        assert_eq!(None, store.destroy(&idle));
+    }
+}
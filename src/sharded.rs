@@ -0,0 +1,477 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::builder::Config;
+use crate::reaper::Reaper;
+use crate::{Cache, Contention, DebugReport, DefaultHashBuilder, EntryMeta, InsertOutcome, ShardStats, Stats, ThreadSafeHashCache};
+
+// ShardSchedule picks which shard a vacuum_step cleans
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShardSchedule {
+    // RoundRobin visits the shards in turn
+    #[default]
+    RoundRobin,
+    // ExpiringRatio visits the shard with the most entries with a TTL relative to its size, where
+    // expired entries are most likely to be piling up
+    ExpiringRatio,
+}
+
+// VacuumReport sums up a vacuum_parallel run over all shards
+#[cfg(feature = "rayon")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VacuumReport {
+    // expired entries removed
+    pub removed: usize,
+    // shards vacuumed at least once
+    pub shards: usize,
+    // shards the time budget ran out before reaching
+    pub skipped: usize,
+    pub elapsed: Duration,
+}
+
+// ShardedCache splits its entries over several ThreadSafeHashCaches by key hash, each with its
+// own lock, so writers (and vacuums) on one shard don't block readers on the others. Capacity
+// limits and the negative filter size given to the builder are divided evenly between shards.
+pub struct ShardedCache<K: Hash+Eq, V, S = DefaultHashBuilder> {
+    shards: Vec<ThreadSafeHashCache<K, V, S>>,
+    router: S,
+    // cursor is the next shard a round-robin vacuum_step visits
+    cursor: AtomicUsize,
+}
+
+impl<K: Hash+Eq, V> ShardedCache<K, V> {
+    pub fn new(shards: usize) -> ShardedCache<K, V> {
+        ShardedCache::with_hasher(shards, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher+Clone> ShardedCache<K, V, S> {
+    pub fn with_hasher(shards: usize, hash_builder: S) -> ShardedCache<K, V, S> {
+        ShardedCache::from_config(Config::default(), shards, hash_builder)
+    }
+
+    // panics if shards is 0
+    pub(crate) fn from_config(config: Config<K, V>, shards: usize, hash_builder: S) -> ShardedCache<K, V, S> {
+        assert!(shards > 0);
+        let mut config = config;
+        config.initial_capacity = config.initial_capacity.div_ceil(shards);
+        config.max_capacity = config.max_capacity.map(|n| n.div_ceil(shards));
+        config.soft_capacity = config.soft_capacity.map(|n| n.div_ceil(shards));
+        config.negative_filter = config.negative_filter.map(|(n, p)| (n.div_ceil(shards), p));
+
+        ShardedCache{
+            shards: (0..shards).map(|i| {
+                let mut config = config.clone();
+                config.rng_seed = config.rng_seed.map(|seed| seed.wrapping_add(i as u64));
+                ThreadSafeHashCache::from_config(config, hash_builder.clone())
+            }).collect(),
+            router: hash_builder,
+            cursor: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher> ShardedCache<K, V, S> {
+    // the shard is picked from the high bits of the hash, since the shard's own map indexes its
+    // buckets by the low bits
+    fn shard_index<Q: Hash+?Sized>(&self, key: &Q) -> usize {
+        let h = self.router.hash_one(key);
+        ((h >> 32) as usize) % self.shards.len()
+    }
+
+    pub(crate) fn shard(&self, key: &K) -> &ThreadSafeHashCache<K, V, S> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shard(&key).insert(key, value)
+    }
+
+    pub fn insert_ttl(&self, key: K, value: V, ttl: Duration) -> Option<V> {
+        self.shard(&key).insert_ttl(key, value, ttl)
+    }
+
+    pub fn insert_with_outcome(&self, key: K, value: V) -> InsertOutcome<V> {
+        self.shard(&key).insert_with_outcome(key, value)
+    }
+
+    pub fn insert_ttl_with_outcome(&self, key: K, value: V, ttl: Duration) -> InsertOutcome<V> {
+        self.shard(&key).insert_ttl_with_outcome(key, value, ttl)
+    }
+
+    pub fn get<F>(&self, key: K, f: F) -> bool where F: Fn(&V) {
+        self.shard(&key).get(key, f)
+    }
+
+    pub fn get_copied(&self, key: &K) -> Option<V> where V: Copy {
+        self.shard(key).get_copied(key)
+    }
+
+    pub fn take(&self, key: K) -> Option<V> {
+        self.shard(&key).take(key)
+    }
+
+    // take_once is ThreadSafeHashCache::take_once on the key's shard
+    pub fn take_once(&self, key: &K) -> Option<V> {
+        self.shard(key).take_once(key)
+    }
+
+    // get_wait is ThreadSafeHashCache::get_wait on the key's shard
+    pub fn get_wait(&self, key: &K, timeout: Duration) -> Option<V> where K: Clone+Send+Sync+'static, V: Clone {
+        self.shard(key).get_wait(key, timeout)
+    }
+
+    // try_insert_within is ThreadSafeHashCache::try_insert_within on the key's shard, so it only
+    // waits for room in that shard
+    pub fn try_insert_within(&self, key: K, value: V, ttl: Duration, timeout: Duration) -> Result<Option<V>, (K, V)> where K: Send+Sync+'static {
+        self.shard(&key).try_insert_within(key, value, ttl, timeout)
+    }
+
+    pub fn metadata(&self, key: &K) -> Option<EntryMeta> {
+        self.shard(key).metadata(key)
+    }
+
+    pub fn touch(&self, key: &K, ttl: Option<Duration>) -> bool {
+        self.shard(key).touch(key, ttl)
+    }
+
+    // expire_all_after caps every shard's remaining TTLs, one shard at a time
+    pub fn expire_all_after(&self, within: Duration, include_persistent: bool) -> usize {
+        self.shards.iter().map(|s| s.expire_all_after(within, include_persistent)).sum()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.len()).sum()
+    }
+
+    pub fn expiring_len(&self) -> usize {
+        self.shards.iter().map(|s| s.expiring_len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|s| s.is_empty())
+    }
+
+    // stats sums the stats of every shard
+    pub fn stats(&self) -> Stats {
+        self.shards.iter().map(|s| s.stats()).fold(Stats::default(), |a, b| a + b)
+    }
+
+    // contention sums the lock contention of every shard
+    pub fn contention(&self) -> Contention {
+        self.shards.iter().map(|s| s.contention()).fold(Contention::default(), |a, b| a + b)
+    }
+
+    // iter_expired yields the expired entries still stored in every shard, copying out one
+    // shard's at a time
+    pub fn iter_expired(&self) -> impl Iterator<Item=(K, V, Duration)> + '_ where K: Clone, V: Clone {
+        self.shards.iter().flat_map(|s| s.iter_expired())
+    }
+
+    // shard_stats describes every shard, in shard index order (as taken by vacuum_shard). The
+    // shards are read one after another, so under concurrent writes they aren't one snapshot.
+    pub fn shard_stats(&self) -> Vec<ShardStats> {
+        self.shards.iter()
+            .map(|s| ShardStats{ len: s.len(), expiring: s.expiring_len(), stats: s.stats(), contention: s.contention() })
+            .collect()
+    }
+
+    // debug_report reports on every shard, in shard index order
+    pub fn debug_report(&self) -> Vec<DebugReport> {
+        self.shards.iter().map(|s| s.debug_report()).collect()
+    }
+
+    // update is ThreadSafeHashCache::update, locking only the key's shard
+    pub fn update<F, R>(&self, key: &K, f: F) -> Option<R> where F: FnOnce(&mut V) -> R {
+        self.shard(key).update(key, f)
+    }
+
+    // fetch_update is ThreadSafeHashCache::fetch_update on the key's shard
+    pub fn fetch_update<F>(&self, key: &K, f: F) -> Result<V, Option<V>> where F: FnMut(&V) -> Option<V>, V: Clone {
+        self.shard(key).fetch_update(key, f)
+    }
+
+    // vacuum vacuums every shard in turn, holding only one shard's lock at a time
+    // panics if retry-threshold is not between 0 and 1.
+    pub fn vacuum(&self, count : usize, retry_threshold : f32 ) {
+        for shard in &self.shards {
+            shard.vacuum(count, retry_threshold);
+        }
+    }
+
+    // next_expiration is the earliest next_expiration of any shard
+    pub fn next_expiration(&self) -> Option<Instant> {
+        self.shards.iter().filter_map(|shard| shard.next_expiration()).min()
+    }
+
+    // auto_vacuum auto-vacuums every shard in turn, each tuning itself, and returns how many
+    // entries were removed
+    pub fn auto_vacuum(&self) -> usize {
+        self.shards.iter().map(|shard| shard.auto_vacuum()).sum()
+    }
+
+    // vacuum_shard vacuums a single shard
+    // panics if the shard doesn't exist, or retry-threshold is not between 0 and 1.
+    pub fn vacuum_shard(&self, shard: usize, count : usize, retry_threshold : f32 ) {
+        self.shards[shard].vacuum(count, retry_threshold)
+    }
+
+    // vacuum_step vacuums the one shard chosen by the schedule and returns its index, so a caller
+    // can spread cleanup over many short steps
+    pub fn vacuum_step(&self, schedule: ShardSchedule, count : usize, retry_threshold : f32 ) -> usize {
+        let shard = match schedule {
+            ShardSchedule::RoundRobin => self.cursor.fetch_add(1, Ordering::Relaxed) % self.shards.len(),
+            ShardSchedule::ExpiringRatio => {
+                let ratio = |s: &ThreadSafeHashCache<K, V, S>| {
+                    let inner = s.read_index();
+                    inner.expiring.len() as f64 / inner.len().max(1) as f64
+                };
+                (0..self.shards.len())
+                    .map(|i| (i, ratio(&self.shards[i])))
+                    .fold((0, -1.0), |best, (i, r)| if r > best.1 { (i, r) } else { best })
+                    .0
+            },
+        };
+        self.vacuum_shard(shard, count, retry_threshold);
+        shard
+    }
+}
+
+impl<K, V, S> ShardedCache<K, V, S>
+    where K: Hash+Eq+Send+Sync+'static, V: Send+Sync+'static, S: BuildHasher+Send+Sync+'static {
+    // start_vacuum runs a vacuum_step every interval on a background thread, until the returned
+    // Reaper is dropped
+    // panics if retry-threshold is not between 0 and 1.
+    pub fn start_vacuum(self: &Arc<Self>, every: Duration, schedule: ShardSchedule, count : usize, retry_threshold : f32 ) -> Reaper {
+        assert!(retry_threshold > 0.0);
+        assert!(retry_threshold < 1.0);
+        let cache = self.clone();
+        Reaper::spawn(every, move || {
+            cache.vacuum_step(schedule, count, retry_threshold);
+        })
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<K, V, S> ShardedCache<K, V, S>
+    where K: Hash+Eq+Send+Sync, V: Send+Sync, S: BuildHasher+Send+Sync {
+    // vacuum_parallel auto-vacuums the shards concurrently on the rayon thread pool, each one
+    // repeatedly until a run finds nothing expired, and stops starting new runs once budget has
+    // passed. A run in progress isn't interrupted, so the call can overrun the budget by one run
+    // per thread.
+    pub fn vacuum_parallel(&self, budget: Duration) -> VacuumReport {
+        use rayon::prelude::*;
+
+        let start = Instant::now();
+        let deadline = start + budget;
+        let report = self.shards.par_iter()
+            .map(|shard| {
+                let mut report = VacuumReport::default();
+                while Instant::now() < deadline {
+                    report.shards = 1;
+                    let removed = shard.auto_vacuum();
+                    report.removed += removed;
+                    if removed == 0 {
+                        break
+                    }
+                }
+                report.skipped = 1 - report.shards;
+                report
+            })
+            .reduce(VacuumReport::default, |a, b| VacuumReport{
+                removed: a.removed + b.removed,
+                shards: a.shards + b.shards,
+                skipped: a.skipped + b.skipped,
+                elapsed: Duration::ZERO,
+            });
+        event!(DEBUG, removed = report.removed, skipped = report.skipped, "parallel vacuum");
+        VacuumReport{ elapsed: start.elapsed(), ..report }
+    }
+}
+
+impl<V, S: BuildHasher> ShardedCache<String, V, S> {
+    // a &str hashes like the equal String, so it's routed to the same shard
+    pub fn insert_str(&self, key: &str, value: V) -> Option<V> {
+        self.shards[self.shard_index(key)].insert_str(key, value)
+    }
+
+    pub fn insert_str_ttl(&self, key: &str, value: V, ttl: Duration) -> Option<V> {
+        self.shards[self.shard_index(key)].insert_str_ttl(key, value, ttl)
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher+Clone+Default> Default for ShardedCache<K, V, S> {
+    // the default shard count is a fixed 16, rather than something derived from the machine
+    fn default() -> Self {
+        ShardedCache::with_hasher(16, S::default())
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher>  Cache<K,V> for ShardedCache<K, V, S>  {
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        ShardedCache::insert(self, key, value)
+    }
+
+    fn insert_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        ShardedCache::insert_ttl(self, key, value, ttl)
+    }
+
+    fn get_with(&self, key: &K, f: &mut dyn FnMut(&V)) -> bool {
+        self.shard(key).get_with(key, f)
+    }
+
+    fn take(&mut self, key: K) -> Option<V> {
+        ShardedCache::take(self, key)
+    }
+
+    fn vacuum(&mut self, count : usize, retry_threshold : f32 ) {
+        ShardedCache::vacuum(self, count, retry_threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CacheBuilder, Contention, ShardSchedule, ShardStats, ShardedCache, Stats, ThreadSafeHashCache};
+    use std::sync::Arc;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn contention() {
+        let cache : ShardedCache<u32,u32> = CacheBuilder::new().build_sharded(4);
+        let (a, b) = (0..).map(|i| (0, i)).find(|&(a, b)| cache.shard_index(&a) != cache.shard_index(&b)).unwrap();
+        cache.insert(a, 0);
+        cache.insert(b, 0);
+
+        // an update in progress on one shard doesn't hold up reads of another
+        std::thread::scope(|s| {
+            cache.update(&a, |v| {
+                s.spawn(|| assert!(cache.get(b, |_| {}))).join().unwrap();
+                *v += 1;
+            });
+        });
+        assert_eq!(Some(1), cache.get_copied(&a));
+        assert_eq!(0, cache.contention().read_waits);
+
+        // without shards, an update only holds up reads of the keys sharing its stripe
+        let single = ThreadSafeHashCache::new();
+        single.insert(a, 0);
+        single.insert(b, 0);
+        let c = (0..).find(|&c| c != a && single.stripes.of(&c) != single.stripes.of(&a)).unwrap();
+        single.insert(c, 0);
+        std::thread::scope(|s| {
+            let read = single.update(&a, |v| {
+                s.spawn(|| assert!(single.get(c, |_| {}))).join().unwrap();
+                let reader = s.spawn(|| single.get_copied(&a));
+                while single.contention().read_waits == 0 {
+                    std::thread::yield_now();
+                }
+                *v += 1;
+                reader
+            }).unwrap().join().unwrap();
+            // the read of the updated key waited, and saw the update
+            assert_eq!(Some(1), read);
+        });
+        assert_eq!(Some(1), single.get_copied(&a));
+        assert_eq!(Contention{ reads: 3, read_waits: 1, writes: 4, write_waits: 0 }, single.contention());
+    }
+
+    #[test]
+    fn sharded() {
+        let cache : ShardedCache<u32,u32> = CacheBuilder::new().max_capacity(256).build_sharded(4);
+        assert_eq!(4, cache.shard_count());
+        for i in 0..128 {
+            cache.insert(i, i);
+        }
+        assert_eq!(128, cache.len());
+        assert!(cache.shards.iter().all(|s| !s.is_empty()));
+        assert_eq!(Some(7), cache.get_copied(&7));
+        assert_eq!(Some(7), cache.take(7));
+        assert!(!cache.get(7, |_| {}));
+    }
+
+    #[test]
+    fn shard_stats() {
+        let cache : ShardedCache<u32,u32> = ShardedCache::new(4);
+        let hot = cache.shard_index(&0);
+        cache.insert_ttl(0, 0, Duration::from_millis(10));
+        for _ in 0..9 {
+            cache.get(0, |_| {});
+        }
+        let mut cold = (1..).filter(|k| cache.shard_index(k) != hot);
+        let (stored, missing) = (cold.next().unwrap(), cold.next().unwrap());
+        cache.insert(stored, 0);
+        cache.get(missing, |_| {});
+
+        let shards = cache.shard_stats();
+        assert_eq!(4, shards.len());
+        assert_eq!((1, 1, 9), (shards[hot].len, shards[hot].expiring, shards[hot].stats.hits));
+        assert_eq!(1.0, shards[hot].stats.hit_ratio());
+        assert_eq!((1, 0), (shards[hot].contention.writes, shards[hot].contention.write_waits));
+        let others: ShardStats = shards.iter().enumerate().filter(|&(i, _)| i != hot).map(|(_, s)| *s)
+            .fold(ShardStats::default(), |a, b| ShardStats{ len: a.len + b.len, stats: a.stats + b.stats, ..a });
+        assert_eq!((1, 0, 1), (others.len, others.stats.hits, others.stats.misses));
+
+        sleep(Duration::from_millis(20));
+        cache.vacuum_shard(hot, 10, 0.25);
+        assert_eq!(1, cache.shard_stats()[hot].stats.expirations);
+        assert_eq!(cache.stats(), cache.shard_stats().iter().fold(Stats::default(), |a, s| a + s.stats));
+    }
+
+    #[test]
+    fn per_shard_vacuum() {
+        let cache : ShardedCache<u32,u32> = ShardedCache::new(4);
+        for i in 0..100 {
+            cache.insert_ttl(i, i, Duration::from_millis(10));
+        }
+        sleep(Duration::from_millis(20));
+
+        // round robin visits every shard once per cycle
+        let visited: Vec<usize> = (0..4).map(|_| cache.vacuum_step(ShardSchedule::RoundRobin, 100, 0.25)).collect();
+        assert_eq!(vec![0, 1, 2, 3], visited);
+        assert!(cache.is_empty());
+
+        // expiring ratio goes straight to the shard with expiring entries
+        cache.insert(0, 0);
+        cache.insert(1, 1);
+        cache.insert_ttl(2, 2, Duration::from_millis(10));
+        let expiring = cache.shard_index(&2);
+        assert_eq!(expiring, cache.vacuum_step(ShardSchedule::ExpiringRatio, 10, 0.25));
+
+        let cache = Arc::new(cache);
+        sleep(Duration::from_millis(20));
+        let reaper = cache.start_vacuum(Duration::from_millis(5), ShardSchedule::ExpiringRatio, 10, 0.25);
+        sleep(Duration::from_millis(40));
+        drop(reaper);
+        assert_eq!(2, cache.len());
+        assert_eq!(101, cache.stats().expirations);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn vacuum_parallel() {
+        let clock = crate::ManualClock::new();
+        let cache : ShardedCache<u32,u32> = CacheBuilder::new().clock(clock.clone()).build_sharded(4);
+        for i in 0..10_000 {
+            cache.insert_ttl(i, i, Duration::new(10 + 90 * (i as u64 % 2), 0));
+        }
+        clock.advance(Duration::new(11, 0));
+
+        // with no time at all no shard is started
+        let report = cache.vacuum_parallel(Duration::ZERO);
+        assert_eq!((0, 0, 4), (report.removed, report.shards, report.skipped));
+
+        let report = cache.vacuum_parallel(Duration::from_secs(10));
+        assert_eq!((4, 0), (report.shards, report.skipped));
+        assert_eq!(report.removed as u64, cache.stats().expirations);
+        // runs stop once a sample turns up nothing, so a few expired stragglers may be left behind
+        assert_eq!(10_000, report.removed + cache.len());
+        assert!(report.removed > 4_500);
+    }
+}
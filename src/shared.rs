@@ -0,0 +1,99 @@
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{DefaultHashBuilder, ThreadSafeHashCache};
+
+// ArcCache stores values behind an Arc, so get can hand out a cheap clone of the Arc and release
+// the lock right away; large values can then be used (or sent to other threads) for as long as
+// needed without blocking writers. A configured cache can be wrapped with ArcCache::from, e.g.
+// ArcCache::from(CacheBuilder::new().max_capacity(100).build_thread_safe())
+pub struct ArcCache<K: Hash+Eq+Clone, V, S = DefaultHashBuilder> {
+    cache: ThreadSafeHashCache<K, Arc<V>, S>,
+}
+
+impl<K: Hash+Eq+Clone, V> ArcCache<K, V> {
+    pub fn new() -> ArcCache<K, V> {
+        ArcCache::from(ThreadSafeHashCache::new())
+    }
+}
+
+impl<K: Hash+Eq+Clone, V, S: BuildHasher> ArcCache<K, V, S> {
+    // cache is the underlying cache, e.g. for vacuuming or subscribing
+    pub fn cache(&self) -> &ThreadSafeHashCache<K, Arc<V>, S> {
+        &self.cache
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<Arc<V>> {
+        self.cache.insert(key, Arc::new(value))
+    }
+
+    pub fn insert_ttl(&self, key: K, value: V, ttl: Duration) -> Option<Arc<V>> {
+        self.cache.insert_ttl(key, Arc::new(value), ttl)
+    }
+
+    // insert_arc stores a value that is already shared
+    pub fn insert_arc(&self, key: K, value: Arc<V>) -> Option<Arc<V>> {
+        self.cache.insert(key, value)
+    }
+
+    // get returns a handle to a live value; the lock is only held while the Arc is cloned
+    pub fn get(&self, key: &K) -> Option<Arc<V>> {
+        if self.cache.definitely_absent(key) {
+            return None
+        }
+        self.cache.read_key(key).hit(key).cloned()
+    }
+
+    pub fn take(&self, key: K) -> Option<Arc<V>> {
+        self.cache.take(key)
+    }
+
+    // vacuum samples the set of potentially expired keys and removes them if expired
+    // panics if retry-threshold is not between 0 and 1.
+    pub fn vacuum(&self, count : usize, retry_threshold : f32 ) {
+        self.cache.vacuum(count, retry_threshold)
+    }
+}
+
+impl<K: Hash+Eq+Clone, V, S: BuildHasher> From<ThreadSafeHashCache<K, Arc<V>, S>> for ArcCache<K, V, S> {
+    fn from(cache: ThreadSafeHashCache<K, Arc<V>, S>) -> Self {
+        ArcCache{ cache }
+    }
+}
+
+impl<K: Hash+Eq+Clone, V, S: BuildHasher+Default> Default for ArcCache<K, V, S> {
+    fn default() -> Self {
+        ArcCache::from(ThreadSafeHashCache::with_hasher(S::default()))
+    }
+}
+
+impl<K: Hash+Eq+Clone+fmt::Debug, V: fmt::Debug, S: BuildHasher> fmt::Debug for ArcCache<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.cache.read().fmt_named("ArcCache", f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ArcCache;
+    use crate::CacheBuilder;
+    use std::sync::Arc;
+    use std::thread::spawn;
+
+    #[test]
+    fn shares_values() {
+        let cache : ArcCache<&str, Vec<u8>> = ArcCache::from(CacheBuilder::new().max_capacity(10).build_thread_safe());
+        cache.insert("blob", vec![0; 1024]);
+
+        let blob = cache.get(&"blob").unwrap();
+        assert_eq!(2, Arc::strong_count(&blob));
+
+        // the handle outlives both the lock and the entry
+        let reader = spawn(move || blob.len());
+        cache.take("blob");
+        assert_eq!(1024, reader.join().unwrap());
+        assert!(cache.get(&"blob").is_none());
+    }
+}
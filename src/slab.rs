@@ -0,0 +1,151 @@
+use std::ops::{Index, IndexMut};
+
+// Slot is either an occupied slot or a link in the free list
+#[derive(Clone)]
+enum Slot<T> {
+    Occupied(T),
+    Vacant(usize),
+}
+
+// Slab is a Vec-backed arena handing out stable indices. Removed slots are threaded into a free
+// list and reused by later inserts, so a cache with steady churn stops allocating once it has
+// reached its working size, and its entries stay packed in one allocation.
+#[derive(Clone)]
+pub(crate) struct Slab<T> {
+    slots: Vec<Slot<T>>,
+    // next_free is the head of the free list; slots.len() when it's empty
+    next_free: usize,
+    len: usize,
+}
+
+impl<T> Slab<T> {
+    pub(crate) fn with_capacity(n: usize) -> Slab<T> {
+        Slab{ slots: Vec::with_capacity(n), next_free: 0, len: 0 }
+    }
+
+    // slots is the number of slots in use or free, i.e. one past the highest index handed out
+    pub(crate) fn slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        let vacant = self.slots.len() - self.len;
+        self.slots.reserve(additional.saturating_sub(vacant));
+    }
+
+    // insert stores a value in a free slot (or a new one) and returns its index
+    pub(crate) fn insert(&mut self, value: T) -> usize {
+        let index = self.next_free;
+        if index == self.slots.len() {
+            self.slots.push(Slot::Occupied(value));
+            self.next_free = self.slots.len();
+        } else {
+            match std::mem::replace(&mut self.slots[index], Slot::Occupied(value)) {
+                Slot::Vacant(next) => self.next_free = next,
+                Slot::Occupied(_) => unreachable!("free list points at an occupied slot"),
+            }
+        }
+        self.len += 1;
+        index
+    }
+
+    pub(crate) fn remove(&mut self, index: usize) -> Option<T> {
+        match self.slots.get_mut(index) {
+            Some(slot @ Slot::Occupied(_)) => {
+                let removed = std::mem::replace(slot, Slot::Vacant(self.next_free));
+                self.next_free = index;
+                self.len -= 1;
+                match removed {
+                    Slot::Occupied(value) => Some(value),
+                    Slot::Vacant(_) => unreachable!(),
+                }
+            },
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<&T> {
+        match self.slots.get(index) {
+            Some(Slot::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        match self.slots.get_mut(index) {
+            Some(Slot::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    // iter yields the occupied slots with their indices, in index order
+    pub(crate) fn iter(&self) -> impl Iterator<Item=(usize, &T)> {
+        self.slots.iter().enumerate().filter_map(|(i, slot)| match slot {
+            Slot::Occupied(value) => Some((i, value)),
+            Slot::Vacant(_) => None,
+        })
+    }
+
+    // shrink_to drops trailing free slots and releases memory down to max(min, the highest
+    // occupied index); free slots below that can't move, since their indices are handed out
+    pub(crate) fn shrink_to(&mut self, min: usize) {
+        while let Some(Slot::Vacant(_)) = self.slots.last() {
+            self.slots.pop();
+        }
+
+        // rebuild the free list, as it may have pointed into the truncated tail
+        self.next_free = self.slots.len();
+        for i in (0..self.slots.len()).rev() {
+            if let Slot::Vacant(next) = &mut self.slots[i] {
+                *next = self.next_free;
+                self.next_free = i;
+            }
+        }
+        self.slots.shrink_to(min);
+    }
+}
+
+impl<T> Index<usize> for Slab<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        self.get(index).expect("vacant slab slot")
+    }
+}
+
+impl<T> IndexMut<usize> for Slab<T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        self.get_mut(index).expect("vacant slab slot")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Slab;
+
+    #[test]
+    fn reuses_slots() {
+        let mut slab = Slab::with_capacity(4);
+        let a = slab.insert("a");
+        let b = slab.insert("b");
+        let c = slab.insert("c");
+        assert_eq!(3, slab.len);
+
+        assert_eq!(Some("b"), slab.remove(b));
+        assert_eq!(None, slab.remove(b));
+        assert_eq!(b, slab.insert("d"));
+        assert_eq!("d", slab[b]);
+
+        // trailing free slots are released, and the free list still works afterwards
+        slab.remove(c);
+        slab.remove(a);
+        slab.shrink_to(0);
+        assert_eq!(vec![(b, &"d")], slab.iter().collect::<Vec<_>>());
+        assert_eq!(a, slab.insert("e"));
+        assert_eq!(2, slab.insert("f"));
+    }
+}
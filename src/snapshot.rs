@@ -0,0 +1,415 @@
+use std::fs::File;
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(feature = "encryption")]
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+#[cfg(feature = "encryption")]
+use chacha20poly1305::XChaCha20Poly1305;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::{HashCache, ThreadSafeHashCache, Value, Warmup};
+
+// Snapshot is a serializable copy of a cache's live entries
+// each TTL is stored both as the time remaining when the snapshot was taken and as a wall-clock
+// (Unix) deadline; loading goes by the deadline, so time spent between saving and loading (a
+// restart, or a copy to another host) counts against the entry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot<K, V> {
+    pub entries: Vec<SnapshotEntry<K, V>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotEntry<K, V> {
+    pub key: K,
+    pub value: V,
+    // remaining TTL, or None for a persistent entry
+    pub ttl: Option<Duration>,
+    // the wall-clock deadline; snapshots from before deadlines were recorded don't have one
+    #[serde(default)]
+    pub deadline: Option<SystemTime>,
+}
+
+// Format is an encoding for snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Json,
+    // compact binary, behind a versioned header so older snapshots can be detected and migrated
+    #[cfg(feature = "bincode")]
+    Bincode,
+    // MessagePack and CBOR, for snapshots shared with tools in other languages; structs are
+    // encoded as maps keyed by field name, and durations and deadlines as {secs, nanos} and
+    // {secs_since_epoch, nanos_since_epoch} maps
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+// the bincode header: a magic number, then the format version as a little-endian u16
+#[cfg(feature = "bincode")]
+const BINCODE_MAGIC: &[u8; 4] = b"HDOR";
+#[cfg(feature = "bincode")]
+const BINCODE_VERSION: u16 = 1;
+
+// encrypted snapshots start with their own magic number, which is also authenticated, then the
+// random 24-byte nonce; the rest is the encoded snapshot sealed with XChaCha20-Poly1305
+#[cfg(feature = "encryption")]
+const SEALED_MAGIC: &[u8; 4] = b"HDRE";
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 24;
+
+#[cfg(any(feature = "msgpack", feature = "cbor"))]
+fn invalid_data<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(feature = "bincode")]
+fn bincode_error(e: bincode::ErrorKind) -> io::Error {
+    match e {
+        bincode::ErrorKind::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+impl<K: Serialize, V: Serialize> Snapshot<K, V> {
+    // write_to encodes the snapshot as JSON
+    pub fn write_to<W: Write>(&self, w: W) -> io::Result<()> {
+        self.encode(w, Format::Json)
+    }
+
+    pub fn encode<W: Write>(&self, w: W, format: Format) -> io::Result<()> {
+        match format {
+            Format::Json => serde_json::to_writer(w, self)?,
+            #[cfg(feature = "bincode")]
+            Format::Bincode => {
+                let mut w = w;
+                w.write_all(BINCODE_MAGIC)?;
+                w.write_all(&BINCODE_VERSION.to_le_bytes())?;
+                bincode::serialize_into(w, self).map_err(|e| bincode_error(*e))?
+            },
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => {
+                let mut w = w;
+                rmp_serde::encode::write_named(&mut w, self).map_err(invalid_data)?
+            },
+            #[cfg(feature = "cbor")]
+            Format::Cbor => ciborium::into_writer(self, w).map_err(invalid_data)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl<K: Serialize, V: Serialize> Snapshot<K, V> {
+    // encode_encrypted encodes the snapshot in format and seals it with key, so it can neither be
+    // read nor altered without the key
+    pub fn encode_encrypted<W: Write>(&self, mut w: W, format: Format, key: &[u8; 32]) -> io::Result<()> {
+        let mut plain = Vec::new();
+        self.encode(&mut plain, format)?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = XChaCha20Poly1305::new(key.into())
+            .encrypt(&nonce, Payload{ msg: &plain, aad: SEALED_MAGIC })
+            .map_err(|_| io::Error::other("failed to encrypt snapshot"))?;
+        w.write_all(SEALED_MAGIC)?;
+        w.write_all(&nonce)?;
+        w.write_all(&sealed)
+    }
+}
+
+impl<K: DeserializeOwned, V: DeserializeOwned> Snapshot<K, V> {
+    pub fn read_from<R: Read>(r: R) -> io::Result<Snapshot<K, V>> {
+        Snapshot::decode(r, Format::Json)
+    }
+
+    pub fn decode<R: Read>(r: R, format: Format) -> io::Result<Snapshot<K, V>> {
+        match format {
+            Format::Json => Ok(serde_json::from_reader(r)?),
+            #[cfg(feature = "bincode")]
+            Format::Bincode => {
+                let mut r = r;
+                let mut header = [0; 6];
+                r.read_exact(&mut header)?;
+                if &header[..4] != BINCODE_MAGIC {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "not a hodor snapshot"))
+                }
+                match u16::from_le_bytes([header[4], header[5]]) {
+                    BINCODE_VERSION => bincode::deserialize_from(r).map_err(|e| bincode_error(*e)),
+                    version => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported snapshot version {}", version))),
+                }
+            },
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => rmp_serde::from_read(r).map_err(invalid_data),
+            #[cfg(feature = "cbor")]
+            Format::Cbor => ciborium::from_reader(r).map_err(invalid_data),
+        }
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl<K: DeserializeOwned, V: DeserializeOwned> Snapshot<K, V> {
+    // decode_encrypted opens a snapshot written by encode_encrypted; a wrong key and a modified
+    // file are both reported as InvalidData
+    pub fn decode_encrypted<R: Read>(mut r: R, format: Format, key: &[u8; 32]) -> io::Result<Snapshot<K, V>> {
+        let mut sealed = Vec::new();
+        r.read_to_end(&mut sealed)?;
+        if sealed.len() < SEALED_MAGIC.len() + NONCE_LEN || &sealed[..SEALED_MAGIC.len()] != SEALED_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an encrypted hodor snapshot"))
+        }
+        let (nonce, msg) = sealed[SEALED_MAGIC.len()..].split_at(NONCE_LEN);
+        let plain = XChaCha20Poly1305::new(key.into())
+            .decrypt(nonce.into(), Payload{ msg, aad: SEALED_MAGIC })
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "snapshot failed authentication"))?;
+        Snapshot::decode(&plain[..], format)
+    }
+}
+
+impl<K, V> Snapshot<K, V> {
+    // into_entries yields (key, value, ttl) triples in the shape warm_from expects, with TTLs
+    // counted from now to each entry's deadline; entries past their deadline are skipped
+    pub fn into_entries(self) -> impl Iterator<Item=(K, V, Option<Duration>)> {
+        let now = SystemTime::now();
+        self.entries.into_iter().filter_map(move |e| {
+            let ttl = match e.deadline {
+                Some(deadline) => Some(deadline.duration_since(now).ok()?),
+                None => e.ttl,
+            };
+            Some((e.key, e.value, ttl))
+        })
+    }
+}
+
+// snapshot_entry returns the entry as it should be persisted, or None if it has already expired
+fn snapshot_entry<K, V>(key: K, v: &Value<V>, now: Instant) -> Option<SnapshotEntry<K, &V>> {
+    let ttl = match v.meta(now).expires_at() {
+        Some(at) if at <= now => return None,
+        Some(at) => Some(at - now),
+        None => None,
+    };
+    let deadline = ttl.map(|ttl| SystemTime::now() + ttl);
+    // SAFETY: see ValueCell::get; a ThreadSafeHashCache snapshots under read, which holds every
+    // stripe
+    Some(SnapshotEntry{ key, value: unsafe { v.value.get() }, ttl, deadline })
+}
+
+impl<K: Hash+Eq+Clone, V, S: BuildHasher> HashCache<K, V, S> {
+    // snapshot copies the live entries of the cache
+    pub fn snapshot(&self) -> Snapshot<K, V> where V: Clone {
+        let now = self.now();
+        let entries = self.entries.iter()
+            .filter_map(|(_, (k, v))| snapshot_entry(K::clone(k), v, now))
+            .map(|e| SnapshotEntry{ key: e.key, value: e.value.clone(), ttl: e.ttl, deadline: e.deadline })
+            .collect();
+        Snapshot{ entries }
+    }
+
+    // save_snapshot writes the live entries to path as JSON without cloning them
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> where K: Serialize, V: Serialize {
+        self.save_snapshot_as(path, Format::Json)
+    }
+
+    pub fn save_snapshot_as<P: AsRef<Path>>(&self, path: P, format: Format) -> io::Result<()> where K: Serialize, V: Serialize {
+        let now = self.now();
+        let entries = self.entries.iter()
+            .filter_map(|(_, (k, v))| snapshot_entry(&**k, v, now))
+            .collect();
+        let mut w = BufWriter::new(File::create(path)?);
+        Snapshot{ entries }.encode(&mut w, format)?;
+        w.flush()
+    }
+
+    // save_snapshot_encrypted is save_snapshot_as, sealing the file with key (see
+    // Snapshot::encode_encrypted)
+    #[cfg(feature = "encryption")]
+    pub fn save_snapshot_encrypted<P: AsRef<Path>>(&self, path: P, format: Format, key: &[u8; 32]) -> io::Result<()> where K: Serialize, V: Serialize {
+        let now = self.now();
+        let entries = self.entries.iter()
+            .filter_map(|(_, (k, v))| snapshot_entry(&**k, v, now))
+            .collect();
+        let mut w = BufWriter::new(File::create(path)?);
+        Snapshot{ entries }.encode_encrypted(&mut w, format, key)?;
+        w.flush()
+    }
+
+    // warm_from_snapshot bulk-loads a snapshot written by save_snapshot, see warm_from
+    pub fn warm_from_snapshot<P: AsRef<Path>>(&mut self, path: P, warmup: Warmup<'_>) -> io::Result<usize> where K: DeserializeOwned, V: DeserializeOwned {
+        self.warm_from_snapshot_as(path, Format::Json, warmup)
+    }
+
+    pub fn warm_from_snapshot_as<P: AsRef<Path>>(&mut self, path: P, format: Format, warmup: Warmup<'_>) -> io::Result<usize> where K: DeserializeOwned, V: DeserializeOwned {
+        let snapshot = Snapshot::decode(BufReader::new(File::open(path)?), format)?;
+        Ok(self.warm_from(snapshot.into_entries(), warmup))
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn warm_from_snapshot_encrypted<P: AsRef<Path>>(&mut self, path: P, format: Format, key: &[u8; 32], warmup: Warmup<'_>) -> io::Result<usize> where K: DeserializeOwned, V: DeserializeOwned {
+        let snapshot = Snapshot::decode_encrypted(BufReader::new(File::open(path)?), format, key)?;
+        Ok(self.warm_from(snapshot.into_entries(), warmup))
+    }
+}
+
+impl<K: Hash+Eq+Clone, V, S: BuildHasher> ThreadSafeHashCache<K, V, S> {
+    // export_snapshot is HashCache::snapshot, copying the live entries and their TTLs for
+    // encoding; ThreadSafeHashCache::snapshot is the in-memory View
+    pub fn export_snapshot(&self) -> Snapshot<K, V> where V: Clone {
+        self.read().snapshot()
+    }
+
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> where K: Serialize, V: Serialize {
+        self.read().save_snapshot(path)
+    }
+
+    pub fn save_snapshot_as<P: AsRef<Path>>(&self, path: P, format: Format) -> io::Result<()> where K: Serialize, V: Serialize {
+        self.read().save_snapshot_as(path, format)
+    }
+
+    pub fn warm_from_snapshot<P: AsRef<Path>>(&self, path: P, warmup: Warmup<'_>) -> io::Result<usize> where K: DeserializeOwned, V: DeserializeOwned {
+        self.warm_from_snapshot_as(path, Format::Json, warmup)
+    }
+
+    pub fn warm_from_snapshot_as<P: AsRef<Path>>(&self, path: P, format: Format, warmup: Warmup<'_>) -> io::Result<usize> where K: DeserializeOwned, V: DeserializeOwned {
+        // decode before taking the lock so readers aren't blocked on file IO
+        let snapshot = Snapshot::decode(BufReader::new(File::open(path)?), format)?;
+        Ok(self.warm_from(snapshot.into_entries(), warmup))
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn save_snapshot_encrypted<P: AsRef<Path>>(&self, path: P, format: Format, key: &[u8; 32]) -> io::Result<()> where K: Serialize, V: Serialize {
+        self.read().save_snapshot_encrypted(path, format, key)
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn warm_from_snapshot_encrypted<P: AsRef<Path>>(&self, path: P, format: Format, key: &[u8; 32], warmup: Warmup<'_>) -> io::Result<usize> where K: DeserializeOwned, V: DeserializeOwned {
+        let snapshot = Snapshot::decode_encrypted(BufReader::new(File::open(path)?), format, key)?;
+        Ok(self.warm_from(snapshot.into_entries(), warmup))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cache, HashCache, Snapshot, SnapshotEntry, ThreadSafeHashCache, Warmup};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut cache : HashCache<String,String> = HashCache::new();
+        cache.insert("id".to_string(), "secret".to_string());
+        cache.insert_ttl("id2".to_string(), "secret2".to_string(), Duration::new(10, 0));
+        cache.insert_ttl("gone".to_string(), "secret3".to_string(), Duration::from_millis(1));
+        std::thread::sleep(Duration::from_millis(5));
+
+        let path = std::env::temp_dir().join(format!("hodor-snapshot-{}.json", std::process::id()));
+        cache.save_snapshot(&path).unwrap();
+
+        let restored : ThreadSafeHashCache<String,String> = ThreadSafeHashCache::new();
+        let loaded = restored.warm_from_snapshot(&path, Warmup::new()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // the expired entry isn't persisted, and the remaining TTL is carried over
+        assert_eq!(2, loaded);
+        assert!(restored.get("id".to_string(), |v| assert_eq!(v, "secret")));
+        let snapshot = restored.export_snapshot();
+        let entry = snapshot.entries.iter().find(|e| e.key == "id2").unwrap();
+        assert!(entry.ttl.unwrap() <= Duration::new(10, 0));
+        assert!(entry.ttl.unwrap() > Duration::new(9, 0));
+    }
+
+    #[test]
+    fn deadlines() {
+        let now = SystemTime::now();
+        let entry = |key, deadline| SnapshotEntry{ key, value: 0, ttl: Some(Duration::new(60, 0)), deadline };
+        let snapshot = Snapshot{ entries: vec![
+            entry("passed", Some(now - Duration::new(1, 0))),
+            entry("pending", Some(now + Duration::new(30, 0))),
+            // no deadline, as in snapshots from older versions
+            entry("legacy", None),
+        ]};
+
+        // the deadline wins over the remaining TTL recorded at save time
+        let entries: Vec<_> = snapshot.into_entries().collect();
+        assert_eq!(2, entries.len());
+        assert_eq!("pending", entries[0].0);
+        assert!(entries[0].2.unwrap() <= Duration::new(30, 0));
+        assert_eq!(("legacy", Some(Duration::new(60, 0))), (entries[1].0, entries[1].2));
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_round_trip() {
+        use crate::Format;
+
+        let cache : ThreadSafeHashCache<String,u64> = ThreadSafeHashCache::new();
+        cache.insert("id".to_string(), 1);
+        cache.insert_ttl("id2".to_string(), 2, Duration::new(10, 0));
+
+        let mut buf = Vec::new();
+        cache.export_snapshot().encode(&mut buf, Format::Bincode).unwrap();
+        assert_eq!(b"HDOR\x01\x00", &buf[..6]);
+        let decoded : Snapshot<String,u64> = Snapshot::decode(&buf[..], Format::Bincode).unwrap();
+        assert_eq!(2, decoded.entries.len());
+
+        // snapshots from other versions, or other formats, are rejected rather than misread
+        buf[4] = 2;
+        let err = Snapshot::<String,u64>::decode(&buf[..], Format::Bincode).unwrap_err();
+        assert_eq!("unsupported snapshot version 2", err.to_string());
+        let mut json = Vec::new();
+        cache.export_snapshot().write_to(&mut json).unwrap();
+        assert!(Snapshot::<String,u64>::decode(&json[..], Format::Bincode).is_err());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_round_trip() {
+        use crate::Format;
+
+        let cache : ThreadSafeHashCache<String,String> = ThreadSafeHashCache::new();
+        cache.insert("id".to_string(), "secret".to_string());
+        let path = std::env::temp_dir().join(format!("hodor-encrypted-{}.bin", std::process::id()));
+        let key = [7; 32];
+        cache.save_snapshot_encrypted(&path, Format::Json, &key).unwrap();
+
+        // the value isn't recoverable from the file
+        let sealed = std::fs::read(&path).unwrap();
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+
+        let restored : ThreadSafeHashCache<String,String> = ThreadSafeHashCache::new();
+        assert_eq!(1, restored.warm_from_snapshot_encrypted(&path, Format::Json, &key, Warmup::new()).unwrap());
+        assert!(restored.get("id".to_string(), |v| assert_eq!(v, "secret")));
+
+        // a wrong key or a modified file is rejected
+        let err = restored.warm_from_snapshot_encrypted(&path, Format::Json, &[8; 32], Warmup::new()).unwrap_err();
+        assert_eq!("snapshot failed authentication", err.to_string());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(Snapshot::<String,String>::decode_encrypted(&tampered[..], Format::Json, &key).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(any(feature = "msgpack", feature = "cbor"))]
+    #[test]
+    fn portable_formats() {
+        use crate::Format;
+
+        let cache : ThreadSafeHashCache<String,String> = ThreadSafeHashCache::new();
+        cache.insert("id".to_string(), "secret".to_string());
+        cache.insert_ttl("id2".to_string(), "secret2".to_string(), Duration::new(10, 0));
+        let snapshot = cache.export_snapshot();
+
+        let mut formats = Vec::new();
+        #[cfg(feature = "msgpack")]
+        formats.push(Format::MessagePack);
+        #[cfg(feature = "cbor")]
+        formats.push(Format::Cbor);
+        for format in formats {
+            let mut buf = Vec::new();
+            snapshot.encode(&mut buf, format).unwrap();
+            let decoded : Snapshot<String,String> = Snapshot::decode(&buf[..], format).unwrap();
+            assert_eq!(snapshot, decoded);
+            assert!(Snapshot::<String,String>::decode(&b"{}"[..], format).is_err());
+        }
+    }
+}
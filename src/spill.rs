@@ -0,0 +1,165 @@
+use std::fmt;
+use std::fs;
+use std::hash::{BuildHasher, Hash};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{DefaultHashBuilder, ThreadSafeHashCache};
+
+// SpillFile is a value written out to disk; the file is deleted once the last handle to it is
+// dropped, i.e. once its entry has left the cache (expired, evicted, replaced or taken) and no
+// get is still reading it
+pub struct SpillFile {
+    path: PathBuf,
+}
+
+impl SpillFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(_e) = fs::remove_file(&self.path) {
+            event!(WARN, error = %_e, path = %self.path.display(), "failed to remove spilled value");
+        }
+    }
+}
+
+impl fmt::Debug for SpillFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SpillFile").field(&self.path).finish()
+    }
+}
+
+// Spilled is a SpillCache value: kept in memory, or on disk if it was over the size threshold
+#[derive(Debug, Clone)]
+pub enum Spilled {
+    Memory(Vec<u8>),
+    Disk(Arc<SpillFile>),
+}
+
+// SpillCache caches byte values, writing those over a size threshold to files in a directory so
+// only a handle to them stays in memory. Large, rarely read values then cost disk space rather
+// than memory, and a read of one costs a file read. A configured cache can be wrapped with
+// SpillCache::new, e.g. SpillCache::new(CacheBuilder::new().max_capacity(1000).build_thread_safe(), dir, 64 * 1024)
+pub struct SpillCache<K: Hash+Eq+Clone, S = DefaultHashBuilder> {
+    cache: ThreadSafeHashCache<K, Spilled, S>,
+    dir: PathBuf,
+    threshold: usize,
+    // next numbers the files written, so every value gets a file of its own
+    next: AtomicU64,
+}
+
+impl<K: Hash+Eq+Clone, S: BuildHasher> SpillCache<K, S> {
+    // new spills values longer than threshold bytes to files in dir, creating it if needed. dir
+    // should belong to this cache alone: files are named by a counter and would collide.
+    pub fn new<P: AsRef<Path>>(cache: ThreadSafeHashCache<K, Spilled, S>, dir: P, threshold: usize) -> io::Result<SpillCache<K, S>> {
+        fs::create_dir_all(&dir)?;
+        Ok(SpillCache{ cache, dir: dir.as_ref().to_path_buf(), threshold, next: AtomicU64::new(0) })
+    }
+
+    // cache is the underlying cache, e.g. for vacuuming or subscribing
+    pub fn cache(&self) -> &ThreadSafeHashCache<K, Spilled, S> {
+        &self.cache
+    }
+
+    // spill writes value to a file of its own if it is over the threshold
+    fn spill(&self, value: Vec<u8>) -> io::Result<Spilled> {
+        if value.len() <= self.threshold {
+            return Ok(Spilled::Memory(value))
+        }
+        let path = self.dir.join(format!("{}.bin", self.next.fetch_add(1, Ordering::Relaxed)));
+        fs::write(&path, &value)?;
+        Ok(Spilled::Disk(Arc::new(SpillFile{ path })))
+    }
+
+    fn load(value: Spilled) -> io::Result<Vec<u8>> {
+        match value {
+            Spilled::Memory(bytes) => Ok(bytes),
+            Spilled::Disk(file) => fs::read(file.path()),
+        }
+    }
+
+    // insert stores a value, writing it to disk first if it is over the threshold; nothing is
+    // stored if that fails
+    pub fn insert(&self, key: K, value: Vec<u8>) -> io::Result<()> {
+        self.cache.insert(key, self.spill(value)?);
+        Ok(())
+    }
+
+    pub fn insert_ttl(&self, key: K, value: Vec<u8>, ttl: Duration) -> io::Result<()> {
+        self.cache.insert_ttl(key, self.spill(value)?, ttl);
+        Ok(())
+    }
+
+    // get returns a live value; a spilled one is read from disk after the lock is released
+    pub fn get(&self, key: &K) -> io::Result<Option<Vec<u8>>> {
+        if self.cache.definitely_absent(key) {
+            return Ok(None)
+        }
+        let value = self.cache.read_key(key).hit(key).cloned();
+        value.map(Self::load).transpose()
+    }
+
+    pub fn take(&self, key: K) -> io::Result<Option<Vec<u8>>> {
+        self.cache.take(key).map(Self::load).transpose()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    // vacuum samples the set of potentially expired keys and removes them if expired, deleting
+    // the files of spilled ones
+    // panics if retry-threshold is not between 0 and 1.
+    pub fn vacuum(&self, count : usize, retry_threshold : f32 ) {
+        self.cache.vacuum(count, retry_threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpillCache;
+    use crate::clock::ManualClock;
+    use crate::CacheBuilder;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn spills_large_values() {
+        let dir = std::env::temp_dir().join(format!("hodor-spill-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let clock = ManualClock::new();
+        let cache = SpillCache::new(CacheBuilder::new().clock(clock.clone()).build_thread_safe(), &dir, 16).unwrap();
+        let files = || fs::read_dir(&dir).unwrap().count();
+
+        cache.insert("small", vec![1; 16]).unwrap();
+        cache.insert_ttl("large", vec![2; 1024], Duration::new(10, 0)).unwrap();
+        cache.insert("replaced", vec![3; 1024]).unwrap();
+        assert_eq!(2, files());
+        assert_eq!(Some(vec![1; 16]), cache.get(&"small").unwrap());
+        assert_eq!(Some(vec![2; 1024]), cache.get(&"large").unwrap());
+
+        // files go with their entries, however they leave
+        cache.insert("replaced", vec![3; 8]).unwrap();
+        assert_eq!(1, files());
+        clock.advance(Duration::new(11, 0));
+        cache.vacuum(10, 0.25);
+        assert_eq!(0, files());
+        assert_eq!(None, cache.get(&"large").unwrap());
+
+        cache.insert("taken", vec![4; 1024]).unwrap();
+        assert_eq!(Some(vec![4; 1024]), cache.take("taken").unwrap());
+        assert_eq!(0, files());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use std::ops::Add;
+
+use crate::eviction::Counter;
+
+// Stats is a point-in-time copy of a cache's (or namespace's) counters. Stats from several
+// caches can be summed with +.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+    // entries removed for capacity
+    pub evictions: u64,
+    // expired entries removed by vacuum, drain_expired or access
+    pub expirations: u64,
+}
+
+impl Stats {
+    // hit_ratio is hits / (hits + misses), or 0 before the first read
+    pub fn hit_ratio(&self) -> f64 {
+        let reads = self.hits + self.misses;
+        if reads == 0 {
+            return 0.0
+        }
+        self.hits as f64 / reads as f64
+    }
+}
+
+impl Add for Stats {
+    type Output = Stats;
+
+    fn add(self, other: Stats) -> Stats {
+        Stats{
+            hits: self.hits + other.hits,
+            misses: self.misses + other.misses,
+            inserts: self.inserts + other.inserts,
+            evictions: self.evictions + other.evictions,
+            expirations: self.expirations + other.expirations,
+        }
+    }
+}
+
+// Contention counts a ThreadSafeHashCache's lock acquisitions, and how many of them had to wait
+// for another thread to release the lock
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Contention {
+    pub reads: u64,
+    pub read_waits: u64,
+    pub writes: u64,
+    pub write_waits: u64,
+}
+
+impl Add for Contention {
+    type Output = Contention;
+
+    fn add(self, other: Contention) -> Contention {
+        Contention{
+            reads: self.reads + other.reads,
+            read_waits: self.read_waits + other.read_waits,
+            writes: self.writes + other.writes,
+            write_waits: self.write_waits + other.write_waits,
+        }
+    }
+}
+
+// ShardStats describes one shard of a ShardedCache, for spotting the hot ones a skewed key
+// distribution makes: compare their entry counts, hit ratios and lock waits. Vacuum's work on
+// the shard shows in stats.expirations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ShardStats {
+    pub len: usize,
+    // entries with a TTL
+    pub expiring: usize,
+    pub stats: Stats,
+    pub contention: Contention,
+}
+
+// LockRecorder holds the live counters behind a Contention
+#[derive(Debug, Default)]
+pub(crate) struct LockRecorder {
+    pub(crate) reads: Counter,
+    pub(crate) read_waits: Counter,
+    pub(crate) writes: Counter,
+    pub(crate) write_waits: Counter,
+}
+
+impl LockRecorder {
+    pub(crate) fn contention(&self) -> Contention {
+        Contention{
+            reads: self.reads.get(),
+            read_waits: self.read_waits.get(),
+            writes: self.writes.get(),
+            write_waits: self.write_waits.get(),
+        }
+    }
+}
+
+// Recorder holds the live counters behind a Stats; like the access counters they can be bumped
+// through a shared reference from the read path
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Recorder {
+    pub(crate) hits: Counter,
+    pub(crate) misses: Counter,
+    pub(crate) inserts: Counter,
+    pub(crate) evictions: Counter,
+    pub(crate) expirations: Counter,
+}
+
+impl Recorder {
+    pub(crate) fn read(&self, hit: bool) {
+        if hit {
+            self.hits.incr();
+        } else {
+            self.misses.incr();
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Stats {
+        Stats{
+            hits: self.hits.get(),
+            misses: self.misses.get(),
+            inserts: self.inserts.get(),
+            evictions: self.evictions.get(),
+            expirations: self.expirations.get(),
+        }
+    }
+}
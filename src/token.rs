@@ -0,0 +1,145 @@
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+use crate::{DefaultHashBuilder, ThreadSafeHashCache};
+
+// TokenDigest is the HMAC-SHA256 of a token that a TokenCache stores in its place. Digests
+// compare in constant time.
+#[derive(Clone, Copy)]
+pub struct TokenDigest([u8; 32]);
+
+impl PartialEq for TokenDigest {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.ct_eq(&other.0).into()
+    }
+}
+
+impl Eq for TokenDigest {}
+
+impl Hash for TokenDigest {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+// only a prefix is shown, enough to tell digests apart in logs
+impl fmt::Debug for TokenDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TokenDigest({:02x}{:02x}{:02x}{:02x}..)", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+// TokenCache caches data about security tokens (sessions, API keys) keyed by an HMAC of the token
+// rather than the token itself, for caches that mediate authentication. How long a lookup takes
+// then depends only on the MAC, which an attacker can't compute without the cache's key, and the
+// final digest comparison is constant-time, so timing a lookup reveals nothing about the tokens
+// stored. The tokens themselves are never kept, so they can't leak from memory or a Debug dump.
+// A configured cache can be wrapped with TokenCache::from, which draws a random key.
+pub struct TokenCache<V, S = DefaultHashBuilder> {
+    cache: ThreadSafeHashCache<TokenDigest, V, S>,
+    mac: Hmac<Sha256>,
+}
+
+impl<V> TokenCache<V> {
+    pub fn new() -> TokenCache<V> {
+        TokenCache::from(ThreadSafeHashCache::new())
+    }
+}
+
+impl<V, S: BuildHasher> TokenCache<V, S> {
+    // with_key uses a given MAC key, e.g. one shared by several processes so their digests agree
+    pub fn with_key(cache: ThreadSafeHashCache<TokenDigest, V, S>, key: &[u8]) -> TokenCache<V, S> {
+        let mac = Hmac::new_from_slice(key).expect("HMAC takes keys of any length");
+        TokenCache{ cache, mac }
+    }
+
+    // cache is the underlying cache, e.g. for vacuuming or subscribing
+    pub fn cache(&self) -> &ThreadSafeHashCache<TokenDigest, V, S> {
+        &self.cache
+    }
+
+    pub fn digest(&self, token: &[u8]) -> TokenDigest {
+        let mut mac = self.mac.clone();
+        mac.update(token);
+        TokenDigest(mac.finalize().into_bytes().into())
+    }
+
+    pub fn insert(&self, token: &[u8], value: V) -> Option<V> {
+        self.cache.insert(self.digest(token), value)
+    }
+
+    pub fn insert_ttl(&self, token: &[u8], value: V, ttl: Duration) -> Option<V> {
+        self.cache.insert_ttl(self.digest(token), value, ttl)
+    }
+
+    pub fn get<F>(&self, token: &[u8], f: F) -> bool where F: Fn(&V) {
+        self.cache.get(self.digest(token), f)
+    }
+
+    pub fn get_copied(&self, token: &[u8]) -> Option<V> where V: Copy {
+        self.cache.get_copied(&self.digest(token))
+    }
+
+    // take removes a token's entry, e.g. on logout or revocation
+    pub fn take(&self, token: &[u8]) -> Option<V> {
+        self.cache.take(self.digest(token))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    // vacuum samples the set of potentially expired keys and removes them if expired
+    // panics if retry-threshold is not between 0 and 1.
+    pub fn vacuum(&self, count : usize, retry_threshold : f32 ) {
+        self.cache.vacuum(count, retry_threshold)
+    }
+}
+
+impl<V, S: BuildHasher> From<ThreadSafeHashCache<TokenDigest, V, S>> for TokenCache<V, S> {
+    fn from(cache: ThreadSafeHashCache<TokenDigest, V, S>) -> Self {
+        let mut key = [0; 32];
+        rand::thread_rng().fill(&mut key);
+        TokenCache::with_key(cache, &key)
+    }
+}
+
+impl<V, S: BuildHasher+Default> Default for TokenCache<V, S> {
+    fn default() -> Self {
+        TokenCache::from(ThreadSafeHashCache::with_hasher(S::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenCache;
+    use crate::ThreadSafeHashCache;
+    use std::time::Duration;
+
+    #[test]
+    fn tokens() {
+        let cache : TokenCache<u32> = TokenCache::with_key(ThreadSafeHashCache::new(), b"secret key");
+        cache.insert_ttl(b"token-a", 1, Duration::new(60, 0));
+        cache.insert(b"token-b", 2);
+
+        assert_eq!(Some(1), cache.get_copied(b"token-a"));
+        assert_eq!(None, cache.get_copied(b"token-c"));
+        assert_eq!(Some(2), cache.take(b"token-b"));
+        assert_eq!(1, cache.len());
+
+        // digests depend on the key
+        let other : TokenCache<u32> = TokenCache::with_key(ThreadSafeHashCache::new(), b"secret key");
+        assert_eq!(cache.digest(b"token-a"), other.digest(b"token-a"));
+        assert_ne!(cache.digest(b"token-a"), TokenCache::<u32>::new().digest(b"token-a"));
+    }
+}
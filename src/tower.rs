@@ -0,0 +1,163 @@
+// tower caches the responses of any tower::Service, e.g. an axum router or a tonic client:
+//
+//     ServiceBuilder::new().layer(CacheLayer::new(cache, |req: &Request| Some((key(req), ttl))))
+//
+// Responses are cloned out of the cache, so the service's Response type must be Clone.
+use std::future::{ready, Future};
+use std::hash::{BuildHasher, Hash};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{DefaultHashBuilder, ThreadSafeHashCache};
+
+// Extractor returns the cache key and TTL for a request, or None to pass it through uncached
+pub type Extractor<Req, K> = dyn Fn(&Req) -> Option<(K, Duration)> + Send + Sync;
+
+// CacheLayer wraps services in a CacheService sharing one cache
+pub struct CacheLayer<Req, K: Hash+Eq+Clone, Resp, S = DefaultHashBuilder> {
+    cache: Arc<ThreadSafeHashCache<K, Resp, S>>,
+    extract: Arc<Extractor<Req, K>>,
+}
+
+impl<Req, K: Hash+Eq+Clone, Resp, S> CacheLayer<Req, K, Resp, S> {
+    pub fn new<F>(cache: Arc<ThreadSafeHashCache<K, Resp, S>>, extract: F) -> CacheLayer<Req, K, Resp, S>
+        where F: Fn(&Req) -> Option<(K, Duration)> + Send + Sync + 'static {
+        CacheLayer{ cache, extract: Arc::new(extract) }
+    }
+
+    pub fn cache(&self) -> &Arc<ThreadSafeHashCache<K, Resp, S>> {
+        &self.cache
+    }
+}
+
+impl<Req, K: Hash+Eq+Clone, Resp, S> Clone for CacheLayer<Req, K, Resp, S> {
+    fn clone(&self) -> Self {
+        CacheLayer{ cache: self.cache.clone(), extract: self.extract.clone() }
+    }
+}
+
+impl<Svc, Req, K: Hash+Eq+Clone, Resp, S> Layer<Svc> for CacheLayer<Req, K, Resp, S> {
+    type Service = CacheService<Svc, Req, K, Resp, S>;
+
+    fn layer(&self, inner: Svc) -> Self::Service {
+        CacheService{ inner, cache: self.cache.clone(), extract: self.extract.clone() }
+    }
+}
+
+// CacheService answers requests with a key from the cache, calling the inner service on a miss
+// and caching its successful response with the request's TTL. Errors aren't cached.
+pub struct CacheService<Svc, Req, K: Hash+Eq+Clone, Resp, S = DefaultHashBuilder> {
+    inner: Svc,
+    cache: Arc<ThreadSafeHashCache<K, Resp, S>>,
+    extract: Arc<Extractor<Req, K>>,
+}
+
+impl<Svc: Clone, Req, K: Hash+Eq+Clone, Resp, S> Clone for CacheService<Svc, Req, K, Resp, S> {
+    fn clone(&self) -> Self {
+        CacheService{ inner: self.inner.clone(), cache: self.cache.clone(), extract: self.extract.clone() }
+    }
+}
+
+impl<Svc, Req, K: Hash+Eq+Clone, Resp, S> CacheService<Svc, Req, K, Resp, S> {
+    pub fn get_ref(&self) -> &Svc {
+        &self.inner
+    }
+}
+
+type BoxFuture<T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send>>;
+
+impl<Svc, Req, K: Hash+Eq+Clone, Resp, S> Service<Req> for CacheService<Svc, Req, K, Resp, S>
+    where Svc: Service<Req, Response = Resp>, Svc::Future: Send + 'static, Svc::Error: Send + 'static,
+          K: Send+Sync+'static, Resp: Clone+Send+Sync+'static, S: BuildHasher+Send+Sync+'static
+{
+    type Response = Resp;
+    type Error = Svc::Error;
+    type Future = BoxFuture<Resp, Svc::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let (key, ttl) = match (self.extract)(&req) {
+            Some(keyed) => keyed,
+            None => return Box::pin(self.inner.call(req)),
+        };
+        if let Some(resp) = self.cache.read_key(&key).hit(&key).cloned() {
+            return Box::pin(ready(Ok(resp)))
+        }
+        let cache = self.cache.clone();
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let resp = fut.await?;
+            cache.insert_ttl(key, resp.clone(), ttl);
+            Ok(resp)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CacheLayer;
+    use crate::ThreadSafeHashCache;
+    use std::future::{ready, Ready};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tower_layer::Layer;
+    use tower_service::Service;
+
+    // Upper uppercases its request, counting the calls that reach it
+    #[derive(Clone)]
+    struct Upper(Arc<AtomicUsize>);
+
+    impl Service<String> for Upper {
+        type Response = String;
+        type Error = String;
+        type Future = Ready<Result<String, String>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), String>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: String) -> Self::Future {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            if req.is_empty() {
+                return ready(Err("empty".to_string()))
+            }
+            ready(Ok(req.to_uppercase()))
+        }
+    }
+
+    #[test]
+    fn caches_responses() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        // requests starting with "!" bypass the cache
+        let layer = CacheLayer::new(Arc::new(ThreadSafeHashCache::new()), |req: &String| {
+            if req.starts_with('!') { None } else { Some((req.clone(), Duration::new(60, 0))) }
+        });
+        let mut svc = layer.layer(Upper(calls.clone()));
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let mut call = |req: &str| rt.block_on(svc.call(req.to_string()));
+
+        assert_eq!(Ok("ID".to_string()), call("id"));
+        assert_eq!(Ok("ID".to_string()), call("id"));
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+
+        assert_eq!(Ok("!ID".to_string()), call("!id"));
+        assert_eq!(Ok("!ID".to_string()), call("!id"));
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+
+        // errors aren't cached
+        assert!(call("").is_err());
+        assert!(call("").is_err());
+        assert_eq!(5, calls.load(Ordering::SeqCst));
+        assert_eq!(1, layer.cache().len());
+    }
+}
@@ -0,0 +1,84 @@
+// event! emits a tracing event under the "hodor" target when the `tracing` feature is enabled,
+// and compiles to nothing otherwise
+macro_rules! event {
+    ($lvl:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(target: "hodor", tracing::Level::$lvl, $($arg)+);
+    };
+}
+
+// span! enters a tracing span for the rest of the enclosing block when the `tracing` feature is
+// enabled, and compiles to nothing otherwise
+macro_rules! span {
+    ($lvl:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::span!(target: "hodor", tracing::Level::$lvl, $($arg)+).entered();
+    };
+}
+
+// timed_lock acquires a lock, reporting how long the caller waited for it when the `tracing`
+// feature is enabled
+macro_rules! timed_lock {
+    ($name:literal, $acquire:expr) => {{
+        #[cfg(feature = "tracing")]
+        let start = std::time::Instant::now();
+        let guard = $acquire;
+        event!(TRACE, lock = $name, wait_us = start.elapsed().as_micros() as u64, "lock acquired");
+        guard
+    }};
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::ThreadSafeHashCache;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    // Counter is a minimal subscriber that counts the spans and events it sees from hodor
+    #[derive(Clone, Default)]
+    struct Counter {
+        spans: Arc<AtomicUsize>,
+        events: Arc<AtomicUsize>,
+    }
+
+    impl Subscriber for Counter {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.target() == "hodor"
+        }
+
+        fn new_span(&self, _: &Attributes<'_>) -> Id {
+            Id::from_u64(self.spans.fetch_add(1, Ordering::SeqCst) as u64 + 1)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {
+            self.events.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn emits_events() {
+        let counter = Counter::default();
+        tracing::subscriber::with_default(counter.clone(), || {
+            let cache : ThreadSafeHashCache<&str,&str> = ThreadSafeHashCache::new();
+            cache.insert_ttl("id", "secret", Duration::new(10, 0));
+            cache.get("id", |_| {});
+            cache.get("nope", |_| {});
+            cache.vacuum(10, 0.25);
+        });
+
+        // one span for the vacuum, and events for the insert, hit, miss, vacuum pass and each lock
+        assert_eq!(1, counter.spans.load(Ordering::SeqCst));
+        assert!(counter.events.load(Ordering::SeqCst) >= 8);
+    }
+}
@@ -0,0 +1,94 @@
+use std::hash::{BuildHasher, Hash};
+use std::time::Duration;
+
+use crate::{Cache, ThreadSafeHashCache};
+
+enum Op<K, V> {
+    Insert(K, V, Option<Duration>),
+    Remove(K),
+}
+
+// Transaction collects the mutations made in ThreadSafeHashCache::transaction; none of them
+// touch the cache until the closure returns
+pub struct Transaction<K, V> {
+    ops: Vec<Op<K, V>>,
+}
+
+impl<K, V> Transaction<K, V> {
+    pub fn insert(&mut self, key: K, value: V) {
+        self.ops.push(Op::Insert(key, value, None))
+    }
+
+    pub fn insert_ttl(&mut self, key: K, value: V, ttl: Duration) {
+        self.ops.push(Op::Insert(key, value, Some(ttl)))
+    }
+
+    pub fn remove(&mut self, key: K) {
+        self.ops.push(Op::Remove(key))
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher> ThreadSafeHashCache<K, V, S> {
+    // transaction applies the mutations f makes to its Transaction under a single write lock, in
+    // the order they were made, so readers see either none or all of them. f runs before the
+    // lock is taken: if it panics, nothing is applied.
+    pub fn transaction<F, R>(&self, f: F) -> R where F: FnOnce(&mut Transaction<K, V>) -> R {
+        let mut txn = Transaction{ ops: Vec::new() };
+        let result = f(&mut txn);
+        if txn.is_empty() {
+            return result
+        }
+
+        let mut inner = self.write();
+        for op in txn.ops {
+            match op {
+                Op::Insert(key, value, None) => { inner.insert(key, value); },
+                Op::Insert(key, value, Some(ttl)) => { inner.insert_ttl(key, value, ttl); },
+                Op::Remove(key) => { inner.take(key); },
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ThreadSafeHashCache;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::time::Duration;
+
+    #[test]
+    fn transactions() {
+        let cache : ThreadSafeHashCache<&str,u32> = ThreadSafeHashCache::new();
+        cache.insert("old", 1);
+
+        let applied = cache.transaction(|txn| {
+            txn.insert("a", 1);
+            txn.insert_ttl("b", 2, Duration::new(10, 0));
+            txn.remove("old");
+            // nothing is visible until the closure returns
+            assert!(cache.get_copied(&"a").is_none());
+            txn.len()
+        });
+        assert_eq!(3, applied);
+        assert_eq!(Some(1), cache.get_copied(&"a"));
+        assert_eq!(Some(2), cache.get_copied(&"b"));
+        assert!(cache.get_copied(&"old").is_none());
+
+        // a panicking transaction leaves the cache as it was
+        let result = panic::catch_unwind(AssertUnwindSafe(|| cache.transaction(|txn| {
+            txn.remove("a");
+            panic!("abort");
+        })));
+        assert!(result.is_err());
+        assert_eq!(Some(1), cache.get_copied(&"a"));
+    }
+}
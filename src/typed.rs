@@ -0,0 +1,121 @@
+use std::any::{Any, TypeId};
+use std::fmt;
+use std::time::Duration;
+
+use crate::ThreadSafeHashCache;
+
+type Entry = Box<dyn Any + Send + Sync>;
+
+// TypedCache holds values of any number of unrelated types, each keyed by a name and its type, so
+// get::<Session>("id") and get::<Profile>("id") are separate entries. It suits application-wide
+// registries caching several kinds of values under one capacity limit. A configured cache can be
+// wrapped with TypedCache::from.
+pub struct TypedCache {
+    cache: ThreadSafeHashCache<(TypeId, String), Entry>,
+}
+
+impl TypedCache {
+    pub fn new() -> TypedCache {
+        TypedCache::from(ThreadSafeHashCache::new())
+    }
+
+    // cache is the underlying cache, e.g. for vacuuming or subscribing
+    pub fn cache(&self) -> &ThreadSafeHashCache<(TypeId, String), Entry> {
+        &self.cache
+    }
+
+    fn key<T: 'static>(key: &str) -> (TypeId, String) {
+        (TypeId::of::<T>(), key.to_owned())
+    }
+
+    // insert stores a value, returning the previous value of the same type under key
+    pub fn insert<T: Any+Send+Sync>(&self, key: &str, value: T) -> Option<T> {
+        let previous = self.cache.insert(Self::key::<T>(key), Box::new(value))?;
+        Some(*previous.downcast().expect("entries are keyed by their type"))
+    }
+
+    pub fn insert_ttl<T: Any+Send+Sync>(&self, key: &str, value: T, ttl: Duration) -> Option<T> {
+        let previous = self.cache.insert_ttl(Self::key::<T>(key), Box::new(value), ttl)?;
+        Some(*previous.downcast().expect("entries are keyed by their type"))
+    }
+
+    // get clones out the live value of type T under key
+    pub fn get<T: Any+Clone>(&self, key: &str) -> Option<T> {
+        let key = Self::key::<T>(key);
+        if self.cache.definitely_absent(&key) {
+            return None
+        }
+        self.cache.read_key(&key).hit(&key).and_then(|v| v.downcast_ref::<T>().cloned())
+    }
+
+    pub fn contains<T: Any>(&self, key: &str) -> bool {
+        self.cache.get(Self::key::<T>(key), |_| {})
+    }
+
+    pub fn take<T: Any>(&self, key: &str) -> Option<T> {
+        let value = self.cache.take(Self::key::<T>(key))?;
+        Some(*value.downcast().expect("entries are keyed by their type"))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    // vacuum samples the set of potentially expired keys and removes them if expired
+    // panics if retry-threshold is not between 0 and 1.
+    pub fn vacuum(&self, count : usize, retry_threshold : f32 ) {
+        self.cache.vacuum(count, retry_threshold)
+    }
+}
+
+impl From<ThreadSafeHashCache<(TypeId, String), Entry>> for TypedCache {
+    fn from(cache: ThreadSafeHashCache<(TypeId, String), Entry>) -> Self {
+        TypedCache{ cache }
+    }
+}
+
+impl Default for TypedCache {
+    fn default() -> Self {
+        TypedCache::new()
+    }
+}
+
+impl fmt::Debug for TypedCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedCache").field("len", &self.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TypedCache;
+    use crate::CacheBuilder;
+    use std::time::Duration;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Session {
+        user: String,
+    }
+
+    #[test]
+    fn typed_entries() {
+        let cache = TypedCache::from(CacheBuilder::new().max_capacity(10).build_thread_safe());
+        cache.insert("id", Session{ user: "hodor".to_string() });
+        cache.insert_ttl("id", 42u64, Duration::new(60, 0));
+
+        // the same name holds one value per type
+        assert_eq!(Some(Session{ user: "hodor".to_string() }), cache.get::<Session>("id"));
+        assert_eq!(Some(42), cache.get::<u64>("id"));
+        assert_eq!(None, cache.get::<u32>("id"));
+        assert_eq!(2, cache.len());
+
+        assert_eq!(Some(42), cache.insert("id", 43u64));
+        assert_eq!(Some(43), cache.take::<u64>("id"));
+        assert!(!cache.contains::<u64>("id"));
+        assert!(cache.contains::<Session>("id"));
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::hash_map::{self, HashMap};
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, PoisonError};
+use std::time::Instant;
+
+use crate::ThreadSafeHashCache;
+
+// View is an immutable copy of a ThreadSafeHashCache's live entries as of when it was taken, made
+// by snapshot. It's a full copy: taking one clones every live entry under the cache's read lock,
+// which writers wait for. Once taken it's read without any lock, and clones share one copy.
+#[derive(Debug)]
+pub struct View<K, V> {
+    entries: Arc<HashMap<K, V>>,
+}
+
+impl<K, V> Clone for View<K, V> {
+    fn clone(&self) -> Self {
+        View{ entries: self.entries.clone() }
+    }
+}
+
+impl<K: Hash+Eq, V> View<K, V> {
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // iter visits the entries in arbitrary order
+    pub fn iter(&self) -> hash_map::Iter<'_, K, V> {
+        self.entries.iter()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a View<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = hash_map::Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+// CachedView is the last snapshot taken, reused until the cache is written to or one of its entries
+// expires
+pub(crate) struct CachedView<K, V> {
+    // generation is the cache's write count when the view was taken
+    generation: u64,
+    // expires is the earliest deadline among the view's entries
+    expires: Option<Instant>,
+    view: View<K, V>,
+}
+
+impl<K: Hash+Eq+Clone, V: Clone, S: BuildHasher> ThreadSafeHashCache<K, V, S> {
+    // snapshot returns a View of the live entries. The first one after a write or expiration
+    // copies them all, in O(n) under the read lock; later ones share that copy until the next.
+    pub fn snapshot(&self) -> View<K, V> {
+        // cached is only written once a copy is complete, so a value's clone panicking leaves it
+        // as it was
+        let mut cached = self.view.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(c) = &*cached {
+            let now = self.now();
+            let fresh = c.expires.is_none_or(|at| now <= at);
+            if fresh && c.generation == self.generation.get() {
+                return c.view.clone()
+            }
+        }
+
+        let inner = self.read();
+        // no writer holds the lock, so the generation matches what's copied
+        let generation = self.generation.get();
+        let now = inner.now();
+        let live = || inner.entries.iter().map(|(_, e)| e).filter(|(_, v)| !v.expired(now));
+        let expires = live().filter_map(|(_, v)| v.expires_at()).min();
+        // SAFETY: read holds every stripe
+        let view = View{ entries: Arc::new(live().map(|(k, v)| (K::clone(k), unsafe { v.value.get() }.clone())).collect()) };
+        *cached = Some(CachedView{ generation, expires, view: view.clone() });
+        view
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::ManualClock;
+    use crate::{CacheBuilder, ThreadSafeHashCache};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn snapshots() {
+        let clock = ManualClock::new();
+        let cache : ThreadSafeHashCache<&str,u32> = CacheBuilder::new().clock(clock.clone()).build_thread_safe();
+        cache.insert("a", 1);
+        cache.insert_ttl("b", 2, Duration::new(10, 0));
+
+        let view = cache.snapshot();
+        assert!(Arc::ptr_eq(&view.entries, &cache.snapshot().entries));
+
+        // a view holds no lock, so the cache can be written to while iterating it, without
+        // changing it
+        for (k, _) in &view {
+            cache.update(k, |v| *v *= 10);
+        }
+        assert_eq!(Some(&1), view.get(&"a"));
+        assert_eq!(Some(&10), cache.snapshot().get(&"a"));
+        assert!(!Arc::ptr_eq(&view.entries, &cache.snapshot().entries));
+
+        // an expiration also makes for a new copy
+        clock.advance(Duration::new(11, 0));
+        assert_eq!(vec![(&"a", &10)], cache.snapshot().iter().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,179 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, Condvar, Mutex, PoisonError, Weak};
+use std::time::{Duration, Instant};
+
+use crate::{Cache, CacheEvent, ThreadSafeHashCache};
+
+// RECHECK bounds how long try_insert_within sleeps between looks for room, since unpinning an
+// entry or reaching its deadline raises no event to wake it
+const RECHECK: Duration = Duration::from_millis(10);
+
+// Signal is a flag raised by a listener and waited on by a blocking call
+struct Signal(Mutex<bool>, Condvar);
+
+impl Signal {
+    fn raise(&self) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.1.notify_one();
+    }
+
+    // wait waits until the flag is raised, and lowers it, or until the deadline passes; it
+    // returns false on timing out
+    fn wait(&self, until: Instant) -> bool {
+        let mut flag = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        while !*flag {
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return false
+            }
+            flag = self.1.wait_timeout(flag, left).unwrap_or_else(PoisonError::into_inner).0;
+        }
+        *flag = false;
+        true
+    }
+}
+
+impl<K: Hash+Eq+Send+Sync+'static, V, S: BuildHasher> ThreadSafeHashCache<K, V, S> {
+    // signal_on returns a Signal raised by every event matching f. The listener unsubscribes at
+    // the first event after the Signal is dropped.
+    fn signal_on<F>(&self, f: F) -> Arc<Signal> where F: Fn(&CacheEvent<&K, &V>) -> bool + Send + Sync + 'static {
+        let signal = Arc::new(Signal(Mutex::new(false), Condvar::new()));
+        let listener: Weak<Signal> = Arc::downgrade(&signal);
+        self.write().listeners.add(Box::new(move |event, _| match listener.upgrade() {
+            Some(signal) => {
+                if f(event) {
+                    signal.raise();
+                }
+                true
+            },
+            None => false,
+        }));
+        signal
+    }
+
+    // get_wait returns a clone of key's live value, blocking until another thread inserts it if
+    // it isn't there yet; None if timeout elapses first. For request/response correlation: the
+    // requester waits on the request id, the thread reading responses inserts under it.
+    pub fn get_wait(&self, key: &K, timeout: Duration) -> Option<V> where K: Clone, V: Clone {
+        let deadline = Instant::now() + timeout;
+        let live = || {
+            let inner = self.read_key(key);
+            let now = inner.now();
+            match inner.lookup(key) {
+                // SAFETY: read_key holds the key's stripe
+                Some(v) if !v.expired(now) => Some(unsafe { v.value.get() }.clone()),
+                _ => None,
+            }
+        };
+        // subscribing takes the write lock, so a value that's already there is returned without
+        if let Some(value) = live() {
+            return Some(value)
+        }
+
+        let watched = key.clone();
+        let signal = self.signal_on(move |event| match *event {
+            CacheEvent::Inserted{ key, .. } | CacheEvent::Replaced{ key, .. } => *key == watched,
+            _ => false,
+        });
+        loop {
+            // checked again once subscribed, since an insert between the first check and
+            // subscribing raised no signal; after that, one between a check and the wait has
+            if let Some(value) = live() {
+                return Some(value)
+            }
+            if !signal.wait(deadline) {
+                return None
+            }
+        }
+    }
+
+    // try_insert_within is insert_ttl with backpressure: when the cache is at max_capacity with
+    // nothing it may evict (every entry is pinned), it blocks until an entry is removed, expires
+    // or is unpinned, instead of growing past the limit. Err hands the entry back if there's
+    // still no room after timeout.
+    pub fn try_insert_within(&self, key: K, value: V, ttl: Duration, timeout: Duration) -> Result<Option<V>, (K, V)> {
+        let deadline = Instant::now() + timeout;
+        let mut signal = None;
+        loop {
+            {
+                let mut inner = self.write();
+                if inner.has_room(&key) {
+                    return Ok(inner.insert_ttl(key, value, ttl))
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err((key, value))
+            }
+            // subscribed on the first wait only, so inserts with room to spare cost nothing extra
+            let signal = signal.get_or_insert_with(|| self.signal_on(|event| matches!(event,
+                CacheEvent::Expired{ .. } | CacheEvent::Evicted{ .. } | CacheEvent::Removed{ .. })));
+            signal.wait(deadline.min(now + RECHECK));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CacheBuilder, ThreadSafeHashCache};
+    use std::sync::Arc;
+    use std::thread::{sleep, spawn};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn get_wait() {
+        let cache : ThreadSafeHashCache<u32,&str> = ThreadSafeHashCache::new();
+        cache.insert(1, "ready");
+        assert_eq!(Some("ready"), cache.get_wait(&1, Duration::ZERO));
+        // a hit doesn't subscribe
+        assert_eq!(0, cache.read().listeners.len());
+
+        let start = Instant::now();
+        assert_eq!(None, cache.get_wait(&2, Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        // the responder's insert wakes the waiting requester; inserts of other keys don't end the
+        // wait
+        let cache = Arc::new(cache);
+        let responder = cache.clone();
+        let handle = spawn(move || {
+            sleep(Duration::from_millis(10));
+            responder.insert_ttl(3, "other", Duration::new(60, 0));
+            sleep(Duration::from_millis(10));
+            responder.insert_ttl(2, "response", Duration::new(60, 0));
+        });
+        assert_eq!(Some("response"), cache.get_wait(&2, Duration::new(5, 0)));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn try_insert_within() {
+        let ttl = Duration::new(60, 0);
+        let cache : ThreadSafeHashCache<u32,u32> = CacheBuilder::new().max_capacity(2).build_thread_safe();
+        assert_eq!(Ok(None), cache.try_insert_within(1, 1, ttl, Duration::ZERO));
+        // with an evictable entry, a full cache makes room as insert would
+        cache.insert_ttl(2, 2, ttl);
+        assert_eq!(Ok(None), cache.try_insert_within(3, 3, ttl, Duration::ZERO));
+        assert_eq!(2, cache.len());
+
+        cache.pin(&2);
+        cache.pin(&3);
+        // overwriting needs no room
+        assert_eq!(Ok(Some(3)), cache.try_insert_within(3, 30, ttl, Duration::ZERO));
+        let start = Instant::now();
+        assert_eq!(Err((4, 4)), cache.try_insert_within(4, 4, ttl, Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(2, cache.len());
+
+        // a consumer taking an entry lets the blocked producer in
+        let cache = Arc::new(cache);
+        let consumer = cache.clone();
+        let handle = spawn(move || {
+            sleep(Duration::from_millis(10));
+            ThreadSafeHashCache::take(&consumer, 2)
+        });
+        assert_eq!(Ok(None), cache.try_insert_within(4, 4, ttl, Duration::new(5, 0)));
+        assert_eq!(Some(2), handle.join().unwrap());
+        assert_eq!(Some(4), cache.get_copied(&4));
+    }
+}
@@ -0,0 +1,115 @@
+use std::hash::{BuildHasher, Hash};
+use std::time::Duration;
+
+use crate::rng::CacheRng;
+use crate::{Cache, HashCache, ThreadSafeHashCache};
+
+// Warmup configures a bulk load done with warm_from before a cache is handed to the application
+// TTLs are staggered by a random jitter so a freshly warmed cache doesn't expire all at once
+pub struct Warmup<'a> {
+    jitter: Duration,
+    every: usize,
+    progress: Option<Box<dyn FnMut(usize) + 'a>>,
+}
+
+impl<'a> Warmup<'a> {
+    pub fn new() -> Warmup<'a> {
+        Warmup{ jitter: Duration::from_secs(0), every: 0, progress: None }
+    }
+
+    // jitter adds a random extra duration in [0, jitter] to every TTL that is loaded
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    // on_progress calls f with the number of entries loaded so far, every `every` entries and
+    // once more when the load finishes
+    pub fn on_progress<F>(mut self, every: usize, f: F) -> Self where F: FnMut(usize) + 'a {
+        self.every = every;
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    fn stagger(&self, ttl: Duration) -> Duration {
+        let jitter = self.jitter.as_nanos() as u64;
+        if jitter == 0 {
+            return ttl
+        }
+        ttl + Duration::from_nanos(CacheRng::new(None).below(jitter + 1))
+    }
+
+    fn report(&mut self, loaded: usize, done: bool) {
+        if let Some(f) = self.progress.as_mut() {
+            if done || (self.every > 0 && loaded.is_multiple_of(self.every)) {
+                f(loaded)
+            }
+        }
+    }
+}
+
+impl Default for Warmup<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher> HashCache<K, V, S> {
+    // warm_from bulk-loads entries, where a ttl of None inserts a persistent entry
+    // returns the number of entries loaded
+    pub fn warm_from<I>(&mut self, iter: I, mut warmup: Warmup<'_>) -> usize where I: IntoIterator<Item=(K, V, Option<Duration>)> {
+        let iter = iter.into_iter();
+        let additional = iter.size_hint().0;
+        self.store.reserve(additional);
+        self.entries.reserve(additional);
+
+        let mut loaded = 0;
+        for (key, value, ttl) in iter {
+            match ttl {
+                Some(ttl) => self.insert_ttl(key, value, warmup.stagger(ttl)),
+                None => self.insert(key, value),
+            };
+            loaded += 1;
+            warmup.report(loaded, false);
+        }
+        warmup.report(loaded, true);
+        loaded
+    }
+}
+
+impl<K: Hash+Eq, V, S: BuildHasher> ThreadSafeHashCache<K, V, S> {
+    // warm_from bulk-loads entries under a single write lock, see HashCache::warm_from
+    pub fn warm_from<I>(&self, iter: I, warmup: Warmup<'_>) -> usize where I: IntoIterator<Item=(K, V, Option<Duration>)> {
+        self.write().warm_from(iter, warmup)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cache, HashCache, Warmup};
+    use std::time::Duration;
+
+    #[test]
+    fn warm_from() {
+        let mut cache : HashCache<usize,usize> = HashCache::new();
+        let mut reports = vec![];
+        let entries = (0..10).map(|i| (i, i, if i % 2 == 0 { Some(Duration::new(10, 0)) } else { None }));
+
+        let loaded = cache.warm_from(entries, Warmup::new()
+            .jitter(Duration::new(5, 0))
+            .on_progress(4, |n| reports.push(n)));
+
+        assert_eq!(10, loaded);
+        assert_eq!(vec![4, 8, 10], reports);
+        assert_eq!(5, cache.expiring.len());
+        assert!(cache.get(3, |v| assert_eq!(*v, 3)));
+
+        // staggered TTLs land between the base TTL and base + jitter
+        for (_, (_, v)) in cache.entries.iter() {
+            if let Some(ttl) = v.ttl() {
+                assert!(ttl >= Duration::new(10, 0));
+                assert!(ttl <= Duration::new(15, 0));
+            }
+        }
+    }
+}
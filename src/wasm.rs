@@ -0,0 +1,74 @@
+// wasm exposes a HashCache to JavaScript through wasm-bindgen, for edge runtimes and browser
+// apps:
+//
+//     const cache = new JsCache(1000);
+//     cache.insertTtl("session:1", { user: 42 }, 30_000);
+//     cache.get("session:1");
+//
+// Keys are strings; values are any JS value, held by reference rather than copied. TTLs are in
+// milliseconds. JS is single threaded, so there's no locking and no background reaper: call
+// vacuum from a timer.
+//
+// Expiry is read from a JsClock, which follows JavaScript's Date.now(), since on
+// wasm32-unknown-unknown std has no clock of its own.
+use wasm_bindgen::prelude::*;
+
+use std::time::Duration;
+
+use crate::{Cache, CacheBuilder, HashCache, JsClock};
+
+#[wasm_bindgen]
+pub struct JsCache(HashCache<String, JsValue>);
+
+#[wasm_bindgen]
+impl JsCache {
+    // new creates a cache holding at most maxCapacity entries, or any number if it's omitted
+    #[wasm_bindgen(constructor)]
+    pub fn new(max_capacity: Option<usize>) -> JsCache {
+        let mut builder = CacheBuilder::new().clock(JsClock::new());
+        if let Some(n) = max_capacity {
+            builder = builder.max_capacity(n);
+        }
+        JsCache(builder.build())
+    }
+
+    // insert stores a persistent entry and returns whether it replaced one
+    pub fn insert(&mut self, key: String, value: JsValue) -> bool {
+        self.0.insert(key, value).is_some()
+    }
+
+    // insertTtl stores an entry expiring after ttlMs milliseconds and returns whether it
+    // replaced one
+    #[wasm_bindgen(js_name = insertTtl)]
+    pub fn insert_ttl(&mut self, key: String, value: JsValue, ttl_ms: f64) -> Result<bool, JsError> {
+        let ttl = Duration::try_from_secs_f64(ttl_ms / 1000.0).map_err(|_| JsError::new("ttlMs must be a non-negative number"))?;
+        Ok(self.0.insert_ttl(key, value, ttl).is_some())
+    }
+
+    // get returns a live value, or undefined if the key is absent or expired
+    pub fn get(&self, key: String) -> JsValue {
+        let mut found = JsValue::UNDEFINED;
+        self.0.get_with(&key, &mut |v| found = v.clone());
+        found
+    }
+
+    pub fn has(&self, key: String) -> bool {
+        self.0.get_with(&key, &mut |_| {})
+    }
+
+    // take removes a key and returns its live value, or undefined
+    pub fn take(&mut self, key: String) -> JsValue {
+        self.0.take(key).unwrap_or(JsValue::UNDEFINED)
+    }
+
+    // vacuum removes expired entries like auto_vacuum, returning how many
+    pub fn vacuum(&mut self) -> usize {
+        self.0.auto_vacuum()
+    }
+
+    // size is the number of entries stored, expired or not
+    #[wasm_bindgen(getter)]
+    pub fn size(&self) -> usize {
+        self.0.len()
+    }
+}